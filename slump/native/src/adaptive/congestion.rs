@@ -0,0 +1,270 @@
+use super::Trigger;
+use crate::error::SlumpError;
+use std::{collections::VecDeque, str::FromStr};
+
+// Loss past this in a window means the link can't carry what we send
+const CONSTRAINED_LOSS: f64 = 0.05;
+// GCC's loss controller: back off above the high mark, probe below the low one
+const HIGH_LOSS: f64 = 0.10;
+const LOW_LOSS: f64 = 0.02;
+const LOSS_INCREASE: f64 = 1.05;
+// The delay controller probes faster, since it backs off before loss does
+const DELAY_INCREASE: f64 = 1.08;
+// On overuse, fall to this share of what actually got through
+const OVERUSE_BACKOFF: f64 = 0.85;
+// RTT samples in the trendline, one per stats window
+const TREND_WINDOWS: usize = 5;
+// Overuse threshold on the RTT slope, in ms per window; it adapts between
+// these so a steadily noisy link doesn't read as congestion
+const MIN_THRESHOLD_MS: f64 = 2.0;
+const MAX_THRESHOLD_MS: f64 = 50.0;
+const INITIAL_THRESHOLD_MS: f64 = 6.0;
+const THRESHOLD_UP: f64 = 0.2;
+const THRESHOLD_DOWN: f64 = 0.05;
+// Never estimate below what a usable stream needs
+const MIN_ESTIMATE_KBPS: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CongestionAlgorithm {
+    #[default]
+    Loss,
+    Delay,
+}
+
+impl CongestionAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CongestionAlgorithm::Loss => "loss",
+            CongestionAlgorithm::Delay => "delay",
+        }
+    }
+
+    pub fn controller(&self, max_kbps: u32) -> Box<dyn CongestionController> {
+        let mut controller: Box<dyn CongestionController> = match self {
+            CongestionAlgorithm::Loss => Box::<LossBased>::default(),
+            CongestionAlgorithm::Delay => Box::<DelayBased>::default(),
+        };
+        controller.reset(max_kbps);
+        controller
+    }
+}
+
+impl FromStr for CongestionAlgorithm {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "loss" => Ok(CongestionAlgorithm::Loss),
+            "delay" => Ok(CongestionAlgorithm::Delay),
+            other => Err(SlumpError::Init(format!(
+                "Unknown congestion control: {}",
+                other
+            ))),
+        }
+    }
+}
+
+// One stats window, as the worst peer saw it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Feedback {
+    // 0-1
+    pub loss: f64,
+    // 0 until a peer has reported one
+    pub rtt_ms: f64,
+    // Media per peer
+    pub sent_kbps: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub available_kbps: f64,
+    // Set while the link is congested, so the adaptive controller degrades
+    pub constrained: Option<Trigger>,
+}
+
+// Send-side bandwidth estimation, fed once per stats window
+pub trait CongestionController: Send {
+    // Starts over at `max_kbps`, the most the encoder is set to send
+    fn reset(&mut self, max_kbps: u32);
+
+    fn update(&mut self, feedback: &Feedback) -> Estimate;
+}
+
+// GCC's loss-based half on its own. Only loss counts as congestion, so it
+// suits links where delay swings without meaning anything, like LTE.
+#[derive(Debug, Default)]
+pub struct LossBased {
+    estimate: f64,
+    max: f64,
+}
+
+impl CongestionController for LossBased {
+    fn reset(&mut self, max_kbps: u32) {
+        self.max = max_kbps as f64;
+        self.estimate = self.max;
+    }
+
+    fn update(&mut self, feedback: &Feedback) -> Estimate {
+        self.estimate = loss_adjusted(self.estimate, feedback.loss, LOSS_INCREASE)
+            .clamp(MIN_ESTIMATE_KBPS.min(self.max), self.max);
+        Estimate {
+            available_kbps: self.estimate,
+            constrained: (feedback.loss > CONSTRAINED_LOSS).then_some(Trigger::Loss),
+        }
+    }
+}
+
+// GCC-style: backs off when queueing delay builds, before the queue
+// overflows into loss. Per-packet arrival times aren't available here, so
+// the delay gradient is the slope of RTT over recent windows. Long fixed
+// delays, as on satellite links, don't count against it; only growth does.
+#[derive(Debug)]
+pub struct DelayBased {
+    estimate: f64,
+    max: f64,
+    rtts: VecDeque<f64>,
+    threshold: f64,
+}
+
+impl Default for DelayBased {
+    fn default() -> Self {
+        Self {
+            estimate: 0.0,
+            max: 0.0,
+            rtts: VecDeque::with_capacity(TREND_WINDOWS),
+            threshold: INITIAL_THRESHOLD_MS,
+        }
+    }
+}
+
+impl DelayBased {
+    // Least-squares slope in ms per window; None until the window fills
+    fn trend(&self) -> Option<f64> {
+        if self.rtts.len() < TREND_WINDOWS {
+            return None;
+        }
+        let n = self.rtts.len() as f64;
+        let mean_x = (n - 1.0) / 2.0;
+        let mean_y = self.rtts.iter().sum::<f64>() / n;
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for (x, y) in self.rtts.iter().enumerate() {
+            let dx = x as f64 - mean_x;
+            covariance += dx * (y - mean_y);
+            variance += dx * dx;
+        }
+        Some(covariance / variance)
+    }
+}
+
+impl CongestionController for DelayBased {
+    fn reset(&mut self, max_kbps: u32) {
+        self.max = max_kbps as f64;
+        self.estimate = self.max;
+        self.rtts.clear();
+        self.threshold = INITIAL_THRESHOLD_MS;
+    }
+
+    fn update(&mut self, feedback: &Feedback) -> Estimate {
+        if feedback.rtt_ms > 0.0 {
+            if self.rtts.len() == TREND_WINDOWS {
+                self.rtts.pop_front();
+            }
+            self.rtts.push_back(feedback.rtt_ms);
+        }
+
+        let trend = self.trend().unwrap_or(0.0);
+        let overusing = trend > self.threshold;
+        let rate = if trend.abs() > self.threshold {
+            THRESHOLD_UP
+        } else {
+            THRESHOLD_DOWN
+        };
+        self.threshold = (self.threshold + rate * (trend.abs() - self.threshold))
+            .clamp(MIN_THRESHOLD_MS, MAX_THRESHOLD_MS);
+
+        let delay_estimate = if overusing {
+            // Start the next trend afresh, so one rise isn't counted twice
+            self.rtts.clear();
+            (feedback.sent_kbps * OVERUSE_BACKOFF).min(self.estimate)
+        } else if trend < -self.threshold {
+            // The queue is draining; hold until it has
+            self.estimate
+        } else {
+            self.estimate * DELAY_INCREASE
+        };
+        // Heavy loss still backs off, as GCC takes the lower of both halves
+        self.estimate = loss_adjusted(delay_estimate, feedback.loss, 1.0)
+            .clamp(MIN_ESTIMATE_KBPS.min(self.max), self.max);
+
+        let constrained = if overusing {
+            Some(Trigger::Delay)
+        } else if feedback.loss > CONSTRAINED_LOSS {
+            Some(Trigger::Loss)
+        } else {
+            None
+        };
+        Estimate {
+            available_kbps: self.estimate,
+            constrained,
+        }
+    }
+}
+
+fn loss_adjusted(estimate: f64, loss: f64, increase: f64) -> f64 {
+    if loss > HIGH_LOSS {
+        estimate * (1.0 - 0.5 * loss)
+    } else if loss < LOW_LOSS {
+        estimate * increase
+    } else {
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feedback(loss: f64, rtt_ms: f64) -> Feedback {
+        Feedback {
+            loss,
+            rtt_ms,
+            sent_kbps: 3000.0,
+        }
+    }
+
+    #[test]
+    fn loss_based_backs_off_on_loss_and_probes_back_to_the_ceiling() {
+        let mut controller = CongestionAlgorithm::Loss.controller(3000);
+        let estimate = controller.update(&feedback(0.2, 0.0));
+        assert!(estimate.available_kbps < 3000.0);
+        assert_eq!(estimate.constrained, Some(Trigger::Loss));
+
+        let mut estimate = controller.update(&feedback(0.0, 0.0));
+        assert_eq!(estimate.constrained, None);
+        for _ in 0..50 {
+            estimate = controller.update(&feedback(0.0, 0.0));
+        }
+        assert_eq!(estimate.available_kbps, 3000.0);
+    }
+
+    #[test]
+    fn loss_based_never_goes_below_the_floor() {
+        let mut controller = CongestionAlgorithm::Loss.controller(3000);
+        let mut estimate = controller.update(&feedback(1.0, 0.0));
+        for _ in 0..100 {
+            estimate = controller.update(&feedback(1.0, 0.0));
+        }
+        assert_eq!(estimate.available_kbps, MIN_ESTIMATE_KBPS);
+    }
+
+    #[test]
+    fn delay_based_blames_rising_rtt() {
+        let mut controller = CongestionAlgorithm::Delay.controller(3000);
+        let mut constrained = None;
+        for window in 0..TREND_WINDOWS * 2 {
+            let estimate = controller.update(&feedback(0.0, 50.0 + window as f64 * 40.0));
+            constrained = constrained.or(estimate.constrained);
+        }
+        assert_eq!(constrained, Some(Trigger::Delay));
+    }
+}
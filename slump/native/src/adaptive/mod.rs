@@ -0,0 +1,668 @@
+mod congestion;
+
+pub use congestion::{CongestionAlgorithm, CongestionController, Estimate, Feedback};
+
+use crate::error::SlumpError;
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+// Fractions of the configured bitrate / fps / resolution the controller steps through
+const BITRATE_STEPS: [f64; 4] = [1.0, 0.7, 0.5, 0.35];
+const FPS_STEPS: [f64; 4] = [1.0, 0.75, 0.5, 0.33];
+const RESOLUTION_STEPS: [f64; 4] = [1.0, 0.75, 0.5, 0.33];
+const MIN_FPS: u32 = 10;
+// Number of unconstrained stats windows before stepping quality back up
+const RECOVERY_WINDOWS: u32 = 5;
+// Windows to wait after stepping down before stepping again, so the last
+// step shows up in the stats before it is judged; stepping every window
+// overshoots and then swings back
+const HOLD_WINDOWS: u32 = 2;
+// Loss the audio's in-band FEC is sized for, in steps so small swings in loss
+// don't reopen the encoder. Capped, since past that the redundancy mostly
+// adds to the congestion it makes up for.
+const FEC_STEP_PERCENT: u32 = 5;
+const MAX_FEC_PERCENT: u32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DegradationPreference {
+    MaintainFramerate,
+    MaintainResolution,
+    #[default]
+    Balanced,
+}
+
+impl FromStr for DegradationPreference {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "maintain-framerate" => Ok(DegradationPreference::MaintainFramerate),
+            "maintain-resolution" => Ok(DegradationPreference::MaintainResolution),
+            "balanced" => Ok(DegradationPreference::Balanced),
+            other => Err(SlumpError::Init(format!(
+                "Unknown degradation preference: {}",
+                other
+            ))),
+        }
+    }
+}
+
+// What the controller can give up under pressure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Knob {
+    Bitrate,
+    Framerate,
+    Resolution,
+}
+
+impl Knob {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Knob::Bitrate => "bitrate",
+            Knob::Framerate => "framerate",
+            Knob::Resolution => "resolution",
+        }
+    }
+}
+
+impl FromStr for Knob {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "bitrate" => Ok(Knob::Bitrate),
+            "framerate" => Ok(Knob::Framerate),
+            "resolution" => Ok(Knob::Resolution),
+            other => Err(SlumpError::Init(format!(
+                "Unknown adaptation knob: {}",
+                other
+            ))),
+        }
+    }
+}
+
+// What to give up first, in order. Knobs left out are never touched.
+pub fn parse_priority(names: &[String]) -> std::result::Result<Vec<Knob>, SlumpError> {
+    let mut priority = Vec::with_capacity(names.len());
+    for name in names {
+        let knob = name.parse::<Knob>()?;
+        if priority.contains(&knob) {
+            return Err(SlumpError::Init(format!("{} is listed twice", name)));
+        }
+        priority.push(knob);
+    }
+    Ok(priority)
+}
+
+// Why the stream runs below its configured size or rate, after WebRTC's
+// qualityLimitationReason
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QualityLimitation {
+    #[default]
+    None,
+    Bandwidth,
+    Cpu,
+    // Constrained, but the degradation preference leaves nothing to give up
+    Configuration,
+}
+
+impl QualityLimitation {
+    pub const ALL: [QualityLimitation; 4] = [
+        QualityLimitation::None,
+        QualityLimitation::Bandwidth,
+        QualityLimitation::Cpu,
+        QualityLimitation::Configuration,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QualityLimitation::None => "none",
+            QualityLimitation::Bandwidth => "bandwidth",
+            QualityLimitation::Cpu => "cpu",
+            QualityLimitation::Configuration => "configuration",
+        }
+    }
+}
+
+// What made the controller move. Bandwidth comes from the congestion
+// controller, as loss or, with the delay-based one, growing delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Loss,
+    Delay,
+    Cpu,
+    // Enough quiet windows to step back up
+    Recovery,
+}
+
+impl Trigger {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Trigger::Loss => "loss",
+            Trigger::Delay => "delay",
+            Trigger::Cpu => "cpu",
+            Trigger::Recovery => "recovery",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adaptation {
+    pub trigger: Trigger,
+    pub from: AdaptiveTarget,
+    pub to: AdaptiveTarget,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveTarget {
+    pub fps: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bitrate_kbps: u32,
+    // Loss in percent the Opus encoder's in-band FEC is sized for. Only audio
+    // is protected; video FEC needs a FlexFEC sender, left for a follow-up.
+    pub fec_percent: u32,
+}
+
+// Moves bitrate, fps, resolution and FEC together off one bandwidth / CPU
+// signal, one step at a time, so they don't fight each other
+#[derive(Debug, Default)]
+pub struct AdaptiveController {
+    preference: DegradationPreference,
+    // Empty to take bitrate first, then follow the degradation preference
+    priority: Vec<Knob>,
+    max_fps: u32,
+    max_width: u32,
+    max_height: u32,
+    max_bitrate_kbps: u32,
+    bitrate_step: usize,
+    fps_step: usize,
+    resolution_step: usize,
+    fec_percent: u32,
+    stable_windows: u32,
+    hold_windows: u32,
+    limitation: QualityLimitation,
+    // Time spent in each limitation, indexed like QualityLimitation::ALL,
+    // up to `limitation_since`
+    durations: [Duration; 4],
+    limitation_since: Option<Instant>,
+}
+
+impl AdaptiveController {
+    pub fn reset(&mut self, width: u32, height: u32, fps: u32, bitrate_kbps: u32) {
+        self.max_fps = fps;
+        self.max_width = width;
+        self.max_height = height;
+        self.max_bitrate_kbps = bitrate_kbps;
+        self.bitrate_step = 0;
+        self.fps_step = 0;
+        self.resolution_step = 0;
+        self.fec_percent = 0;
+        self.stable_windows = 0;
+        self.hold_windows = 0;
+        self.set_limitation(QualityLimitation::None);
+    }
+
+    // For a new run; reconfiguring keeps counting
+    pub fn clear_durations(&mut self) {
+        self.durations = Default::default();
+        self.limitation_since = Some(Instant::now());
+    }
+
+    pub fn limitation(&self) -> QualityLimitation {
+        self.limitation
+    }
+
+    // Including the time in the current limitation so far
+    pub fn limitation_durations(&self) -> [(QualityLimitation, Duration); 4] {
+        let mut durations = self.durations;
+        if let Some(since) = self.limitation_since {
+            durations[self.limitation as usize] += since.elapsed();
+        }
+        let mut all = [(QualityLimitation::None, Duration::ZERO); 4];
+        for (i, limitation) in QualityLimitation::ALL.into_iter().enumerate() {
+            all[i] = (limitation, durations[i]);
+        }
+        all
+    }
+
+    fn set_limitation(&mut self, limitation: QualityLimitation) {
+        let now = Instant::now();
+        if let Some(since) = self.limitation_since {
+            self.durations[self.limitation as usize] += now - since;
+        }
+        self.limitation = limitation;
+        self.limitation_since = Some(now);
+    }
+
+    pub fn preference(&self) -> DegradationPreference {
+        self.preference
+    }
+
+    pub fn set_preference(&mut self, preference: DegradationPreference) {
+        self.preference = preference;
+    }
+
+    // Knobs dropped from the list go straight back to full
+    pub fn set_priority(&mut self, priority: Vec<Knob>) {
+        if !priority.is_empty() {
+            for knob in [Knob::Bitrate, Knob::Framerate, Knob::Resolution] {
+                if !priority.contains(&knob) {
+                    *self.step_mut(knob) = 0;
+                }
+            }
+        }
+        self.priority = priority;
+    }
+
+    // Keeps the current step, now taken from the new ceiling
+    pub fn set_max_bitrate(&mut self, bitrate_kbps: u32) {
+        self.max_bitrate_kbps = bitrate_kbps;
+    }
+
+    // The configured size and rate, before any degradation
+    pub fn max(&self) -> AdaptiveTarget {
+        AdaptiveTarget {
+            fps: self.max_fps,
+            width: self.max_width,
+            height: self.max_height,
+            bitrate_kbps: self.max_bitrate_kbps,
+            fec_percent: 0,
+        }
+    }
+
+    pub fn target(&self) -> AdaptiveTarget {
+        let fps = ((self.max_fps as f64 * FPS_STEPS[self.fps_step]).round() as u32)
+            .max(MIN_FPS.min(self.max_fps));
+        let scale = RESOLUTION_STEPS[self.resolution_step];
+
+        // Encoders need even dimensions for 4:2:0 output
+        AdaptiveTarget {
+            fps,
+            width: ((self.max_width as f64 * scale) as u32) & !1,
+            height: ((self.max_height as f64 * scale) as u32) & !1,
+            bitrate_kbps: self.bitrate_at(self.bitrate_step),
+            fec_percent: self.fec_percent,
+        }
+    }
+
+    // Feed one stats window with `loss` as 0-1; returns the change if the
+    // target moved. Bandwidth is whatever the congestion controller blames.
+    pub fn update(&mut self, estimate: &Estimate, loss: f64, cpu: bool) -> Option<Adaptation> {
+        let before = self.target();
+        let steps = self.steps();
+        let bandwidth = estimate.constrained;
+        let constrained = bandwidth.is_some() || cpu;
+        // Opus spends its FEC out of its own bitrate, so video's budget is
+        // the whole estimate
+        let budget = estimate.available_kbps;
+
+        // Protection follows loss up at once, but only comes down with the
+        // rest of the recovery
+        let fec_percent = fec_for_loss(loss);
+        self.fec_percent = self.fec_percent.max(fec_percent);
+
+        if constrained {
+            self.stable_windows = 0;
+            if self.hold_windows > 0 {
+                self.hold_windows -= 1;
+            } else if self.degrade(bandwidth.is_some(), budget) {
+                self.hold_windows = HOLD_WINDOWS;
+            }
+        } else {
+            self.hold_windows = 0;
+            self.stable_windows += 1;
+            if self.stable_windows >= RECOVERY_WINDOWS {
+                self.stable_windows = 0;
+                self.fec_percent = fec_percent;
+                self.recover(budget);
+            }
+        }
+
+        let after = self.target();
+        let stepped = self.steps() != steps;
+        // A degraded stream stays limited by what degraded it until it has
+        // fully recovered
+        let limitation = if self.steps() == [0; 3] {
+            if constrained && !stepped {
+                QualityLimitation::Configuration
+            } else {
+                QualityLimitation::None
+            }
+        } else if stepped && constrained {
+            if bandwidth.is_some() {
+                QualityLimitation::Bandwidth
+            } else {
+                QualityLimitation::Cpu
+            }
+        } else {
+            self.limitation
+        };
+        if limitation != self.limitation {
+            self.set_limitation(limitation);
+        }
+
+        let trigger = match bandwidth {
+            Some(trigger) => trigger,
+            None if cpu => Trigger::Cpu,
+            None if after.fec_percent > before.fec_percent => Trigger::Loss,
+            None => Trigger::Recovery,
+        };
+        (after != before).then_some(Adaptation {
+            trigger,
+            from: before,
+            to: after,
+        })
+    }
+
+    fn bitrate_at(&self, step: usize) -> u32 {
+        (self.max_bitrate_kbps as f64 * BITRATE_STEPS[step]).round() as u32
+    }
+
+    fn steps(&self) -> [usize; 3] {
+        [self.bitrate_step, self.fps_step, self.resolution_step]
+    }
+
+    fn step_mut(&mut self, knob: Knob) -> &mut usize {
+        match knob {
+            Knob::Bitrate => &mut self.bitrate_step,
+            Knob::Framerate => &mut self.fps_step,
+            Knob::Resolution => &mut self.resolution_step,
+        }
+    }
+
+    fn can_drop(&self, knob: Knob) -> bool {
+        match knob {
+            Knob::Bitrate => self.bitrate_step + 1 < BITRATE_STEPS.len(),
+            Knob::Framerate => self.can_drop_fps(),
+            Knob::Resolution => self.can_drop_resolution(),
+        }
+    }
+
+    fn can_drop_fps(&self) -> bool {
+        self.fps_step + 1 < FPS_STEPS.len()
+    }
+
+    fn can_drop_resolution(&self) -> bool {
+        self.resolution_step + 1 < RESOLUTION_STEPS.len()
+    }
+
+    // Straight to the highest step that fits the budget, at least one down
+    fn drop_bitrate(&mut self, budget: f64) {
+        self.bitrate_step += 1;
+        while self.can_drop(Knob::Bitrate) && self.bitrate_at(self.bitrate_step) as f64 > budget {
+            self.bitrate_step += 1;
+        }
+    }
+
+    // Only as far back up as the estimate says the link will carry
+    fn raise_bitrate(&mut self, budget: f64) {
+        if self.bitrate_step > 0 && self.bitrate_at(self.bitrate_step - 1) as f64 <= budget {
+            self.bitrate_step -= 1;
+        }
+    }
+
+    // False if there was nothing left to give up. Bitrate does nothing for
+    // a CPU-bound encoder, so only bandwidth takes it down.
+    fn degrade(&mut self, bandwidth: bool, budget: f64) -> bool {
+        if self.priority.is_empty() {
+            if bandwidth && self.can_drop(Knob::Bitrate) {
+                self.drop_bitrate(budget);
+                return true;
+            }
+            let steps = self.steps();
+            self.degrade_by_preference();
+            return self.steps() != steps;
+        }
+
+        let knob = self
+            .priority
+            .iter()
+            .copied()
+            .filter(|&knob| bandwidth || knob != Knob::Bitrate)
+            .find(|&knob| self.can_drop(knob));
+        match knob {
+            Some(Knob::Bitrate) => self.drop_bitrate(budget),
+            Some(knob) => *self.step_mut(knob) += 1,
+            None => return false,
+        }
+        true
+    }
+
+    // In reverse, so what was given up last comes back first
+    fn recover(&mut self, budget: f64) {
+        if self.priority.is_empty() {
+            if self.fps_step == 0 && self.resolution_step == 0 {
+                self.raise_bitrate(budget);
+            } else {
+                self.recover_by_preference();
+            }
+            return;
+        }
+
+        let knob = self
+            .priority
+            .iter()
+            .rev()
+            .copied()
+            .find(|&knob| self.steps()[knob as usize] > 0);
+        match knob {
+            Some(Knob::Bitrate) => self.raise_bitrate(budget),
+            Some(knob) => *self.step_mut(knob) -= 1,
+            None => {}
+        }
+    }
+
+    fn degrade_by_preference(&mut self) {
+        match self.preference {
+            DegradationPreference::MaintainFramerate => {
+                if self.can_drop_resolution() {
+                    self.resolution_step += 1;
+                }
+            }
+            DegradationPreference::MaintainResolution => {
+                if self.can_drop_fps() {
+                    self.fps_step += 1;
+                }
+            }
+            DegradationPreference::Balanced => {
+                // Drop whichever dimension has been degraded the least so far
+                if self.can_drop_fps()
+                    && (self.fps_step <= self.resolution_step || !self.can_drop_resolution())
+                {
+                    self.fps_step += 1;
+                } else if self.can_drop_resolution() {
+                    self.resolution_step += 1;
+                }
+            }
+        }
+    }
+
+    fn recover_by_preference(&mut self) {
+        match self.preference {
+            DegradationPreference::MaintainFramerate => {
+                if self.fps_step > 0 {
+                    self.fps_step -= 1;
+                } else if self.resolution_step > 0 {
+                    self.resolution_step -= 1;
+                }
+            }
+            DegradationPreference::MaintainResolution => {
+                if self.resolution_step > 0 {
+                    self.resolution_step -= 1;
+                } else if self.fps_step > 0 {
+                    self.fps_step -= 1;
+                }
+            }
+            DegradationPreference::Balanced => {
+                if self.resolution_step >= self.fps_step && self.resolution_step > 0 {
+                    self.resolution_step -= 1;
+                } else if self.fps_step > 0 {
+                    self.fps_step -= 1;
+                }
+            }
+        }
+    }
+}
+
+// In FEC_STEP_PERCENT steps, so small swings in loss don't churn it
+fn fec_for_loss(loss: f64) -> u32 {
+    let steps = (loss * 100.0 / FEC_STEP_PERCENT as f64).round() as u32;
+    (steps * FEC_STEP_PERCENT).min(MAX_FEC_PERCENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> AdaptiveController {
+        let mut controller = AdaptiveController::default();
+        controller.reset(1920, 1080, 60, 6000);
+        controller
+    }
+
+    fn congested(available_kbps: f64) -> Estimate {
+        Estimate {
+            available_kbps,
+            constrained: Some(Trigger::Loss),
+        }
+    }
+
+    fn clear(available_kbps: f64) -> Estimate {
+        Estimate {
+            available_kbps,
+            constrained: None,
+        }
+    }
+
+    #[test]
+    fn drops_bitrate_first_then_holds_before_the_next_step() {
+        let mut controller = controller();
+        let adaptation = controller.update(&congested(5000.0), 0.0, false).unwrap();
+        assert_eq!(adaptation.trigger, Trigger::Loss);
+        assert_eq!(adaptation.to.bitrate_kbps, 4200);
+        assert_eq!(controller.limitation(), QualityLimitation::Bandwidth);
+
+        for _ in 0..HOLD_WINDOWS {
+            assert!(controller.update(&congested(5000.0), 0.0, false).is_none());
+        }
+        let adaptation = controller.update(&congested(5000.0), 0.0, false).unwrap();
+        assert_eq!(adaptation.to.bitrate_kbps, 3000);
+        assert_eq!((adaptation.to.width, adaptation.to.fps), (1920, 60));
+    }
+
+    #[test]
+    fn skips_straight_to_the_bitrate_that_fits() {
+        let mut controller = controller();
+        let adaptation = controller.update(&congested(2500.0), 0.0, false).unwrap();
+        assert_eq!(adaptation.to.bitrate_kbps, 2100);
+    }
+
+    #[test]
+    fn recovers_only_after_quiet_windows_and_only_as_far_as_fits() {
+        let mut controller = controller();
+        controller.update(&congested(3500.0), 0.0, false).unwrap();
+        assert_eq!(controller.target().bitrate_kbps, 3000);
+
+        for _ in 1..RECOVERY_WINDOWS {
+            assert!(controller.update(&clear(10000.0), 0.0, false).is_none());
+        }
+        let adaptation = controller.update(&clear(10000.0), 0.0, false).unwrap();
+        assert_eq!(adaptation.trigger, Trigger::Recovery);
+        assert_eq!(adaptation.to.bitrate_kbps, 4200);
+        // Still below the configured rate, so still bandwidth limited
+        assert_eq!(controller.limitation(), QualityLimitation::Bandwidth);
+
+        // 6000 doesn't fit in 5000, so it stays put
+        for _ in 0..RECOVERY_WINDOWS {
+            assert!(controller.update(&clear(5000.0), 0.0, false).is_none());
+        }
+        assert_eq!(controller.target().bitrate_kbps, 4200);
+    }
+
+    #[test]
+    fn quiet_windows_end_the_hold() {
+        let mut controller = controller();
+        controller.update(&congested(5000.0), 0.0, false).unwrap();
+        for _ in 1..RECOVERY_WINDOWS {
+            controller.update(&clear(10000.0), 0.0, false);
+        }
+        // The quiet windows ended the hold, so this steps down again
+        let adaptation = controller.update(&congested(5000.0), 0.0, false).unwrap();
+        assert_eq!(adaptation.to.bitrate_kbps, 3000);
+        for _ in 1..RECOVERY_WINDOWS {
+            assert!(controller.update(&clear(10000.0), 0.0, false).is_none());
+        }
+        let adaptation = controller.update(&clear(10000.0), 0.0, false).unwrap();
+        assert_eq!(adaptation.to.bitrate_kbps, 4200);
+        for _ in 0..RECOVERY_WINDOWS {
+            controller.update(&clear(10000.0), 0.0, false);
+        }
+        assert_eq!(controller.target(), controller.max());
+        assert_eq!(controller.limitation(), QualityLimitation::None);
+    }
+
+    #[test]
+    fn cpu_pressure_leaves_bitrate_and_balances_fps_and_resolution() {
+        let mut controller = controller();
+        let adaptation = controller.update(&clear(10000.0), 0.0, true).unwrap();
+        assert_eq!(adaptation.trigger, Trigger::Cpu);
+        assert_eq!((adaptation.to.fps, adaptation.to.bitrate_kbps), (45, 6000));
+        assert_eq!(controller.limitation(), QualityLimitation::Cpu);
+
+        for _ in 0..HOLD_WINDOWS {
+            controller.update(&clear(10000.0), 0.0, true);
+        }
+        let adaptation = controller.update(&clear(10000.0), 0.0, true).unwrap();
+        assert_eq!((adaptation.to.width, adaptation.to.height), (1440, 810));
+        assert_eq!(adaptation.to.fps, 45);
+
+        // What went last comes back first
+        for _ in 0..RECOVERY_WINDOWS {
+            controller.update(&clear(10000.0), 0.0, false);
+        }
+        assert_eq!(controller.target().width, 1920);
+        assert_eq!(controller.target().fps, 45);
+    }
+
+    #[test]
+    fn priority_decides_what_goes_first() {
+        let mut controller = controller();
+        controller.set_priority(vec![Knob::Resolution, Knob::Bitrate]);
+        let adaptation = controller.update(&congested(1000.0), 0.0, false).unwrap();
+        assert_eq!(adaptation.to.width, 1440);
+        assert_eq!(adaptation.to.bitrate_kbps, 6000);
+    }
+
+    #[test]
+    fn nothing_left_to_give_up_is_a_configuration_limit() {
+        let mut controller = controller();
+        controller.set_priority(vec![Knob::Bitrate]);
+        assert!(controller.update(&clear(10000.0), 0.0, true).is_none());
+        assert_eq!(controller.limitation(), QualityLimitation::Configuration);
+    }
+
+    #[test]
+    fn fec_follows_loss_up_at_once_and_down_with_recovery() {
+        let mut controller = controller();
+        let adaptation = controller.update(&clear(10000.0), 0.08, false).unwrap();
+        assert_eq!(adaptation.trigger, Trigger::Loss);
+        assert_eq!(adaptation.to.fec_percent, 10);
+        // Nothing else moves for loss the congestion controller didn't blame
+        assert_eq!(adaptation.to.bitrate_kbps, 6000);
+        assert_eq!(controller.limitation(), QualityLimitation::None);
+
+        // Less loss keeps it until enough quiet windows have passed
+        for _ in 2..RECOVERY_WINDOWS {
+            assert!(controller.update(&clear(10000.0), 0.02, false).is_none());
+        }
+        let adaptation = controller.update(&clear(10000.0), 0.02, false).unwrap();
+        assert_eq!(adaptation.trigger, Trigger::Recovery);
+        assert_eq!(adaptation.to.fec_percent, 0);
+
+        controller.update(&clear(10000.0), 0.9, false).unwrap();
+        assert_eq!(controller.target().fec_percent, MAX_FEC_PERCENT);
+    }
+}
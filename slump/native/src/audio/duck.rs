@@ -0,0 +1,108 @@
+use super::{CHANNELS, SAMPLE_RATE};
+use crate::error::{Result, SlumpError};
+use std::time::Duration;
+
+const MAX_AMOUNT_DB: f64 = 60.0;
+const MAX_RAMP: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DuckSettings {
+    // How far system audio drops while the microphone has speech; 0 mixes
+    // without ducking
+    pub amount_db: f64,
+    // Microphone level, as 20 ms RMS, that counts as speech
+    pub threshold_dbfs: f64,
+    // How fast it drops once speech starts, and comes back after it stops
+    pub attack: Duration,
+    pub release: Duration,
+}
+
+impl Default for DuckSettings {
+    fn default() -> Self {
+        Self {
+            amount_db: 12.0,
+            threshold_dbfs: -40.0,
+            attack: Duration::from_millis(20),
+            release: Duration::from_millis(500),
+        }
+    }
+}
+
+impl DuckSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=MAX_AMOUNT_DB).contains(&self.amount_db) {
+            return Err(SlumpError::Init(format!(
+                "Ducking amount must be 0-{} dB, got {}",
+                MAX_AMOUNT_DB, self.amount_db
+            )));
+        }
+        if !(-100.0..=0.0).contains(&self.threshold_dbfs) {
+            return Err(SlumpError::Init(format!(
+                "Speech threshold must be -100 to 0 dBFS, got {}",
+                self.threshold_dbfs
+            )));
+        }
+        if self.attack > MAX_RAMP || self.release > MAX_RAMP {
+            return Err(SlumpError::Init(format!(
+                "Attack and release must be at most {} ms",
+                MAX_RAMP.as_millis()
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Mixes system audio under the microphone, pulling it down while the
+// microphone has speech in it. Speech here is just level over a threshold:
+// a noisy room with a low threshold keeps it ducked.
+pub struct Ducker {
+    settings: DuckSettings,
+    // Applied to system audio, eased towards 1 or the ducked level
+    gain: f32,
+}
+
+impl Ducker {
+    pub fn new(settings: DuckSettings) -> Self {
+        Self {
+            settings,
+            gain: 1.0,
+        }
+    }
+
+    pub fn set(&mut self, settings: DuckSettings) {
+        self.settings = settings;
+    }
+
+    // Adds `system` into `mic` in place; both interleaved stereo, one tick
+    pub fn mix(&mut self, mic: &mut [f32], system: &[f32]) {
+        let sum_squares: f64 = mic.iter().map(|&sample| (sample as f64).powi(2)).sum();
+        let rms = (sum_squares / mic.len().max(1) as f64).sqrt();
+        let speaking = 20.0 * rms.max(1e-9).log10() >= self.settings.threshold_dbfs;
+
+        let (target, ramp) = if speaking {
+            (
+                10f64.powf(-self.settings.amount_db / 20.0) as f32,
+                self.settings.attack,
+            )
+        } else {
+            (1.0, self.settings.release)
+        };
+        // One-pole easing per sample frame, so the level never steps
+        let frames = ramp.as_secs_f64() * SAMPLE_RATE as f64;
+        let coefficient = if frames < 1.0 {
+            0.0
+        } else {
+            (-1.0 / frames).exp() as f32
+        };
+
+        for (mic, system) in mic
+            .chunks_exact_mut(CHANNELS as usize)
+            .zip(system.chunks_exact(CHANNELS as usize))
+        {
+            self.gain = target + (self.gain - target) * coefficient;
+            for (out, &sample) in mic.iter_mut().zip(system) {
+                *out = (*out + sample * self.gain).clamp(-1.0, 1.0);
+            }
+        }
+    }
+}
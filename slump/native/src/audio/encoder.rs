@@ -0,0 +1,123 @@
+use super::{CHANNELS, FRAME_SIZE, SAMPLE_RATE};
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::{codec, format, frame, util::channel_layout::ChannelLayout, Dictionary, Packet};
+
+// Opus for the peers' audio track. Roughly what a browser sends for stereo
+// music-quality audio.
+pub const DEFAULT_AUDIO_BITRATE_KBPS: u32 = 64;
+// libopus's own range
+pub const MIN_AUDIO_BITRATE_KBPS: u32 = 6;
+pub const MAX_AUDIO_BITRATE_KBPS: u32 = 510;
+
+const SAMPLE_FORMAT: format::Sample = format::Sample::F32(format::sample::Type::Packed);
+
+pub struct AudioEncoder {
+    encoder: codec::encoder::Audio,
+    bitrate_kbps: u32,
+    // Loss in percent the in-band FEC is sized for; 0 turns it off
+    fec_percent: u32,
+    // Interleaved samples short of a whole 20ms frame
+    pending: Vec<f32>,
+    samples_written: i64,
+}
+
+// Like VideoEncoder, only ever used by the one thread holding it
+unsafe impl Send for AudioEncoder {}
+
+impl AudioEncoder {
+    pub fn new(bitrate_kbps: u32, fec_percent: u32) -> Result<Self> {
+        Ok(Self {
+            encoder: Self::open(bitrate_kbps, fec_percent)?,
+            bitrate_kbps,
+            fec_percent,
+            pending: Vec::new(),
+            samples_written: 0,
+        })
+    }
+
+    fn open(bitrate_kbps: u32, fec_percent: u32) -> Result<codec::encoder::Audio> {
+        let codec = ffmpeg_next::encoder::find_by_name("libopus")
+            .ok_or_else(|| SlumpError::Audio("Opus encoder not available".into()))?;
+
+        let mut encoder = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .audio()?;
+        encoder.set_rate(SAMPLE_RATE);
+        encoder.set_channel_layout(ChannelLayout::STEREO);
+        encoder.set_format(SAMPLE_FORMAT);
+        encoder.set_bit_rate(bitrate_kbps as usize * 1000);
+        encoder.set_time_base((1, SAMPLE_RATE));
+
+        // One packet per capture frame, matching the 20ms the SDP assumes
+        let mut options = Dictionary::new();
+        options.set("frame_duration", "20");
+        options.set("application", "audio");
+        // In-band FEC: each packet also carries a coarser copy of the one
+        // before, for the receiver to use if that one was lost. It comes out
+        // of the same bitrate, more of it the more loss is expected.
+        options.set("fec", if fec_percent > 0 { "1" } else { "0" });
+        options.set("packet_loss", &fec_percent.to_string());
+
+        Ok(encoder.open_with(options)?)
+    }
+
+    pub fn bitrate_kbps(&self) -> u32 {
+        self.bitrate_kbps
+    }
+
+    // Like libvpx, libopus takes its settings only when opened
+    pub fn set_bitrate(&mut self, bitrate_kbps: u32) -> Result<()> {
+        if bitrate_kbps == self.bitrate_kbps {
+            return Ok(());
+        }
+
+        self.encoder = Self::open(bitrate_kbps, self.fec_percent)?;
+        self.bitrate_kbps = bitrate_kbps;
+        Ok(())
+    }
+
+    pub fn set_fec(&mut self, fec_percent: u32) -> Result<()> {
+        if fec_percent == self.fec_percent {
+            return Ok(());
+        }
+
+        self.encoder = Self::open(self.bitrate_kbps, fec_percent)?;
+        self.fec_percent = fec_percent;
+        Ok(())
+    }
+
+    // Interleaved stereo at SAMPLE_RATE, in reads of any length; one packet
+    // comes back for each whole 20ms frame
+    pub fn encode(&mut self, samples: &[f32]) -> Result<Vec<Vec<u8>>> {
+        self.pending.extend_from_slice(samples);
+        let chunk = FRAME_SIZE * CHANNELS as usize;
+
+        let mut packets = Vec::new();
+        while self.pending.len() >= chunk {
+            let mut audio_frame =
+                frame::Audio::new(SAMPLE_FORMAT, FRAME_SIZE, ChannelLayout::STEREO);
+            audio_frame.set_rate(SAMPLE_RATE as u32);
+            audio_frame.set_pts(Some(self.samples_written));
+            // Packed, so the one plane holds the samples as they came
+            for (bytes, sample) in audio_frame
+                .data_mut(0)
+                .chunks_exact_mut(std::mem::size_of::<f32>())
+                .zip(&self.pending[..chunk])
+            {
+                bytes.copy_from_slice(&sample.to_ne_bytes());
+            }
+            self.pending.drain(..chunk);
+            self.samples_written += FRAME_SIZE as i64;
+
+            self.encoder.send_frame(&audio_frame)?;
+            let mut packet = Packet::empty();
+            while self.encoder.receive_packet(&mut packet).is_ok() {
+                if let Some(data) = packet.data() {
+                    packets.push(data.to_vec());
+                }
+            }
+        }
+
+        Ok(packets)
+    }
+}
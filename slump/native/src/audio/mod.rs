@@ -1,3 +1,11 @@
+mod duck;
+mod encoder;
+
+pub use duck::{DuckSettings, Ducker};
+pub use encoder::{
+    AudioEncoder, DEFAULT_AUDIO_BITRATE_KBPS, MAX_AUDIO_BITRATE_KBPS, MIN_AUDIO_BITRATE_KBPS,
+};
+
 use crate::error::{Result, SlumpError};
 use ffmpeg_next::{
     codec,
@@ -9,12 +17,16 @@ use ffmpeg_next::{
 use ringbuf::{HeapRb, Rb};
 use std::{
     sync::{Arc, Mutex},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-const SAMPLE_RATE: i32 = 48000;
-const CHANNELS: u16 = 2; // Stereo
-const FRAME_SIZE: usize = 960; // 20ms at 48kHz
+pub const SAMPLE_RATE: i32 = 48000;
+pub const CHANNELS: u16 = 2; // Stereo
+pub const FRAME_SIZE: usize = 960; // 20ms at 48kHz
+// Integer sources top out just under 1.0, so full scale starts a hair below
+const CLIP_LEVEL: f32 = 0.999;
+// Reported for digital silence instead of -inf
+const SILENCE_DBFS: f64 = -120.0;
 
 pub struct AudioCapture {
     input_ctx: ffmpeg_next::format::context::Input,
@@ -22,11 +34,21 @@ pub struct AudioCapture {
     decoder: codec::decoder::Audio,
     resampler: Option<ffmpeg_next::software::resampling::Context>,
     ring_buffer: Arc<Mutex<HeapRb<f32>>>,
+    // Samples lost because the ring was full
+    dropped_samples: u64,
     start_time: Instant,
 }
 
 impl AudioCapture {
     pub fn new() -> Result<Self> {
+        Self::with_device(None)
+    }
+
+    // `device` is a dshow/avfoundation/pulse device name; None picks the default input
+    pub fn with_device(device: Option<&str>) -> Result<Self> {
+        if !cfg!(feature = "device") {
+            return Err(SlumpError::Audio("Built without the device feature; capture is unavailable".into()));
+        }
         let input_format = if cfg!(windows) {
             "dshow"
         } else if cfg!(target_os = "macos") {
@@ -35,12 +57,13 @@ impl AudioCapture {
             "pulse"
         };
 
-        let input_url = if cfg!(windows) {
-            "audio=Microphone"
-        } else if cfg!(target_os = "macos") {
-            ":0"
-        } else {
-            "default"
+        let input_url = match device {
+            Some(device) if cfg!(windows) => format!("audio={}", device),
+            Some(device) if cfg!(target_os = "macos") => format!(":{}", device),
+            Some(device) => device.to_string(),
+            None if cfg!(windows) => "audio=Microphone".to_string(),
+            None if cfg!(target_os = "macos") => ":0".to_string(),
+            None => "default".to_string(),
         };
 
         let mut options = Dictionary::new();
@@ -48,11 +71,29 @@ impl AudioCapture {
         options.set("channels", &CHANNELS.to_string());
         options.set("threads", "0");
 
+        Self::open(input_format, &input_url, options)
+    }
+
+    // A 440 Hz tone from FFmpeg's lavfi device, for machines without a sound
+    // card. One packet per 20ms tick, like a real device.
+    pub fn synthetic() -> Result<Self> {
+        if !cfg!(feature = "device") {
+            return Err(SlumpError::Audio("Built without the device feature; test sources are unavailable".into()));
+        }
+        ffmpeg_next::init().map_err(|e| SlumpError::Init(e.to_string()))?;
+        let source = format!(
+            "sine=frequency=440:sample_rate={}:samples_per_frame={}",
+            SAMPLE_RATE, FRAME_SIZE
+        );
+        Self::open("lavfi", &source, Dictionary::new())
+    }
+
+    fn open(input_format: &str, input_url: &str, options: Dictionary) -> Result<Self> {
         let mut input_ctx = ffmpeg_next::format::input_with_dictionary(
-            &format!("{}", input_format),
-            input_url,
+            &input_format,
+            &input_url,
             options,
-        )?;
+        ).map_err(SlumpError::from_device_open)?;
 
         let stream = input_ctx
             .streams()
@@ -71,8 +112,9 @@ impl AudioCapture {
         
         let decoder = decoder.open()?;
         
-        // Create resampler if needed
-        let resampler = if decoder.format() != ffmpeg_next::format::Sample::FLTP || 
+        // Create resampler if needed; everything downstream reads interleaved
+        // samples, so that's packed rather than planar
+        let resampler = if decoder.format() != ffmpeg_next::format::Sample::F32(ffmpeg_next::format::sample::Type::Packed) || 
                           decoder.rate() != SAMPLE_RATE || 
                           decoder.channel_layout().channels() != CHANNELS {
             Some(
@@ -80,7 +122,7 @@ impl AudioCapture {
                     decoder.format(),
                     decoder.channel_layout(),
                     decoder.rate(),
-                    ffmpeg_next::format::Sample::FLTP,
+                    ffmpeg_next::format::Sample::F32(ffmpeg_next::format::sample::Type::Packed),
                     ffmpeg_next::channel_layout::ChannelLayout::STEREO,
                     SAMPLE_RATE,
                     ffmpeg_next::software::resampling::Flag::FAST_INTEGER,
@@ -99,6 +141,7 @@ impl AudioCapture {
             decoder,
             resampler,
             ring_buffer,
+            dropped_samples: 0,
             start_time: Instant::now(),
         })
     }
@@ -127,24 +170,48 @@ impl AudioCapture {
                 &decoded
             };
 
-            // Convert to interleaved f32 and push to ring buffer
+            // Interleaved f32 into the ring buffer. The plane is padded past
+            // the last sample, so only take what the frame holds.
             let data = processed.data(0);
             let samples = unsafe {
                 std::slice::from_raw_parts(
                     data.as_ptr() as *const f32,
-                    data.len() / std::mem::size_of::<f32>(),
+                    (processed.samples() * CHANNELS as usize).min(data.len() / std::mem::size_of::<f32>()),
                 )
             };
             
             let mut rb = self.ring_buffer.lock().unwrap();
             for &sample in samples {
-                let _ = rb.push(sample);
+                if rb.push(sample).is_err() {
+                    self.dropped_samples += 1;
+                }
             }
         }
         
         Ok(())
     }
 
+    // Replaces the ring with one holding `duration` of audio; what was buffered is lost
+    pub fn set_buffer(&mut self, duration: Duration) {
+        let samples = (duration.as_secs_f64() * SAMPLE_RATE as f64) as usize * CHANNELS as usize;
+        *self.ring_buffer.lock().unwrap() = HeapRb::new(samples.max(FRAME_SIZE * CHANNELS as usize));
+    }
+
+    pub fn buffered(&self) -> Duration {
+        let samples = self.ring_buffer.lock().unwrap().len() / CHANNELS as usize;
+        Duration::from_secs_f64(samples as f64 / SAMPLE_RATE as f64)
+    }
+
+    pub fn dropped_samples(&self) -> u64 {
+        self.dropped_samples
+    }
+
+    // Share of the ring in use, 0-1
+    pub fn occupancy(&self) -> f64 {
+        let rb = self.ring_buffer.lock().unwrap();
+        rb.len() as f64 / rb.capacity() as f64
+    }
+
     pub fn read_audio(&self, buffer: &mut [f32]) -> usize {
         let mut rb = self.ring_buffer.lock().unwrap();
         let count = buffer.len().min(rb.len());
@@ -161,6 +228,54 @@ impl AudioCapture {
     }
 }
 
+// Level of the captured audio, over whatever was read since it was last taken
+#[derive(Default)]
+pub struct AudioMeter {
+    peak: f32,
+    sum_squares: f64,
+    samples: u64,
+    // At full scale, since start
+    clipped: u64,
+}
+
+impl AudioMeter {
+    pub fn measure(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            let magnitude = sample.abs();
+            self.peak = self.peak.max(magnitude);
+            self.sum_squares += (sample as f64) * (sample as f64);
+            if magnitude >= CLIP_LEVEL {
+                self.clipped += 1;
+            }
+        }
+        self.samples += samples.len() as u64;
+    }
+
+    // Peak and RMS in dBFS; None if nothing was read
+    pub fn take_levels(&mut self) -> Option<(f64, f64)> {
+        if self.samples == 0 {
+            return None;
+        }
+        let rms = (self.sum_squares / self.samples as f64).sqrt();
+        let levels = (dbfs(self.peak as f64), dbfs(rms));
+        self.peak = 0.0;
+        self.sum_squares = 0.0;
+        self.samples = 0;
+        Some(levels)
+    }
+
+    pub fn clipped(&self) -> u64 {
+        self.clipped
+    }
+}
+
+fn dbfs(amplitude: f64) -> f64 {
+    if amplitude <= 0.0 {
+        return SILENCE_DBFS;
+    }
+    (20.0 * amplitude.log10()).max(SILENCE_DBFS)
+}
+
 impl Drop for AudioCapture {
     fn drop(&mut self) {
         let _ = self.decoder.send_eof();
@@ -0,0 +1,186 @@
+use crate::cancel::Cancellation;
+use crate::error::{Result, SlumpError};
+use crate::video::{VideoCapture, VideoEncoder};
+use ffmpeg_next::{format::Pixel, software::scaling, util::frame};
+use std::time::{Duration, Instant};
+
+// Recommendations leave this much of the measured throughput unused, since a
+// real stream also packetizes, sends and shares the machine
+const HEADROOM: f64 = 0.8;
+// Below this a smaller picture is recommended over a choppier one
+const MIN_RECOMMENDED_FPS: u32 = 24;
+const MIN_RECOMMENDED_WIDTH: u32 = 640;
+
+pub struct BenchmarkSettings {
+    pub display_index: usize,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub bitrate_kbps: u32,
+    pub min_bitrate_kbps: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BenchmarkReport {
+    // None when the display could not be opened; capture_error says why
+    pub capture_fps: Option<f64>,
+    pub capture_error: Option<String>,
+    // Source size the scale stage was measured with
+    pub source_width: u32,
+    pub source_height: u32,
+    pub scale_fps: f64,
+    pub encode_fps: f64,
+    // What the encoder produced for the configured bitrate, at the configured fps
+    pub encoded_bitrate_kbps: f64,
+    pub recommended_width: u32,
+    pub recommended_height: u32,
+    pub recommended_fps: u32,
+    pub recommended_bitrate_kbps: u32,
+}
+
+// Runs capture, scale and encode one after the other, each for a third of
+// `duration` and each as fast as it will go. Encoded data is counted and
+// dropped; nothing is sent.
+pub fn run(
+    duration: Duration,
+    settings: &BenchmarkSettings,
+    cancel: &Cancellation,
+) -> Result<BenchmarkReport> {
+    ffmpeg_next::init().map_err(|e| SlumpError::Init(e.to_string()))?;
+    let phase = duration / 3;
+    let mut report = BenchmarkReport::default();
+
+    // Capture is paced by the display, so this is the rate it delivers, not a ceiling
+    let mut source = (Pixel::BGRA, settings.width, settings.height);
+    match VideoCapture::new(
+        settings.display_index,
+        settings.width,
+        settings.height,
+        false,
+    ) {
+        Ok(mut video) => {
+            let started = Instant::now();
+            let mut frames = 0u64;
+            while started.elapsed() < phase {
+                cancel.check()?;
+                if video.capture_frame().ok().flatten().is_some() {
+                    frames += 1;
+                }
+            }
+            report.capture_fps = Some(frames as f64 / started.elapsed().as_secs_f64());
+            if let Some(frame) = video.source_frame() {
+                source = (frame.format(), frame.width(), frame.height());
+            }
+        }
+        Err(e) => report.capture_error = Some(e.to_string()),
+    }
+    report.source_width = source.1;
+    report.source_height = source.2;
+
+    let mut input = frame::Video::new(source.0, source.1, source.2);
+    fill_pattern(&mut input, 0);
+    let mut scaler = scaling::Context::get(
+        source.0,
+        source.1,
+        source.2,
+        VideoEncoder::PIXEL_FORMAT,
+        settings.width,
+        settings.height,
+        scaling::Flags::BILINEAR,
+    )?;
+    let mut scaled = frame::Video::empty();
+    report.scale_fps = measure(phase, cancel, |_| {
+        scaler.run(&input, &mut scaled)?;
+        Ok(())
+    })?;
+
+    // Moving content, so the encoder does the work a changing screen costs
+    let mut encoder = VideoEncoder::new(
+        settings.width,
+        settings.height,
+        settings.fps,
+        settings.bitrate_kbps,
+    )?;
+    let mut picture =
+        frame::Video::new(VideoEncoder::PIXEL_FORMAT, settings.width, settings.height);
+    let mut encoded_bytes = 0u64;
+    let mut encoded_frames = 0u64;
+    report.encode_fps = measure(phase, cancel, |index| {
+        fill_pattern(&mut picture, index);
+        if let Some(data) = encoder.encode(&picture)? {
+            encoded_bytes += data.len() as u64;
+        }
+        encoded_frames += 1;
+        Ok(())
+    })?;
+    if encoded_frames > 0 {
+        let stream_seconds = encoded_frames as f64 / settings.fps as f64;
+        report.encoded_bitrate_kbps = encoded_bytes as f64 * 8.0 / 1000.0 / stream_seconds;
+    }
+
+    recommend(&mut report, settings);
+    Ok(report)
+}
+
+// Calls `step` with a frame index until `duration` is up; returns calls per second
+fn measure(
+    duration: Duration,
+    cancel: &Cancellation,
+    mut step: impl FnMut(u64) -> Result<()>,
+) -> Result<f64> {
+    let started = Instant::now();
+    let mut count = 0u64;
+    while started.elapsed() < duration {
+        cancel.check()?;
+        step(count)?;
+        count += 1;
+    }
+    Ok(count as f64 / started.elapsed().as_secs_f64())
+}
+
+// Detailed pattern that shifts every frame; all planes get the same bytes,
+// which is enough to keep the scaler and encoder honest
+pub fn fill_pattern(frame: &mut frame::Video, index: u64) {
+    let shift = (index * 3) as usize;
+    for plane in 0..frame.planes() {
+        let stride = frame.stride(plane);
+        for (y, row) in frame.data_mut(plane).chunks_mut(stride).enumerate() {
+            for (x, byte) in row.iter_mut().enumerate() {
+                *byte = ((x + shift) ^ y) as u8;
+            }
+        }
+    }
+}
+
+fn recommend(report: &mut BenchmarkReport, settings: &BenchmarkSettings) {
+    let mut fps = settings.fps as f64;
+    if let Some(capture_fps) = report.capture_fps {
+        fps = fps.min(capture_fps.floor());
+    }
+
+    // Scale and encode cost grows with the output area, capture's does not
+    let processing = report.scale_fps.min(report.encode_fps) * HEADROOM;
+    let mut area = 1.0;
+    if processing < fps {
+        if processing >= MIN_RECOMMENDED_FPS as f64 {
+            fps = processing.floor();
+        } else {
+            fps = fps.min(MIN_RECOMMENDED_FPS as f64);
+            area = processing / fps;
+        }
+    }
+
+    let min_scale = MIN_RECOMMENDED_WIDTH as f64 / settings.width as f64;
+    let scale = area.sqrt().max(min_scale).min(1.0);
+    let even = |value: f64| (value as u32 & !1).max(2);
+    report.recommended_width = even(settings.width as f64 * scale);
+    report.recommended_height = even(settings.height as f64 * scale);
+    report.recommended_fps = (fps as u32).max(1);
+
+    // Keep bits per pixel per second where the caller had it
+    let load = (report.recommended_width as f64 * report.recommended_height as f64)
+        * report.recommended_fps as f64
+        / (settings.width as f64 * settings.height as f64 * settings.fps as f64);
+    report.recommended_bitrate_kbps =
+        ((settings.bitrate_kbps as f64 * load) as u32).max(settings.min_bitrate_kbps);
+}
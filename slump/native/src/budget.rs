@@ -0,0 +1,135 @@
+use crate::error::{Result, SlumpError};
+use std::time::{Duration, Instant};
+
+// Frames queued between capture, encode and send when nothing is configured:
+// two raw frames for the encoder, eight encoded, about a quarter second at 30fps
+const DEFAULT_BUFFERED_FRAMES: usize = 10;
+// Capture only cares that the newest raw frame is encoded
+const MAX_ENCODE_QUEUE_DEPTH: usize = 2;
+const DEFAULT_AUDIO_BUFFER: Duration = Duration::from_secs(1);
+const MAX_AUDIO_BUFFER: Duration = Duration::from_secs(10);
+// Packets per track the NACK responder keeps for retransmission, webrtc-rs's default
+const DEFAULT_RTP_HISTORY: u16 = 1024;
+const MAX_RTP_HISTORY: u16 = 32768;
+// One warning per resource per interval, however many drops there were
+const WARNING_INTERVAL: Duration = Duration::from_secs(30);
+
+// Caps on what one stream may hold in memory. Each is enforced by dropping
+// the oldest data, never by growing.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryBudget {
+    // Raw and encoded frames waiting between pipeline stages
+    pub buffered_frames: usize,
+    pub audio_buffer: Duration,
+    pub rtp_history: u16,
+    // None leaves the replay buffer bounded by its window alone
+    pub replay_bytes: Option<usize>,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self {
+            buffered_frames: DEFAULT_BUFFERED_FRAMES,
+            audio_buffer: DEFAULT_AUDIO_BUFFER,
+            rtp_history: DEFAULT_RTP_HISTORY,
+            replay_bytes: None,
+        }
+    }
+}
+
+impl MemoryBudget {
+    pub fn validate(&self) -> Result<()> {
+        if self.buffered_frames < 2 {
+            return Err(SlumpError::Init(format!(
+                "At least 2 buffered frames are needed, one per queue, got {}",
+                self.buffered_frames
+            )));
+        }
+        if self.audio_buffer < Duration::from_millis(20) || self.audio_buffer > MAX_AUDIO_BUFFER {
+            return Err(SlumpError::Init(format!(
+                "Audio buffer must be 20-{} ms, got {} ms",
+                MAX_AUDIO_BUFFER.as_millis(),
+                self.audio_buffer.as_millis()
+            )));
+        }
+        // The responder indexes its ring with a mask
+        if !self.rtp_history.is_power_of_two() || self.rtp_history > MAX_RTP_HISTORY {
+            return Err(SlumpError::Init(format!(
+                "RTP history must be a power of two up to {}, got {}",
+                MAX_RTP_HISTORY, self.rtp_history
+            )));
+        }
+        if self.replay_bytes == Some(0) {
+            return Err(SlumpError::Init(
+                "Replay buffer cap must be non-zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Depths for the encode and send queues; encoded frames get the rest
+    pub fn queue_depths(&self) -> (usize, usize) {
+        let encode = (self.buffered_frames / 2).clamp(1, MAX_ENCODE_QUEUE_DEPTH);
+        (encode, self.buffered_frames - encode)
+    }
+
+    pub fn rtp_history_log2(&self) -> u8 {
+        self.rtp_history.trailing_zeros() as u8
+    }
+}
+
+// What the capped buffers hold right now
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryUsage {
+    pub buffered_frames: usize,
+    pub audio_buffered: Duration,
+    // Estimated from packets sent, since the responder doesn't expose its fill
+    pub rtp_history_packets: u64,
+    pub replay_bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Frames,
+    Audio,
+    Replay,
+}
+
+impl Resource {
+    fn describe(&self, dropped: u64) -> String {
+        match self {
+            Resource::Frames => format!("Frame buffer cap dropped {} frames", dropped),
+            Resource::Audio => format!("Audio buffer cap dropped {} samples", dropped),
+            Resource::Replay => format!(
+                "Replay buffer cap dropped {} packets; saved replays will be shorter than the window",
+                dropped
+            ),
+        }
+    }
+}
+
+// Turns the drop counters of the capped buffers into rate-limited warnings
+#[derive(Default)]
+pub struct DropMonitor {
+    // Drop count at the last warning and when that went out
+    seen: [(u64, Option<Instant>); 3],
+}
+
+impl DropMonitor {
+    // `dropped` is a running total; returns a warning when it has grown and
+    // the last one for this resource is old enough
+    pub fn check(&mut self, resource: Resource, dropped: u64) -> Option<String> {
+        let (seen, warned) = &mut self.seen[resource as usize];
+        // Counters start over when a capture or buffer is reopened
+        if dropped < *seen {
+            *seen = 0;
+        }
+        if dropped == *seen || warned.is_some_and(|at| at.elapsed() < WARNING_INTERVAL) {
+            return None;
+        }
+        let message = resource.describe(dropped - *seen);
+        *seen = dropped;
+        *warned = Some(Instant::now());
+        Some(message)
+    }
+}
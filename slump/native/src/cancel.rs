@@ -0,0 +1,55 @@
+use crate::error::{Result, SlumpError};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::Notify;
+
+// Flag a caller flips to abandon a long operation. Blocking steps check it
+// between stages; async ones race it, so ICE gathering or a signaling round
+// trip stops as soon as it is set.
+#[derive(Clone, Default)]
+pub struct Cancellation {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(SlumpError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn run<T>(&self, work: impl Future<Output = Result<T>>) -> Result<T> {
+        // Registered before the flag is read, so a cancel in between still wakes it
+        let notified = self.inner.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        self.check()?;
+
+        tokio::select! {
+            _ = notified => Err(SlumpError::Cancelled),
+            result = work => result,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::error::{Result, SlumpError};
+use std::{str::FromStr, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaptionKind {
+    // Live captions
+    #[default]
+    Caption,
+    // Song or programme titles
+    Title,
+    // Anything machine-readable; only viewers are expected to parse it
+    Telemetry,
+}
+
+impl CaptionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CaptionKind::Caption => "caption",
+            CaptionKind::Title => "title",
+            CaptionKind::Telemetry => "telemetry",
+        }
+    }
+}
+
+impl FromStr for CaptionKind {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "caption" => Ok(CaptionKind::Caption),
+            "title" => Ok(CaptionKind::Title),
+            "telemetry" => Ok(CaptionKind::Telemetry),
+            other => Err(SlumpError::Init(format!("Unknown caption kind: {}", other))),
+        }
+    }
+}
+
+// The last frame captured: its capture tick and wall-clock capture time,
+// matching the burn-in, and the RTP timestamp it goes to peers with, so
+// viewers can line text up with the frame they are showing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStamp {
+    pub index: u64,
+    pub capture_ms: f64,
+    pub rtp_timestamp: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Caption {
+    pub kind: CaptionKind,
+    pub text: String,
+    pub duration: Duration,
+}
+
+impl Caption {
+    // What goes out on the captions data channel
+    pub fn message(&self, frame: FrameStamp) -> String {
+        serde_json::json!({
+            "kind": self.kind.as_str(),
+            "text": self.text,
+            "frame": frame.index,
+            "timestampMs": frame.capture_ms,
+            "rtpTimestamp": frame.rtp_timestamp,
+            "durationMs": self.duration.as_millis() as u64,
+        })
+        .to_string()
+    }
+}
@@ -0,0 +1,44 @@
+use crate::logging;
+use napi::{Env, Status};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Identifies one JS environment the addon is loaded into: the main thread,
+// each worker_thread and each Electron context get their own. Objects
+// holding JS callbacks belong to the environment that created them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContextId(u64);
+
+struct InstanceData {
+    id: ContextId,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+// The id is assigned the first time an environment calls in. That is also
+// when its teardown hook goes in, so per-environment state is dropped before
+// the environment's threadsafe functions become invalid.
+pub fn current(env: &mut Env) -> napi::Result<ContextId> {
+    if let Some(data) = env.get_instance_data::<InstanceData>()? {
+        return Ok(data.id);
+    }
+    let id = ContextId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    env.set_instance_data(InstanceData { id }, (), |_| {})?;
+    env.add_env_cleanup_hook(id, |id| logging::remove_context(id))?;
+    Ok(id)
+}
+
+// Errors if `owner` is not the calling environment. JS can't normally hand
+// an object to another thread, but Electron contexts sharing an isolate can.
+pub fn check(env: &mut Env, owner: ContextId, what: &str) -> napi::Result<()> {
+    if current(env)? == owner {
+        return Ok(());
+    }
+    Err(napi::Error::new(
+        Status::InvalidArg,
+        format!(
+            "{} was created in another JS context (worker thread or Electron context); \
+             create a separate instance in each context",
+            what
+        ),
+    ))
+}
@@ -0,0 +1,48 @@
+use crate::error::{Result, SlumpError};
+use crate::logging;
+use serde_json::Value;
+use std::{fs::File, io::Write};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+// What a support bundle holds besides the logs, gathered while the stream
+// lock is held so the file can be written without it
+pub struct Bundle {
+    // Running state, failure, versions
+    pub session: Value,
+    // The settings the stream was started with, credentials left out
+    pub config: Value,
+    // One entry per stats tick, oldest first
+    pub stats: Vec<Value>,
+    // SDP, candidates and pair states per peer
+    pub peers: Vec<Value>,
+}
+
+// Writes `bundle` and the recent log lines to a zip at `path`; one file per
+// part so support can open just the one they need
+pub fn write(path: &str, bundle: &Bundle) -> Result<()> {
+    let failed =
+        |e: &dyn std::fmt::Display| SlumpError::Init(format!("Failed to write {}: {}", path, e));
+
+    let file = File::create(path)
+        .map_err(|e| SlumpError::Init(format!("Failed to create {}: {}", path, e)))?;
+    let mut zip = ZipWriter::new(file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let parts = [
+        ("session.json", serde_json::to_vec_pretty(&bundle.session)),
+        ("config.json", serde_json::to_vec_pretty(&bundle.config)),
+        ("stats.json", serde_json::to_vec_pretty(&bundle.stats)),
+        ("peers.json", serde_json::to_vec_pretty(&bundle.peers)),
+        (
+            "logs.txt",
+            Ok(logging::recent_lines().concat().into_bytes()),
+        ),
+    ];
+    for (name, data) in parts {
+        let data = data.map_err(|e| failed(&e))?;
+        zip.start_file(name, options).map_err(|e| failed(&e))?;
+        zip.write_all(&data).map_err(|e| failed(&e))?;
+    }
+    zip.finish().map_err(|e| failed(&e))?;
+    Ok(())
+}
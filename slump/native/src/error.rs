@@ -23,6 +23,218 @@ pub enum SlumpError {
     
     #[error("Not implemented: {0}")]
     NotImplemented(String),
+    
+    #[error("Operation cancelled")]
+    Cancelled,
+
+    #[error("Invalid options: {}", crate::validate::describe(.0))]
+    InvalidOptions(Vec<crate::validate::Violation>),
+
+    #[error("{0}")]
+    Panicked(String),
+
+    #[error("Device busy: {0}")]
+    DeviceBusy(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Device not found: {0}")]
+    DeviceNotFound(String),
+
+    #[error("Encoder session limit reached: {0}")]
+    EncoderSessionLimit(String),
+
+    #[error("ICE connection failed: {0}")]
+    IceFailed(String),
+
+    #[error("Signaling connection closed: {0}")]
+    SignalingClosed(String),
+
+    // Context for JS; the message stays the underlying error's
+    #[error("{source} (device {device})")]
+    Device {
+        device: String,
+        source: Box<SlumpError>,
+    },
+
+    #[error("{source} (peer {peer_id})")]
+    Peer {
+        peer_id: String,
+        source: Box<SlumpError>,
+    },
+}
+
+// What JS gets besides the message
+#[derive(Debug, Clone)]
+pub struct ErrorInfo {
+    pub code: &'static str,
+    pub retryable: bool,
+    pub device: Option<String>,
+    pub peer_id: Option<String>,
+    pub hint: Option<RecoveryHint>,
+    // Every option at fault, for ERR_INVALID_OPTIONS
+    pub violations: Vec<crate::validate::Violation>,
+}
+
+// What the UI should suggest to the user; like the codes these strings are
+// part of the JS contract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryHint {
+    CloseOtherApps,
+    GrantPermission,
+    CheckDevice,
+    UseSoftwareEncoder,
+    ConfigureTurn,
+    ReconnectSignaling,
+    FixOptions,
+    RestartStream,
+}
+
+impl RecoveryHint {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecoveryHint::CloseOtherApps => "close_other_apps",
+            RecoveryHint::GrantPermission => "grant_permission",
+            RecoveryHint::CheckDevice => "check_device",
+            RecoveryHint::UseSoftwareEncoder => "use_software_encoder",
+            RecoveryHint::ConfigureTurn => "configure_turn",
+            RecoveryHint::ReconnectSignaling => "reconnect_signaling",
+            RecoveryHint::FixOptions => "fix_options",
+            RecoveryHint::RestartStream => "restart_stream",
+        }
+    }
+}
+
+impl SlumpError {
+    // Stable identifiers JS branches on; never rename one once released
+    pub fn code(&self) -> &'static str {
+        match self {
+            SlumpError::Ffmpeg(_) => "ERR_FFMPEG",
+            SlumpError::Webrtc(_) => "ERR_WEBRTC",
+            SlumpError::Audio(_) => "ERR_AUDIO_CAPTURE",
+            SlumpError::Video(_) => "ERR_VIDEO_CAPTURE",
+            SlumpError::Network(_) => "ERR_NETWORK",
+            SlumpError::Init(_) => "ERR_INIT",
+            SlumpError::NotImplemented(_) => "ERR_NOT_IMPLEMENTED",
+            SlumpError::Cancelled => "ERR_CANCELLED",
+            SlumpError::InvalidOptions(_) => "ERR_INVALID_OPTIONS",
+            SlumpError::Panicked(_) => "ERR_PANICKED",
+            SlumpError::DeviceBusy(_) => "ERR_DEVICE_BUSY",
+            SlumpError::PermissionDenied(_) => "ERR_PERMISSION_DENIED",
+            SlumpError::DeviceNotFound(_) => "ERR_DEVICE_NOT_FOUND",
+            SlumpError::EncoderSessionLimit(_) => "ERR_ENCODER_SESSION_LIMIT",
+            SlumpError::IceFailed(_) => "ERR_ICE_FAILED",
+            SlumpError::SignalingClosed(_) => "ERR_SIGNALING_CLOSED",
+            SlumpError::Device { source, .. } | SlumpError::Peer { source, .. } => source.code(),
+        }
+    }
+
+    // Whether the same call may succeed later: networks recover and devices
+    // get released, but bad settings and missing features stay that way
+    pub fn retryable(&self) -> bool {
+        match self {
+            SlumpError::Webrtc(_)
+            | SlumpError::Network(_)
+            | SlumpError::Audio(_)
+            | SlumpError::Video(_)
+            | SlumpError::Panicked(_)
+            | SlumpError::DeviceBusy(_)
+            | SlumpError::EncoderSessionLimit(_)
+            | SlumpError::IceFailed(_)
+            | SlumpError::SignalingClosed(_) => true,
+            SlumpError::Ffmpeg(_)
+            | SlumpError::PermissionDenied(_)
+            | SlumpError::DeviceNotFound(_)
+            | SlumpError::Init(_)
+            | SlumpError::NotImplemented(_)
+            | SlumpError::Cancelled
+            | SlumpError::InvalidOptions(_) => false,
+            SlumpError::Device { source, .. } | SlumpError::Peer { source, .. } => {
+                source.retryable()
+            }
+        }
+    }
+
+    pub fn hint(&self) -> Option<RecoveryHint> {
+        match self {
+            SlumpError::DeviceBusy(_) => Some(RecoveryHint::CloseOtherApps),
+            SlumpError::PermissionDenied(_) => Some(RecoveryHint::GrantPermission),
+            SlumpError::DeviceNotFound(_) => Some(RecoveryHint::CheckDevice),
+            SlumpError::EncoderSessionLimit(_) => Some(RecoveryHint::UseSoftwareEncoder),
+            SlumpError::IceFailed(_) => Some(RecoveryHint::ConfigureTurn),
+            SlumpError::SignalingClosed(_) => Some(RecoveryHint::ReconnectSignaling),
+            SlumpError::InvalidOptions(_) => Some(RecoveryHint::FixOptions),
+            SlumpError::Panicked(_) => Some(RecoveryHint::RestartStream),
+            SlumpError::Device { source, .. } | SlumpError::Peer { source, .. } => source.hint(),
+            _ => None,
+        }
+    }
+
+    // Opening a capture device fails with a bare errno; sort out the ones
+    // the user can do something about
+    pub fn from_device_open(err: ffmpeg_next::Error) -> Self {
+        const EPERM: i32 = 1;
+        const ENOENT: i32 = 2;
+        const EACCES: i32 = 13;
+        const EBUSY: i32 = 16;
+        const ENODEV: i32 = 19;
+
+        let message = err.to_string();
+        match err {
+            ffmpeg_next::Error::Other { errno: EBUSY } => SlumpError::DeviceBusy(message),
+            ffmpeg_next::Error::Other { errno: EPERM | EACCES } => {
+                SlumpError::PermissionDenied(message)
+            }
+            ffmpeg_next::Error::Other { errno: ENOENT | ENODEV } => {
+                SlumpError::DeviceNotFound(message)
+            }
+            _ => SlumpError::Ffmpeg(message),
+        }
+    }
+
+    pub fn on_device(self, device: impl Into<String>) -> Self {
+        SlumpError::Device {
+            device: device.into(),
+            source: Box::new(self),
+        }
+    }
+
+    pub fn on_peer(self, peer_id: impl Into<String>) -> Self {
+        SlumpError::Peer {
+            peer_id: peer_id.into(),
+            source: Box::new(self),
+        }
+    }
+
+    pub fn info(&self) -> ErrorInfo {
+        let mut info = ErrorInfo {
+            code: self.code(),
+            retryable: self.retryable(),
+            device: None,
+            peer_id: None,
+            hint: self.hint(),
+            violations: Vec::new(),
+        };
+        let mut current = self;
+        loop {
+            match current {
+                SlumpError::Device { device, source } => {
+                    info.device.get_or_insert_with(|| device.clone());
+                    current = source;
+                }
+                SlumpError::Peer { peer_id, source } => {
+                    info.peer_id.get_or_insert_with(|| peer_id.clone());
+                    current = source;
+                }
+                SlumpError::InvalidOptions(violations) => {
+                    info.violations = violations.clone();
+                    return info;
+                }
+                _ => return info,
+            }
+        }
+    }
 }
 
 impl From<ffmpeg_next::Error> for SlumpError {
@@ -0,0 +1,48 @@
+use crate::webrtc::TrackKind;
+
+// What happened to the frames of one track since start()
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameCounts {
+    pub captured: u64,
+    // Identical to the previous frame, so not encoded again
+    pub duplicated: u64,
+    // Evicted or refused by a full queue between stages
+    pub dropped_queue_full: u64,
+    // The encoder was being replaced or failed on the frame
+    pub dropped_encoder_busy: u64,
+    // Never captured: the capture loop fell whole frames behind and skipped
+    // ahead rather than bursting
+    pub dropped_pacing: u64,
+    pub encoded: u64,
+    // Written to at least one peer
+    pub sent: u64,
+    // PLIs and FIRs from viewers, and how many of them a keyframe already
+    // on its way answered
+    pub keyframe_requests: u64,
+    pub keyframe_requests_folded: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameAccounting {
+    pub video: FrameCounts,
+    pub camera: FrameCounts,
+    pub audio: FrameCounts,
+}
+
+impl FrameAccounting {
+    pub fn track(&mut self, kind: TrackKind) -> &mut FrameCounts {
+        match kind {
+            TrackKind::Video => &mut self.video,
+            TrackKind::Camera => &mut self.camera,
+            TrackKind::Audio => &mut self.audio,
+        }
+    }
+
+    pub fn tracks(&self) -> [(TrackKind, &FrameCounts); 3] {
+        [
+            (TrackKind::Video, &self.video),
+            (TrackKind::Camera, &self.camera),
+            (TrackKind::Audio, &self.audio),
+        ]
+    }
+}
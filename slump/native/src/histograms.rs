@@ -0,0 +1,95 @@
+use hdrhistogram::Histogram;
+use std::time::Duration;
+
+// Microseconds, up to a minute, to three significant digits
+const HIGHEST_MICROS: u64 = 60_000_000;
+const SIGNIFICANT_DIGITS: u8 = 3;
+// Summary buckets start at 1 ms and double
+const FIRST_BUCKET_MICROS: u64 = 1_000;
+
+// Full distributions of the per-frame timings that stats only average, so a
+// handful of long frames among thousands still shows
+pub struct FrameHistograms {
+    // Between one captured frame and the next
+    pub capture_interval: Timings,
+    pub encode: Timings,
+    // How late the capture loop woke for each frame's deadline
+    pub pacing_error: Timings,
+}
+
+impl Default for FrameHistograms {
+    fn default() -> Self {
+        Self {
+            capture_interval: Timings::new(),
+            encode: Timings::new(),
+            pacing_error: Timings::new(),
+        }
+    }
+}
+
+impl FrameHistograms {
+    pub fn reset(&mut self) {
+        self.capture_interval.0.reset();
+        self.encode.0.reset();
+        self.pacing_error.0.reset();
+    }
+}
+
+pub struct Timings(Histogram<u64>);
+
+// What one histogram holds, in milliseconds
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub count: u64,
+    pub min_ms: f64,
+    pub mean_ms: f64,
+    pub stdev_ms: f64,
+    pub max_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub p999_ms: f64,
+    // Upper bound and count per bucket, 1 ms and doubling
+    pub buckets: Vec<(f64, u64)>,
+}
+
+impl Timings {
+    fn new() -> Self {
+        // The bounds are constants that hdrhistogram accepts
+        Self(Histogram::new_with_bounds(1, HIGHEST_MICROS, SIGNIFICANT_DIGITS).unwrap())
+    }
+
+    // Anything past a minute counts as a minute
+    pub fn record(&mut self, elapsed: Duration) {
+        self.0
+            .saturating_record((elapsed.as_micros() as u64).clamp(1, HIGHEST_MICROS));
+    }
+
+    pub fn summary(&self) -> Summary {
+        let histogram = &self.0;
+        if histogram.is_empty() {
+            return Summary::default();
+        }
+        let ms = |micros: u64| micros as f64 / 1000.0;
+        Summary {
+            count: histogram.len(),
+            min_ms: ms(histogram.min()),
+            mean_ms: histogram.mean() / 1000.0,
+            stdev_ms: histogram.stdev() / 1000.0,
+            max_ms: ms(histogram.max()),
+            p50_ms: ms(histogram.value_at_quantile(0.5)),
+            p90_ms: ms(histogram.value_at_quantile(0.9)),
+            p99_ms: ms(histogram.value_at_quantile(0.99)),
+            p999_ms: ms(histogram.value_at_quantile(0.999)),
+            buckets: histogram
+                .iter_log(FIRST_BUCKET_MICROS, 2.0)
+                .map(|bucket| {
+                    (
+                        ms(bucket.value_iterated_to()),
+                        bucket.count_since_last_iteration(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
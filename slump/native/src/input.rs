@@ -0,0 +1,398 @@
+use crate::error::{Result, SlumpError};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    thread::JoinHandle,
+};
+
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct InputKinds: u8 {
+        const MOVE = 1;
+        // Buttons, down and up
+        const CLICK = 1 << 1;
+        const SCROLL = 1 << 2;
+        const KEYBOARD = 1 << 3;
+    }
+}
+
+impl FromStr for InputKinds {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "move" => Ok(InputKinds::MOVE),
+            "click" => Ok(InputKinds::CLICK),
+            "scroll" => Ok(InputKinds::SCROLL),
+            "keyboard" => Ok(InputKinds::KEYBOARD),
+            other => Err(SlumpError::Init(format!("Unknown input kind: {}", other))),
+        }
+    }
+}
+
+// What a viewer sends on the control channel, one JSON object per message
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum InputEvent {
+    // 0-1 across the shared capture on both axes
+    MouseMove { x: f64, y: f64 },
+    MouseButton { button: u32, down: bool },
+    Wheel { dx: f64, dy: f64 },
+    // KeyboardEvent.code, so the layout doesn't matter
+    Key { code: String, down: bool },
+}
+
+impl InputEvent {
+    pub fn kind(&self) -> InputKinds {
+        match self {
+            InputEvent::MouseMove { .. } => InputKinds::MOVE,
+            InputEvent::MouseButton { .. } => InputKinds::CLICK,
+            InputEvent::Wheel { .. } => InputKinds::SCROLL,
+            InputEvent::Key { .. } => InputKinds::KEYBOARD,
+        }
+    }
+
+    // As it appears in the "type" field
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputEvent::MouseMove { .. } => "mouseMove",
+            InputEvent::MouseButton { .. } => "mouseButton",
+            InputEvent::Wheel { .. } => "wheel",
+            InputEvent::Key { .. } => "key",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    // The viewer's first input without a grant; the app should ask
+    NeedsApproval,
+    Denied,
+}
+
+// Who may control the machine and with what. Viewers start with nothing and
+// the app grants each one; every event is checked under the stream lock, so a
+// revoke stops the very next one.
+pub struct InputPolicy {
+    // Kinds no grant can go beyond
+    allowed: InputKinds,
+    grants: HashMap<String, InputKinds>,
+    // Viewers already reported as asking, so a stream of moves asks once
+    asked: HashSet<String>,
+}
+
+impl Default for InputPolicy {
+    fn default() -> Self {
+        Self {
+            allowed: InputKinds::all(),
+            grants: HashMap::new(),
+            asked: HashSet::new(),
+        }
+    }
+}
+
+impl InputPolicy {
+    pub fn set_allowed(&mut self, allowed: InputKinds) {
+        self.allowed = allowed;
+    }
+
+    pub fn grant(&mut self, viewer_id: &str, kinds: InputKinds) {
+        self.grants.insert(viewer_id.to_string(), kinds);
+    }
+
+    pub fn revoke(&mut self, viewer_id: &str) -> bool {
+        self.grants.remove(viewer_id).is_some()
+    }
+
+    // The panic switch. Revoked viewers don't get to ask again; the app can
+    // still grant them.
+    pub fn revoke_all(&mut self) -> Vec<String> {
+        let revoked: Vec<String> = self
+            .grants
+            .drain()
+            .map(|(viewer_id, _)| viewer_id)
+            .collect();
+        self.asked.extend(revoked.iter().cloned());
+        revoked
+    }
+
+    // A viewer that left; one reconnecting under the same id starts over
+    pub fn forget(&mut self, viewer_id: &str) {
+        self.grants.remove(viewer_id);
+        self.asked.remove(viewer_id);
+    }
+
+    pub fn check(&mut self, viewer_id: &str, event: &InputEvent) -> Verdict {
+        match self.grants.get(viewer_id) {
+            Some(&kinds) if (kinds & self.allowed).contains(event.kind()) => Verdict::Allowed,
+            Some(_) => Verdict::Denied,
+            None if self.asked.insert(viewer_id.to_string()) => Verdict::NeedsApproval,
+            None => Verdict::Denied,
+        }
+    }
+}
+
+// A global shortcut such as "Ctrl+Shift+F12". Keys are A-Z, 0-9, F1-F24,
+// Escape, Space and Pause, with Ctrl, Alt, Shift and Win/Meta in front.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Accelerator {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub meta: bool,
+    // Windows virtual-key code
+    pub key: u16,
+}
+
+impl FromStr for Accelerator {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || SlumpError::Init(format!("Invalid hotkey: {}", s));
+        let mut accelerator = Accelerator {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            meta: false,
+            key: 0,
+        };
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().ok_or_else(invalid)?.to_ascii_uppercase();
+        for part in parts {
+            let flag = match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => &mut accelerator.ctrl,
+                "alt" => &mut accelerator.alt,
+                "shift" => &mut accelerator.shift,
+                "win" | "meta" | "super" => &mut accelerator.meta,
+                _ => return Err(invalid()),
+            };
+            if std::mem::replace(flag, true) {
+                return Err(invalid());
+            }
+        }
+
+        let function_key = key
+            .strip_prefix('F')
+            .and_then(|n| n.parse::<u16>().ok())
+            .filter(|n| (1..=24).contains(n));
+        accelerator.key = match (key.as_bytes(), function_key) {
+            (_, Some(n)) => 0x70 + n - 1,
+            ([c], _) if c.is_ascii_alphanumeric() => *c as u16,
+            _ if key == "ESCAPE" || key == "ESC" => 0x1b,
+            _ if key == "SPACE" => 0x20,
+            _ if key == "PAUSE" => 0x13,
+            _ => return Err(invalid()),
+        };
+        // A bare letter or space would be taken from every other app
+        let modified = accelerator.ctrl || accelerator.alt || accelerator.meta;
+        if !modified && function_key.is_none() && key != "PAUSE" {
+            return Err(invalid());
+        }
+        Ok(accelerator)
+    }
+}
+
+// The panic switch as an OS-wide shortcut, so it works while the app's window
+// is hidden, unfocused or hung. Registered on its own thread, which owns the
+// registration and gets the presses in its message queue; dropping this
+// unregisters it.
+pub struct PanicHotkey {
+    #[cfg(windows)]
+    thread_id: u32,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(windows)]
+impl PanicHotkey {
+    pub fn register(
+        accelerator: Accelerator,
+        on_press: impl Fn() + Send + 'static,
+    ) -> Result<Self> {
+        use windows::Win32::{
+            Foundation::HWND,
+            System::Threading::GetCurrentThreadId,
+            UI::{
+                Input::KeyboardAndMouse::{
+                    RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL,
+                    MOD_NOREPEAT, MOD_SHIFT, MOD_WIN,
+                },
+                WindowsAndMessaging::{GetMessageW, MSG, WM_HOTKEY},
+            },
+        };
+
+        const HOTKEY_ID: i32 = 1;
+        let mut modifiers = MOD_NOREPEAT;
+        for (held, modifier) in [
+            (accelerator.ctrl, MOD_CONTROL),
+            (accelerator.alt, MOD_ALT),
+            (accelerator.shift, MOD_SHIFT),
+            (accelerator.meta, MOD_WIN),
+        ] {
+            if held {
+                modifiers = HOT_KEY_MODIFIERS(modifiers.0 | modifier.0);
+            }
+        }
+
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("slump-panic-hotkey".into())
+            .spawn(move || {
+                let registered = unsafe {
+                    RegisterHotKey(HWND(0), HOTKEY_ID, modifiers, accelerator.key as u32)
+                };
+                if !registered.as_bool() {
+                    let _ = ready_tx.send(None);
+                    return;
+                }
+                let _ = ready_tx.send(Some(unsafe { GetCurrentThreadId() }));
+
+                // Ends on the WM_QUIT drop posts; -1 is an error
+                let mut message = MSG::default();
+                while unsafe { GetMessageW(&mut message, HWND(0), 0, 0) }.0 > 0 {
+                    if message.message == WM_HOTKEY {
+                        on_press();
+                    }
+                }
+                unsafe {
+                    UnregisterHotKey(HWND(0), HOTKEY_ID);
+                }
+            })
+            .map_err(|e| SlumpError::Init(format!("Failed to start hotkey thread: {}", e)))?;
+
+        match ready_rx.recv() {
+            Ok(Some(thread_id)) => Ok(Self {
+                thread_id,
+                thread: Some(thread),
+            }),
+            _ => {
+                let _ = thread.join();
+                Err(SlumpError::DeviceBusy(
+                    "Hotkey is already registered by another application".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(not(windows))]
+impl PanicHotkey {
+    pub fn register(
+        _accelerator: Accelerator,
+        _on_press: impl Fn() + Send + 'static,
+    ) -> Result<Self> {
+        Err(SlumpError::NotImplemented(
+            "A global panic hotkey is only registered on Windows; bind revokeAllInput to a shortcut in the app"
+                .to_string(),
+        ))
+    }
+}
+
+impl Drop for PanicHotkey {
+    fn drop(&mut self) {
+        #[cfg(windows)]
+        unsafe {
+            use windows::Win32::{
+                Foundation::{LPARAM, WPARAM},
+                UI::WindowsAndMessaging::{PostThreadMessageW, WM_QUIT},
+            };
+            PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0));
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mouse_move() -> InputEvent {
+        InputEvent::MouseMove { x: 0.5, y: 0.5 }
+    }
+
+    fn key() -> InputEvent {
+        InputEvent::Key {
+            code: "KeyA".to_string(),
+            down: true,
+        }
+    }
+
+    #[test]
+    fn denies_by_default_and_asks_once() {
+        let mut policy = InputPolicy::default();
+        assert_eq!(policy.check("a", &mouse_move()), Verdict::NeedsApproval);
+        assert_eq!(policy.check("a", &mouse_move()), Verdict::Denied);
+        assert_eq!(policy.check("a", &key()), Verdict::Denied);
+        // Each viewer asks for itself
+        assert_eq!(policy.check("b", &key()), Verdict::NeedsApproval);
+    }
+
+    #[test]
+    fn grants_only_what_is_also_allowed() {
+        let mut policy = InputPolicy::default();
+        policy.grant("a", InputKinds::MOVE | InputKinds::KEYBOARD);
+        assert_eq!(policy.check("a", &mouse_move()), Verdict::Allowed);
+        assert_eq!(policy.check("a", &key()), Verdict::Allowed);
+        assert_eq!(
+            policy.check("a", &InputEvent::Wheel { dx: 0.0, dy: 1.0 }),
+            Verdict::Denied
+        );
+
+        policy.set_allowed(InputKinds::MOVE | InputKinds::CLICK);
+        assert_eq!(policy.check("a", &mouse_move()), Verdict::Allowed);
+        assert_eq!(policy.check("a", &key()), Verdict::Denied);
+    }
+
+    #[test]
+    fn revoke_all_stops_input_without_asking_again() {
+        let mut policy = InputPolicy::default();
+        policy.grant("a", InputKinds::all());
+        policy.grant("b", InputKinds::MOVE);
+        let mut revoked = policy.revoke_all();
+        revoked.sort();
+        assert_eq!(revoked, ["a", "b"]);
+        assert_eq!(policy.check("a", &mouse_move()), Verdict::Denied);
+        assert_eq!(policy.check("b", &mouse_move()), Verdict::Denied);
+
+        // The app can still grant again
+        policy.grant("a", InputKinds::MOVE);
+        assert_eq!(policy.check("a", &mouse_move()), Verdict::Allowed);
+    }
+
+    #[test]
+    fn forgotten_viewers_start_over() {
+        let mut policy = InputPolicy::default();
+        policy.grant("a", InputKinds::all());
+        policy.revoke_all();
+        policy.forget("a");
+        assert_eq!(policy.check("a", &mouse_move()), Verdict::NeedsApproval);
+
+        policy.grant("b", InputKinds::all());
+        policy.forget("b");
+        assert_eq!(policy.check("b", &mouse_move()), Verdict::NeedsApproval);
+    }
+
+    #[test]
+    fn parses_hotkeys() {
+        let accelerator: Accelerator = "Ctrl+Shift+F12".parse().unwrap();
+        assert!(accelerator.ctrl && accelerator.shift && !accelerator.alt);
+        assert_eq!(accelerator.key, 0x7b);
+        assert_eq!("alt+q".parse::<Accelerator>().unwrap().key, b'Q' as u16);
+        assert_eq!("Pause".parse::<Accelerator>().unwrap().key, 0x13);
+
+        for invalid in [
+            "",
+            "Q",
+            "Shift+Space",
+            "Ctrl+Ctrl+Q",
+            "Hyper+Q",
+            "Ctrl+F25",
+            "Ctrl+",
+        ] {
+            assert!(invalid.parse::<Accelerator>().is_err(), "{invalid}");
+        }
+    }
+}
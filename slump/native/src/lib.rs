@@ -1,318 +1,7977 @@
+mod adaptive;
 mod audio;
+mod benchmark;
+mod budget;
+mod cancel;
+mod captions;
+mod context;
+mod diagnostics;
 mod error;
+mod frames;
+mod histograms;
+mod input;
+mod logging;
+mod loopback;
+mod metrics;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod output;
+mod pacing;
+mod panic;
+mod permissions;
+mod pipeline;
+mod presence;
+mod probe;
+mod quality;
+mod rates;
+mod recording;
+mod runtime;
+mod scene;
+mod signaling;
+mod substitute;
+mod tap;
+mod task;
+mod trace;
+mod usage;
+mod validate;
 mod video;
+mod watchdog;
 mod webrtc;
 
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    collections::{HashMap, HashSet, VecDeque},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
-use audio::AudioCapture;
+use adaptive::{
+    AdaptiveController, AdaptiveTarget, CongestionAlgorithm, CongestionController,
+    DegradationPreference, Feedback, Knob, QualityLimitation,
+};
+use audio::{AudioCapture, AudioEncoder, AudioMeter, DuckSettings, Ducker};
+use budget::{DropMonitor, MemoryBudget, MemoryUsage, Resource};
+use cancel::Cancellation;
+use captions::{Caption, FrameStamp};
+use context::ContextId;
 use error::Result;
+use ffmpeg_next::Frame;
+use frames::{FrameAccounting, FrameCounts};
+use histograms::FrameHistograms;
+use input::{Accelerator, InputEvent, InputKinds, InputPolicy, PanicHotkey, Verdict};
+use metrics::{Metrics, MetricsServer};
 use napi::{
     bindgen_prelude::*,
     threadsafe_function::{ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode},
-    JsFunction,
+    JsBuffer, JsFunction,
 };
 use napi_derive::napi;
-use video::VideoCapture;
-use webrtc::{SignalMessage, WebRTCTransport};
+#[cfg(feature = "otlp")]
+use otlp::{OtlpExporter, OtlpSettings};
+#[cfg(feature = "moq")]
+use output::MoqPublisher;
+#[cfg(feature = "ndi")]
+use output::NdiSender;
+#[cfg(feature = "rist")]
+use output::RistSettings;
+#[cfg(feature = "rtmp")]
+use output::RtmpSettings;
+#[cfg(feature = "rtsp")]
+use output::RtspServer;
+#[cfg(feature = "srt")]
+use output::SrtSettings;
+#[cfg(feature = "udp")]
+use output::UdpSettings;
+use output::{OutputSink, SharedEncoder, Tee, VideoParams, MAX_AUDIO_OFFSET_MS};
+#[cfg(feature = "segment")]
+use output::{SegmentFormat, SegmentSettings};
+use pacing::Pacer;
+use permissions::Permission;
+use pipeline::{
+    DelayLine, Delayed, DropPolicy, Owned, QueueStats, StageDevice, StageReceiver, StageSender,
+    StageTimings,
+};
+use presence::{Change, LeaveReason, Presence, PresenceMessage};
+use quality::ConnectionScores;
+use rates::{PeerTotals, RateWindow, Rates, Totals};
+use recording::{
+    Clip, ClipFormat, ClipSettings, Recorder, RecordingAlert, RecordingEncodeSettings,
+    RecordingFormat, RecordingLimits, RecordingSummary, ReplayBuffer, SegmentPolicy,
+};
+use scene::{Crossfade, Scene, Scenes};
+use signaling::{
+    SignalingAuth, SignalingClient, SignalingEvent, SignalingServer, TlsSettings, WhipClient,
+};
+use substitute::{Substitute, Substitutes};
+use tap::{Backing, FrameTap, TapSource, TappedFrame};
+use task::Blocking;
+use tokio::sync::{mpsc, oneshot};
+use tracing::Instrument;
+use usage::{CpuLap, GpuEncoder, ProcessCpu, StageCpu};
+use validate::Violations;
+use video::{
+    Annotation, Chroma, Color, ColorRange, ColorSpace, Colorimetry, Corner, CursorTracker,
+    Decision, ExternalFrames, FrameDedup, GameTarget, KeyframeLimiter, Obscure, Overlay,
+    PrivacyRegions, QualityRegion, QualityRegions, Region, SecureDesktop, Shape, VideoCapture,
+    VideoCodec, VideoEncoder, VideoSource,
+};
+use watchdog::{Stall, Watchdog};
+use webrtc::{
+    AudioTimestamps, Capabilities, Impairment, ImpairmentSettings, MediaClock, SentBitrates,
+    SignalEnvelope, SignalMessage, StatsProbe, TrackKind, TrackLoss, TrackSwap, TrackWriter,
+    WebRTCTransport,
+};
 
-struct SlumpStream {
-    video_capture: Option<VideoCapture>,
-    audio_capture: Option<AudioCapture>,
-    transport: Option<WebRTCTransport>,
+const DEFAULT_PEER_ID: &str = "default";
+const WHIP_PEER_ID: &str = "whip";
+const DEFAULT_CAMERA_BITRATE_KBPS: u32 = 1000;
+// Used for any StreamOptions field left out
+const DEFAULT_WIDTH: u32 = 1920;
+const DEFAULT_HEIGHT: u32 = 1080;
+const DEFAULT_FPS: u32 = 30;
+const DEFAULT_BITRATE_KBPS: u32 = 4000;
+const DEFAULT_STUN_SERVER: &str = "stun:stun.l.google.com:19302";
+const DEFAULT_WATCHDOG_TIMEOUT_MS: u32 = 5000;
+const DEFAULT_STATS_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_STATS_INTERVAL_MS: u32 = 100;
+const DEFAULT_RATE_WINDOW: Duration = Duration::from_secs(5);
+// A Warning goes out once a track's loss has been above this percent for
+// this many one-second windows in a row
+const DEFAULT_LOSS_WARNING_PERCENT: f64 = 5.0;
+const DEFAULT_LOSS_WARNING_WINDOWS: u32 = 3;
+const DEFAULT_LOG_FILE_SIZE_MB: u32 = 10;
+const DEFAULT_LOG_FILES: u32 = 5;
+const MAX_TRACE_WINDOW_MS: u32 = 60_000;
+const DEFAULT_FRAME_CALLBACK_FPS: u32 = 5;
+const DEFAULT_METRICS_HOST: &str = "127.0.0.1";
+const MAX_WIDTH: u32 = 7680;
+const MAX_HEIGHT: u32 = 4320;
+const MAX_FPS: u32 = 240;
+const MAX_BENCHMARK_SECONDS: u32 = 60;
+const MAX_LOOPBACK_SECONDS: u32 = 30;
+const MIN_BITRATE_KBPS: u32 = 100;
+const MAX_BITRATE_KBPS: u32 = 100_000;
+// Tee sink names for outputs that have at most one instance
+const RECORDING_OUTPUT: &str = "recording";
+const REPLAY_OUTPUT: &str = "replay";
+#[cfg(feature = "rtsp")]
+const RTSP_OUTPUT: &str = "rtsp";
+#[cfg(feature = "ndi")]
+const NDI_OUTPUT: &str = "ndi";
+#[cfg(feature = "moq")]
+const MOQ_OUTPUT: &str = "moq";
+#[cfg(feature = "otlp")]
+const DEFAULT_OTLP_INTERVAL_MS: u32 = 10_000;
+#[cfg(feature = "otlp")]
+const MIN_OTLP_INTERVAL_MS: u32 = 1_000;
+const CAMERA_WIDTH: u32 = 1280;
+const CAMERA_HEIGHT: u32 = 720;
+const CAMERA_FPS: u32 = 30;
+// How often the placeholder is re-sent while paused, so late joiners get a picture
+const PLACEHOLDER_INTERVAL: Duration = Duration::from_secs(1);
+// Drawn on the placeholder sent while the secure desktop is up
+const SECURE_DESKTOP_NOTICE: &str = "Waiting for secure desktop";
+// With duplicate skipping on, how long a static screen goes without a frame
+const DUPLICATE_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+// ...and how long it stays static before a refresh is sent as a keyframe
+const DEFAULT_STATIC_KEYFRAME_INTERVAL_MS: u32 = 5000;
+// At most one keyframe this often for viewer requests, per track
+const DEFAULT_KEYFRAME_REQUEST_INTERVAL_MS: u32 = 1000;
+// Stats ticks kept for diagnostics bundles, ten minutes at one a second
+const STATS_HISTORY_LEN: usize = 600;
+const DEFAULT_ANNOTATION_THICKNESS: u32 = 4;
+const DEFAULT_ANNOTATION_TEXT_SIZE: f64 = 0.04;
+const DEFAULT_CAPTION_DURATION_MS: u32 = 4000;
+
+struct StreamState {
+    // Handed to the capture and encode stage threads while the stream runs;
+    // see Owned
+    video_capture: Owned<VideoCapture, StreamState>,
+    video_encoder: Owned<VideoEncoder, StreamState>,
+    // Where push_video_frame queues frames while video_source is "external"
+    external_frames: Option<Arc<parking_lot::Mutex<ExternalFrames>>>,
+    // What the capture stage last reported of its capture
+    capture_status: CaptureStatus,
+    // The camera's capture and encode stages have these, and the audio
+    // stage the microphone and system audio, the same way
+    camera_capture: Owned<VideoCapture, StreamState>,
+    camera_encoder: Owned<VideoEncoder, StreamState>,
+    camera_device: Option<String>,
+    // Capture sources picked at start, reused when a track is re-added
+    video_source: VideoSource,
+    display_index: usize,
+    // The game to hook for video_source "game"
+    game: Option<GameTarget>,
+    audio_device: Option<String>,
+    // Mixed under the microphone, ducked while it has speech
+    system_audio: Owned<AudioCapture, StreamState>,
+    system_audio_device: Option<String>,
+    ducker: Ducker,
+    capture_cursor: bool,
+    // Set when viewers are sent the pointer position, see cursor_metadata
+    cursor: Option<CursorTracker>,
+    // Whether a UAC prompt or the lock screen has the display, see
+    // secure_desktop_placeholder
+    secure_desktop: SecureDesktop,
+    secure_desktop_placeholder: bool,
+    // What every capture is converted to
+    colorimetry: Colorimetry,
+    chroma: Chroma,
+    // Set when frames identical to the previous one skip the encoder
+    dedup: Option<FrameDedup>,
+    budget: MemoryBudget,
+    // Set when the stream was started with a simulated bad network
+    impairment: Option<Impairment>,
+    // Which captures may be replaced by test sources
+    substitutes: Substitutes,
+    video_bitrate_kbps: u32,
+    camera_bitrate_kbps: u32,
+    audio_capture: Owned<AudioCapture, StreamState>,
+    // Opus for the peers, open whenever the microphone is
+    audio_encoder: Owned<AudioEncoder, StreamState>,
+    audio_bitrate_kbps: u32,
+    // What the audio stage last reported of the microphone, and the levels
+    // it measured since the last stats report
+    audio_status: AudioStatus,
+    audio_meter: AudioMeter,
+    peers: HashMap<String, WebRTCTransport>,
+    peer_capabilities: HashMap<String, Capabilities>,
+    stun_servers: Vec<String>,
+    turn_servers: Vec<(String, Option<String>, Option<String>)>,
+    stats_settings: StatsSettings,
+    adaptive: AdaptiveController,
+    // Send-side bandwidth estimate the adaptive controller acts on
+    congestion: Box<dyn CongestionController>,
+    events: Option<ThreadsafeFunction<StreamEvent>>,
+    signaling_tx: Option<mpsc::UnboundedSender<SignalingEvent>>,
+    // Tells the worker loop to exit; stop joins the thread before releasing anything
+    shutdown_tx: Option<oneshot::Sender<()>>,
+    worker: Option<std::thread::JoinHandle<()>>,
+    signaling_server: Option<SignalingServer>,
+    signaling_client: Option<SignalingClient>,
+    whip: Option<WhipClient>,
+    // MPEG-TS contribution outputs (SRT, RIST) keyed by protocol
+    outputs: Tee,
+    recording_limits: RecordingLimits,
+    // None when the watchdog is disabled
+    watchdog: Option<Watchdog>,
     running: bool,
+    // Set when a pipeline thread panicked; the stream produces nothing until
+    // it is stopped and started again
+    failure: Option<String>,
+    // Peers get no media while paused; captures, encoders and outputs keep running
+    paused: bool,
+    placeholder: Option<Frame>,
     stats: Arc<Mutex<StreamStats>>,
+    // Devices opened by prepare() for the next start
+    prepared: Option<Prepared>,
+    // Recent stats ticks and the settings of the last start, for export_diagnostics
+    stats_history: VecDeque<serde_json::Value>,
+    config: serde_json::Value,
+    // Outlives stop and start, like the server that exposes it
+    metrics: Arc<Mutex<Metrics>>,
+    metrics_server: Option<MetricsServer>,
+    #[cfg(feature = "otlp")]
+    otlp: Option<OtlpExporter>,
+    // Queues between the capture, encode and send stages while running
+    queues: Vec<Arc<QueueStats>>,
+    // RTP timestamps for what goes to peers, from start
+    clock: Arc<MediaClock>,
+    // Per-item time in each stage, averaged into every stats report
+    timings: Arc<StageTimings>,
+    // CPU time of the stage threads, likewise
+    cpu: Arc<StageCpu>,
+    // Per-frame timing distributions since start, see get_histograms
+    histograms: Arc<parking_lot::Mutex<FrameHistograms>>,
+    frame_callback: Option<FrameCallback>,
+    // Which viewers may send remote input, see grant_input
+    input: InputPolicy,
+    // Viewers that announced themselves, see get_viewers
+    presence: Presence,
+    // Annotations burned into outgoing frames, see add_annotation
+    overlay: Overlay,
+    // Debug text drawn over everything, see set_burn_in
+    burn_in: Option<Corner>,
+    // What push_caption stamps captions with
+    last_frame: FrameStamp,
+    // Areas obscured before anything leaves, see set_privacy_region
+    privacy: PrivacyRegions,
+    // Areas encoded at higher quality, see set_quality_region
+    quality_regions: QualityRegions,
+    // Idle scenes, see switch_scene, and the fade into the live one
+    scenes: Scenes,
+    crossfade: Option<Arc<Crossfade>>,
+    // Set by a switch without a fade, so the cut starts on a keyframe
+    scene_cut: bool,
+    // Viewer keyframe requests, per video track
+    video_keyframes: KeyframeLimiter,
+    camera_keyframes: KeyframeLimiter,
+}
+
+// Captures and encoder opened ahead of start. They are only used by a start
+// whose options open the same devices the same way; anything else reopens.
+struct Prepared {
+    key: PrepareKey,
+    video_capture: Option<VideoCapture>,
+    video_encoder: Option<VideoEncoder>,
+    audio_capture: Option<AudioCapture>,
+}
+
+// The start options that decide how devices and the encoder are opened
+#[derive(Debug, Clone, PartialEq)]
+struct PrepareKey {
+    video: Option<bool>,
+    audio: Option<bool>,
+    video_source: VideoSource,
+    display_index: usize,
+    game: Option<GameTarget>,
+    capture_cursor: bool,
+    colorimetry: Colorimetry,
+    chroma: Chroma,
+    audio_device: Option<String>,
+    audio_buffer: Duration,
+    substitutes: Substitutes,
+    width: u32,
+    height: u32,
+    fps: u32,
+    bitrate_kbps: u32,
+}
+
+// Captured frames handed to JS, see set_frame_callback
+struct FrameCallback {
+    callback: ThreadsafeFunction<TappedFrame>,
+    tap: FrameTap,
 }
 
 #[derive(Default, Clone)]
 struct StreamStats {
-    video_frames_sent: u64,
-    audio_frames_sent: u64,
-    video_bitrate: f64,
-    audio_bitrate: f64,
+    frames: FrameAccounting,
+    // All peers together, measured on the wire
+    sent_kbps: SentBitrates,
     rtt: f64,
     jitter: f64,
+    fraction_lost: f64,
+    track_loss: Vec<TrackLossStats>,
+    // From the congestion controller
+    available_kbps: f64,
+    fps: f64,
+    capture_fps: f64,
+    capture_ms: f64,
+    encode_ms: f64,
+    send_ms: f64,
+    encode_latency_ms: f64,
+    send_latency_ms: f64,
+    glass_to_glass_ms: Option<f64>,
+    // Wire counters since start; the frame ones come from `frames`
+    totals: Totals,
+    rates: Rates,
+    quality_limitation: QualityLimitation,
+    limitation_durations: [(QualityLimitation, Duration); 4],
+    memory: MemoryUsage,
+    // Peak and RMS dBFS over the last report interval
+    audio_levels: Option<(f64, f64)>,
+    audio_clipped: u64,
+    audio_dropped: u64,
+    // 0-1
+    audio_buffer_fill: f64,
+    audio_packets_sent: u64,
+    // What the Opus encoder was set to, while there is one
+    audio_target_kbps: Option<u32>,
+    // Percent of all cores, and of one core per stage thread
+    process_cpu_percent: Option<f64>,
+    capture_cpu_percent: f64,
+    encode_cpu_percent: f64,
+    send_cpu_percent: f64,
+    gpu_encoder_percent: Option<f64>,
     timestamp: Instant,
 }
 
-impl Default for SlumpStream {
+impl StreamStats {
+    fn report(&self, groups: StatGroups) -> Stats {
+        let pick = |group: StatGroups, value: f64| groups.contains(group).then_some(value);
+        Stats {
+            capture_fps: pick(StatGroups::CAPTURE, self.capture_fps),
+            capture_ms: pick(StatGroups::CAPTURE, self.capture_ms),
+            fps: pick(StatGroups::ENCODER, self.fps),
+            frames: groups.contains(StatGroups::ENCODER).then(|| {
+                self.frames
+                    .tracks()
+                    .into_iter()
+                    .map(|(kind, counts)| TrackFrameStats::new(kind, counts))
+                    .collect()
+            }),
+            video_kbps: pick(StatGroups::ENCODER, self.sent_kbps.video_kbps),
+            encode_ms: pick(StatGroups::ENCODER, self.encode_ms),
+            rtt: pick(StatGroups::NETWORK, self.rtt),
+            send_ms: pick(StatGroups::NETWORK, self.send_ms),
+            encode_latency_ms: pick(StatGroups::ENCODER, self.encode_latency_ms),
+            quality_limitation: groups
+                .contains(StatGroups::ENCODER)
+                .then(|| self.quality_limitation.as_str().to_string()),
+            quality_limitation_durations: groups
+                .contains(StatGroups::ENCODER)
+                .then(|| QualityLimitationDurations::new(&self.limitation_durations)),
+            send_latency_ms: pick(StatGroups::NETWORK, self.send_latency_ms),
+            rates: groups
+                .intersects(StatGroups::ENCODER | StatGroups::NETWORK)
+                .then(|| WindowedRates::from(&self.rates)),
+            estimated_glass_to_glass_ms: self
+                .glass_to_glass_ms
+                .filter(|_| groups.contains(StatGroups::NETWORK)),
+            jitter: pick(StatGroups::NETWORK, self.jitter),
+            fraction_lost: pick(StatGroups::NETWORK, self.fraction_lost),
+            available_bitrate_kbps: pick(StatGroups::NETWORK, self.available_kbps),
+            track_loss: groups
+                .contains(StatGroups::NETWORK)
+                .then(|| self.track_loss.clone()),
+            audio_kbps: pick(StatGroups::AUDIO, self.sent_kbps.audio_kbps),
+            audio_target_kbps: self
+                .audio_target_kbps
+                .filter(|_| groups.contains(StatGroups::AUDIO))
+                .map(f64::from),
+            audio_peak_dbfs: self
+                .audio_levels
+                .filter(|_| groups.contains(StatGroups::AUDIO))
+                .map(|(peak, _)| peak),
+            audio_rms_dbfs: self
+                .audio_levels
+                .filter(|_| groups.contains(StatGroups::AUDIO))
+                .map(|(_, rms)| rms),
+            audio_clipped_samples: pick(StatGroups::AUDIO, self.audio_clipped as f64),
+            audio_dropped_samples: pick(StatGroups::AUDIO, self.audio_dropped as f64),
+            audio_buffer_percent: pick(StatGroups::AUDIO, self.audio_buffer_fill * 100.0),
+            audio_packets_sent: pick(StatGroups::AUDIO, self.audio_packets_sent as f64),
+            sent_kbps: groups
+                .contains(StatGroups::NETWORK)
+                .then(|| TrackBitrates::from(&self.sent_kbps)),
+            buffered_frames: pick(StatGroups::MEMORY, self.memory.buffered_frames as f64),
+            audio_buffered_ms: pick(
+                StatGroups::MEMORY,
+                self.memory.audio_buffered.as_secs_f64() * 1000.0,
+            ),
+            rtp_history_packets: pick(StatGroups::MEMORY, self.memory.rtp_history_packets as f64),
+            replay_buffer_bytes: pick(StatGroups::MEMORY, self.memory.replay_bytes as f64),
+            process_cpu_percent: self
+                .process_cpu_percent
+                .filter(|_| groups.contains(StatGroups::SYSTEM)),
+            capture_cpu_percent: pick(StatGroups::SYSTEM, self.capture_cpu_percent),
+            encode_cpu_percent: pick(StatGroups::SYSTEM, self.encode_cpu_percent),
+            send_cpu_percent: pick(StatGroups::SYSTEM, self.send_cpu_percent),
+            gpu_encoder_percent: self
+                .gpu_encoder_percent
+                .filter(|_| groups.contains(StatGroups::SYSTEM)),
+        }
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct StatGroups: u8 {
+        const CAPTURE = 1;
+        const ENCODER = 1 << 1;
+        // Also gates PeerQuality and ConnectionQuality events
+        const NETWORK = 1 << 2;
+        const AUDIO = 1 << 3;
+        const MEMORY = 1 << 4;
+        const SYSTEM = 1 << 5;
+    }
+}
+
+impl FromStr for StatGroups {
+    type Err = error::SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "capture" => Ok(StatGroups::CAPTURE),
+            "encoder" => Ok(StatGroups::ENCODER),
+            "network" => Ok(StatGroups::NETWORK),
+            "audio" => Ok(StatGroups::AUDIO),
+            "memory" => Ok(StatGroups::MEMORY),
+            "system" => Ok(StatGroups::SYSTEM),
+            other => Err(error::SlumpError::Init(format!(
+                "Unknown stats group: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct StatsSettings {
+    interval: Duration,
+    // Pull mode keeps get_stats current without sending events
+    push: bool,
+    groups: StatGroups,
+    glass_to_glass: bool,
+    rate_window: Duration,
+    // 0 turns the loss warning off
+    loss_warning_percent: f64,
+    loss_warning_windows: u32,
+}
+
+// Counts one-second windows in a row over the loss threshold per peer and
+// track, and warns once per run of them
+#[derive(Default)]
+struct LossMonitor {
+    streaks: HashMap<(String, TrackKind), u32>,
+}
+
+impl LossMonitor {
+    fn check(
+        &mut self,
+        peer_id: &str,
+        loss: &TrackLoss,
+        settings: &StatsSettings,
+    ) -> Option<String> {
+        // No report arrived in this window, so there is nothing to judge
+        if loss.window_packets == 0 {
+            return None;
+        }
+        let key = (peer_id.to_string(), loss.kind);
+        if settings.loss_warning_percent == 0.0
+            || loss.window_loss_percent < settings.loss_warning_percent
+        {
+            self.streaks.remove(&key);
+            return None;
+        }
+        let streak = self.streaks.entry(key).or_default();
+        *streak += 1;
+        (*streak == settings.loss_warning_windows).then(|| {
+            format!(
+                "Peer {} lost {:.1}% of {} packets, above {}% for {} windows in a row",
+                peer_id,
+                loss.window_loss_percent,
+                loss.kind.as_str(),
+                settings.loss_warning_percent,
+                settings.loss_warning_windows
+            )
+        })
+    }
+
+    fn retain_peers(&mut self, keep: impl Fn(&str) -> bool) {
+        self.streaks.retain(|(peer_id, _), _| keep(peer_id));
+    }
+}
+
+impl Default for StatsSettings {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_STATS_INTERVAL,
+            push: true,
+            groups: StatGroups::all(),
+            glass_to_glass: false,
+            rate_window: DEFAULT_RATE_WINDOW,
+            loss_warning_percent: DEFAULT_LOSS_WARNING_PERCENT,
+            loss_warning_windows: DEFAULT_LOSS_WARNING_WINDOWS,
+        }
+    }
+}
+
+impl Default for StreamState {
     fn default() -> Self {
         Self {
-            video_capture: None,
-            audio_capture: None,
-            transport: None,
+            video_capture: Owned::default(),
+            video_encoder: Owned::default(),
+            external_frames: None,
+            capture_status: CaptureStatus::default(),
+            camera_capture: Owned::default(),
+            camera_encoder: Owned::default(),
+            camera_device: None,
+            video_source: VideoSource::default(),
+            display_index: 0,
+            game: None,
+            audio_device: None,
+            system_audio: Owned::default(),
+            system_audio_device: None,
+            ducker: Ducker::new(DuckSettings::default()),
+            capture_cursor: false,
+            cursor: None,
+            secure_desktop: SecureDesktop::default(),
+            secure_desktop_placeholder: false,
+            colorimetry: Colorimetry::default(),
+            chroma: Chroma::default(),
+            dedup: None,
+            budget: MemoryBudget::default(),
+            impairment: None,
+            substitutes: Substitutes::default(),
+            video_bitrate_kbps: 0,
+            camera_bitrate_kbps: DEFAULT_CAMERA_BITRATE_KBPS,
+            audio_capture: Owned::default(),
+            audio_encoder: Owned::default(),
+            audio_bitrate_kbps: audio::DEFAULT_AUDIO_BITRATE_KBPS,
+            audio_status: AudioStatus::default(),
+            audio_meter: AudioMeter::default(),
+            peers: HashMap::new(),
+            peer_capabilities: HashMap::new(),
+            stun_servers: Vec::new(),
+            turn_servers: Vec::new(),
+            stats_settings: StatsSettings::default(),
+            adaptive: AdaptiveController::default(),
+            congestion: CongestionAlgorithm::default().controller(0),
+            events: None,
+            signaling_tx: None,
+            shutdown_tx: None,
+            worker: None,
+            signaling_server: None,
+            signaling_client: None,
+            whip: None,
+            outputs: Tee::default(),
+            recording_limits: RecordingLimits::default(),
+            watchdog: None,
             running: false,
+            failure: None,
+            paused: false,
+            placeholder: None,
             stats: Arc::new(Mutex::new(StreamStats::default())),
+            prepared: None,
+            stats_history: VecDeque::new(),
+            config: serde_json::Value::Null,
+            metrics: Arc::new(Mutex::new(Metrics::default())),
+            metrics_server: None,
+            #[cfg(feature = "otlp")]
+            otlp: None,
+            queues: Vec::new(),
+            clock: Arc::new(MediaClock::default()),
+            timings: Arc::new(StageTimings::default()),
+            cpu: Arc::new(StageCpu::default()),
+            histograms: Arc::new(parking_lot::Mutex::new(FrameHistograms::default())),
+            frame_callback: None,
+            input: InputPolicy::default(),
+            presence: Presence::default(),
+            overlay: Overlay::default(),
+            burn_in: None,
+            last_frame: FrameStamp::default(),
+            privacy: PrivacyRegions::default(),
+            quality_regions: QualityRegions::default(),
+            scenes: Scenes::default(),
+            crossfade: None,
+            scene_cut: false,
+            video_keyframes: KeyframeLimiter::default(),
+            camera_keyframes: KeyframeLimiter::default(),
         }
     }
 }
 
-static mut STREAM: Option<SlumpStream> = None;
-static STREAM_INIT: std::sync::Once = std::sync::Once::new();
+impl StreamState {
+    // webrtc-rs verifies turns: servers against the public web PKI roots
+    // only, so a private CA, pins or insecure mode can't follow signaling
+    // there. Refused rather than leaving the TURN connection quietly
+    // verified differently from what was asked for.
+    fn check_turns_tls(&self, tls: &TlsSettings) -> napi::Result<()> {
+        if tls.is_default() {
+            return Ok(());
+        }
+        let turns: Vec<&str> = self
+            .turn_servers
+            .iter()
+            .map(|(url, _, _)| url.as_str())
+            .filter(|url| url.starts_with("turns:"))
+            .collect();
+        if turns.is_empty() {
+            return Ok(());
+        }
+        Err(napi::Error::new(
+            napi::Status::InvalidArg,
+            format!(
+                "Custom TLS settings can't apply to {}; use turn: or drop the CA, pins and insecure options",
+                turns.join(", ")
+            ),
+        ))
+    }
 
-#[napi]
-pub fn start_stream(
-    width: u32,
-    height: u32,
-    fps: u32,
-    bitrate: u32,
-    stun_servers: Vec<String>,
-    on_event: JsFunction,
-) -> napi::Result<bool> {
-    STREAM_INIT.call_once(|| unsafe {
-        STREAM = Some(SlumpStream::default());
-    });
+    // Drops what start opened before it was cancelled
+    fn release_captures(&mut self) {
+        self.set_video_capture(None);
+        self.video_encoder.set(None);
+        self.audio_capture.set(None);
+        self.audio_encoder.set(None);
+        self.system_audio.set(None);
+    }
 
-    let stream = unsafe { STREAM.as_mut() }.ok_or_else(|| {
-        napi::Error::new(
-            napi::Status::GenericFailure,
-            "Failed to initialize stream".to_string(),
-        )
-    })?;
+    // Kept across microphone changes; only its bitrate and FEC change it
+    fn open_audio_encoder(&mut self) -> Result<()> {
+        if !self.audio_encoder.is_some() {
+            self.audio_encoder.set(Some(AudioEncoder::new(
+                self.audio_bitrate_kbps,
+                self.adaptive.target().fec_percent,
+            )?));
+        }
+        Ok(())
+    }
 
-    if stream.running {
-        return Ok(false);
+    // Every video capture goes in through here, so what others need of it is
+    // at hand while the capture stage has it
+    fn set_video_capture(&mut self, video: Option<VideoCapture>) {
+        self.note_video_capture(video.as_ref());
+        self.video_capture.set(video);
     }
 
-    // Initialize video capture
-    stream.video_capture = Some(
-        VideoCapture::new(0, width, height).map_err(|e| {
-            napi::Error::new(
-                napi::Status::GenericFailure,
-                format!("Failed to initialize video capture: {}", e),
-            )
-        })?,
-    );
+    fn note_video_capture(&mut self, video: Option<&VideoCapture>) {
+        self.external_frames = video.and_then(VideoCapture::external_frames);
+        self.capture_status = CaptureStatus {
+            source_paced: video.is_some_and(VideoCapture::is_source_paced),
+            ..CaptureStatus::default()
+        };
+    }
 
-    // Initialize audio capture
-    stream.audio_capture = Some(AudioCapture::new().map_err(|e| {
-        napi::Error::new(
-            napi::Status::GenericFailure,
-            format!("Failed to initialize audio capture: {}", e),
+    fn open_audio(&self, device: Option<&str>) -> Result<AudioCapture> {
+        let mut audio = self.substitutes.audio.open(
+            "Audio capture",
+            || {
+                AudioCapture::with_device(device).map_err(|e| {
+                    permissions::explain(e, Permission::Microphone)
+                        .on_device(device.unwrap_or("default"))
+                })
+            },
+            AudioCapture::synthetic,
+        )?;
+        audio.set_buffer(self.budget.audio_buffer);
+        Ok(audio)
+    }
+
+    fn open_video(&self, width: u32, height: u32) -> Result<VideoCapture> {
+        self.open_source(
+            self.video_source,
+            self.display_index,
+            self.capture_cursor,
+            width,
+            height,
         )
-    })?);
+    }
 
-    // Initialize WebRTC transport
-    let transport = tokio::runtime::Runtime::new()
-        .map_err(|e| {
-            napi::Error::new(
-                napi::Status::GenericFailure,
-                format!("Failed to create runtime: {}", e),
-            )
-        })?
-        .block_on(async {
-            WebRTCTransport::new(stun_servers, vec![]).await.map_err(|e| {
-                napi::Error::new(
-                    napi::Status::GenericFailure,
-                    format!("Failed to create WebRTC transport: {}", e),
+    fn open_source(
+        &self,
+        source: VideoSource,
+        display_index: usize,
+        capture_cursor: bool,
+        width: u32,
+        height: u32,
+    ) -> Result<VideoCapture> {
+        match source {
+            VideoSource::Screen => self.open_display(display_index, width, height, capture_cursor),
+            VideoSource::External => VideoCapture::external(width, height).and_then(|mut video| {
+                video.set_colorimetry(self.colorimetry)?;
+                video.set_chroma(self.chroma)?;
+                Ok(video)
+            }),
+            VideoSource::Game => {
+                let game = self.game.as_ref().ok_or_else(|| {
+                    error::SlumpError::Init("Game capture needs game_process and game_hook".into())
+                })?;
+                VideoCapture::game(game, width, height)
+                    .and_then(|mut video| {
+                        video.set_colorimetry(self.colorimetry)?;
+                        video.set_chroma(self.chroma)?;
+                        Ok(video)
+                    })
+                    .map_err(|e| e.on_device(game.process.as_str()))
+            }
+        }
+    }
+
+    // The live scene's overlay and privacy regions, or an idle scene's
+    fn scene_layers(
+        &mut self,
+        scene: Option<&str>,
+    ) -> napi::Result<(&mut Overlay, &mut PrivacyRegions)> {
+        match scene {
+            Some(name) if name != self.scenes.active() => {
+                let scene = self.scenes.get_mut(name).ok_or_else(|| {
+                    napi::Error::new(napi::Status::InvalidArg, format!("Unknown scene: {}", name))
+                })?;
+                Ok((&mut scene.overlay, &mut scene.privacy))
+            }
+            _ => Ok((&mut self.overlay, &mut self.privacy)),
+        }
+    }
+
+    // Swaps the scene in under the state lock. While the stream runs, the
+    // capture stage switches captures on its next tick, and only then parks
+    // the outgoing capture with its scene, still open, and starts the fade
+    // from its last frame.
+    fn switch_scene(&mut self, name: &str, crossfade: Option<Duration>) -> Result<bool> {
+        if name == self.scenes.active() {
+            return Ok(false);
+        }
+        if self.running && !self.video_capture.is_some() {
+            return Err(error::SlumpError::Init(
+                "The stream has no video track to switch".into(),
+            ));
+        }
+        let Some(mut scene) = self.scenes.take(name) else {
+            return Err(error::SlumpError::Init(format!("Unknown scene: {}", name)));
+        };
+
+        // Parked while stopped, or sized for an earlier adaptive target
+        let target = self.adaptive.target();
+        let opened = match scene.capture.take() {
+            Some(mut capture) => capture
+                .set_output_size(target.width, target.height)
+                .map(|()| Some(capture)),
+            None if self.running => self
+                .open_source(
+                    scene.video_source,
+                    scene.display_index,
+                    scene.capture_cursor,
+                    target.width,
+                    target.height,
                 )
+                .map(Some),
+            None => Ok(None),
+        };
+        let capture = match opened {
+            Ok(capture) => capture,
+            Err(e) => {
+                self.scenes.add(name.to_string(), scene)?;
+                return Err(e);
+            }
+        };
+
+        self.note_video_capture(capture.as_ref());
+        let previous_name = self.scenes.active().to_string();
+        let previous = Scene {
+            video_source: std::mem::replace(&mut self.video_source, scene.video_source),
+            display_index: std::mem::replace(&mut self.display_index, scene.display_index),
+            capture_cursor: std::mem::replace(&mut self.capture_cursor, scene.capture_cursor),
+            capture: None,
+            overlay: std::mem::replace(&mut self.overlay, scene.overlay),
+            privacy: std::mem::replace(&mut self.privacy, scene.privacy),
+        };
+        self.scenes.park(previous, name.to_string());
+
+        let crossfade = crossfade.filter(|duration| !duration.is_zero());
+        if let Some(slot) = self.video_capture.local() {
+            // Stopped, so there is nothing to fade from
+            if let Some(outgoing) = std::mem::replace(slot, capture) {
+                self.scenes.restore(&previous_name, outgoing);
+            }
+            self.crossfade = None;
+            self.scene_cut = true;
+        } else {
+            self.video_capture.swap(capture, move |outgoing, stream| {
+                let Some(outgoing) = outgoing else {
+                    return;
+                };
+                // Fades from what viewers saw last, privacy regions and all
+                let fade_from = crossfade.and_then(|duration| {
+                    let scene = stream.scenes.get_mut(&previous_name)?;
+                    let mut from = outgoing.get_last_frame()?.clone();
+                    scene.privacy.apply(&mut from, outgoing.source_size());
+                    scene.overlay.draw(&mut from);
+                    Some(Arc::new(Crossfade::new(from, duration)))
+                });
+                stream.scene_cut = fade_from.is_none();
+                stream.crossfade = fade_from;
+                stream.scenes.restore(&previous_name, outgoing);
+            });
+        }
+        log::info!("Switched to scene {}", name);
+        Ok(true)
+    }
+
+    fn open_display(
+        &self,
+        display_index: usize,
+        width: u32,
+        height: u32,
+        capture_cursor: bool,
+    ) -> Result<VideoCapture> {
+        self.substitutes
+            .video
+            .open(
+                "Display capture",
+                || {
+                    VideoCapture::new(display_index, width, height, capture_cursor).map_err(|e| {
+                        permissions::explain(e, Permission::ScreenRecording)
+                            .on_device(format!("display {}", display_index))
+                    })
+                },
+                || VideoCapture::synthetic(width, height, self.adaptive.target().fps),
+            )
+            .and_then(|mut video| {
+                video.set_colorimetry(self.colorimetry)?;
+                video.set_chroma(self.chroma)?;
+                Ok(video)
             })
-        })??;
+    }
 
-    stream.transport = Some(transport);
-    stream.running = true;
+    // A camera substituted with "always" needs no device name
+    fn open_camera(&self) -> napi::Result<VideoCapture> {
+        let device = match (&self.camera_device, self.substitutes.camera) {
+            (Some(device), _) => device.clone(),
+            (None, Substitute::Always) => String::new(),
+            (None, _) => {
+                return Err(napi::Error::new(
+                    napi::Status::InvalidArg,
+                    "No camera device configured".to_string(),
+                ))
+            }
+        };
+        self.substitutes
+            .camera
+            .open(
+                "Camera capture",
+                || {
+                    VideoCapture::new_camera(&device, CAMERA_WIDTH, CAMERA_HEIGHT)
+                        .map_err(|e| permissions::explain(e, Permission::Camera).on_device(&device))
+                },
+                || VideoCapture::synthetic(CAMERA_WIDTH, CAMERA_HEIGHT, CAMERA_FPS),
+            )
+            .and_then(|mut camera| {
+                camera.set_colorimetry(self.colorimetry)?;
+                Ok(camera)
+            })
+            .map_err(|e| operation_error("initialize camera capture", e))
+    }
 
-    // Start streaming loop in a separate thread
-    let stats_clone = stream.stats.clone();
-    let on_event_ts: ThreadsafeFunction<StreamEvent> = on_event
-        .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<StreamEvent>| {
-            Ok(vec![ctx.value])
-        })?;
+    // Tracks to negotiate, matching whichever captures are active
+    fn track_kinds(&self) -> Vec<TrackKind> {
+        let mut kinds = Vec::new();
+        if self.video_capture.is_some() {
+            kinds.push(TrackKind::Video);
+        }
+        if self.camera_capture.is_some() {
+            kinds.push(TrackKind::Camera);
+        }
+        if self.audio_capture.is_some() {
+            kinds.push(TrackKind::Audio);
+        }
+        kinds
+    }
 
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async {
-            let mut video_interval = tokio::time::interval(Duration::from_millis(1000 / fps as u64));
-            let mut stats_interval = tokio::time::interval(Duration::from_secs(1));
-            let mut last_stats_time = Instant::now();
-            let mut last_video_bytes = 0;
-            let mut last_audio_bytes = 0;
-
-            loop {
-                tokio::select! {
-                    _ = video_interval.tick() => {
-                        // Capture and send video frame
-                        if let (Some(video), Some(transport)) = 
-                            (unsafe { STREAM.as_mut() }.and_then(|s| s.video_capture.as_mut()), 
-                             unsafe { STREAM.as_mut() }.and_then(|s| s.transport.as_mut())) 
-                        {
-                            if let Ok(Some(frame)) = video.capture_frame() {
-                                if let Err(e) = transport.send_video_frame(&frame, 0).await {
-                                    log::error!("Failed to send video frame: {}", e);
-                                }
-                                let mut stats = stats_clone.lock().unwrap();
-                                stats.video_frames_sent += 1;
-                                stats.video_bitrate = (frame.len() as f64 * 8.0 * fps as f64) / 1000.0;
-                            }
-                        }
-                    }
-                    _ = stats_interval.tick() => {
-                        // Update and emit stats
-                        let now = Instant::now();
-                        let elapsed = now.duration_since(last_stats_time).as_secs_f64();
-                        last_stats_time = now;
-
-                        let stats = stats_clone.lock().unwrap();
-                        let video_kbps = stats.video_bitrate;
-                        let audio_kbps = stats.audio_bitrate;
-                        let rtt = stats.rtt;
-                        let jitter = stats.jitter;
-                        let fps = stats.video_frames_sent as f64 / elapsed;
-                        
-                        let _ = on_event_ts.call_async(StreamEvent::Stats {
-                            video_kbps,
-                            audio_kbps,
-                            rtt,
-                            jitter,
-                            fps,
-                        });
+    // Reopens one stage in place; peers keep their connection throughout. A
+    // transport's fresh tracks come back as swaps, for the caller to apply
+    // once the state is unlocked.
+    fn restart_stage(&mut self, stall: &Stall) -> Result<Vec<TrackSwap>> {
+        let target = self.adaptive.target();
+        match stall {
+            Stall::VideoCapture => {
+                let video = self.open_video(target.width, target.height)?;
+                self.set_video_capture(Some(video));
+            }
+            Stall::AudioCapture => {
+                let audio = self.open_audio(self.audio_device.as_deref())?;
+                self.audio_capture.set(Some(audio));
+            }
+            Stall::VideoEncoder => {
+                self.video_encoder.set(Some(VideoEncoder::new(
+                    target.width,
+                    target.height,
+                    target.fps,
+                    target.bitrate_kbps,
+                )?));
+            }
+            // Fresh local tracks reset the packetizers without renegotiating
+            Stall::Transport { peer_id } => {
+                let mut swaps = Vec::new();
+                if let Some(transport) = self.peers.get(peer_id) {
+                    for kind in [TrackKind::Video, TrackKind::Camera, TrackKind::Audio] {
+                        swaps.extend(transport.track_swap(kind)?);
                     }
-                    else => break,
                 }
+                return Ok(swaps);
             }
-        });
-    });
+        }
+        Ok(Vec::new())
+    }
 
-    Ok(true)
+    // Encode settings for outputs that run their own encoder
+    fn output_video_params(&self) -> VideoParams {
+        let target = self.adaptive.target();
+        VideoParams {
+            width: target.width,
+            height: target.height,
+            fps: target.fps,
+            bitrate_kbps: self.video_bitrate_kbps,
+            colorimetry: self.colorimetry,
+            chroma: self.chroma,
+        }
+    }
+
+    // Encode shared by every encoded output, started with the first one
+    fn shared_encoder(&mut self) -> Result<&SharedEncoder> {
+        let params = self.output_video_params();
+        let with_audio = self.audio_capture.is_some();
+        self.outputs.encoder(params, with_audio)
+    }
+
+    fn presence_changed(&self, viewer_id: String, change: Option<Change>) {
+        let (Some(change), Some(events)) = (change, &self.events) else {
+            return;
+        };
+        let count = self.presence.count() as u32;
+        let event = match change {
+            Change::Joined { name } => StreamEvent::ViewerJoined {
+                viewer_id,
+                name,
+                count,
+            },
+            Change::Left { name, reason } => StreamEvent::ViewerLeft {
+                viewer_id,
+                name,
+                reason: reason.as_str().to_string(),
+                count,
+            },
+        };
+        let _ = events.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+    }
+
+    // Hands what the peers asked for to each track's limiter
+    fn collect_keyframe_requests(&mut self) {
+        for transport in self.peers.values() {
+            for (kind, count) in transport.take_keyframe_requests() {
+                let limiter = match kind {
+                    TrackKind::Video => &mut self.video_keyframes,
+                    TrackKind::Camera => &mut self.camera_keyframes,
+                    TrackKind::Audio => continue,
+                };
+                let folded = limiter.request(count);
+                let mut stats = self.stats.lock().unwrap();
+                let counts = stats.frames.track(kind);
+                counts.keyframe_requests += count as u64;
+                counts.keyframe_requests_folded += folded as u64;
+            }
+        }
+    }
+
+    fn add_output<T: OutputSink>(
+        &mut self,
+        name: &str,
+        open: impl FnOnce(&SharedEncoder) -> Result<T>,
+    ) -> Result<bool> {
+        if !self.running || self.outputs.contains(name) {
+            return Ok(false);
+        }
+        let sink = open(self.shared_encoder()?)?;
+        Ok(self.outputs.insert(name, Box::new(sink)))
+    }
 }
 
+// Audio devices and outputs hold raw FFmpeg/OS handles; every access goes
+// through the mutex, so the state is only ever used by one thread at a time
+unsafe impl Send for StreamState {}
+
+// Handle returned to JS. Each instance owns its devices, peers, outputs and
+// worker thread, and belongs to the JS context that constructed it; every
+// method throws when called from any other.
 #[napi]
-pub fn stop_stream() -> napi::Result<bool> {
-    let stream = unsafe { STREAM.as_mut() }.ok_or_else(|| {
-        napi::Error::new(
-            napi::Status::GenericFailure,
-            "Stream not initialized".to_string(),
-        )
-    })?;
+pub struct SlumpStream {
+    state: Arc<parking_lot::Mutex<StreamState>>,
+    context: ContextId,
+    // Outside the state: its thread takes the state lock on a press, and
+    // dropping it waits for that thread
+    panic_hotkey: parking_lot::Mutex<Option<PanicHotkey>>,
+}
 
-    if !stream.running {
-        return Ok(false);
+// Optional last argument to start, connect_signaling, start_whip and
+// export_clip. cancel() abandons the call if it is still running and rejects
+// its promise with a Cancelled status; afterwards it has no effect.
+#[napi]
+#[derive(Default)]
+pub struct CancelHandle {
+    inner: Cancellation,
+}
+
+#[napi]
+impl CancelHandle {
+    #[napi(constructor)]
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    stream.running = false;
-    stream.video_capture = None;
-    stream.audio_capture = None;
-    stream.transport = None;
+    #[napi]
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
 
-    Ok(true)
+    #[napi]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
 }
 
-#[napi(object)]
-pub struct Stats {
-    pub video_kbps: f64,
-    pub audio_kbps: f64,
-    pub rtt: f64,
-    pub jitter: f64,
-    pub fps: f64,
+fn cancellation(handle: Option<ClassInstance<CancelHandle>>) -> Cancellation {
+    handle
+        .map(|handle| handle.inner.clone())
+        .unwrap_or_default()
 }
 
-#[napi]
-pub fn get_stats() -> napi::Result<Stats> {
-    let stream = unsafe { STREAM.as_ref() }.ok_or_else(|| {
-        napi::Error::new(
+// Cancellation gets its own status so JS can tell it apart from a failure;
+// anything else rejects with the error's code, retryable flag and context
+fn operation_error(action: &str, e: error::SlumpError) -> napi::Error {
+    match e {
+        error::SlumpError::Cancelled => napi::Error::new(napi::Status::Cancelled, e.to_string()),
+        e => task::failure(
             napi::Status::GenericFailure,
-            "Stream not initialized".to_string(),
-        )
-    })?;
+            format!("Failed to {}: {}", action, e),
+            e.info(),
+        ),
+    }
+}
 
-    let stats = stream.stats.lock().unwrap();
-    Ok(Stats {
-        video_kbps: stats.video_bitrate,
-        audio_kbps: stats.audio_bitrate,
-        rtt: stats.rtt,
-        jitter: stats.jitter,
-        fps: 0.0, // Will be updated in the streaming loop
-    })
+// Bad options reject start() and prepare() like any other failure, rather
+// than throwing, so they carry ERR_INVALID_OPTIONS and the fix_options hint
+fn options_error(e: error::SlumpError) -> napi::Error {
+    task::failure(napi::Status::InvalidArg, e.to_string(), e.info())
 }
 
-#[napi]
-pub fn handle_signal(signal: String) -> napi::Result<()> {
-    let stream = unsafe { STREAM.as_mut() }.ok_or_else(|| {
-        napi::Error::new(
-            napi::Status::GenericFailure,
-            "Stream not initialized".to_string(),
-        )
-    })?;
+#[napi(object)]
+pub struct IceServerOptions {
+    // stun:, turn: or turns: URLs sharing the credentials below
+    pub urls: Vec<String>,
+    pub username: Option<String>,
+    pub credential: Option<String>,
+}
 
-    if let Some(transport) = &mut stream.transport {
-        // Forward signaling messages to WebRTC transport
-        // This would be implemented to handle SDP offers/answers and ICE candidates
-        // from the JavaScript side
-    }
+// Every field is optional; new settings get added here rather than as
+// positional arguments to start()
+#[napi(object)]
+pub struct StreamOptions {
+    // Leaving video or audio unset streams whichever is available
+    pub video: Option<bool>,
+    pub audio: Option<bool>,
+    // "screen" (the default), "external", where frames come from
+    // push_video_frame instead of a display, or the experimental "game"
+    pub video_source: Option<String>,
+    pub display_index: Option<u32>,
+    // Experimental, for video_source "game" in Windows builds with the
+    // game-capture feature: the process to capture, by id
+    // or executable name ("game.exe"), and the hook library to load into it.
+    // The hook takes frames from the game's present call (D3D11, D3D12,
+    // Vulkan or OpenGL) before composition, so exclusive fullscreen games
+    // that display capture shows black come through, with a frame less
+    // latency. It ships separately, has to match the game's bitness, and
+    // games with anti-cheat may refuse it or ban for it.
+    pub game_process: Option<String>,
+    pub game_hook: Option<String>,
+    pub audio_device: Option<String>,
+    // A second input mixed under the microphone, normally a loopback of
+    // system or game audio: "Stereo Mix" or a virtual cable on Windows,
+    // BlackHole on macOS, a monitor source such as
+    // "alsa_output.pci-0000_00_1f.3.analog-stereo.monitor" with PulseAudio.
+    // Streams go on with the microphone alone if it can't be opened.
+    pub system_audio_device: Option<String>,
+    // How system audio makes way for speech on the microphone; see
+    // DuckingOptions for the defaults. Only used with system_audio_device.
+    pub ducking: Option<DuckingOptions>,
+    // Shifts audio against video in recordings and encoded outputs, and for
+    // peers holds back sends of whichever should play later, to undo a fixed
+    // skew, e.g. 80 for a capture card whose audio arrives early; negative
+    // plays audio sooner. Up to 2000 either way.
+    pub audio_offset_ms: Option<i32>,
+    // Draw the mouse pointer into the capture; off by default
+    pub capture_cursor: Option<bool>,
+    // Send the pointer's position and shape to viewers on the control
+    // channel as it changes, so they can draw a local cursor that moves
+    // without video latency. Independent of capture_cursor, which decides
+    // whether it is in the frames too. Display captures on Windows only for
+    // now; elsewhere nothing is sent. Off by default.
+    pub cursor_metadata: Option<bool>,
+    // While Windows shows the secure desktop (UAC prompts, Ctrl+Alt+Del, the
+    // lock screen) display capture goes black. A SecureDesktop event is sent
+    // either way; with this set, viewers get a "waiting for secure desktop"
+    // frame once a second instead. Capturing the secure desktop itself takes
+    // a service running as SYSTEM. Elevated windows on the normal desktop
+    // can be captured once the host, or a capture helper it launches, has
+    // uiAccess="true" in its manifest, which Windows only honours for signed
+    // executables installed under Program Files. Off by default.
+    pub secure_desktop_placeholder: Option<bool>,
+    // What captures are converted to: "bt601" (default), "bt709" or "bt2020",
+    // in "limited" (default) or "full" range. H.264 outputs and recordings
+    // carry it; VP8 can't, and browsers decode it as BT.601 limited.
+    pub color_space: Option<String>,
+    pub color_range: Option<String>,
+    // Chroma subsampling captures run in: "420" (default), "422" or "444".
+    // Keeps small coloured text sharp in H.264 outputs and recordings, at
+    // the cost of players that only decode 4:2:0. VP8 and NDI are 4:2:0
+    // only and get a downsampled copy, so viewers see no change.
+    pub chroma: Option<String>,
+    // Don't encode frames identical to the one before, still sending one a
+    // second; cuts bitrate for mostly static shares. Off by default.
+    pub skip_duplicate_frames: Option<bool>,
+    // While skipping, send a keyframe after this long without a change so
+    // late joiners and lossy receivers recover; 0 disables. Default 5000.
+    pub static_keyframe_interval_ms: Option<u32>,
+    // Keyframe requests from viewers (PLI, FIR) are folded into at most one
+    // keyframe per track this often; 0 answers each. Default 1000.
+    pub keyframe_request_interval_ms: Option<u32>,
+    // Caps on buffered media for long sessions; unset fields keep their defaults
+    pub memory_budget: Option<MemoryBudgetOptions>,
+    // Degrades outgoing packets to test adaptation; for development only
+    pub network_impairment: Option<NetworkImpairmentOptions>,
+    // Generated test sources in place of capture devices, for CI machines
+    // and servers without a display or sound card
+    pub substitute_devices: Option<SubstituteDeviceOptions>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+    pub codec: Option<String>,
+    pub bitrate_kbps: Option<u32>,
+    // Defaults to a public STUN server
+    pub ice_servers: Option<Vec<IceServerOptions>>,
+    pub stats: Option<StatsOptions>,
+    // Restart a capture, encoder or peer that makes no progress for this
+    // long; 0 disables the watchdog
+    pub watchdog_timeout_ms: Option<u32>,
+    // How bandwidth is estimated: "loss" (the default) only backs off on
+    // packet loss; "delay" also backs off as RTT climbs, before loss sets in
+    pub congestion_control: Option<String>,
+    // What the adaptive controller gives up first under pressure, from
+    // "bitrate", "framerate" and "resolution"; knobs left out are kept.
+    // Unset takes bitrate first, then follows the degradation preference.
+    pub adaptation_priority: Option<Vec<String>>,
+}
+
+struct StreamSettings {
+    video: Option<bool>,
+    audio: Option<bool>,
+    video_source: VideoSource,
+    display_index: usize,
+    game: Option<GameTarget>,
+    audio_device: Option<String>,
+    system_audio_device: Option<String>,
+    ducking: DuckSettings,
+    audio_offset_ms: i32,
+    capture_cursor: bool,
+    cursor_metadata: bool,
+    secure_desktop_placeholder: bool,
+    colorimetry: Colorimetry,
+    chroma: Chroma,
+    skip_duplicate_frames: bool,
+    static_keyframe_interval: Option<Duration>,
+    keyframe_request_interval: Duration,
+    budget: MemoryBudget,
+    impairment: Option<ImpairmentSettings>,
+    substitutes: Substitutes,
+    width: u32,
+    height: u32,
+    fps: u32,
+    bitrate_kbps: u32,
+    stun_servers: Vec<String>,
+    turn_servers: Vec<(String, Option<String>, Option<String>)>,
+    stats: StatsSettings,
+    watchdog_timeout: Option<Duration>,
+    congestion: CongestionAlgorithm,
+    adaptation_priority: Vec<Knob>,
+}
 
+// Limits shared by start() and update_stream()
+fn validate_resolution(width: u32, height: u32) -> Result<()> {
+    // YUV 4:2:0 needs even dimensions
+    if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0 {
+        return Err(error::SlumpError::Init(format!(
+            "Resolution must be even and non-zero, got {}x{}",
+            width, height
+        )));
+    }
+    if width > MAX_WIDTH || height > MAX_HEIGHT {
+        return Err(error::SlumpError::Init(format!(
+            "Resolution {}x{} exceeds {}x{}",
+            width, height, MAX_WIDTH, MAX_HEIGHT
+        )));
+    }
     Ok(())
 }
 
-#[napi]
-pub fn set_video_quality(quality: u32) -> napi::Result<()> {
-    let stream = unsafe { STREAM.as_mut() }.ok_or_else(|| {
-        napi::Error::new(
-            napi::Status::GenericFailure,
-            "Stream not initialized".to_string(),
-        )
-    })?;
+fn validate_fps(fps: u32) -> Result<()> {
+    if fps == 0 || fps > MAX_FPS {
+        return Err(error::SlumpError::Init(format!(
+            "fps must be 1-{}, got {}",
+            MAX_FPS, fps
+        )));
+    }
+    Ok(())
+}
 
-    if let Some(video) = &mut stream.video_capture {
-        // Adjust video quality settings
-        // This would be implemented to adjust bitrate, resolution, etc.
+fn validate_audio_offset(ms: i32) -> Result<()> {
+    if ms.unsigned_abs() > MAX_AUDIO_OFFSET_MS.unsigned_abs() {
+        return Err(error::SlumpError::Init(format!(
+            "Audio offset must be within ±{} ms, got {}",
+            MAX_AUDIO_OFFSET_MS, ms
+        )));
     }
+    Ok(())
+}
 
+fn validate_bitrate(bitrate_kbps: u32) -> Result<()> {
+    if !(MIN_BITRATE_KBPS..=MAX_BITRATE_KBPS).contains(&bitrate_kbps) {
+        return Err(error::SlumpError::Init(format!(
+            "Bitrate must be {}-{} kbps, got {}",
+            MIN_BITRATE_KBPS, MAX_BITRATE_KBPS, bitrate_kbps
+        )));
+    }
     Ok(())
 }
 
-#[napi]
-pub fn set_audio_quality(quality: u32) -> napi::Result<()> {
-    let stream = unsafe { STREAM.as_mut() }.ok_or_else(|| {
-        napi::Error::new(
-            napi::Status::GenericFailure,
-            "Stream not initialized".to_string(),
-        )
-    })?;
+impl StreamSettings {
+    fn prepare_key(&self) -> PrepareKey {
+        PrepareKey {
+            video: self.video,
+            audio: self.audio,
+            video_source: self.video_source,
+            display_index: self.display_index,
+            game: self.game.clone(),
+            capture_cursor: self.capture_cursor,
+            colorimetry: self.colorimetry,
+            chroma: self.chroma,
+            audio_device: self.audio_device.clone(),
+            audio_buffer: self.budget.audio_buffer,
+            substitutes: self.substitutes,
+            width: self.width,
+            height: self.height,
+            fps: self.fps,
+            bitrate_kbps: self.bitrate_kbps,
+        }
+    }
 
-    if let Some(audio) = &mut stream.audio_capture {
-        // Adjust audio quality settings
+    // For diagnostics bundles; TURN credentials are left out
+    fn describe(&self) -> serde_json::Value {
+        serde_json::json!({
+            "video": self.video,
+            "audio": self.audio,
+            "video_source": format!("{:?}", self.video_source),
+            "display_index": self.display_index,
+            "game_process": self.game.as_ref().map(|game| &game.process),
+            "game_hook": self.game.as_ref().map(|game| game.hook.display().to_string()),
+            "audio_device": self.audio_device,
+            "system_audio_device": self.system_audio_device,
+            "ducking": format!("{:?}", self.ducking),
+            "audio_offset_ms": self.audio_offset_ms,
+            "capture_cursor": self.capture_cursor,
+            "cursor_metadata": self.cursor_metadata,
+            "secure_desktop_placeholder": self.secure_desktop_placeholder,
+            "color_space": self.colorimetry.space.as_str(),
+            "color_range": self.colorimetry.range.as_str(),
+            "chroma": self.chroma.as_str(),
+            "skip_duplicate_frames": self.skip_duplicate_frames,
+            "static_keyframe_interval_ms": self.static_keyframe_interval.map(|interval| interval.as_millis() as u64),
+            "keyframe_request_interval_ms": self.keyframe_request_interval.as_millis() as u64,
+            "memory_budget": format!("{:?}", self.budget),
+            "network_impairment": self.impairment.map(|impairment| format!("{:?}", impairment)),
+            "substitute_devices": format!("{:?}", self.substitutes),
+            "width": self.width,
+            "height": self.height,
+            "fps": self.fps,
+            "bitrate_kbps": self.bitrate_kbps,
+            "stun_servers": self.stun_servers,
+            "turn_servers": self.turn_servers.iter().map(|(url, _, _)| url).collect::<Vec<_>>(),
+            "stats": format!("{:?}", self.stats),
+            "watchdog_timeout_ms": self.watchdog_timeout.map(|timeout| timeout.as_millis() as u64),
+            "congestion_control": self.congestion.as_str(),
+            "adaptation_priority": self.adaptation_priority.iter().map(|knob| knob.as_str()).collect::<Vec<_>>(),
+        })
     }
+}
 
-    Ok(())
+impl StreamOptions {
+    // Checks everything before failing, so the error lists every violation
+    fn into_settings(self) -> Result<StreamSettings> {
+        let mut violations = Violations::default();
+
+        if self.video == Some(false) && self.audio == Some(false) {
+            violations.add("video", "At least one of video or audio must be enabled");
+        }
+
+        let video_source = match &self.video_source {
+            Some(source) => violations
+                .check("video_source", source.parse::<VideoSource>())
+                .unwrap_or_default(),
+            None => VideoSource::default(),
+        };
+        if video_source == VideoSource::External && self.video == Some(false) {
+            violations.add(
+                "video_source",
+                "An external video source needs video enabled",
+            );
+        }
+        let game = match (self.game_process, self.game_hook) {
+            (Some(process), Some(hook)) => Some(GameTarget {
+                process,
+                hook: hook.into(),
+            }),
+            (Some(_), None) => {
+                violations.add("game_hook", "game_process needs a hook library to load");
+                None
+            }
+            (None, Some(_)) => {
+                violations.add("game_process", "game_hook needs a process to load into");
+                None
+            }
+            (None, None) => None,
+        };
+        if video_source == VideoSource::Game {
+            if !cfg!(all(windows, feature = "game-capture")) {
+                violations.add(
+                    "video_source",
+                    "Game capture needs a Windows build with the game-capture feature",
+                );
+            } else if game.is_none() && self.video != Some(false) {
+                violations.add(
+                    "game_process",
+                    "A game video source needs game_process and game_hook",
+                );
+            }
+        }
+
+        let width = self.width.unwrap_or(DEFAULT_WIDTH);
+        let height = self.height.unwrap_or(DEFAULT_HEIGHT);
+        let field = if self.width.is_some() {
+            "width"
+        } else {
+            "height"
+        };
+        violations.check(field, validate_resolution(width, height));
+        let fps = self.fps.unwrap_or(DEFAULT_FPS);
+        violations.check("fps", validate_fps(fps));
+        let bitrate_kbps = self.bitrate_kbps.unwrap_or(DEFAULT_BITRATE_KBPS);
+        violations.check("bitrate_kbps", validate_bitrate(bitrate_kbps));
+        let audio_offset_ms = self.audio_offset_ms.unwrap_or(0);
+        violations.check("audio_offset_ms", validate_audio_offset(audio_offset_ms));
+
+        // Where displays can be listed, the index has to exist and the output
+        // can't be larger than the screen it scales down from
+        let display_index = self.display_index.unwrap_or(0) as usize;
+        if video_source == VideoSource::Screen && self.video != Some(false) {
+            let displays = video::list_displays();
+            match displays.get(display_index) {
+                None if !displays.is_empty() => violations.add(
+                    "display_index",
+                    format!("No display {}; {} connected", display_index, displays.len()),
+                ),
+                Some(display) if width > display.width || height > display.height => violations
+                    .add(
+                        field,
+                        format!(
+                            "{}x{} is larger than display {} ({}x{})",
+                            width, height, display_index, display.width, display.height
+                        ),
+                    ),
+                _ => {}
+            }
+        }
+
+        // Only VP8 is negotiated for now, so anything else is rejected up front
+        let codec = match &self.codec {
+            Some(codec) => violations.check("codec", codec.parse::<VideoCodec>()),
+            None => Some(VideoCodec::default()),
+        };
+        if let Some(codec) =
+            codec.filter(|codec| self.video != Some(false) && !codec.is_available())
+        {
+            violations.add(
+                "codec",
+                format!("This FFmpeg build has no {} encoder", codec.encoder_name()),
+            );
+        }
+
+        let mut stun_servers = Vec::new();
+        let mut turn_servers = Vec::new();
+        match self.ice_servers {
+            Some(ice_servers) => {
+                for server in ice_servers {
+                    for url in server.urls {
+                        if url.starts_with("stun:") || url.starts_with("stuns:") {
+                            stun_servers.push(url);
+                        } else if url.starts_with("turn:") || url.starts_with("turns:") {
+                            turn_servers.push((
+                                url,
+                                server.username.clone(),
+                                server.credential.clone(),
+                            ));
+                        } else {
+                            violations.add(
+                                "ice_servers",
+                                format!("Unsupported ICE server URL: {}", url),
+                            );
+                        }
+                    }
+                }
+            }
+            None => stun_servers.push(DEFAULT_STUN_SERVER.to_string()),
+        }
+
+        let budget = match self.memory_budget {
+            Some(budget) => violations
+                .check("memory_budget", budget.into_budget())
+                .unwrap_or_default(),
+            None => MemoryBudget::default(),
+        };
+        let substitutes = match self.substitute_devices {
+            Some(substitutes) => violations
+                .check("substitute_devices", substitutes.into_substitutes())
+                .unwrap_or_default(),
+            None => Substitutes::default(),
+        };
+        let impairment = self.network_impairment.and_then(|impairment| {
+            violations.check("network_impairment", impairment.into_settings())
+        });
+        let ducking = match self.ducking {
+            Some(ducking) => violations
+                .check("ducking", ducking.into_settings())
+                .unwrap_or_default(),
+            None => DuckSettings::default(),
+        };
+        let stats = match self.stats {
+            Some(stats) => violations
+                .check("stats", stats.into_settings())
+                .unwrap_or_default(),
+            None => StatsSettings::default(),
+        };
+        let congestion = match &self.congestion_control {
+            Some(algorithm) => violations
+                .check(
+                    "congestion_control",
+                    algorithm.parse::<CongestionAlgorithm>(),
+                )
+                .unwrap_or_default(),
+            None => CongestionAlgorithm::default(),
+        };
+        let colorimetry = Colorimetry {
+            space: match &self.color_space {
+                Some(space) => violations
+                    .check("color_space", space.parse::<ColorSpace>())
+                    .unwrap_or_default(),
+                None => ColorSpace::default(),
+            },
+            range: match &self.color_range {
+                Some(range) => violations
+                    .check("color_range", range.parse::<ColorRange>())
+                    .unwrap_or_default(),
+                None => ColorRange::default(),
+            },
+        };
+        let chroma = match &self.chroma {
+            Some(chroma) => violations
+                .check("chroma", chroma.parse::<Chroma>())
+                .unwrap_or_default(),
+            None => Chroma::default(),
+        };
+        let adaptation_priority = match &self.adaptation_priority {
+            Some(priority) => violations
+                .check("adaptation_priority", adaptive::parse_priority(priority))
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+
+        violations.into_result()?;
+        Ok(StreamSettings {
+            video: self.video,
+            audio: self.audio,
+            video_source,
+            display_index,
+            game,
+            audio_device: self.audio_device,
+            system_audio_device: self.system_audio_device,
+            ducking,
+            audio_offset_ms,
+            capture_cursor: self.capture_cursor.unwrap_or(false),
+            cursor_metadata: self.cursor_metadata.unwrap_or(false),
+            secure_desktop_placeholder: self.secure_desktop_placeholder.unwrap_or(false),
+            colorimetry,
+            chroma,
+            skip_duplicate_frames: self.skip_duplicate_frames.unwrap_or(false),
+            static_keyframe_interval: match self
+                .static_keyframe_interval_ms
+                .unwrap_or(DEFAULT_STATIC_KEYFRAME_INTERVAL_MS)
+            {
+                0 => None,
+                ms => Some(Duration::from_millis(ms as u64)),
+            },
+            keyframe_request_interval: Duration::from_millis(
+                self.keyframe_request_interval_ms
+                    .unwrap_or(DEFAULT_KEYFRAME_REQUEST_INTERVAL_MS) as u64,
+            ),
+            budget,
+            impairment,
+            substitutes,
+            width,
+            height,
+            fps,
+            bitrate_kbps,
+            stun_servers,
+            turn_servers,
+            stats,
+            watchdog_timeout: match self
+                .watchdog_timeout_ms
+                .unwrap_or(DEFAULT_WATCHDOG_TIMEOUT_MS)
+            {
+                0 => None,
+                ms => Some(Duration::from_millis(ms as u64)),
+            },
+            congestion,
+            adaptation_priority,
+        })
+    }
+}
+
+#[napi(object)]
+pub struct OptionViolation {
+    // The StreamOptions field at fault, e.g. "width" or "memory_budget"
+    pub field: String,
+    pub message: String,
 }
 
+// Runs start()'s checks without starting anything; empty when the options
+// would be accepted
 #[napi]
-pub fn is_running() -> bool {
-    unsafe { STREAM.as_ref() }
-        .map(|s| s.running)
-        .unwrap_or(false)
+pub fn validate_stream_options(options: StreamOptions) -> Vec<OptionViolation> {
+    let violations = match options.into_settings() {
+        Ok(_) => Vec::new(),
+        Err(error::SlumpError::InvalidOptions(violations)) => violations,
+        Err(e) => vec![validate::Violation {
+            field: String::new(),
+            message: e.to_string(),
+        }],
+    };
+    violations
+        .into_iter()
+        .map(|violation| OptionViolation {
+            field: violation.field,
+            message: violation.message,
+        })
+        .collect()
 }
 
-#[napi(js_name = "StreamEvent")]
-pub enum StreamEvent {
-    Stats {
-        video_kbps: f64,
-        audio_kbps: f64,
-        rtt: f64,
-        jitter: f64,
-        fps: f64,
-    },
-    Error(String),
-    Connected,
-    Disconnected,
-    Warning(String),
+#[napi(object)]
+pub struct DisplayCapability {
+    pub index: u32,
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: Option<u32>,
 }
 
-// FFI-safe wrapper for the stream event
-#[napi]
-impl StreamEvent {
-    #[napi(constructor)]
-    pub fn new() -> Self {
+#[napi(object)]
+pub struct SystemCapabilities {
+    pub video_capture_backends: Vec<String>,
+    pub audio_capture_backends: Vec<String>,
+    pub hardware_encoders: Vec<String>,
+    pub codecs: Vec<String>,
+    // Empty where screens can't be enumerated up front (macOS, Linux)
+    pub displays: Vec<DisplayCapability>,
+    // Largest display and refresh rate, within what StreamOptions accepts
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_fps: u32,
+}
+
+impl From<probe::MachineCapabilities> for SystemCapabilities {
+    fn from(probed: probe::MachineCapabilities) -> Self {
+        let largest = probed
+            .displays
+            .iter()
+            .max_by_key(|display| display.width as u64 * display.height as u64);
+        let max_fps = probed
+            .displays
+            .iter()
+            .filter_map(|display| display.refresh_rate)
+            .max()
+            .map_or(MAX_FPS, |hz| hz.min(MAX_FPS));
+
+        SystemCapabilities {
+            max_width: largest.map_or(MAX_WIDTH, |display| display.width.min(MAX_WIDTH)),
+            max_height: largest.map_or(MAX_HEIGHT, |display| display.height.min(MAX_HEIGHT)),
+            max_fps,
+            video_capture_backends: probed.video_capture_backends,
+            audio_capture_backends: probed.audio_capture_backends,
+            hardware_encoders: probed.hardware_encoders,
+            codecs: probed.codecs,
+            displays: probed
+                .displays
+                .into_iter()
+                .map(|display| DisplayCapability {
+                    index: display.index as u32,
+                    width: display.width,
+                    height: display.height,
+                    refresh_rate: display.refresh_rate,
+                })
+                .collect(),
+        }
+    }
+}
+
+// Test-opens encoders, so it runs off the JS thread like start()
+#[napi]
+pub fn probe_capabilities() -> AsyncTask<Blocking<SystemCapabilities>> {
+    Blocking::spawn(|| {
+        probe::probe()
+            .map(Into::into)
+            .map_err(|e| operation_error("probe capabilities", e))
+    })
+}
+
+#[napi(object)]
+pub struct PermissionReport {
+    // "granted", "denied", "restricted" (blocked by policy), "not_determined"
+    // (never asked) or "unknown" (no such gate on this platform)
+    pub screen_recording: String,
+    pub microphone: String,
+    pub camera: String,
+}
+
+// Where each capture stands with the OS, so an app can walk the user through
+// granting access before start() rather than after it fails. Only macOS gates
+// capture like this; elsewhere everything is "unknown". With `prompt`, the
+// system prompt is shown for anything not granted yet; it returns without
+// waiting for the answer, so check again once the user has responded. Screen
+// Recording reads as "denied" until granted, since macOS doesn't say whether
+// it has asked, and a grant only takes effect once the app restarts.
+#[napi]
+pub fn check_permissions(prompt: Option<bool>) -> PermissionReport {
+    let state = |permission| {
+        let state = permissions::check(permission);
+        if prompt.unwrap_or(false) && state != permissions::PermissionState::Granted {
+            permissions::request(permission);
+        }
+        state.as_str().to_string()
+    };
+    PermissionReport {
+        screen_recording: state(Permission::ScreenRecording),
+        microphone: state(Permission::Microphone),
+        camera: state(Permission::Camera),
+    }
+}
+
+#[napi(object)]
+pub struct BenchmarkOptions {
+    pub display_index: Option<u32>,
+    // Output settings to measure; the same defaults as StreamOptions
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+    pub bitrate_kbps: Option<u32>,
+}
+
+#[napi(object)]
+pub struct BenchmarkReport {
+    // Null when the display could not be opened; capture_error says why
+    pub capture_fps: Option<f64>,
+    pub capture_error: Option<String>,
+    pub source_width: u32,
+    pub source_height: u32,
+    // Frames per second each stage managed flat out
+    pub scale_fps: f64,
+    pub encode_fps: f64,
+    pub encoded_bitrate_kbps: f64,
+    // Settings this machine should sustain, with some headroom
+    pub recommended_width: u32,
+    pub recommended_height: u32,
+    pub recommended_fps: u32,
+    pub recommended_bitrate_kbps: u32,
+}
+
+impl From<benchmark::BenchmarkReport> for BenchmarkReport {
+    fn from(report: benchmark::BenchmarkReport) -> Self {
+        BenchmarkReport {
+            capture_fps: report.capture_fps,
+            capture_error: report.capture_error,
+            source_width: report.source_width,
+            source_height: report.source_height,
+            scale_fps: report.scale_fps,
+            encode_fps: report.encode_fps,
+            encoded_bitrate_kbps: report.encoded_bitrate_kbps,
+            recommended_width: report.recommended_width,
+            recommended_height: report.recommended_height,
+            recommended_fps: report.recommended_fps,
+            recommended_bitrate_kbps: report.recommended_bitrate_kbps,
+        }
+    }
+}
+
+// Measures capture, scaling and encoding on this machine for `seconds`,
+// without sending anything. Competes with a running stream for CPU, so run it
+// before starting one.
+#[napi]
+pub fn run_benchmark(
+    seconds: u32,
+    options: Option<BenchmarkOptions>,
+    cancel: Option<ClassInstance<CancelHandle>>,
+) -> napi::Result<AsyncTask<Blocking<BenchmarkReport>>> {
+    if seconds == 0 || seconds > MAX_BENCHMARK_SECONDS {
+        return Err(napi::Error::new(
+            napi::Status::InvalidArg,
+            format!(
+                "Benchmark length must be 1-{} seconds, got {}",
+                MAX_BENCHMARK_SECONDS, seconds
+            ),
+        ));
+    }
+    let options = options.unwrap_or(BenchmarkOptions {
+        display_index: None,
+        width: None,
+        height: None,
+        fps: None,
+        bitrate_kbps: None,
+    });
+    let settings = benchmark::BenchmarkSettings {
+        display_index: options.display_index.unwrap_or(0) as usize,
+        width: options.width.unwrap_or(DEFAULT_WIDTH),
+        height: options.height.unwrap_or(DEFAULT_HEIGHT),
+        fps: options.fps.unwrap_or(DEFAULT_FPS),
+        bitrate_kbps: options.bitrate_kbps.unwrap_or(DEFAULT_BITRATE_KBPS),
+        min_bitrate_kbps: MIN_BITRATE_KBPS,
+    };
+    validate_resolution(settings.width, settings.height)
+        .and_then(|_| validate_fps(settings.fps))
+        .and_then(|_| validate_bitrate(settings.bitrate_kbps))
+        .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+
+    let cancel = cancellation(cancel);
+    Ok(Blocking::spawn(move || {
+        benchmark::run(Duration::from_secs(seconds as u64), &settings, &cancel)
+            .map(Into::into)
+            .map_err(|e| operation_error("run benchmark", e))
+    }))
+}
+
+#[napi(object)]
+pub struct LoopbackOptions {
+    // Same defaults as StreamOptions
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+    pub bitrate_kbps: Option<u32>,
+}
+
+#[napi(object)]
+pub struct LoopbackReport {
+    pub connect_ms: f64,
+    pub frames_sent: f64,
+    pub frames_received: f64,
+    // As seen by the receiving peer
+    pub fps: f64,
+    pub bitrate_kbps: f64,
+    // From drawing a frame to its last packet arriving
+    pub latency_ms: f64,
+    pub max_latency_ms: f64,
+}
+
+impl From<loopback::LoopbackReport> for LoopbackReport {
+    fn from(report: loopback::LoopbackReport) -> Self {
+        LoopbackReport {
+            connect_ms: report.connect_ms,
+            frames_sent: report.frames_sent as f64,
+            frames_received: report.frames_received as f64,
+            fps: report.fps,
+            bitrate_kbps: report.bitrate_kbps,
+            latency_ms: report.latency_ms,
+            max_latency_ms: report.max_latency_ms,
+        }
+    }
+}
+
+// Streams a test pattern between two peer connections inside this process
+// for `seconds` and reports what arrived: a check that encoding and the
+// WebRTC stack work before going live. Nothing leaves the machine.
+#[napi]
+pub fn run_loopback_test(
+    seconds: u32,
+    options: Option<LoopbackOptions>,
+    cancel: Option<ClassInstance<CancelHandle>>,
+) -> napi::Result<AsyncTask<Blocking<LoopbackReport>>> {
+    if seconds == 0 || seconds > MAX_LOOPBACK_SECONDS {
+        return Err(napi::Error::new(
+            napi::Status::InvalidArg,
+            format!(
+                "Loopback test length must be 1-{} seconds, got {}",
+                MAX_LOOPBACK_SECONDS, seconds
+            ),
+        ));
+    }
+    let options = options.unwrap_or(LoopbackOptions {
+        width: None,
+        height: None,
+        fps: None,
+        bitrate_kbps: None,
+    });
+    let settings = loopback::LoopbackSettings {
+        width: options.width.unwrap_or(DEFAULT_WIDTH),
+        height: options.height.unwrap_or(DEFAULT_HEIGHT),
+        fps: options.fps.unwrap_or(DEFAULT_FPS),
+        bitrate_kbps: options.bitrate_kbps.unwrap_or(DEFAULT_BITRATE_KBPS),
+        rtp_history: MemoryBudget::default().rtp_history,
+    };
+    validate_resolution(settings.width, settings.height)
+        .and_then(|_| validate_fps(settings.fps))
+        .and_then(|_| validate_bitrate(settings.bitrate_kbps))
+        .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+
+    let cancel = cancellation(cancel);
+    Ok(Blocking::spawn(move || {
+        loopback::run(Duration::from_secs(seconds as u64), &settings, &cancel)
+            .map(Into::into)
+            .map_err(|e| operation_error("run loopback test", e))
+    }))
+}
+
+#[napi(object)]
+pub struct LogRecord {
+    // error, warn, info, debug or trace
+    pub level: String,
+    pub target: String,
+    pub message: String,
+    pub timestamp_ms: f64,
+}
+
+#[napi(object)]
+pub struct LogFileOptions {
+    pub max_size_mb: Option<u32>,
+    // Rotated files kept next to the active one
+    pub max_files: Option<u32>,
+}
+
+// off, error, warn, info, debug or trace; info until set. Like the callback
+// and file, it only applies to the calling worker thread or Electron context.
+#[napi]
+pub fn set_log_level(mut env: napi::Env, level: String) -> napi::Result<()> {
+    let context = context::current(&mut env)?;
+    let level = level
+        .parse::<log::LevelFilter>()
+        .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+    logging::set_level(context, level);
+    Ok(())
+}
+
+// Native log records go to `callback` until it is replaced or cleared with
+// null. Each worker thread or Electron context has its own callback, and all
+// of them receive every record at their context's level.
+#[napi]
+pub fn set_log_callback(mut env: napi::Env, callback: Option<JsFunction>) -> napi::Result<()> {
+    let context = context::current(&mut env)?;
+    let Some(callback) = callback else {
+        logging::set_callback(context, None);
+        return Ok(());
+    };
+
+    let mut callback_ts: ThreadsafeFunction<LogRecord> = callback
+        .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<LogRecord>| {
+            Ok(vec![ctx.value])
+        })?;
+    // A log sink shouldn't keep the process alive
+    callback_ts.unref(&env)?;
+
+    logging::set_callback(
+        context,
+        Some(Box::new(move |record| {
+            let _ = callback_ts.call(
+                LogRecord {
+                    level: record.level().as_str().to_lowercase(),
+                    target: record.target().to_string(),
+                    message: record.args().to_string(),
+                    timestamp_ms: logging::timestamp_ms(),
+                },
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        })),
+    );
+    Ok(())
+}
+
+// Writes native logs to a size-rotated file; null stops file logging. Each
+// context has its own; two writing the same path would rotate it under
+// each other.
+#[napi]
+pub fn set_log_file(
+    mut env: napi::Env,
+    path: Option<String>,
+    options: Option<LogFileOptions>,
+) -> napi::Result<()> {
+    let context = context::current(&mut env)?;
+    let Some(path) = path else {
+        logging::set_file(context, None);
+        return Ok(());
+    };
+
+    let options = options.unwrap_or(LogFileOptions {
+        max_size_mb: None,
+        max_files: None,
+    });
+    let max_size_mb = options.max_size_mb.unwrap_or(DEFAULT_LOG_FILE_SIZE_MB);
+    if max_size_mb == 0 {
+        return Err(napi::Error::new(
+            napi::Status::InvalidArg,
+            "Log file size must be at least 1 MB".to_string(),
+        ));
+    }
+
+    let file = logging::RotatingFile::open(
+        &path,
+        max_size_mb as u64 * 1024 * 1024,
+        options.max_files.unwrap_or(DEFAULT_LOG_FILES),
+    )
+    .map_err(|e| {
+        napi::Error::new(
+            napi::Status::GenericFailure,
+            format!("Failed to open log file {}: {}", path, e),
+        )
+    })?;
+    logging::set_file(context, Some(file));
+    Ok(())
+}
+
+// Records per-frame pipeline spans (capture, decode, scale, encode, outputs,
+// send) for `duration_ms` and writes them as a Chrome trace that Perfetto
+// opens; resolves to the number of spans written
+#[napi]
+pub fn record_trace(path: String, duration_ms: u32) -> napi::Result<AsyncTask<Blocking<u32>>> {
+    if duration_ms == 0 || duration_ms > MAX_TRACE_WINDOW_MS {
+        return Err(napi::Error::new(
+            napi::Status::InvalidArg,
+            format!(
+                "Trace window must be 1-{} ms, got {}",
+                MAX_TRACE_WINDOW_MS, duration_ms
+            ),
+        ));
+    }
+
+    Ok(Blocking::spawn(move || {
+        trace::record(&path, Duration::from_millis(duration_ms as u64))
+            .map(|spans| spans as u32)
+            .map_err(|e| operation_error("record trace", e))
+    }))
+}
+
+#[napi]
+impl SlumpStream {
+    #[napi(constructor)]
+    pub fn new(mut env: napi::Env) -> napi::Result<Self> {
+        panic::install_hook();
+        // Keeps recent log lines for export_diagnostics even with no log sink set
+        logging::install();
+        let context = context::current(&mut env)?;
+        let state = Arc::new(parking_lot::Mutex::new(StreamState::default()));
+
+        // A worker thread or Electron context can exit with the stream still
+        // running; its callbacks must be gone before the environment is
+        let weak = Arc::downgrade(&state);
+        env.add_env_cleanup_hook(weak, |weak| {
+            let Some(shared) = weak.upgrade() else {
+                return;
+            };
+            if let Err(e) = stop_stream(&shared) {
+                log::warn!("Failed to stop stream on context teardown: {}", e);
+            }
+            let mut state = shared.lock();
+            state.frame_callback = None;
+            state.prepared = None;
+        })?;
+
+        Ok(Self {
+            state,
+            context,
+            panic_hotkey: parking_lot::Mutex::new(None),
+        })
+    }
+
+    // Opens the capture devices and encoder start() would open with these
+    // options, so permission prompts show now and a following start() with the
+    // same options goes live without waiting on devices. Resolves false while
+    // running; preparing again replaces what was prepared.
+    #[napi]
+    pub fn prepare(
+        &self,
+        mut env: napi::Env,
+        options: StreamOptions,
+        cancel: Option<ClassInstance<CancelHandle>>,
+    ) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let cancel = cancellation(cancel);
+        let settings = match options.into_settings() {
+            Ok(settings) => settings,
+            Err(e) => return Ok(Blocking::spawn(move || Err(options_error(e)))),
+        };
+
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            if stream.running {
+                return Ok(false);
+            }
+            // Release the previous set first; it may hold the same devices
+            stream.prepared = None;
+            let key = settings.prepare_key();
+            stream.video_source = key.video_source;
+            stream.display_index = key.display_index;
+            stream.game = key.game;
+            stream.capture_cursor = key.capture_cursor;
+            stream.substitutes = key.substitutes;
+            stream.colorimetry = key.colorimetry;
+            stream.chroma = key.chroma;
+            stream.budget = settings.budget;
+            // The runtime is built on first use; do that now too
+            runtime::get().map_err(|e| {
+                napi::Error::new(
+                    napi::Status::GenericFailure,
+                    format!("Failed to create runtime: {}", e),
+                )
+            })?;
+
+            // Same fallbacks as start(): a device that was not asked for
+            // explicitly may be missing
+            let mut video_capture = None;
+            if key.video != Some(false) {
+                match stream.open_video(key.width, key.height) {
+                    Ok(video) => video_capture = Some(video),
+                    Err(e) if key.video.is_none() => {
+                        log::warn!("Video capture unavailable: {}", e);
+                    }
+                    Err(e) => return Err(operation_error("prepare video capture", e)),
+                }
+            }
+            cancel
+                .check()
+                .map_err(|e| operation_error("prepare stream", e))?;
+
+            let mut audio_capture = None;
+            if key.audio != Some(false) {
+                match stream.open_audio(key.audio_device.as_deref()) {
+                    Ok(audio) => audio_capture = Some(audio),
+                    Err(e) if key.audio.is_none() => {
+                        log::warn!("Audio capture unavailable: {}", e);
+                    }
+                    Err(e) => return Err(operation_error("prepare audio capture", e)),
+                }
+            }
+            cancel
+                .check()
+                .map_err(|e| operation_error("prepare stream", e))?;
+
+            // Sized the way start() will size it, from the controller's top step
+            stream
+                .adaptive
+                .reset(key.width, key.height, key.fps, key.bitrate_kbps);
+            let target = stream.adaptive.target();
+            let video_encoder = match video_capture {
+                Some(_) => Some(
+                    VideoEncoder::new(target.width, target.height, target.fps, target.bitrate_kbps)
+                        .map_err(|e| operation_error("prepare video encoder", e))?,
+                ),
+                None => None,
+            };
+
+            stream.prepared = Some(Prepared {
+                key,
+                video_capture,
+                video_encoder,
+                audio_capture,
+            });
+            Ok(true)
+        }))
+    }
+
+    // Closes whatever prepare() opened; false if nothing was prepared
+    #[napi]
+    pub fn unprepare(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        Ok(self.state.lock().prepared.take().is_some())
+    }
+
+    #[napi]
+    pub fn start(
+        &self,
+        mut env: napi::Env,
+        options: StreamOptions,
+        on_event: JsFunction,
+        cancel: Option<ClassInstance<CancelHandle>>,
+    ) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let cancel = cancellation(cancel);
+        let settings = match options.into_settings() {
+            Ok(settings) => settings,
+            Err(e) => return Ok(Blocking::spawn(move || Err(options_error(e)))),
+        };
+        let StreamSettings {
+            video: video_enabled,
+            audio: audio_enabled,
+            width,
+            height,
+            fps,
+            bitrate_kbps: bitrate,
+            ..
+        } = settings;
+        let config = settings.describe();
+        let prepare_key = settings.prepare_key();
+
+        // The callback has to be wrapped on the JS thread
+        let on_event_ts: ThreadsafeFunction<StreamEvent> = on_event
+            .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<StreamEvent>| {
+                Ok(vec![ctx.value])
+            })?;
+
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            if stream.running {
+                return Ok(false);
+            }
+            // Device opening and ICE gathering can take seconds, so a cancel is
+            // honoured between stages and releases whatever was opened
+            let abandon = |stream: &mut StreamState| {
+                stream.release_captures();
+                operation_error("start stream", error::SlumpError::Cancelled)
+            };
+            if cancel.is_cancelled() {
+                return Err(abandon(stream));
+            }
+
+            // Initialize video capture. Unless video was explicitly requested, a missing
+            // display falls back to an audio-only stream.
+            stream.set_video_capture(None);
+            stream.video_encoder.set(None);
+            stream.video_bitrate_kbps = bitrate;
+            // Everything below is sized from the controller, which starts at the top
+            stream.adaptive.reset(width, height, fps, bitrate);
+            stream
+                .adaptive
+                .set_priority(settings.adaptation_priority.clone());
+            stream.adaptive.clear_durations();
+            // Separate instances can capture different displays and inputs side by side
+            stream.video_source = settings.video_source;
+            stream.display_index = settings.display_index;
+            stream.game = settings.game;
+            stream.audio_device = settings.audio_device;
+            stream.system_audio_device = settings.system_audio_device;
+            stream.ducker = Ducker::new(settings.ducking);
+            stream.outputs.set_audio_offset(settings.audio_offset_ms);
+            stream.clock = Arc::new(MediaClock::new(settings.audio_offset_ms));
+            stream.capture_cursor = settings.capture_cursor;
+            stream.cursor = settings.cursor_metadata.then(CursorTracker::default);
+            stream.secure_desktop = SecureDesktop::default();
+            stream.secure_desktop_placeholder = settings.secure_desktop_placeholder;
+            stream.budget = settings.budget;
+            stream.impairment = settings.impairment.map(Impairment::new);
+            stream.substitutes = settings.substitutes;
+            stream.colorimetry = settings.colorimetry;
+            stream.chroma = settings.chroma;
+            stream.config = config;
+            stream.stats_history.clear();
+            stream.dedup = settings.skip_duplicate_frames.then(|| {
+                FrameDedup::new(
+                    DUPLICATE_REFRESH_INTERVAL,
+                    settings.static_keyframe_interval,
+                )
+            });
+            stream.video_keyframes = KeyframeLimiter::new(settings.keyframe_request_interval);
+            stream.camera_keyframes = KeyframeLimiter::new(settings.keyframe_request_interval);
+            // Devices from prepare() with other options are closed here, before
+            // the same devices are opened again
+            let mut prepared = match stream.prepared.take() {
+                Some(prepared) if prepared.key == prepare_key => prepared,
+                _ => Prepared {
+                    key: prepare_key,
+                    video_capture: None,
+                    video_encoder: None,
+                    audio_capture: None,
+                },
+            };
+            if video_enabled != Some(false) {
+                let video = match prepared.video_capture.take() {
+                    Some(video) => Ok(video),
+                    None => stream.open_video(width, height),
+                };
+                match video {
+                    Ok(video) => stream.set_video_capture(Some(video)),
+                    Err(e) if video_enabled.is_none() => {
+                        log::warn!("Video capture unavailable, streaming audio only: {}", e);
+                        let _ = on_event_ts.call(
+                            StreamEvent::Warning(format!("Video capture unavailable: {}", e)),
+                            ThreadsafeFunctionCallMode::NonBlocking,
+                        );
+                    }
+                    Err(e) => return Err(operation_error("initialize video capture", e)),
+                }
+            }
+
+            if cancel.is_cancelled() {
+                return Err(abandon(stream));
+            }
+
+            // Initialize audio capture, with the same fallback to video only
+            stream.audio_capture.set(None);
+            if audio_enabled != Some(false) {
+                let audio = match prepared.audio_capture.take() {
+                    Some(audio) => Ok(audio),
+                    None => stream.open_audio(stream.audio_device.as_deref()),
+                };
+                match audio {
+                    Ok(audio) => stream.audio_capture.set(Some(audio)),
+                    Err(e) if audio_enabled.is_none() => {
+                        log::warn!("Audio capture unavailable, streaming video only: {}", e);
+                        let _ = on_event_ts.call(
+                            StreamEvent::Warning(format!("Audio capture unavailable: {}", e)),
+                            ThreadsafeFunctionCallMode::NonBlocking,
+                        );
+                    }
+                    Err(e) => return Err(operation_error("initialize audio capture", e)),
+                }
+            }
+            stream.audio_encoder.set(None);
+            if stream.audio_capture.is_some() {
+                if let Err(e) = stream.open_audio_encoder() {
+                    if audio_enabled.is_some() {
+                        return Err(operation_error("initialize audio encoder", e));
+                    }
+                    log::warn!("Audio encoder unavailable, streaming video only: {}", e);
+                    let _ = on_event_ts.call(
+                        StreamEvent::Warning(format!("Audio encoder unavailable: {}", e)),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                    stream.audio_capture.set(None);
+                }
+            }
+            stream.system_audio.set(None);
+            if let (true, Some(device)) =
+                (stream.audio_capture.is_some(), &stream.system_audio_device)
+            {
+                match AudioCapture::with_device(Some(device)) {
+                    Ok(mut system) => {
+                        system.set_buffer(stream.budget.audio_buffer);
+                        stream.system_audio.set(Some(system));
+                    }
+                    Err(e) => {
+                        log::warn!(
+                            "System audio unavailable, streaming the microphone only: {}",
+                            e
+                        );
+                        let _ = on_event_ts.call(
+                            StreamEvent::Warning(format!(
+                                "System audio unavailable: {}",
+                                e.on_device(device.as_str())
+                            )),
+                            ThreadsafeFunctionCallMode::NonBlocking,
+                        );
+                    }
+                }
+            }
+
+            if cancel.is_cancelled() {
+                return Err(abandon(stream));
+            }
+
+            if stream.video_capture.is_some() {
+                stream
+                    .video_encoder
+                    .set(Some(match prepared.video_encoder.take() {
+                        Some(encoder) => encoder,
+                        None => {
+                            let target = stream.adaptive.target();
+                            VideoEncoder::new(
+                                target.width,
+                                target.height,
+                                target.fps,
+                                target.bitrate_kbps,
+                            )
+                            .map_err(|e| operation_error("initialize video encoder", e))?
+                        }
+                    }));
+            }
+
+            if !stream.video_capture.is_some() && !stream.audio_capture.is_some() {
+                return Err(napi::Error::new(
+                    napi::Status::GenericFailure,
+                    "No capture device available".to_string(),
+                ));
+            }
+            let track_kinds = stream.track_kinds();
+
+            // Initialize WebRTC transport
+            let transport = runtime::get()
+                .map_err(|e| {
+                    napi::Error::new(
+                        napi::Status::GenericFailure,
+                        format!("Failed to create runtime: {}", e),
+                    )
+                })?
+                .block_on(cancel.run(WebRTCTransport::new(
+                    DEFAULT_PEER_ID.to_string(),
+                    settings.stun_servers.clone(),
+                    settings.turn_servers.clone(),
+                    &track_kinds,
+                    stream.budget.rtp_history,
+                    stream.impairment.clone(),
+                )));
+            let transport = match transport {
+                Ok(transport) => transport,
+                Err(error::SlumpError::Cancelled) => return Err(abandon(stream)),
+                Err(e) => return Err(operation_error("create WebRTC transport", e)),
+            };
+
+            stream.peers.clear();
+            stream.peers.insert(DEFAULT_PEER_ID.to_string(), transport);
+            stream.stun_servers = settings.stun_servers;
+            stream.turn_servers = settings.turn_servers;
+            stream.stats_settings = settings.stats;
+            stream.watchdog = settings.watchdog_timeout.map(Watchdog::new);
+            stream.congestion = settings.congestion.controller(stream.video_bitrate_kbps);
+            stream.events = Some(on_event_ts.clone());
+            let (signaling_tx, mut signaling_rx) = mpsc::unbounded_channel();
+            stream.signaling_tx = Some(signaling_tx);
+            let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+            stream.shutdown_tx = Some(shutdown_tx);
+            stream.running = true;
+            stream.failure = None;
+            {
+                let mut stats = stream.stats.lock().unwrap();
+                stats.frames = FrameAccounting::default();
+                stats.totals = Totals::default();
+            }
+            stream.histograms.lock().reset();
+
+            // A full send queue refuses the newest frame rather than evicting one
+            // from the middle, so what is queued still decodes in order and the
+            // keyframe requested on the drop resyncs viewers
+            let (encode_depth, send_depth) = stream.budget.queue_depths();
+            let (encode_tx, encode_rx) =
+                pipeline::bounded("encode", encode_depth, DropPolicy::DropOldest);
+            let (send_tx, send_rx) = pipeline::bounded("send", send_depth, DropPolicy::DropNewest);
+            let (camera_tx, camera_rx) =
+                pipeline::bounded("camera encode", encode_depth, DropPolicy::DropOldest);
+            stream.queues = vec![encode_tx.stats(), send_tx.stats(), camera_tx.stats()];
+            // The stages have these to themselves until they exit; everyone
+            // else goes through Owned
+            let capture_device = stream.video_capture.hand_off();
+            let encoder_device = stream.video_encoder.hand_off();
+            let camera_device = stream.camera_capture.hand_off();
+            let camera_encoder_device = stream.camera_encoder.hand_off();
+            let microphone_device = stream.audio_capture.hand_off();
+            let audio_encoder_device = stream.audio_encoder.hand_off();
+            let system_audio_device = stream.system_audio.hand_off();
+
+            // Start streaming loop in a separate thread
+            let stats_clone = stream.stats.clone();
+            let metrics_clone = stream.metrics.clone();
+            let worker_state = shared.clone();
+            let failure_state = shared.clone();
+
+            let worker = move || {
+                let rt = runtime::get().unwrap();
+                // Capture, encode and send each get a thread, joined only by the
+                // queues, and so do the camera and audio; this loop keeps
+                // signaling and housekeeping
+                let capture_stop = Arc::new(AtomicBool::new(false));
+                let capture_stage = spawn_capture_stage(
+                    worker_state.clone(),
+                    capture_device,
+                    encode_tx,
+                    capture_stop.clone(),
+                    on_event_ts.clone(),
+                );
+                let encode_stage =
+                    spawn_encode_stage(worker_state.clone(), encoder_device, encode_rx, send_tx);
+                let send_stage = spawn_send_stage(
+                    worker_state.clone(),
+                    send_rx,
+                    stats_clone.clone(),
+                    metrics_clone.clone(),
+                );
+                let camera_capture_stage = spawn_camera_capture_stage(
+                    worker_state.clone(),
+                    camera_device,
+                    camera_tx,
+                    capture_stop.clone(),
+                );
+                let camera_encode_stage = spawn_camera_encode_stage(
+                    worker_state.clone(),
+                    camera_encoder_device,
+                    camera_rx,
+                );
+                let audio_stage = spawn_audio_stage(
+                    worker_state.clone(),
+                    AudioStageDevices {
+                        microphone: microphone_device,
+                        system: system_audio_device,
+                        encoder: audio_encoder_device,
+                    },
+                    capture_stop.clone(),
+                    on_event_ts.clone(),
+                );
+                rt.block_on(async {
+                    // Housekeeping (recording, peers, adaptation, watchdog) stays on one-second
+                    // windows; only reporting follows the configurable interval
+                    let mut stats_interval = tokio::time::interval(Duration::from_secs(1));
+                    let mut report_period = DEFAULT_STATS_INTERVAL;
+                    // Not ticking right away, which would report a rate over no time at all
+                    let mut report_interval = tokio::time::interval_at(
+                        tokio::time::Instant::now() + report_period,
+                        report_period,
+                    );
+                    let mut last_stats_time = Instant::now();
+                    let mut last_video_frames = 0;
+                    let mut drop_monitor = DropMonitor::default();
+                    let mut loss_monitor = LossMonitor::default();
+                    let mut connection_scores = ConnectionScores::default();
+                    let mut peer_totals = PeerTotals::default();
+                    let mut rate_window = RateWindow::new(DEFAULT_RATE_WINDOW);
+                    let mut process_cpu = ProcessCpu::new();
+                    let gpu_encoder = GpuEncoder::open();
+                    // Whether the last report already warned that encoding can't keep up
+                    let mut encode_behind = false;
+                    // Peers whose ICE failure was already reported
+                    let mut ice_failed = HashSet::new();
+
+                    loop {
+                        tokio::select! {
+                            _ = &mut shutdown_rx => break,
+                            Some(event) = signaling_rx.recv() => {
+                                handle_signaling_event(&worker_state, event).await;
+                            }
+                            _ = report_interval.tick() => {
+                                let mut guard = worker_state.lock();
+                                let reporting = guard.stats_settings;
+                                let capture_fps = if guard.video_capture.is_some() { guard.capture_status.frame_rate } else { 0.0 };
+                                let target_fps = guard.adaptive.target().fps;
+                                let audio = guard
+                                    .audio_capture
+                                    .is_some()
+                                    .then_some((guard.audio_status.dropped_samples, guard.audio_status.occupancy));
+                                let audio_levels = guard.audio_meter.take_levels();
+                                let audio_clipped = guard.audio_meter.clipped();
+                                let audio_target_kbps = guard.audio_encoder.is_some().then_some(guard.audio_bitrate_kbps);
+                                let timings = guard.timings.clone();
+                                let cpu = guard.cpu.clone();
+                                drop(guard);
+
+                                // set_stats_options can change the period from outside the loop
+                                if reporting.interval != report_period {
+                                    report_period = reporting.interval;
+                                    report_interval = tokio::time::interval_at(
+                                        tokio::time::Instant::now() + report_period,
+                                        report_period,
+                                    );
+                                }
+
+                                let now = Instant::now();
+                                let period = now.duration_since(last_stats_time);
+                                let elapsed = period.as_secs_f64();
+                                last_stats_time = now;
+
+                                let mut stats = stats_clone.lock().unwrap();
+                                // Encoder output, so it holds with no viewers connected
+                                let encoded = stats.frames.video.encoded;
+                                if elapsed > 0.0 {
+                                    stats.fps = encoded.saturating_sub(last_video_frames) as f64 / elapsed;
+                                }
+                                last_video_frames = encoded;
+                                stats.capture_fps = capture_fps;
+                                let totals = Totals {
+                                    frames_captured: stats.frames.video.captured,
+                                    frames_encoded: encoded,
+                                    frames_sent: stats.frames.video.sent,
+                                    ..stats.totals
+                                };
+                                rate_window.set_window(reporting.rate_window);
+                                stats.rates = rate_window.push(now, totals);
+                                stats.capture_ms = timings.capture.take_average_ms();
+                                stats.encode_ms = timings.encode.take_average_ms();
+                                stats.send_ms = timings.send.take_average_ms();
+                                stats.encode_latency_ms = timings.to_encoded.take_average_ms();
+                                stats.send_latency_ms = timings.to_sent.take_average_ms();
+                                // Half the round trip stands in for the one-way network delay
+                                stats.glass_to_glass_ms = (reporting.glass_to_glass && stats.send_latency_ms > 0.0 && stats.rtt > 0.0)
+                                    .then(|| stats.send_latency_ms + stats.rtt / 2.0);
+                                stats.process_cpu_percent = process_cpu.sample();
+                                stats.capture_cpu_percent = cpu.capture.take_percent(period);
+                                stats.encode_cpu_percent = cpu.encode.take_percent(period);
+                                stats.send_cpu_percent = cpu.send.take_percent(period);
+                                stats.gpu_encoder_percent = gpu_encoder.as_ref().and_then(GpuEncoder::utilization);
+                                stats.audio_levels = audio_levels;
+                                stats.audio_clipped = audio_clipped;
+                                stats.audio_target_kbps = audio_target_kbps;
+                                let (audio_dropped, audio_buffer_fill) = audio.unwrap_or_default();
+                                stats.audio_dropped = audio_dropped;
+                                stats.audio_buffer_fill = audio_buffer_fill;
+
+                                // Each frame taking longer than the frame rate leaves means the
+                                // encode queue overflows; once per episode
+                                let frame_budget_ms = 1000.0 / target_fps.max(1) as f64;
+                                let behind = stats.encode_ms > frame_budget_ms;
+                                if behind && !encode_behind {
+                                    let warning = format!(
+                                        "Encoding takes {:.1} ms per frame, more than the {:.1} ms available at {} fps; \
+                                         lower the resolution or frame rate, or use a hardware encoder",
+                                        stats.encode_ms, frame_budget_ms, target_fps
+                                    );
+                                    log::warn!("{}", warning);
+                                    let _ = on_event_ts.call(StreamEvent::Warning(warning), ThreadsafeFunctionCallMode::NonBlocking);
+                                }
+                                encode_behind = behind;
+                                stats.timestamp = now;
+                                if reporting.push {
+                                    let _ = on_event_ts.call(
+                                        StreamEvent::from(stats.report(reporting.groups)),
+                                        ThreadsafeFunctionCallMode::NonBlocking,
+                                    );
+                                }
+                            }
+                            _ = stats_interval.tick() => {
+                                let mut guard = worker_state.lock();
+                                let stream = &mut *guard;
+                                // A stage thread panicked; stop() does the rest
+                                if stream.failure.is_some() {
+                                    break;
+                                }
+                                let reporting = stream.stats_settings;
+
+                                for (viewer_id, change) in stream.presence.expire() {
+                                    stream.presence_changed(viewer_id, Some(change));
+                                }
+
+                                let mut disk_full = false;
+                                let limits = stream.recording_limits;
+                                if let Some(recorder) = stream.outputs.get_mut::<Recorder>(RECORDING_OUTPUT) {
+                                    for segment in recorder.take_completed_segments() {
+                                        let _ = on_event_ts.call(
+                                            StreamEvent::RecordingSegment {
+                                                path: segment.path,
+                                                duration_secs: segment.duration.as_secs_f64(),
+                                                size_bytes: segment.size_bytes as f64,
+                                            },
+                                            ThreadsafeFunctionCallMode::NonBlocking,
+                                        );
+                                    }
+                                    let _ = on_event_ts.call(
+                                        StreamEvent::RecordingProgress {
+                                            path: recorder.path().to_string(),
+                                            duration_secs: recorder.duration().as_secs_f64(),
+                                            size_bytes: recorder.size_bytes() as f64,
+                                            paused: recorder.is_paused(),
+                                        },
+                                        ThreadsafeFunctionCallMode::NonBlocking,
+                                    );
+
+                                    for alert in recorder.check_health(&limits) {
+                                        disk_full |= matches!(alert, RecordingAlert::DiskFull { .. });
+                                        log::warn!("{}", alert);
+                                        let _ = on_event_ts.call(
+                                            StreamEvent::Warning(alert.to_string()),
+                                            ThreadsafeFunctionCallMode::NonBlocking,
+                                        );
+                                    }
+                                }
+
+                                // Out of space: finalize the file while it can still be written, keep streaming
+                                if disk_full {
+                                    if let Some(recorder) = stream.outputs.take::<Recorder>(RECORDING_OUTPUT) {
+                                        match recorder.stop() {
+                                            Ok(summary) => {
+                                                let _ = on_event_ts.call(summary.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                                            }
+                                            Err(e) => log::error!("Failed to finalize recording: {}", e),
+                                        }
+                                    }
+                                }
+
+                                // Peer stats are awaited with the state unlocked; whoever
+                                // left meanwhile is skipped once it is locked again
+                                let probes: Vec<StatsProbe> = stream.peers.values().map(WebRTCTransport::stats_probe).collect();
+                                drop(guard);
+                                let mut refreshed = Vec::with_capacity(probes.len());
+                                for probe in &probes {
+                                    refreshed.push((probe.peer_id().to_string(), probe.refresh().await));
+                                }
+                                let mut guard = worker_state.lock();
+                                let stream = &mut *guard;
+                                if stream.failure.is_some() {
+                                    break;
+                                }
+
+                                // Per-peer stats and quality events
+                                let mut worst_rtt: f64 = 0.0;
+                                let mut worst_jitter: f64 = 0.0;
+                                let mut sent_kbps = SentBitrates::default();
+                                let mut worst_loss: f64 = 0.0;
+                                let mut audio_packets_sent = 0;
+                                let mut totals = stats_clone.lock().unwrap().totals;
+                                peer_totals.retain_peers(|peer_id| stream.peers.contains_key(peer_id));
+                                let mut track_loss: Vec<TrackLossStats> = Vec::new();
+                                loss_monitor.retain_peers(|peer_id| stream.peers.contains_key(peer_id));
+                                connection_scores.retain_peers(|peer_id| stream.peers.contains_key(peer_id));
+                                let mut rtp_history_packets = 0;
+                                let rtp_history_cap = stream.budget.rtp_history as u64 * stream.track_kinds().len() as u64;
+                                ice_failed.retain(|peer_id: &String| stream.peers.contains_key(peer_id));
+                                for transport in stream.peers.values() {
+                                    if transport.ice_failed() && ice_failed.insert(transport.peer_id().to_string()) {
+                                        let e = error::SlumpError::IceFailed("no candidate pair succeeded".into())
+                                            .on_peer(transport.peer_id());
+                                        log::warn!("{}", e);
+                                        let _ = on_event_ts.call(e.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                                    }
+                                }
+                                for (peer_id, refreshed) in refreshed {
+                                    if !stream.peers.contains_key(&peer_id) {
+                                        continue;
+                                    }
+                                    match refreshed {
+                                        Ok(peer_stats) => {
+                                            worst_rtt = worst_rtt.max(peer_stats.rtt);
+                                            worst_jitter = worst_jitter.max(peer_stats.jitter);
+                                            sent_kbps += peer_stats.sent_kbps;
+                                            worst_loss = worst_loss.max(peer_stats.packet_loss / 100.0);
+                                            audio_packets_sent += peer_stats.audio_packets_sent;
+                                            peer_totals.add(
+                                                &mut totals,
+                                                &peer_id,
+                                                peer_stats.sent_bytes.total(),
+                                                peer_stats.packets_sent,
+                                                peer_stats.tracks.iter().map(|track| track.packets_lost.max(0) as u64).sum(),
+                                            );
+                                            for loss in &peer_stats.tracks {
+                                                if let Some(warning) = loss_monitor.check(&peer_id, loss, &reporting) {
+                                                    log::warn!("{}", warning);
+                                                    let _ = on_event_ts.call(StreamEvent::Warning(warning), ThreadsafeFunctionCallMode::NonBlocking);
+                                                }
+                                                // Worst peer per track
+                                                let loss = TrackLossStats::from(loss);
+                                                match track_loss.iter_mut().find(|worst| worst.kind == loss.kind) {
+                                                    Some(worst) => {
+                                                        worst.loss_percent = worst.loss_percent.max(loss.loss_percent);
+                                                        worst.window_loss_percent = worst.window_loss_percent.max(loss.window_loss_percent);
+                                                    }
+                                                    None => track_loss.push(loss),
+                                                }
+                                            }
+                                            rtp_history_packets += peer_stats.packets_sent.min(rtp_history_cap);
+                                            if let Some(watchdog) = stream.watchdog.as_mut() {
+                                                watchdog.peer_progress(&peer_id, peer_stats.packets_sent);
+                                            }
+                                            metrics_clone.lock().unwrap().peer_stats(
+                                                &peer_id,
+                                                peer_stats.bytes_sent,
+                                                peer_stats.packets_sent,
+                                                peer_stats.rtt,
+                                                peer_stats.packet_loss,
+                                            );
+                                            let score = connection_scores.update(
+                                                &peer_id,
+                                                peer_stats.packet_loss,
+                                                peer_stats.rtt,
+                                                peer_stats.jitter,
+                                            );
+                                            if let Some(score) = score.filter(|_| reporting.push && reporting.groups.contains(StatGroups::NETWORK)) {
+                                                let _ = on_event_ts.call(
+                                                    StreamEvent::ConnectionQuality {
+                                                        peer_id: peer_id.clone(),
+                                                        score: score.score,
+                                                        limited_by: score.limited_by.map(|factor| factor.as_str().to_string()),
+                                                    },
+                                                    ThreadsafeFunctionCallMode::NonBlocking,
+                                                );
+                                            }
+                                            if reporting.push && reporting.groups.contains(StatGroups::NETWORK) {
+                                                let _ = on_event_ts.call(
+                                                    StreamEvent::PeerQuality {
+                                                        peer_id: peer_id.clone(),
+                                                        bitrate_kbps: peer_stats.bitrate,
+                                                        packet_loss: peer_stats.packet_loss,
+                                                        rtt: peer_stats.rtt,
+                                                        selected_candidate: peer_stats.selected_candidate,
+                                                    },
+                                                    ThreadsafeFunctionCallMode::NonBlocking,
+                                                );
+                                            }
+                                        }
+                                        Err(e) => {
+                                            log::warn!("Failed to collect stats for {}: {}", peer_id, e);
+                                        }
+                                    }
+                                }
+                                {
+                                    let mut stats = stats_clone.lock().unwrap();
+                                    stats.rtt = worst_rtt;
+                                    stats.jitter = worst_jitter;
+                                    stats.fraction_lost = worst_loss;
+                                    stats.track_loss = track_loss;
+                                    stats.sent_kbps = sent_kbps;
+                                    stats.audio_packets_sent = audio_packets_sent;
+                                    stats.totals = totals;
+                                }
+
+                                // The worst peer decides; media is what the encoders can scale back
+                                let feedback = Feedback {
+                                    loss: worst_loss,
+                                    rtt_ms: worst_rtt,
+                                    sent_kbps: (sent_kbps.video_kbps + sent_kbps.camera_kbps + sent_kbps.audio_kbps)
+                                        / stream.peers.len().max(1) as f64,
+                                };
+                                let estimate = stream.congestion.update(&feedback);
+                                stats_clone.lock().unwrap().available_kbps = estimate.available_kbps;
+
+                                // What the capped buffers hold, and a warning when a cap has been dropping data
+                                let replay = stream.outputs.get_mut::<ReplayBuffer>(REPLAY_OUTPUT).map(|replay| (replay.bytes(), replay.evicted()));
+                                let usage = MemoryUsage {
+                                    buffered_frames: stream.queues.iter().map(|queue| queue.depth()).sum(),
+                                    audio_buffered: if stream.audio_capture.is_some() { stream.audio_status.buffered } else { Duration::ZERO },
+                                    rtp_history_packets,
+                                    replay_bytes: replay.map_or(0, |(bytes, _)| bytes),
+                                };
+                                let sample = {
+                                    let mut stats = stats_clone.lock().unwrap();
+                                    stats.memory = usage;
+                                    serde_json::json!({
+                                        "timestamp_ms": logging::timestamp_ms(),
+                                        "stats": stats.report(StatGroups::all()),
+                                    })
+                                };
+                                if stream.stats_history.len() == STATS_HISTORY_LEN {
+                                    stream.stats_history.pop_front();
+                                }
+                                stream.stats_history.push_back(sample);
+                                let drops = [
+                                    (Resource::Frames, stream.queues.iter().map(|queue| queue.dropped()).sum()),
+                                    (Resource::Audio, if stream.audio_capture.is_some() { stream.audio_status.dropped_samples } else { 0 }),
+                                    (Resource::Replay, replay.map_or(0, |(_, evicted)| evicted)),
+                                ];
+                                for (resource, dropped) in drops {
+                                    if let Some(warning) = drop_monitor.check(resource, dropped) {
+                                        log::warn!("{}", warning);
+                                        let _ = on_event_ts.call(StreamEvent::Warning(warning), ThreadsafeFunctionCallMode::NonBlocking);
+                                    }
+                                }
+                                {
+                                    let mut metrics = metrics_clone.lock().unwrap();
+                                    metrics.retain_peers(|peer_id| stream.peers.contains_key(peer_id));
+                                    metrics.frame_pool_misses = stream.capture_status.pool_misses;
+                                }
+
+                                // Restart whichever stage stopped making progress, leaving the rest running.
+                                // Pushed and game frames arrive whenever the application has one, so a gap is not a stall,
+                                // and nor is the secure desktop, which capture can't see past.
+                                let video_active = stream.video_capture.is_some() && !stream.capture_status.source_paced
+                                    && !stream.secure_desktop.is_active();
+                                let audio_active = stream.audio_capture.is_some();
+                                let stalls = match stream.watchdog.as_mut() {
+                                    Some(watchdog) => {
+                                        watchdog.retain_peers(|peer_id| stream.peers.contains_key(peer_id));
+                                        watchdog.check(video_active, audio_active)
+                                    }
+                                    None => Vec::new(),
+                                };
+                                // A stalled transport gets fresh tracks once the state is unlocked, below
+                                let mut swapping = Vec::new();
+                                for (stall, idle) in stalls {
+                                    log::warn!("Watchdog: {} made no progress for {:.1}s, restarting", stall, idle.as_secs_f64());
+                                    let restarted = match stream.restart_stage(&stall) {
+                                        Ok(swaps) if !swaps.is_empty() => {
+                                            swapping.push((stall, idle, swaps));
+                                            continue;
+                                        }
+                                        Ok(_) => true,
+                                        Err(e) => {
+                                            log::error!("Failed to restart {}: {}", stall, e);
+                                            false
+                                        }
+                                    };
+                                    let _ = on_event_ts.call(
+                                        StreamEvent::PipelineStalled {
+                                            stage: stall.to_string(),
+                                            stalled_secs: idle.as_secs_f64(),
+                                            restarted,
+                                        },
+                                        ThreadsafeFunctionCallMode::NonBlocking,
+                                    );
+                                }
+
+                                // Let the adaptive controller trade bitrate, fps and resolution, and size FEC
+                                let cpu_constrained = stream.video_capture.is_some()
+                                    && !stream.capture_status.source_paced
+                                    && stream.capture_status.frame_rate < stream.adaptive.target().fps as f64 * 0.8;
+
+                                let adaptation = stream.adaptive.update(&estimate, worst_loss, cpu_constrained);
+                                {
+                                    let mut stats = stats_clone.lock().unwrap();
+                                    stats.quality_limitation = stream.adaptive.limitation();
+                                    stats.limitation_durations = stream.adaptive.limitation_durations();
+                                }
+                                if let Some(adaptation) = adaptation {
+                                    let (from, target) = (adaptation.from, adaptation.to);
+                                    log::info!(
+                                        "Adapting video to {}x{}@{} at {} kbps with {}% audio FEC on {} ({:?})",
+                                        target.width,
+                                        target.height,
+                                        target.fps,
+                                        target.bitrate_kbps,
+                                        target.fec_percent,
+                                        adaptation.trigger.as_str(),
+                                        stream.adaptive.preference()
+                                    );
+                                    let capture_fps = if stream.video_capture.is_some() { stream.capture_status.frame_rate } else { 0.0 };
+                                    // Both land on the stage threads before their next frame
+                                    stream.video_capture.change(move |video| {
+                                        if let Err(e) = video.set_output_size(target.width, target.height) {
+                                            log::error!("Failed to rescale video: {}", e);
+                                        }
+                                    });
+                                    if target.bitrate_kbps != from.bitrate_kbps {
+                                        stream.video_encoder.change(move |encoder| {
+                                            if let Err(e) = encoder.set_bitrate(target.bitrate_kbps) {
+                                                log::error!("Failed to retarget video encoder: {}", e);
+                                            }
+                                        });
+                                    }
+                                    if target.fec_percent != from.fec_percent {
+                                        stream.audio_encoder.change(move |encoder| {
+                                            if let Err(e) = encoder.set_fec(target.fec_percent) {
+                                                log::error!("Failed to set audio FEC: {}", e);
+                                            }
+                                        });
+                                    }
+                                    let _ = on_event_ts.call(
+                                        StreamEvent::Adaptation {
+                                            trigger: adaptation.trigger.as_str().to_string(),
+                                            old_width: from.width,
+                                            old_height: from.height,
+                                            old_fps: from.fps,
+                                            new_width: target.width,
+                                            new_height: target.height,
+                                            new_fps: target.fps,
+                                            old_bitrate_kbps: from.bitrate_kbps,
+                                            bitrate_kbps: target.bitrate_kbps,
+                                            fec_percent: target.fec_percent,
+                                            packet_loss: worst_loss * 100.0,
+                                            rtt: worst_rtt,
+                                            capture_fps,
+                                            sent_kbps: sent_kbps.video_kbps,
+                                        },
+                                        ThreadsafeFunctionCallMode::NonBlocking,
+                                    );
+                                }
+
+                                if swapping.is_empty() {
+                                    continue;
+                                }
+                                drop(guard);
+                                for (stall, idle, swaps) in swapping {
+                                    let mut restarted = true;
+                                    let mut applied = Vec::new();
+                                    for swap in swaps {
+                                        match swap.apply().await {
+                                            Ok(()) => applied.push(swap),
+                                            Err(e) => {
+                                                log::error!("Failed to restart {}: {}", stall, e);
+                                                restarted = false;
+                                            }
+                                        }
+                                    }
+                                    let mut stream = worker_state.lock();
+                                    for swap in applied {
+                                        if let Some(transport) = stream.peers.get_mut(swap.peer_id()) {
+                                            transport.finish_swap(swap);
+                                        }
+                                    }
+                                    drop(stream);
+                                    let _ = on_event_ts.call(
+                                        StreamEvent::PipelineStalled {
+                                            stage: stall.to_string(),
+                                            stalled_secs: idle.as_secs_f64(),
+                                            restarted,
+                                        },
+                                        ThreadsafeFunctionCallMode::NonBlocking,
+                                    );
+                                }
+                            }
+                            else => break,
+                        }
+                    }
+                });
+
+                // Once capture stops, its queue closes and the encode and send
+                // stages drain what is queued and exit in turn
+                capture_stop.store(true, Ordering::SeqCst);
+                for (name, stage) in [
+                    ("Capture", capture_stage),
+                    ("Encode", encode_stage),
+                    ("Send", send_stage),
+                    ("Camera capture", camera_capture_stage),
+                    ("Camera encode", camera_encode_stage),
+                    ("Audio", audio_stage),
+                ] {
+                    if stage.join().is_err() {
+                        log::error!("{} stage panicked", name);
+                    }
+                }
+            };
+            // A panic anywhere in the loop reaches JS instead of silently ending the thread
+            stream.worker = Some(std::thread::spawn(move || {
+                if let Err(report) = panic::catch(worker) {
+                    fail_stream(&failure_state, "control", report);
+                }
+            }));
+
+            Ok(true)
+        }))
+    }
+
+    #[napi]
+    pub fn stop(&self, mut env: napi::Env) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || stop_stream(&shared)))
+    }
+}
+
+// Only the fields that are set are applied
+#[napi(object)]
+pub struct StreamUpdate {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+    pub bitrate_kbps: Option<u32>,
+    pub capture_cursor: Option<bool>,
+    pub audio_device: Option<String>,
+}
+
+#[napi(object)]
+pub struct StreamUpdateResult {
+    // Settings that changed
+    pub applied: Vec<String>,
+    // Components that had to be reopened rather than adjusted in place
+    pub recreated: Vec<String>,
+}
+
+#[napi]
+impl SlumpStream {
+    // Applies a patch to the running stream. Resolution and bitrate are
+    // adjusted in place, by the capture and encode stages before their next
+    // frame, so a failure there is logged rather than returned; fps reopens
+    // the encoder, cursor capture reopens the display grab and a new audio
+    // device reopens audio capture.
+    #[napi]
+    pub fn update_stream(
+        &self,
+        mut env: napi::Env,
+        patch: StreamUpdate,
+    ) -> napi::Result<AsyncTask<Blocking<StreamUpdateResult>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            if !stream.running {
+                return Err(napi::Error::new(
+                    napi::Status::GenericFailure,
+                    "Stream is not running".to_string(),
+                ));
+            }
+
+            let invalid =
+                |e: error::SlumpError| napi::Error::new(napi::Status::InvalidArg, e.to_string());
+            let current = stream.adaptive.max();
+            let width = patch.width.unwrap_or(current.width);
+            let height = patch.height.unwrap_or(current.height);
+            let fps = patch.fps.unwrap_or(current.fps);
+            let bitrate_kbps = patch.bitrate_kbps.unwrap_or(stream.video_bitrate_kbps);
+            validate_resolution(width, height).map_err(invalid)?;
+            validate_fps(fps).map_err(invalid)?;
+            validate_bitrate(bitrate_kbps).map_err(invalid)?;
+
+            let mut result = StreamUpdateResult {
+                applied: Vec::new(),
+                recreated: Vec::new(),
+            };
+            let resize = (width, height) != (current.width, current.height);
+            let refps = fps != current.fps;
+            let rebitrate = bitrate_kbps != stream.video_bitrate_kbps;
+            let cursor = patch
+                .capture_cursor
+                .filter(|&cursor| cursor != stream.capture_cursor);
+            let audio_device = patch
+                .audio_device
+                .filter(|device| stream.audio_device.as_ref() != Some(device));
+            // A new ceiling restarts adaptation from the top
+            let target = if resize || refps {
+                AdaptiveTarget {
+                    fps,
+                    width,
+                    height,
+                    bitrate_kbps,
+                    fec_percent: 0,
+                }
+            } else {
+                stream.adaptive.target()
+            };
+
+            // Open everything that can fail before touching the running stream,
+            // so a bad device leaves the old setup in place
+            let video = match cursor {
+                Some(cursor)
+                    if stream.video_capture.is_some()
+                        && stream.video_source == VideoSource::Screen =>
+                {
+                    Some(
+                        stream
+                            .open_display(stream.display_index, target.width, target.height, cursor)
+                            .map_err(|e| operation_error("initialize video capture", e))?,
+                    )
+                }
+                _ => None,
+            };
+            let encoder = if refps && stream.video_encoder.is_some() {
+                Some(
+                    VideoEncoder::new(target.width, target.height, target.fps, target.bitrate_kbps)
+                        .map_err(|e| operation_error("initialize video encoder", e))?,
+                )
+            } else {
+                None
+            };
+            let audio = match &audio_device {
+                Some(device) if stream.audio_capture.is_some() => Some(
+                    stream
+                        .open_audio(Some(device))
+                        .map_err(|e| operation_error("initialize audio capture", e))?,
+                ),
+                _ => None,
+            };
+
+            if resize || refps {
+                // The new size and rate become the ceiling the adaptive controller works down from
+                stream.adaptive.reset(width, height, fps, bitrate_kbps);
+                // Loss protection starts over with the rest
+                stream.audio_encoder.change(|encoder| {
+                    if let Err(e) = encoder.set_fec(0) {
+                        log::error!("Failed to set audio FEC: {}", e);
+                    }
+                });
+                if resize {
+                    stream.video_capture.change(move |video| {
+                        if let Err(e) = video.set_output_size(target.width, target.height) {
+                            log::error!("Failed to rescale video: {}", e);
+                        }
+                    });
+                    if stream.placeholder.is_some() {
+                        stream.placeholder =
+                            Some(video::placeholder_frame(target.width, target.height));
+                    }
+                    result.applied.push("resolution".to_string());
+                }
+                if refps {
+                    result.applied.push("fps".to_string());
+                }
+            }
+
+            if let Some(encoder) = encoder {
+                stream.video_encoder.set(Some(encoder));
+                result.recreated.push("video_encoder".to_string());
+            } else if rebitrate {
+                // Whatever step the controller is on, now taken from the new ceiling
+                stream.adaptive.set_max_bitrate(bitrate_kbps);
+                let target_kbps = stream.adaptive.target().bitrate_kbps;
+                stream.video_encoder.change(move |encoder| {
+                    if let Err(e) = encoder.set_bitrate(target_kbps) {
+                        log::error!("Failed to set video bitrate: {}", e);
+                    }
+                });
+            }
+            if rebitrate {
+                stream.video_bitrate_kbps = bitrate_kbps;
+                stream.congestion.reset(bitrate_kbps);
+                result.applied.push("bitrate".to_string());
+            }
+
+            if let Some(cursor) = cursor {
+                stream.capture_cursor = cursor;
+                if let Some(video) = video {
+                    stream.set_video_capture(Some(video));
+                    result.recreated.push("video_capture".to_string());
+                }
+                result.applied.push("capture_cursor".to_string());
+            }
+
+            if let Some(device) = audio_device {
+                stream.audio_device = Some(device);
+                if let Some(audio) = audio {
+                    stream.audio_capture.set(Some(audio));
+                    result.recreated.push("audio_capture".to_string());
+                }
+                result.applied.push("audio_device".to_string());
+            }
+
+            Ok(result)
+        }))
+    }
+}
+
+#[napi(object)]
+pub struct MemoryBudgetOptions {
+    // Raw and encoded frames queued between capture, encode and send; default 10
+    pub max_buffered_frames: Option<u32>,
+    // Captured audio waiting to be sent; default 1000
+    pub audio_buffer_ms: Option<u32>,
+    // Sent packets per track kept to answer retransmission requests; a power
+    // of two, default 1024
+    pub rtp_history_packets: Option<u32>,
+    // Cuts the replay buffer short of its window; unbounded by default
+    pub replay_buffer_mb: Option<u32>,
+}
+
+impl MemoryBudgetOptions {
+    fn into_budget(self) -> Result<MemoryBudget> {
+        let defaults = MemoryBudget::default();
+        let budget = MemoryBudget {
+            buffered_frames: self
+                .max_buffered_frames
+                .map_or(defaults.buffered_frames, |frames| frames as usize),
+            audio_buffer: self
+                .audio_buffer_ms
+                .map_or(defaults.audio_buffer, |ms| Duration::from_millis(ms as u64)),
+            rtp_history: match self.rtp_history_packets {
+                Some(packets) => u16::try_from(packets).map_err(|_| {
+                    error::SlumpError::Init(format!("RTP history {} is too large", packets))
+                })?,
+                None => defaults.rtp_history,
+            },
+            replay_bytes: self.replay_buffer_mb.map(|mb| mb as usize * 1024 * 1024),
+        };
+        budget.validate()?;
+        Ok(budget)
+    }
+}
+
+#[napi(object)]
+pub struct SubstituteDeviceOptions {
+    // Each "never" (the default), "missing" to fall back when the device
+    // can't be opened, or "always"
+    pub video: Option<String>,
+    pub audio: Option<String>,
+    pub camera: Option<String>,
+}
+
+impl SubstituteDeviceOptions {
+    fn into_substitutes(self) -> Result<Substitutes> {
+        let parse = |mode: Option<String>| -> Result<Substitute> {
+            mode.map_or(Ok(Substitute::default()), |mode| mode.parse())
+        };
+        Ok(Substitutes {
+            video: parse(self.video)?,
+            audio: parse(self.audio)?,
+            camera: parse(self.camera)?,
+        })
+    }
+}
+
+#[napi(object)]
+pub struct NetworkImpairmentOptions {
+    // Share of outgoing packets dropped, 0-100
+    pub loss_percent: Option<f64>,
+    pub latency_ms: Option<u32>,
+    // Random extra delay of up to this much per packet
+    pub jitter_ms: Option<u32>,
+    // Uplink cap shared by all peers; excess queues, then drops
+    pub bandwidth_kbps: Option<u32>,
+}
+
+impl NetworkImpairmentOptions {
+    fn into_settings(self) -> Result<ImpairmentSettings> {
+        let settings = ImpairmentSettings {
+            loss_percent: self.loss_percent.unwrap_or(0.0),
+            latency: Duration::from_millis(self.latency_ms.unwrap_or(0) as u64),
+            jitter: Duration::from_millis(self.jitter_ms.unwrap_or(0) as u64),
+            bandwidth_kbps: self.bandwidth_kbps,
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+}
+
+#[napi(object)]
+pub struct DuckingOptions {
+    // How far system audio drops while there is speech; defaults to 12, 0
+    // turns ducking off
+    pub amount_db: Option<f64>,
+    // Microphone level that counts as speech; defaults to -40
+    pub threshold_dbfs: Option<f64>,
+    // Defaults to 20 and 500
+    pub attack_ms: Option<u32>,
+    pub release_ms: Option<u32>,
+}
+
+impl DuckingOptions {
+    fn into_settings(self) -> Result<DuckSettings> {
+        let defaults = DuckSettings::default();
+        let settings = DuckSettings {
+            amount_db: self.amount_db.unwrap_or(defaults.amount_db),
+            threshold_dbfs: self.threshold_dbfs.unwrap_or(defaults.threshold_dbfs),
+            attack: self
+                .attack_ms
+                .map_or(defaults.attack, |ms| Duration::from_millis(ms as u64)),
+            release: self
+                .release_ms
+                .map_or(defaults.release, |ms| Duration::from_millis(ms as u64)),
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+}
+
+#[napi(object)]
+pub struct StatsOptions {
+    // Defaults to 1000
+    pub interval_ms: Option<u32>,
+    // "push" sends Stats events, "pull" only keeps get_stats current
+    pub mode: Option<String>,
+    // Any of "capture", "encoder", "network", "audio", "memory" and "system";
+    // defaults to all
+    pub groups: Option<Vec<String>>,
+    // A Warning event goes out when a track loses more than this percent of
+    // its packets for `loss_warning_windows` seconds in a row. Defaults to 5
+    // and 3; 0 turns it off.
+    pub loss_warning_percent: Option<f64>,
+    pub loss_warning_windows: Option<u32>,
+    // Adds estimated_glass_to_glass_ms: capture to send plus half the worst
+    // RTT. Off by default, since the viewer's jitter buffer, decode and
+    // display aren't in it.
+    pub estimate_glass_to_glass: Option<bool>,
+    // Span the rates in stats are averaged over. Defaults to 5000.
+    pub rate_window_ms: Option<u32>,
+}
+
+impl StatsOptions {
+    fn into_settings(self) -> Result<StatsSettings> {
+        let defaults = StatsSettings::default();
+        let interval = match self.interval_ms {
+            Some(ms) if ms < MIN_STATS_INTERVAL_MS => {
+                return Err(error::SlumpError::Init(format!(
+                    "Stats interval must be at least {} ms, got {}",
+                    MIN_STATS_INTERVAL_MS, ms
+                )))
+            }
+            Some(ms) => Duration::from_millis(ms as u64),
+            None => defaults.interval,
+        };
+        let push = match self.mode.as_deref() {
+            None | Some("push") => true,
+            Some("pull") => false,
+            Some(other) => {
+                return Err(error::SlumpError::Init(format!(
+                    "Unknown stats mode: {}",
+                    other
+                )))
+            }
+        };
+        let groups = match self.groups {
+            Some(groups) => groups
+                .iter()
+                .map(|group| group.parse::<StatGroups>())
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .fold(StatGroups::empty(), |all, group| all | group),
+            None => defaults.groups,
+        };
+        let rate_window = match self.rate_window_ms {
+            Some(ms) if ms < MIN_STATS_INTERVAL_MS => {
+                return Err(error::SlumpError::Init(format!(
+                    "Rate window must be at least {} ms, got {}",
+                    MIN_STATS_INTERVAL_MS, ms
+                )))
+            }
+            Some(ms) => Duration::from_millis(ms as u64),
+            None => defaults.rate_window,
+        };
+        let loss_warning_percent = self
+            .loss_warning_percent
+            .unwrap_or(defaults.loss_warning_percent);
+        if !(0.0..=100.0).contains(&loss_warning_percent) {
+            return Err(error::SlumpError::Init(format!(
+                "Loss warning threshold must be 0-100%, got {}",
+                loss_warning_percent
+            )));
+        }
+        let loss_warning_windows = self
+            .loss_warning_windows
+            .unwrap_or(defaults.loss_warning_windows);
+        if loss_warning_windows == 0 {
+            return Err(error::SlumpError::Init(
+                "Loss warning windows must be at least 1".into(),
+            ));
+        }
+        Ok(StatsSettings {
+            interval,
+            push,
+            groups,
+            glass_to_glass: self.estimate_glass_to_glass.unwrap_or(false),
+            rate_window,
+            loss_warning_percent,
+            loss_warning_windows,
+        })
+    }
+}
+
+// Groups left out of the stats options are None
+#[napi(object)]
+#[derive(serde::Serialize)]
+pub struct Stats {
+    // capture
+    pub capture_fps: Option<f64>,
+    // Average time per frame spent in each pipeline stage over the last report
+    // interval, in milliseconds; send covers every peer
+    pub capture_ms: Option<f64>,
+    // encoder
+    pub fps: Option<f64>,
+    // Where each track's frames went since start
+    pub frames: Option<Vec<TrackFrameStats>>,
+    // Sent to all peers together, media only; see sent_kbps for the rest
+    pub video_kbps: Option<f64>,
+    pub encode_ms: Option<f64>,
+    // What keeps the video below its configured size or rate: "none",
+    // "bandwidth", "cpu" or "configuration", and seconds spent in each
+    // since start
+    pub quality_limitation: Option<String>,
+    pub quality_limitation_durations: Option<QualityLimitationDurations>,
+    // network, worst peer, from its RTCP reports
+    pub rtt: Option<f64>,
+    pub jitter: Option<f64>,
+    // 0-1
+    pub fraction_lost: Option<f64>,
+    // What the congestion controller thinks the worst peer's link carries
+    pub available_bitrate_kbps: Option<f64>,
+    // Per track, worst peer
+    pub track_loss: Option<Vec<TrackLossStats>>,
+    pub send_ms: Option<f64>,
+    // Average from capture to the frame leaving the encoder, and to it being
+    // written to every peer, over the last report interval
+    pub encode_latency_ms: Option<f64>,
+    pub send_latency_ms: Option<f64>,
+    // See estimate_glass_to_glass in the stats options
+    pub estimated_glass_to_glass_ms: Option<f64>,
+    // Bytes on the wire to all peers by track, and what retransmissions,
+    // FEC and padding add on top
+    pub sent_kbps: Option<TrackBitrates>,
+    // Per second over the rate window in the stats options
+    pub rates: Option<WindowedRates>,
+    // audio: what the audio track sent, all peers together, against the
+    // Opus bitrate it was set to, per peer; no target without an encoder
+    pub audio_kbps: Option<f64>,
+    pub audio_target_kbps: Option<f64>,
+    // Captured level over the last report interval; None while nothing is
+    // captured
+    pub audio_peak_dbfs: Option<f64>,
+    pub audio_rms_dbfs: Option<f64>,
+    // Since start: samples at full scale, and samples lost to a full ring
+    pub audio_clipped_samples: Option<f64>,
+    pub audio_dropped_samples: Option<f64>,
+    // How full the capture ring is, 0-100
+    pub audio_buffer_percent: Option<f64>,
+    // RTP packets on the audio track, all peers together
+    pub audio_packets_sent: Option<f64>,
+    // memory, what the capped buffers hold now
+    pub buffered_frames: Option<f64>,
+    pub audio_buffered_ms: Option<f64>,
+    pub rtp_history_packets: Option<f64>,
+    pub replay_buffer_bytes: Option<f64>,
+    // system: process CPU as a percent of all cores, each stage thread as a
+    // percent of one, and the GPU's hardware encoder where NVML can tell
+    pub process_cpu_percent: Option<f64>,
+    pub capture_cpu_percent: Option<f64>,
+    pub encode_cpu_percent: Option<f64>,
+    pub send_cpu_percent: Option<f64>,
+    pub gpu_encoder_percent: Option<f64>,
+}
+
+impl From<error::SlumpError> for StreamEvent {
+    fn from(e: error::SlumpError) -> Self {
+        let info = e.info();
+        StreamEvent::Error {
+            message: e.to_string(),
+            code: info.code.to_string(),
+            retryable: info.retryable,
+            device: info.device,
+            peer_id: info.peer_id,
+            hint: info.hint.map(|hint| hint.as_str().to_string()),
+        }
+    }
+}
+
+impl From<Stats> for StreamEvent {
+    fn from(stats: Stats) -> Self {
+        StreamEvent::Stats {
+            capture_fps: stats.capture_fps,
+            capture_ms: stats.capture_ms,
+            fps: stats.fps,
+            frames: stats.frames,
+            video_kbps: stats.video_kbps,
+            encode_ms: stats.encode_ms,
+            quality_limitation: stats.quality_limitation,
+            quality_limitation_durations: stats.quality_limitation_durations,
+            rtt: stats.rtt,
+            jitter: stats.jitter,
+            fraction_lost: stats.fraction_lost,
+            available_bitrate_kbps: stats.available_bitrate_kbps,
+            track_loss: stats.track_loss,
+            send_ms: stats.send_ms,
+            encode_latency_ms: stats.encode_latency_ms,
+            send_latency_ms: stats.send_latency_ms,
+            estimated_glass_to_glass_ms: stats.estimated_glass_to_glass_ms,
+            sent_kbps: stats.sent_kbps,
+            rates: stats.rates,
+            audio_kbps: stats.audio_kbps,
+            audio_target_kbps: stats.audio_target_kbps,
+            audio_peak_dbfs: stats.audio_peak_dbfs,
+            audio_rms_dbfs: stats.audio_rms_dbfs,
+            audio_clipped_samples: stats.audio_clipped_samples,
+            audio_dropped_samples: stats.audio_dropped_samples,
+            audio_buffer_percent: stats.audio_buffer_percent,
+            audio_packets_sent: stats.audio_packets_sent,
+            buffered_frames: stats.buffered_frames,
+            audio_buffered_ms: stats.audio_buffered_ms,
+            rtp_history_packets: stats.rtp_history_packets,
+            replay_buffer_bytes: stats.replay_buffer_bytes,
+            process_cpu_percent: stats.process_cpu_percent,
+            capture_cpu_percent: stats.capture_cpu_percent,
+            encode_cpu_percent: stats.encode_cpu_percent,
+            send_cpu_percent: stats.send_cpu_percent,
+            gpu_encoder_percent: stats.gpu_encoder_percent,
+        }
+    }
+}
+
+#[napi]
+impl SlumpStream {
+    #[napi]
+    pub fn get_stats(&self, mut env: napi::Env) -> napi::Result<Stats> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let state = self.state.lock();
+        let stream = &*state;
+
+        let stats = stream.stats.lock().unwrap();
+        Ok(stats.report(stream.stats_settings.groups))
+    }
+
+    // Depth and drops for each queue between pipeline stages; a queue that
+    // stays full or keeps dropping points at the stage after it
+    #[napi]
+    pub fn get_pipeline_stats(&self, mut env: napi::Env) -> napi::Result<Vec<QueueInfo>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        Ok(self
+            .state
+            .lock()
+            .queues
+            .iter()
+            .map(|queue| QueueInfo {
+                stage: queue.stage.to_string(),
+                policy: queue.policy.name().to_string(),
+                capacity: queue.capacity as u32,
+                depth: queue.depth() as u32,
+                high_water: queue.high_water() as u32,
+                dropped: queue.dropped() as i64,
+            })
+            .collect())
+    }
+
+    // Distributions of capture intervals, encode times and pacing error
+    // since start, or since the last call that passed reset
+    #[napi]
+    pub fn get_histograms(
+        &self,
+        mut env: napi::Env,
+        reset: Option<bool>,
+    ) -> napi::Result<FrameTimingHistograms> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let histograms = self.state.lock().histograms.clone();
+        let mut histograms = histograms.lock();
+        let summaries = FrameTimingHistograms {
+            capture_interval: histograms.capture_interval.summary().into(),
+            encode: histograms.encode.summary().into(),
+            pacing_error: histograms.pacing_error.summary().into(),
+        };
+        if reset.unwrap_or(false) {
+            histograms.reset();
+        }
+        Ok(summaries)
+    }
+
+    // Hands captured frames to `callback` as RawFrame objects, at most max_fps
+    // a second and only while the previous one has been taken; null stops it.
+    // Survives stop and start.
+    #[napi]
+    pub fn set_frame_callback(
+        &self,
+        mut env: napi::Env,
+        callback: Option<JsFunction>,
+        options: Option<FrameCallbackOptions>,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let Some(callback) = callback else {
+            self.state.lock().frame_callback = None;
+            return Ok(());
+        };
+
+        let options = options.unwrap_or(FrameCallbackOptions {
+            max_fps: None,
+            source: None,
+        });
+        let max_fps = options.max_fps.unwrap_or(DEFAULT_FRAME_CALLBACK_FPS);
+        validate_fps(max_fps)
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        let source = match options.source {
+            Some(source) => source
+                .parse::<TapSource>()
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?,
+            None => TapSource::default(),
+        };
+
+        // One frame in flight at most, so a slow consumer sheds frames
+        let mut callback_ts: ThreadsafeFunction<TappedFrame> = callback
+            .create_threadsafe_function(1, |ctx: ThreadSafeCallContext<TappedFrame>| {
+                Ok(vec![RawFrame::new(&ctx.env, ctx.value)?])
+            })?;
+        callback_ts.unref(&env)?;
+
+        self.state.lock().frame_callback = Some(FrameCallback {
+            callback: callback_ts,
+            tap: FrameTap::new(source, max_fps),
+        });
+        Ok(())
+    }
+
+    // Takes effect from the next report
+    #[napi]
+    pub fn set_stats_options(&self, mut env: napi::Env, options: StatsOptions) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let settings = options
+            .into_settings()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        self.state.lock().stats_settings = settings;
+        Ok(())
+    }
+
+    // Changes the simulated network for every peer from the next packet;
+    // only on a stream started with network_impairment
+    #[napi]
+    pub fn set_network_impairment(
+        &self,
+        mut env: napi::Env,
+        options: NetworkImpairmentOptions,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let settings = options
+            .into_settings()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        match &self.state.lock().impairment {
+            Some(impairment) => {
+                impairment.set(settings);
+                Ok(())
+            }
+            None => Err(napi::Error::new(
+                napi::Status::InvalidArg,
+                "Network impairment must be enabled when the stream starts".to_string(),
+            )),
+        }
+    }
+
+    // Changes how system audio ducks under speech from the next audio tick;
+    // unset fields go back to their defaults
+    #[napi]
+    pub fn set_ducking(&self, mut env: napi::Env, options: DuckingOptions) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let settings = options
+            .into_settings()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        self.state.lock().ducker.set(settings);
+        Ok(())
+    }
+
+    // Prometheus text format, for callers that serve metrics themselves
+    #[napi]
+    pub fn get_metrics_text(&self, mut env: napi::Env) -> napi::Result<String> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let metrics = self.state.lock().metrics.clone();
+        let text = metrics.lock().unwrap().render();
+        Ok(text)
+    }
+
+    // Serves the same text over HTTP until stopped or the stream is dropped;
+    // stopping the stream leaves it up. Returns the URL to scrape.
+    #[napi]
+    pub fn start_metrics_server(
+        &self,
+        mut env: napi::Env,
+        port: u32,
+        host: Option<String>,
+    ) -> napi::Result<String> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let port = u16::try_from(port).map_err(|_| {
+            napi::Error::new(
+                napi::Status::InvalidArg,
+                format!("Invalid metrics port: {}", port),
+            )
+        })?;
+        let host = host.unwrap_or_else(|| DEFAULT_METRICS_HOST.to_string());
+
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        // Restarting on a new port replaces the previous server
+        stream.metrics_server = None;
+        let server = MetricsServer::start(&host, port, stream.metrics.clone())
+            .map_err(|e| operation_error("start metrics server", e))?;
+
+        let url = server.url();
+        stream.metrics_server = Some(server);
+        Ok(url)
+    }
+
+    #[napi]
+    pub fn stop_metrics_server(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.metrics_server.take().is_some())
+    }
+
+    #[napi]
+    pub fn add_peer(
+        &self,
+        mut env: napi::Env,
+        peer_id: String,
+    ) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            if !stream.running || stream.peers.contains_key(&peer_id) {
+                return Ok(false);
+            }
+
+            let transport = runtime::get()
+                .map_err(|e| {
+                    napi::Error::new(
+                        napi::Status::GenericFailure,
+                        format!("Failed to create runtime: {}", e),
+                    )
+                })?
+                .block_on(WebRTCTransport::new(
+                    peer_id.clone(),
+                    stream.stun_servers.clone(),
+                    stream.turn_servers.clone(),
+                    &stream.track_kinds(),
+                    stream.budget.rtp_history,
+                    stream.impairment.clone(),
+                ))
+                .map_err(|e| operation_error("create WebRTC transport", e.on_peer(&peer_id)))?;
+
+            stream.peers.insert(peer_id, transport);
+            Ok(true)
+        }))
+    }
+
+    #[napi]
+    pub fn remove_peer(
+        &self,
+        mut env: napi::Env,
+        peer_id: String,
+    ) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            let Some(mut transport) = stream.peers.remove(&peer_id) else {
+                return Ok(false);
+            };
+            stream.input.forget(&peer_id);
+            let left = stream.presence.leave(&peer_id, LeaveReason::Disconnected);
+            stream.presence_changed(peer_id.clone(), left);
+
+            runtime::get()
+                .map_err(|e| {
+                    napi::Error::new(
+                        napi::Status::GenericFailure,
+                        format!("Failed to create runtime: {}", e),
+                    )
+                })?
+                .block_on(transport.close())
+                .map_err(|e| operation_error("close peer", e.on_peer(&peer_id)))?;
+
+            Ok(true)
+        }))
+    }
+
+    // Lets a connected viewer's input through, limited to `kinds` ("move",
+    // "click", "scroll", "keyboard"; all by default) and the input filter.
+    // Replaces any earlier grant.
+    #[napi]
+    pub fn grant_input(
+        &self,
+        mut env: napi::Env,
+        viewer_id: String,
+        kinds: Option<Vec<String>>,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let kinds = match kinds {
+            Some(kinds) => parse_input_kinds(&kinds)?,
+            None => InputKinds::all(),
+        };
+        let mut state = self.state.lock();
+        if !state.peers.contains_key(&viewer_id) {
+            return Err(napi::Error::new(
+                napi::Status::InvalidArg,
+                format!("Unknown viewer: {}", viewer_id),
+            ));
+        }
+        state.input.grant(&viewer_id, kinds);
+        Ok(())
+    }
+
+    // False if the viewer had no grant
+    #[napi]
+    pub fn revoke_input(&self, mut env: napi::Env, viewer_id: String) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        Ok(self.state.lock().input.revoke(&viewer_id))
+    }
+
+    // The panic switch: no input gets through from any viewer after this
+    // returns, and none of them is asked about again. set_panic_hotkey does
+    // the same from a global shortcut. Returns the viewers that lost control.
+    #[napi]
+    pub fn revoke_all_input(&self, mut env: napi::Env) -> napi::Result<Vec<String>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        Ok(revoke_all_input(&self.state))
+    }
+
+    // Registers an OS-wide shortcut such as "Ctrl+Shift+F12" that revokes all
+    // remote input the moment it is pressed, even with the app's window
+    // unfocused or its JS thread busy, and sends InputRevoked. None removes
+    // it. Windows only for now; elsewhere this fails with ERR_NOT_IMPLEMENTED.
+    #[napi]
+    pub fn set_panic_hotkey(
+        &self,
+        mut env: napi::Env,
+        accelerator: Option<String>,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let accelerator = accelerator
+            .map(|accelerator| accelerator.parse::<Accelerator>())
+            .transpose()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+
+        // The old shortcut goes first, so the same one can be registered again
+        let mut panic_hotkey = self.panic_hotkey.lock();
+        panic_hotkey.take();
+        let Some(accelerator) = accelerator else {
+            return Ok(());
+        };
+        let weak = Arc::downgrade(&self.state);
+        let hotkey = PanicHotkey::register(accelerator, move || {
+            let Some(state) = weak.upgrade() else {
+                return;
+            };
+            let revoked = revoke_all_input(&state);
+            if let Some(events) = &state.lock().events {
+                let _ = events.call(
+                    StreamEvent::InputRevoked {
+                        viewer_ids: revoked,
+                    },
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                );
+            }
+        })
+        .map_err(|e| operation_error("register panic hotkey", e))?;
+        *panic_hotkey = Some(hotkey);
+        Ok(())
+    }
+
+    // Viewers that joined over the control channel, sorted by name
+    #[napi]
+    pub fn get_viewers(&self, mut env: napi::Env) -> napi::Result<Vec<ViewerInfo>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        Ok(self
+            .state
+            .lock()
+            .presence
+            .viewers()
+            .into_iter()
+            .map(|(viewer_id, name)| ViewerInfo { viewer_id, name })
+            .collect())
+    }
+
+    // Input kinds any viewer may send, whatever it was granted; all by default
+    #[napi]
+    pub fn set_input_filter(&self, mut env: napi::Env, kinds: Vec<String>) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let kinds = parse_input_kinds(&kinds)?;
+        self.state.lock().input.set_allowed(kinds);
+        Ok(())
+    }
+
+    // Draws into every outgoing frame, for peers and outputs alike, until the
+    // TTL runs out or it is removed. Goes on the live scene unless `scene`
+    // names another. Survives stop and start. Returns an id for
+    // remove_annotation.
+    #[napi]
+    pub fn add_annotation(
+        &self,
+        mut env: napi::Env,
+        options: AnnotationOptions,
+        scene: Option<String>,
+    ) -> napi::Result<u32> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let annotation = options
+            .into_annotation()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        let mut state = self.state.lock();
+        let (overlay, _) = state.scene_layers(scene.as_deref())?;
+        Ok(overlay.add(annotation))
+    }
+
+    // False if it was never there or has already expired
+    #[napi]
+    pub fn remove_annotation(
+        &self,
+        mut env: napi::Env,
+        id: u32,
+        scene: Option<String>,
+    ) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let (overlay, _) = state.scene_layers(scene.as_deref())?;
+        Ok(overlay.remove(id))
+    }
+
+    #[napi]
+    pub fn clear_annotations(&self, mut env: napi::Env, scene: Option<String>) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let (overlay, _) = state.scene_layers(scene.as_deref())?;
+        overlay.clear();
+        Ok(())
+    }
+
+    // Draws the capture time, frame number and bitrate into a corner of every
+    // outgoing frame, so latency and drops can be measured by photographing
+    // both screens at once. `corner` is "top-left" (the default), "top-right",
+    // "bottom-left" or "bottom-right". Survives stop and start.
+    #[napi]
+    pub fn set_burn_in(
+        &self,
+        mut env: napi::Env,
+        enabled: bool,
+        corner: Option<String>,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let corner = match corner {
+            Some(corner) => corner
+                .parse::<Corner>()
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?,
+            None => Corner::default(),
+        };
+        self.state.lock().burn_in = enabled.then_some(corner);
+        Ok(())
+    }
+
+    // Blurs or pixelates an area of the capture in everything that leaves,
+    // from the next frame on. Setting an id again moves or resizes it. Set
+    // on another scene before switching to it, so none of its frames go out
+    // unobscured. Survives stop and start.
+    #[napi]
+    pub fn set_privacy_region(
+        &self,
+        mut env: napi::Env,
+        id: String,
+        options: PrivacyRegionOptions,
+        scene: Option<String>,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let region = options
+            .into_region()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        let mut state = self.state.lock();
+        let (_, privacy) = state.scene_layers(scene.as_deref())?;
+        privacy.set(id, region);
+        Ok(())
+    }
+
+    #[napi]
+    pub fn remove_privacy_region(
+        &self,
+        mut env: napi::Env,
+        id: String,
+        scene: Option<String>,
+    ) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let (_, privacy) = state.scene_layers(scene.as_deref())?;
+        Ok(privacy.remove(&id))
+    }
+
+    #[napi]
+    pub fn clear_privacy_regions(
+        &self,
+        mut env: napi::Env,
+        scene: Option<String>,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let (_, privacy) = state.scene_layers(scene.as_deref())?;
+        privacy.clear();
+        Ok(())
+    }
+
+    // Encodes an area of the capture, such as a code editor pane, at higher
+    // quality than the rest of the frame, from the next frame on. Applies to
+    // whatever scene is live. Setting an id again moves or resizes it.
+    // Survives stop and start.
+    #[napi]
+    pub fn set_quality_region(
+        &self,
+        mut env: napi::Env,
+        id: String,
+        options: QualityRegionOptions,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let region = options
+            .into_region()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        self.state.lock().quality_regions.set(id, region);
+        Ok(())
+    }
+
+    #[napi]
+    pub fn remove_quality_region(&self, mut env: napi::Env, id: String) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        Ok(self.state.lock().quality_regions.remove(&id))
+    }
+
+    #[napi]
+    pub fn clear_quality_regions(&self, mut env: napi::Env) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        self.state.lock().quality_regions.clear();
+        Ok(())
+    }
+
+    // Opens the scene's capture now, so switching to it later waits on
+    // nothing. Replaces an idle scene of the same name.
+    #[napi]
+    pub fn add_scene(
+        &self,
+        mut env: napi::Env,
+        name: String,
+        options: Option<SceneOptions>,
+    ) -> napi::Result<AsyncTask<Blocking<()>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let options = options.unwrap_or_default();
+            let source = match options.source.as_deref() {
+                Some(source) => source
+                    .parse::<VideoSource>()
+                    .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?,
+                None => VideoSource::Screen,
+            };
+            let display_index = options.display_index.unwrap_or(0) as usize;
+            let mut state = shared.lock();
+            let stream = &mut *state;
+            let capture_cursor = options.capture_cursor.unwrap_or(stream.capture_cursor);
+            if name == stream.scenes.active() {
+                return Err(napi::Error::new(
+                    napi::Status::InvalidArg,
+                    format!("Scene {} is live; switch away from it first", name),
+                ));
+            }
+
+            let target = stream.adaptive.target();
+            let capture = stream
+                .open_source(
+                    source,
+                    display_index,
+                    capture_cursor,
+                    target.width,
+                    target.height,
+                )
+                .map_err(|e| operation_error("initialize scene capture", e))?;
+            stream
+                .scenes
+                .add(
+                    name,
+                    Scene {
+                        video_source: source,
+                        display_index,
+                        capture_cursor,
+                        capture: Some(capture),
+                        overlay: Overlay::default(),
+                        privacy: PrivacyRegions::default(),
+                    },
+                )
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))
+        }))
+    }
+
+    // Closes an idle scene's capture; the live scene can't be removed
+    #[napi]
+    pub fn remove_scene(&self, mut env: napi::Env, name: String) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        self.state
+            .lock()
+            .scenes
+            .remove(&name)
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))
+    }
+
+    // Makes the named scene live from the next frame, in place: the encoder,
+    // outputs and peers carry on as they were. Without a crossfade the first
+    // frame is a keyframe. False if it was already live.
+    #[napi]
+    pub fn switch_scene(
+        &self,
+        mut env: napi::Env,
+        name: String,
+        crossfade_ms: Option<u32>,
+    ) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let crossfade = crossfade_ms.map(|ms| Duration::from_millis(ms as u64));
+        self.state
+            .lock()
+            .switch_scene(&name, crossfade)
+            .map_err(|e| operation_error("switch scene", e))
+    }
+
+    #[napi]
+    pub fn get_scenes(&self, mut env: napi::Env) -> napi::Result<SceneList> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let state = self.state.lock();
+        Ok(SceneList {
+            active: state.scenes.active().to_string(),
+            names: state.scenes.names(),
+        })
+    }
+}
+
+#[napi(object)]
+pub struct ViewerInfo {
+    pub viewer_id: String,
+    pub name: String,
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct SceneOptions {
+    // "screen" (default), "external", or "game" to hook the stream's
+    // game_process
+    pub source: Option<String>,
+    // For "screen"; 0 by default
+    pub display_index: Option<u32>,
+    // The stream's setting by default
+    pub capture_cursor: Option<bool>,
+}
+
+#[napi(object)]
+pub struct SceneList {
+    // "default" until the first switch: what the stream was started with
+    pub active: String,
+    pub names: Vec<String>,
+}
+
+#[napi(object)]
+pub struct AnnotationOptions {
+    // "line", "rect" or "text"
+    pub kind: String,
+    // Coordinates run 0-1 across the frame. A line goes from (x, y) to
+    // (x2, y2) and a rect spans them as corners; text starts with its top
+    // left at (x, y).
+    pub x: f64,
+    pub y: f64,
+    pub x2: Option<f64>,
+    pub y2: Option<f64>,
+    pub text: Option<String>,
+    // "#rrggbb" or "#rrggbbaa"; opaque red by default
+    pub color: Option<String>,
+    // Stroke in output pixels, 4 by default
+    pub thickness: Option<u32>,
+    // Rects only
+    pub fill: Option<bool>,
+    // Line height as a share of the frame height, 0.04 by default
+    pub text_size: Option<f64>,
+    // Stays until removed when unset
+    pub ttl_ms: Option<u32>,
+}
+
+impl AnnotationOptions {
+    fn into_annotation(self) -> Result<Annotation> {
+        let point = |x: f64, y: f64| {
+            if (0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y) {
+                Ok((x, y))
+            } else {
+                Err(error::SlumpError::Init(format!(
+                    "Annotation points must be within 0-1, got ({}, {})",
+                    x, y
+                )))
+            }
+        };
+        let from = point(self.x, self.y)?;
+        let to = || match (self.x2, self.y2) {
+            (Some(x2), Some(y2)) => point(x2, y2),
+            _ => Err(error::SlumpError::Init(format!(
+                "A {} annotation needs x2 and y2",
+                self.kind
+            ))),
+        };
+        let shape = match self.kind.as_str() {
+            "line" => Shape::Line { from, to: to()? },
+            "rect" => Shape::Rect {
+                from,
+                to: to()?,
+                fill: self.fill.unwrap_or(false),
+            },
+            "text" => {
+                let size = self.text_size.unwrap_or(DEFAULT_ANNOTATION_TEXT_SIZE);
+                if !(size > 0.0 && size <= 1.0) {
+                    return Err(error::SlumpError::Init(format!(
+                        "Text size must be within 0-1, got {}",
+                        size
+                    )));
+                }
+                Shape::Text {
+                    at: from,
+                    text: self.text.clone().ok_or_else(|| {
+                        error::SlumpError::Init("A text annotation needs text".into())
+                    })?,
+                    size,
+                }
+            }
+            other => {
+                return Err(error::SlumpError::Init(format!(
+                    "Unknown annotation kind: {}",
+                    other
+                )))
+            }
+        };
+        Ok(Annotation {
+            shape,
+            color: match &self.color {
+                Some(color) => Color::parse(color)?,
+                None => Color::RED,
+            },
+            thickness: self.thickness.unwrap_or(DEFAULT_ANNOTATION_THICKNESS),
+            ttl: self.ttl_ms.map(|ms| Duration::from_millis(ms as u64)),
+        })
+    }
+}
+
+#[napi(object)]
+pub struct PrivacyRegionOptions {
+    // In capture pixels: screen coordinates for a display, the source's own
+    // size for anything else
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    // "blur" (default) or "pixelate"
+    pub mode: Option<String>,
+}
+
+impl PrivacyRegionOptions {
+    fn into_region(self) -> Result<Region> {
+        if self.width == 0 || self.height == 0 {
+            return Err(error::SlumpError::Init(format!(
+                "Privacy region must not be empty, got {}x{}",
+                self.width, self.height
+            )));
+        }
+        Ok(Region {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+            mode: match self.mode.as_deref() {
+                Some(mode) => mode.parse()?,
+                None => Obscure::Blur,
+            },
+        })
+    }
+}
+
+#[napi(object)]
+pub struct QualityRegionOptions {
+    // In capture pixels, as for privacy regions
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    // 0-1, how much sharper than the rest of the frame; 1 (the default) is
+    // as close to lossless as the encoder allows. It comes out of the
+    // same bitrate, so the rest of the frame softens to pay for it.
+    pub boost: Option<f64>,
+}
+
+impl QualityRegionOptions {
+    fn into_region(self) -> Result<QualityRegion> {
+        if self.width == 0 || self.height == 0 {
+            return Err(error::SlumpError::Init(format!(
+                "Quality region must not be empty, got {}x{}",
+                self.width, self.height
+            )));
+        }
+        let boost = self.boost.unwrap_or(1.0);
+        if !(boost > 0.0 && boost <= 1.0) {
+            return Err(error::SlumpError::Init(format!(
+                "Quality region boost must be in (0, 1], got {}",
+                boost
+            )));
+        }
+        Ok(QualityRegion {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+            boost,
+        })
+    }
+}
+
+fn revoke_all_input(state: &parking_lot::Mutex<StreamState>) -> Vec<String> {
+    let revoked = state.lock().input.revoke_all();
+    if !revoked.is_empty() {
+        log::warn!("Remote input revoked for {}", revoked.join(", "));
+    }
+    revoked
+}
+
+fn parse_input_kinds(kinds: &[String]) -> napi::Result<InputKinds> {
+    kinds
+        .iter()
+        .map(|kind| kind.parse::<InputKinds>())
+        .collect::<Result<Vec<_>>>()
+        .map(|kinds| {
+            kinds
+                .into_iter()
+                .fold(InputKinds::empty(), |all, kind| all | kind)
+        })
+        .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))
+}
+
+#[napi(object)]
+pub struct FrameCallbackOptions {
+    // 5 unless set
+    pub max_fps: Option<u32>,
+    // "scaled" (default): planar YUV in the stream's chroma subsampling, at
+    // the output size.
+    // "capture": the capture device's own size and pixel format.
+    pub source: Option<String>,
+}
+
+#[napi(object)]
+pub struct RawFrame {
+    // Plane i starts at offsets[i] with rows strides[i] bytes apart. Usually
+    // the native frame itself rather than a copy, so treat it as read-only;
+    // holding on to it keeps a capture buffer out of the pool.
+    pub data: JsBuffer,
+    // FFmpeg pixel format name, e.g. "yuv420p" or "bgra"
+    pub format: String,
+    pub width: u32,
+    pub height: u32,
+    pub strides: Vec<u32>,
+    pub offsets: Vec<u32>,
+    pub timestamp_ms: f64,
+}
+
+impl RawFrame {
+    // Runs on the JS thread, where buffers can be created
+    fn new(env: &napi::Env, tapped: TappedFrame) -> napi::Result<Self> {
+        let data = match tapped.backing {
+            // The Buffer points into the frame and holds its own reference to
+            // it, released when JS collects the Buffer
+            Backing::Shared(frame) => {
+                let bytes = tap::frame_bytes(&frame);
+                let keep = tap::share(&frame).ok_or_else(|| {
+                    napi::Error::new(
+                        napi::Status::GenericFailure,
+                        "Failed to reference frame".to_string(),
+                    )
+                })?;
+                match unsafe {
+                    env.create_buffer_with_borrowed_data(
+                        bytes.as_ptr(),
+                        bytes.len(),
+                        keep,
+                        |keep, _| drop(keep),
+                    )
+                } {
+                    Ok(buffer) => buffer.into_raw(),
+                    // Runtimes built with the V8 sandbox refuse external memory
+                    Err(_) => env.create_buffer_with_data(bytes.to_vec())?.into_raw(),
+                }
+            }
+            // The Vec becomes the Buffer's backing store, so nothing is copied again
+            Backing::Copied(data) => env.create_buffer_with_data(data)?.into_raw(),
+        };
+
+        Ok(RawFrame {
+            data,
+            format: tapped.format,
+            width: tapped.width,
+            height: tapped.height,
+            strides: tapped.strides,
+            offsets: tapped.offsets,
+            timestamp_ms: tapped.timestamp_ms,
+        })
+    }
+}
+
+#[napi(object)]
+#[derive(serde::Serialize)]
+pub struct FrameTimingHistograms {
+    // From one captured frame to the next
+    pub capture_interval: HistogramSummary,
+    pub encode: HistogramSummary,
+    // How late the capture loop woke for each frame
+    pub pacing_error: HistogramSummary,
+}
+
+// All in milliseconds
+#[napi(object)]
+#[derive(serde::Serialize)]
+pub struct HistogramSummary {
+    pub count: i64,
+    pub min_ms: f64,
+    pub mean_ms: f64,
+    pub stdev_ms: f64,
+    pub max_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub p999_ms: f64,
+    // From 1 ms, doubling up to the largest value seen
+    pub buckets: Vec<HistogramBucket>,
+}
+
+#[napi(object)]
+#[derive(serde::Serialize)]
+pub struct HistogramBucket {
+    pub upper_ms: f64,
+    pub count: i64,
+}
+
+impl From<histograms::Summary> for HistogramSummary {
+    fn from(summary: histograms::Summary) -> Self {
+        Self {
+            count: summary.count as i64,
+            min_ms: summary.min_ms,
+            mean_ms: summary.mean_ms,
+            stdev_ms: summary.stdev_ms,
+            max_ms: summary.max_ms,
+            p50_ms: summary.p50_ms,
+            p90_ms: summary.p90_ms,
+            p99_ms: summary.p99_ms,
+            p999_ms: summary.p999_ms,
+            buckets: summary
+                .buckets
+                .into_iter()
+                .map(|(upper_ms, count)| HistogramBucket {
+                    upper_ms,
+                    count: count as i64,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[napi(object)]
+#[derive(serde::Serialize)]
+pub struct QueueInfo {
+    // Stage that consumes the queue: "encode" or "send"
+    pub stage: String,
+    // "drop_oldest" or "drop_newest"
+    pub policy: String,
+    pub capacity: u32,
+    pub depth: u32,
+    pub high_water: u32,
+    pub dropped: i64,
+}
+
+#[napi(object)]
+#[derive(serde::Serialize)]
+pub struct PeerStats {
+    pub peer_id: String,
+    pub bitrate_kbps: f64,
+    pub packet_loss: f64,
+    pub rtt: f64,
+    pub jitter: f64,
+    pub packets_sent: i64,
+    pub selected_candidate: Option<String>,
+    pub tracks: Vec<TrackLossStats>,
+    pub sent_kbps: TrackBitrates,
+}
+
+#[napi(object)]
+#[derive(Clone, serde::Serialize)]
+pub struct WindowedRates {
+    // What the samples span; shorter than the window until it has filled
+    pub window_ms: f64,
+    // On the wire, all peers, media and overhead
+    pub bitrate_kbps: f64,
+    pub packets_per_sec: f64,
+    // Packets the peers report lost
+    pub lost_packets_per_sec: f64,
+    pub loss_percent: f64,
+    // Video frames captured, encoded and sent
+    pub capture_fps: f64,
+    pub encode_fps: f64,
+    pub send_fps: f64,
+}
+
+impl From<&Rates> for WindowedRates {
+    fn from(rates: &Rates) -> Self {
+        Self {
+            window_ms: rates.window.as_secs_f64() * 1000.0,
+            bitrate_kbps: rates.bitrate_kbps,
+            packets_per_sec: rates.packets_per_sec,
+            lost_packets_per_sec: rates.lost_per_sec,
+            loss_percent: rates.loss_percent,
+            capture_fps: rates.capture_fps,
+            encode_fps: rates.encode_fps,
+            send_fps: rates.send_fps,
+        }
+    }
+}
+
+#[napi(object)]
+#[derive(Clone, serde::Serialize)]
+pub struct TrackBitrates {
+    pub video_kbps: f64,
+    pub camera_kbps: f64,
+    pub audio_kbps: f64,
+    pub rtx_kbps: f64,
+    pub fec_kbps: f64,
+    pub padding_kbps: f64,
+}
+
+impl From<&SentBitrates> for TrackBitrates {
+    fn from(sent: &SentBitrates) -> Self {
+        Self {
+            video_kbps: sent.video_kbps,
+            camera_kbps: sent.camera_kbps,
+            audio_kbps: sent.audio_kbps,
+            rtx_kbps: sent.rtx_kbps,
+            fec_kbps: sent.fec_kbps,
+            padding_kbps: sent.padding_kbps,
+        }
+    }
+}
+
+#[napi(object)]
+#[derive(Clone, serde::Serialize)]
+pub struct QualityLimitationDurations {
+    pub none: f64,
+    pub bandwidth: f64,
+    pub cpu: f64,
+    pub configuration: f64,
+}
+
+impl QualityLimitationDurations {
+    fn new(durations: &[(QualityLimitation, Duration); 4]) -> Self {
+        let seconds = |wanted: QualityLimitation| {
+            durations
+                .iter()
+                .find(|(limitation, _)| *limitation == wanted)
+                .map_or(0.0, |(_, duration)| duration.as_secs_f64())
+        };
+        Self {
+            none: seconds(QualityLimitation::None),
+            bandwidth: seconds(QualityLimitation::Bandwidth),
+            cpu: seconds(QualityLimitation::Cpu),
+            configuration: seconds(QualityLimitation::Configuration),
+        }
+    }
+}
+
+#[napi(object)]
+#[derive(Clone, serde::Serialize)]
+pub struct TrackFrameStats {
+    // "video", "camera" or "audio"
+    pub kind: String,
+    pub captured: f64,
+    // Unchanged frames not encoded again, see skip_duplicate_frames
+    pub duplicated: f64,
+    pub dropped_queue_full: f64,
+    pub dropped_encoder_busy: f64,
+    // Skipped because capture fell behind its frame rate
+    pub dropped_pacing: f64,
+    pub encoded: f64,
+    pub sent: f64,
+    // PLIs and FIRs received, and those answered by a keyframe already due;
+    // see keyframe_request_interval_ms
+    pub keyframe_requests: f64,
+    pub keyframe_requests_folded: f64,
+}
+
+impl TrackFrameStats {
+    fn new(kind: TrackKind, counts: &FrameCounts) -> Self {
+        Self {
+            kind: kind.as_str().to_string(),
+            captured: counts.captured as f64,
+            duplicated: counts.duplicated as f64,
+            dropped_queue_full: counts.dropped_queue_full as f64,
+            dropped_encoder_busy: counts.dropped_encoder_busy as f64,
+            dropped_pacing: counts.dropped_pacing as f64,
+            encoded: counts.encoded as f64,
+            sent: counts.sent as f64,
+            keyframe_requests: counts.keyframe_requests as f64,
+            keyframe_requests_folded: counts.keyframe_requests_folded as f64,
+        }
+    }
+}
+
+// Packet loss on one track as the viewer reports it, in percent
+#[napi(object)]
+#[derive(Clone, serde::Serialize)]
+pub struct TrackLossStats {
+    // "video", "camera" or "audio"
+    pub kind: String,
+    // Since the track started
+    pub loss_percent: f64,
+    // Over the last second
+    pub window_loss_percent: f64,
+}
+
+impl From<&TrackLoss> for TrackLossStats {
+    fn from(loss: &TrackLoss) -> Self {
+        Self {
+            kind: loss.kind.as_str().to_string(),
+            loss_percent: loss.loss_percent,
+            window_loss_percent: loss.window_loss_percent,
+        }
+    }
+}
+
+#[napi]
+impl SlumpStream {
+    // The last SDP each way, negotiated codecs and payload types, header
+    // extensions and the current encode settings, to answer "why this codec
+    // at this bitrate" from the app
+    #[napi]
+    pub fn get_negotiation_debug(
+        &self,
+        mut env: napi::Env,
+    ) -> napi::Result<AsyncTask<Blocking<NegotiationDebug>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let state = shared.lock();
+            let stream = &*state;
+
+            let negotiations = runtime::get()
+                .map_err(|e| {
+                    napi::Error::new(
+                        napi::Status::GenericFailure,
+                        format!("Failed to create runtime: {}", e),
+                    )
+                })?
+                .block_on(async {
+                    let mut negotiations = Vec::new();
+                    for transport in stream.peers.values() {
+                        negotiations.push((
+                            transport.peer_id().to_string(),
+                            transport.negotiation().await,
+                        ));
+                    }
+                    negotiations
+                });
+
+            let video = stream.output_video_params();
+            let mut summary = format!(
+                "video: {}x{} @ {}fps, {} kbps; camera: {} kbps; audio: {} kbps\n",
+                video.width,
+                video.height,
+                video.fps,
+                video.bitrate_kbps,
+                stream.camera_bitrate_kbps,
+                stream.audio_bitrate_kbps
+            );
+            let peers = negotiations
+                .into_iter()
+                .map(|(peer_id, negotiation)| {
+                    let peer_summary = negotiation.to_string();
+                    summary.push_str(&format!("\npeer {}\n{}", peer_id, peer_summary));
+                    PeerNegotiation {
+                        peer_id,
+                        signaling_state: negotiation.signaling_state,
+                        local_sdp: negotiation.local_sdp,
+                        remote_sdp: negotiation.remote_sdp,
+                        codecs: negotiation.codecs.into_iter().map(Into::into).collect(),
+                        header_extensions: negotiation
+                            .header_extensions
+                            .into_iter()
+                            .map(Into::into)
+                            .collect(),
+                        summary: peer_summary,
+                    }
+                })
+                .collect();
+
+            Ok(NegotiationDebug {
+                width: video.width,
+                height: video.height,
+                fps: video.fps,
+                video_bitrate_kbps: video.bitrate_kbps,
+                camera_bitrate_kbps: stream.camera_bitrate_kbps,
+                audio_bitrate_kbps: stream.audio_bitrate_kbps,
+                peers,
+                summary,
+            })
+        }))
+    }
+
+    #[napi]
+    pub fn get_peer_stats(&self, mut env: napi::Env) -> napi::Result<Vec<PeerStats>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let state = self.state.lock();
+        let stream = &*state;
+
+        Ok(stream
+            .peers
+            .values()
+            .filter_map(|transport| {
+                transport.get_stats().map(|stats| PeerStats {
+                    peer_id: transport.peer_id().to_string(),
+                    bitrate_kbps: stats.bitrate,
+                    packet_loss: stats.packet_loss,
+                    rtt: stats.rtt,
+                    jitter: stats.jitter,
+                    packets_sent: stats.packets_sent as i64,
+                    selected_candidate: stats.selected_candidate,
+                    tracks: stats.tracks.iter().map(TrackLossStats::from).collect(),
+                    sent_kbps: TrackBitrates::from(&stats.sent_kbps),
+                })
+            })
+            .collect())
+    }
+
+    // Everything in one JSON string for logs and support uploads: every
+    // stats group whatever the stats options select, each peer, the queues
+    // between stages and the timing histograms
+    #[napi]
+    pub fn get_stats_json(&self, mut env: napi::Env) -> napi::Result<String> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let stats = {
+            let state = self.state.lock();
+            let stats = state.stats.lock().unwrap().report(StatGroups::all());
+            stats
+        };
+        Ok(serde_json::json!({
+            "timestamp_ms": logging::timestamp_ms(),
+            "running": self.is_running(),
+            "failure": self.get_failure(),
+            "stats": stats,
+            "peers": self.get_peer_stats()?,
+            "queues": self.get_pipeline_stats(),
+            "histograms": self.get_histograms(None),
+        })
+        .to_string())
+    }
+}
+
+#[napi(object)]
+pub struct CodecInfo {
+    pub mid: String,
+    // "video" or "audio"
+    pub kind: String,
+    pub mime_type: String,
+    pub payload_type: u32,
+    pub clock_rate: u32,
+    pub channels: u32,
+    pub fmtp: String,
+    pub feedback: Vec<String>,
+}
+
+impl From<webrtc::NegotiatedCodec> for CodecInfo {
+    fn from(codec: webrtc::NegotiatedCodec) -> Self {
+        CodecInfo {
+            mid: codec.mid,
+            kind: codec.kind,
+            mime_type: codec.mime_type,
+            payload_type: codec.payload_type as u32,
+            clock_rate: codec.clock_rate,
+            channels: codec.channels as u32,
+            fmtp: codec.fmtp,
+            feedback: codec.feedback,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct HeaderExtensionInfo {
+    pub mid: String,
+    pub id: i32,
+    pub uri: String,
+}
+
+impl From<webrtc::HeaderExtension> for HeaderExtensionInfo {
+    fn from(extension: webrtc::HeaderExtension) -> Self {
+        HeaderExtensionInfo {
+            mid: extension.mid,
+            id: extension.id as i32,
+            uri: extension.uri,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct PeerNegotiation {
+    pub peer_id: String,
+    pub signaling_state: String,
+    pub local_sdp: Option<String>,
+    pub remote_sdp: Option<String>,
+    pub codecs: Vec<CodecInfo>,
+    pub header_extensions: Vec<HeaderExtensionInfo>,
+    // Codecs and extensions one per line, for showing as is
+    pub summary: String,
+}
+
+#[napi(object)]
+pub struct NegotiationDebug {
+    // What the video encoder is producing now, after any adaptation
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub video_bitrate_kbps: u32,
+    pub camera_bitrate_kbps: u32,
+    // Opus, as set with set_track_bitrate("audio", ...)
+    pub audio_bitrate_kbps: u32,
+    pub peers: Vec<PeerNegotiation>,
+    // Encoder settings followed by every peer's summary
+    pub summary: String,
+}
+
+#[napi(object)]
+pub struct RenegotiationOffer {
+    pub peer_id: String,
+    pub sdp: String,
+}
+
+#[napi]
+impl SlumpStream {
+    #[napi]
+    pub fn add_track(
+        &self,
+        mut env: napi::Env,
+        kind: String,
+    ) -> napi::Result<AsyncTask<Blocking<Vec<RenegotiationOffer>>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            let kind = kind
+                .parse::<TrackKind>()
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+
+            // Bring the matching capture back up before the track goes live
+            match kind {
+                TrackKind::Video if !stream.video_capture.is_some() => {
+                    let target = stream.adaptive.target();
+                    let video = stream
+                        .open_video(target.width, target.height)
+                        .map_err(|e| operation_error("initialize video capture", e))?;
+                    stream.set_video_capture(Some(video));
+                    stream.video_encoder.set(Some(
+                        VideoEncoder::new(
+                            target.width,
+                            target.height,
+                            target.fps,
+                            target.bitrate_kbps,
+                        )
+                        .map_err(|e| operation_error("initialize video encoder", e))?,
+                    ));
+                }
+                TrackKind::Camera if !stream.camera_capture.is_some() => {
+                    let camera = stream.open_camera()?;
+                    stream.camera_capture.set(Some(camera));
+                    stream.camera_encoder.set(Some(
+                        VideoEncoder::new(
+                            CAMERA_WIDTH,
+                            CAMERA_HEIGHT,
+                            CAMERA_FPS,
+                            stream.camera_bitrate_kbps,
+                        )
+                        .map_err(|e| operation_error("initialize camera encoder", e))?,
+                    ));
+                }
+                TrackKind::Audio if !stream.audio_capture.is_some() => {
+                    let audio = stream
+                        .open_audio(stream.audio_device.as_deref())
+                        .map_err(|e| operation_error("initialize audio capture", e))?;
+                    stream.audio_capture.set(Some(audio));
+                    stream
+                        .open_audio_encoder()
+                        .map_err(|e| operation_error("initialize audio encoder", e))?;
+                }
+                _ => {}
+            }
+
+            let rt = runtime::get().map_err(|e| {
+                napi::Error::new(
+                    napi::Status::GenericFailure,
+                    format!("Failed to create runtime: {}", e),
+                )
+            })?;
+
+            rt.block_on(async {
+                let mut offers = Vec::new();
+                for transport in stream.peers.values_mut() {
+                    if transport.add_track(kind).await? {
+                        offers.push(RenegotiationOffer {
+                            peer_id: transport.peer_id().to_string(),
+                            sdp: transport.create_offer().await?,
+                        });
+                    }
+                }
+                Ok::<_, error::SlumpError>(offers)
+            })
+            .map_err(|e| operation_error("add track", e))
+        }))
+    }
+
+    #[napi]
+    pub fn remove_track(
+        &self,
+        mut env: napi::Env,
+        kind: String,
+    ) -> napi::Result<AsyncTask<Blocking<Vec<RenegotiationOffer>>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            let kind = kind
+                .parse::<TrackKind>()
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+
+            let rt = runtime::get().map_err(|e| {
+                napi::Error::new(
+                    napi::Status::GenericFailure,
+                    format!("Failed to create runtime: {}", e),
+                )
+            })?;
+
+            let offers = rt
+                .block_on(async {
+                    let mut offers = Vec::new();
+                    for transport in stream.peers.values_mut() {
+                        if transport.remove_track(kind).await? {
+                            offers.push(RenegotiationOffer {
+                                peer_id: transport.peer_id().to_string(),
+                                sdp: transport.create_offer().await?,
+                            });
+                        }
+                    }
+                    Ok::<_, error::SlumpError>(offers)
+                })
+                .map_err(|e| operation_error("remove track", e))?;
+
+            // Release the device once nothing is sending it
+            match kind {
+                TrackKind::Video => {
+                    stream.set_video_capture(None);
+                    stream.video_encoder.set(None);
+                }
+                TrackKind::Camera => {
+                    stream.camera_capture.set(None);
+                    stream.camera_encoder.set(None);
+                }
+                TrackKind::Audio => {
+                    stream.audio_capture.set(None);
+                    stream.audio_encoder.set(None);
+                }
+            }
+
+            Ok(offers)
+        }))
+    }
+
+    // Supplies the next video frame when the stream was started with
+    // video_source "external". data holds the planes back to back with no row
+    // padding; timestamp is in milliseconds on the caller's clock. Frames
+    // pushed faster than the stream's fps replace each other, and false means
+    // the frame was dropped: not running, not external, or older than the last.
+    #[napi]
+    pub fn push_video_frame(
+        &self,
+        mut env: napi::Env,
+        data: Buffer,
+        format: String,
+        width: u32,
+        height: u32,
+        timestamp: f64,
+    ) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let frames = {
+            let stream = self.state.lock();
+            if !stream.running {
+                return Ok(false);
+            }
+            let Some(frames) = stream.external_frames.clone() else {
+                return Ok(false);
+            };
+            frames
+        };
+        // Converted without the state lock held
+        frames
+            .lock()
+            .push(&data, &format, width, height, timestamp as i64)
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))
+    }
+
+    #[napi]
+    pub fn set_video_source(
+        &self,
+        mut env: napi::Env,
+        display_index: u32,
+    ) -> napi::Result<AsyncTask<Blocking<()>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            let target = stream.adaptive.target();
+            let video = stream
+                .open_display(
+                    display_index as usize,
+                    target.width,
+                    target.height,
+                    stream.capture_cursor,
+                )
+                .map_err(|e| operation_error("initialize video capture", e))?;
+
+            // Swap the sender's track so receivers reset their decoder for the new source
+            runtime::get()
+                .map_err(|e| {
+                    napi::Error::new(
+                        napi::Status::GenericFailure,
+                        format!("Failed to create runtime: {}", e),
+                    )
+                })?
+                .block_on(async {
+                    for transport in stream.peers.values_mut() {
+                        transport.replace_track(TrackKind::Video).await?;
+                    }
+                    Ok::<_, error::SlumpError>(())
+                })
+                .map_err(|e| operation_error("replace video track", e))?;
+
+            stream.set_video_capture(Some(video));
+            stream.video_source = VideoSource::Screen;
+            stream.display_index = display_index as usize;
+            Ok(())
+        }))
+    }
+
+    #[napi]
+    pub fn set_camera_device(&self, mut env: napi::Env, device: String) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        stream.camera_device = Some(device);
+        Ok(())
+    }
+
+    #[napi]
+    pub fn set_track_bitrate(
+        &self,
+        mut env: napi::Env,
+        kind: String,
+        bitrate_kbps: u32,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let kind = kind
+            .parse::<TrackKind>()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+
+        match kind {
+            TrackKind::Video => {
+                // A new ceiling; the adaptive controller may still hold it lower
+                stream.video_bitrate_kbps = bitrate_kbps;
+                stream.adaptive.set_max_bitrate(bitrate_kbps);
+                stream.congestion.reset(bitrate_kbps);
+                let target_kbps = stream.adaptive.target().bitrate_kbps;
+                // Set by the encode stage, which logs a failure
+                stream.video_encoder.change(move |encoder| {
+                    if let Err(e) = encoder.set_bitrate(target_kbps) {
+                        log::error!("Failed to set video bitrate: {}", e);
+                    }
+                });
+            }
+            TrackKind::Camera => {
+                stream.camera_bitrate_kbps = bitrate_kbps;
+                // Set by the camera's encode stage, which logs a failure
+                stream.camera_encoder.change(move |encoder| {
+                    if let Err(e) = encoder.set_bitrate(bitrate_kbps) {
+                        log::error!("Failed to set camera bitrate: {}", e);
+                    }
+                });
+            }
+            TrackKind::Audio => {
+                if !(audio::MIN_AUDIO_BITRATE_KBPS..=audio::MAX_AUDIO_BITRATE_KBPS)
+                    .contains(&bitrate_kbps)
+                {
+                    return Err(napi::Error::new(
+                        napi::Status::InvalidArg,
+                        format!(
+                            "Audio bitrate must be {}-{} kbps",
+                            audio::MIN_AUDIO_BITRATE_KBPS,
+                            audio::MAX_AUDIO_BITRATE_KBPS
+                        ),
+                    ));
+                }
+                stream.audio_bitrate_kbps = bitrate_kbps;
+                // Set by the audio stage, which logs a failure
+                stream.audio_encoder.change(move |encoder| {
+                    if let Err(e) = encoder.set_bitrate(bitrate_kbps) {
+                        log::error!("Failed to set audio bitrate: {}", e);
+                    }
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    #[napi]
+    pub fn handle_signal(&self, mut env: napi::Env, signal: String) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        // Accepts both versioned envelopes and legacy bare messages
+        let message = serde_json::from_str::<SignalEnvelope>(&signal)
+            .map(|envelope| envelope.message)
+            .map_err(|e| {
+                napi::Error::new(
+                    napi::Status::InvalidArg,
+                    format!("Invalid signal message: {}", e),
+                )
+            })?;
+
+        // Forward signaling messages from the JavaScript side to the WebRTC transport
+        if let Some(transport) = stream.peers.get(DEFAULT_PEER_ID) {
+            transport
+                .handle_signal(&message)
+                .map_err(|e| operation_error("handle signal", e))?;
+        }
+
+        Ok(())
+    }
+
+    #[napi]
+    pub fn start_signaling_server(&self, mut env: napi::Env, port: u32) -> napi::Result<String> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let events = stream.signaling_tx.clone().ok_or_else(|| {
+            napi::Error::new(
+                napi::Status::GenericFailure,
+                "Stream is not running".to_string(),
+            )
+        })?;
+
+        // Restarting on a new port replaces the previous server
+        stream.signaling_server = None;
+        let server = SignalingServer::start(port as u16, events)
+            .map_err(|e| operation_error("start signaling server", e))?;
+
+        let url = server.url();
+        stream.signaling_server = Some(server);
+        Ok(url)
+    }
+
+    #[napi]
+    pub fn stop_signaling_server(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.signaling_server.take().is_some())
+    }
+}
+
+#[napi(object)]
+pub struct TlsOptions {
+    pub ca_file: Option<String>,
+    pub pinned_certificates: Option<Vec<String>>,
+    pub insecure_skip_verify: Option<bool>,
+}
+
+impl From<TlsOptions> for TlsSettings {
+    fn from(options: TlsOptions) -> Self {
+        TlsSettings {
+            ca_file: options.ca_file,
+            pinned_certificates: options.pinned_certificates.unwrap_or_default(),
+            insecure_skip_verify: options.insecure_skip_verify.unwrap_or(false),
+        }
+    }
+}
+
+#[napi(object)]
+pub struct SignalingAuthOptions {
+    pub bearer_token: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+    pub query: Option<HashMap<String, String>>,
+    pub tls: Option<TlsOptions>,
+}
+
+impl From<SignalingAuthOptions> for SignalingAuth {
+    fn from(options: SignalingAuthOptions) -> Self {
+        SignalingAuth {
+            bearer_token: options.bearer_token,
+            headers: options.headers.unwrap_or_default().into_iter().collect(),
+            query: options.query.unwrap_or_default().into_iter().collect(),
+            tls: options.tls.map(Into::into).unwrap_or_default(),
+        }
+    }
+}
+
+#[napi]
+impl SlumpStream {
+    #[napi]
+    pub fn connect_signaling(
+        &self,
+        mut env: napi::Env,
+        url: String,
+        auth: Option<SignalingAuthOptions>,
+        cancel: Option<ClassInstance<CancelHandle>>,
+    ) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let cancel = cancellation(cancel);
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            let events = stream.signaling_tx.clone().ok_or_else(|| {
+                napi::Error::new(
+                    napi::Status::GenericFailure,
+                    "Stream is not running".to_string(),
+                )
+            })?;
+
+            let transport = stream.peers.get(DEFAULT_PEER_ID).ok_or_else(|| {
+                napi::Error::new(
+                    napi::Status::GenericFailure,
+                    "No default peer to signal for".to_string(),
+                )
+            })?;
+
+            let auth: SignalingAuth = auth.map(Into::into).unwrap_or_default();
+            auth.websocket_request(&url)
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+            if !auth.tls.is_default() {
+                auth.tls
+                    .client_config()
+                    .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+            }
+            stream.check_turns_tls(&auth.tls)?;
+
+            let client = SignalingClient::connect(url, auth, DEFAULT_PEER_ID.to_string(), events);
+            transport.forward_local_candidates(client.sender());
+
+            // The offer is queued until the client connects
+            let offer = runtime::get()
+                .map_err(|e| {
+                    napi::Error::new(
+                        napi::Status::GenericFailure,
+                        format!("Failed to create runtime: {}", e),
+                    )
+                })?
+                .block_on(cancel.run(transport.create_offer()))
+                .map_err(|e| operation_error("create offer", e))?;
+            client.send(SignalMessage::Offer { sdp: offer });
+
+            stream.signaling_client = Some(client);
+            Ok(true)
+        }))
+    }
+
+    #[napi]
+    pub fn disconnect_signaling(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.signaling_client.take().is_some())
+    }
+
+    #[napi]
+    pub fn start_whip(
+        &self,
+        mut env: napi::Env,
+        endpoint: String,
+        auth: Option<SignalingAuthOptions>,
+        cancel: Option<ClassInstance<CancelHandle>>,
+    ) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let cancel = cancellation(cancel);
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            if !stream.running || stream.whip.is_some() {
+                return Ok(false);
+            }
+
+            let auth: SignalingAuth = auth.map(Into::into).unwrap_or_default();
+            stream.check_turns_tls(&auth.tls)?;
+            let mut whip = WhipClient::new(endpoint, auth)
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+            let (candidate_tx, mut candidate_rx) = mpsc::unbounded_channel();
+
+            let transport = runtime::get()
+                .map_err(|e| {
+                    napi::Error::new(
+                        napi::Status::GenericFailure,
+                        format!("Failed to create runtime: {}", e),
+                    )
+                })?
+                .block_on(cancel.run(async {
+                    let transport = WebRTCTransport::new(
+                        WHIP_PEER_ID.to_string(),
+                        stream.stun_servers.clone(),
+                        stream.turn_servers.clone(),
+                        &stream.track_kinds(),
+                        stream.budget.rtp_history,
+                        stream.impairment.clone(),
+                    )
+                    .await?;
+
+                    // Candidates gathered before the POST completes wait in the channel
+                    transport.forward_local_candidates(candidate_tx);
+                    let offer = transport.create_offer().await?;
+                    let answer = whip.publish(&offer).await?;
+                    transport.set_remote_answer(answer).await?;
+                    Ok::<_, error::SlumpError>(transport)
+                }))
+                .map_err(|e| operation_error("start WHIP session", e))?;
+
+            // Trickle ICE candidates to the WHIP resource as they are gathered
+            let trickle = whip.clone();
+            if let Ok(rt) = runtime::get() {
+                rt.spawn(async move {
+                    while let Some(message) = candidate_rx.recv().await {
+                        if let SignalMessage::Ice { candidate } = message {
+                            if let Err(e) = trickle.trickle(&candidate).await {
+                                log::warn!("{}", e);
+                            }
+                        }
+                    }
+                });
+            }
+
+            stream.peers.insert(WHIP_PEER_ID.to_string(), transport);
+            stream.whip = Some(whip);
+            Ok(true)
+        }))
+    }
+
+    #[napi]
+    pub fn stop_whip(&self, mut env: napi::Env) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            let Some(mut whip) = stream.whip.take() else {
+                return Ok(false);
+            };
+            let mut transport = stream.peers.remove(WHIP_PEER_ID);
+
+            runtime::get()
+                .map_err(|e| {
+                    napi::Error::new(
+                        napi::Status::GenericFailure,
+                        format!("Failed to create runtime: {}", e),
+                    )
+                })?
+                .block_on(async {
+                    if let Some(transport) = transport.as_mut() {
+                        transport.close().await?;
+                    }
+                    whip.teardown().await
+                })
+                .map_err(|e| operation_error("stop WHIP session", e))?;
+
+            Ok(true)
+        }))
+    }
+
+    // Names of the outputs currently fed by the tee
+    #[napi]
+    pub fn list_outputs(&self, mut env: napi::Env) -> napi::Result<Vec<String>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.outputs.names())
+    }
+}
+
+#[cfg(feature = "srt")]
+#[napi]
+impl SlumpStream {
+    #[napi]
+    pub fn start_srt_output(
+        &self,
+        mut env: napi::Env,
+        address: String,
+        mode: Option<String>,
+        latency_ms: Option<u32>,
+        passphrase: Option<String>,
+    ) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            let settings = SrtSettings {
+                address,
+                mode: mode.as_deref().unwrap_or("caller").parse().map_err(
+                    |e: error::SlumpError| {
+                        napi::Error::new(napi::Status::InvalidArg, e.to_string())
+                    },
+                )?,
+                latency_ms: latency_ms.unwrap_or(120),
+                passphrase,
+            };
+
+            stream
+                .add_output("srt", |encoder| settings.open(encoder))
+                .map_err(|e| operation_error("start SRT output", e))
+        }))
+    }
+
+    #[napi]
+    pub fn stop_srt_output(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.outputs.remove("srt").is_some())
+    }
+}
+
+#[cfg(feature = "rist")]
+#[napi]
+impl SlumpStream {
+    #[napi]
+    pub fn start_rist_output(
+        &self,
+        mut env: napi::Env,
+        address: String,
+        profile: Option<String>,
+        buffer_ms: Option<u32>,
+        secret: Option<String>,
+    ) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            let settings = RistSettings {
+                address,
+                profile: profile.as_deref().unwrap_or("simple").parse().map_err(
+                    |e: error::SlumpError| {
+                        napi::Error::new(napi::Status::InvalidArg, e.to_string())
+                    },
+                )?,
+                buffer_ms: buffer_ms.unwrap_or(1000),
+                secret,
+            };
+
+            stream
+                .add_output("rist", |encoder| settings.open(encoder))
+                .map_err(|e| operation_error("start RIST output", e))
+        }))
+    }
+
+    #[napi]
+    pub fn stop_rist_output(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.outputs.remove("rist").is_some())
+    }
+}
+
+#[cfg(feature = "rtsp")]
+#[napi]
+impl SlumpStream {
+    // Video only for now; returns the rtsp:// URL to hand to players on the LAN
+    #[napi]
+    pub fn start_rtsp_server(
+        &self,
+        mut env: napi::Env,
+        port: u32,
+        path: Option<String>,
+    ) -> napi::Result<String> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        // Restarting on a new port replaces the previous server
+        stream.outputs.remove(RTSP_OUTPUT);
+        let mut url = None;
+        let started = stream
+            .add_output(RTSP_OUTPUT, |encoder| {
+                let server = RtspServer::start(
+                    port as u16,
+                    path.as_deref().unwrap_or("live"),
+                    encoder.video().time_base(),
+                )?;
+                url = Some(server.url());
+                Ok(server)
+            })
+            .map_err(|e| operation_error("start RTSP server", e))?;
+
+        match url {
+            Some(url) if started => Ok(url),
+            _ => Err(napi::Error::new(
+                napi::Status::GenericFailure,
+                "Stream is not running".to_string(),
+            )),
+        }
+    }
+
+    #[napi]
+    pub fn stop_rtsp_server(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.outputs.remove(RTSP_OUTPUT).is_some())
+    }
+}
+
+#[cfg(feature = "otlp")]
+#[napi(object)]
+pub struct OtlpOptions {
+    // Collector base URL, e.g. "http://localhost:4318"
+    pub endpoint: String,
+    // Sent with every request, e.g. an API key
+    pub headers: Option<HashMap<String, String>>,
+    // Defaults to 10000
+    pub interval_ms: Option<u32>,
+    // Defaults to "slump"
+    pub service_name: Option<String>,
+    // Tracing spans as well as metrics; defaults to true
+    pub traces: Option<bool>,
+}
+
+#[cfg(feature = "otlp")]
+impl OtlpOptions {
+    fn into_settings(self) -> Result<OtlpSettings> {
+        let endpoint = reqwest::Url::parse(&self.endpoint).map_err(|e| {
+            error::SlumpError::Init(format!("Invalid OTLP endpoint {}: {}", self.endpoint, e))
+        })?;
+        if !matches!(endpoint.scheme(), "http" | "https") {
+            return Err(error::SlumpError::Init(format!(
+                "OTLP endpoint must be http or https, got {}",
+                self.endpoint
+            )));
+        }
+        let interval_ms = self.interval_ms.unwrap_or(DEFAULT_OTLP_INTERVAL_MS);
+        if interval_ms < MIN_OTLP_INTERVAL_MS {
+            return Err(error::SlumpError::Init(format!(
+                "OTLP export interval must be at least {} ms, got {}",
+                MIN_OTLP_INTERVAL_MS, interval_ms
+            )));
+        }
+        Ok(OtlpSettings {
+            endpoint: self.endpoint,
+            headers: self.headers.unwrap_or_default(),
+            interval: Duration::from_millis(interval_ms as u64),
+            service_name: self.service_name.unwrap_or_else(|| "slump".to_string()),
+            traces: self.traces.unwrap_or(true),
+        })
+    }
+}
+
+#[cfg(feature = "otlp")]
+#[napi]
+impl SlumpStream {
+    // Pushes this stream's metrics, and spans from the whole addon, to an
+    // OTLP/HTTP collector until stopped or the stream is dropped; stopping
+    // the stream leaves it running. Starting again replaces it.
+    #[napi]
+    pub fn start_otlp_export(&self, mut env: napi::Env, options: OtlpOptions) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let settings = options
+            .into_settings()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        // The old exporter flushes on drop, so not under the lock
+        let previous = self.state.lock().otlp.take();
+        drop(previous);
+
+        let metrics = self.state.lock().metrics.clone();
+        let exporter = OtlpExporter::start(settings, metrics)
+            .map_err(|e| operation_error("start OTLP export", e))?;
+        self.state.lock().otlp = Some(exporter);
+        Ok(())
+    }
+
+    #[napi]
+    pub fn stop_otlp_export(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let exporter = self.state.lock().otlp.take();
+        Ok(exporter.is_some())
+    }
+}
+
+#[cfg(feature = "ndi")]
+#[napi]
+impl SlumpStream {
+    // Requires the NDI runtime; the source shows up in OBS/vMix under `name`
+    #[napi]
+    pub fn start_ndi_output(&self, mut env: napi::Env, name: Option<String>) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        stream
+            .add_output(NDI_OUTPUT, |encoder| {
+                NdiSender::new(name.as_deref().unwrap_or("slump"), encoder.params().fps)
+            })
+            .map_err(|e| operation_error("start NDI output", e))
+    }
+
+    #[napi]
+    pub fn stop_ndi_output(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.outputs.remove(NDI_OUTPUT).is_some())
+    }
+}
+
+#[cfg(feature = "rtmp")]
+#[napi]
+impl SlumpStream {
+    // Several RTMP destinations can run at once, each under its own name
+    #[napi]
+    pub fn start_rtmp_output(
+        &self,
+        mut env: napi::Env,
+        name: String,
+        url: String,
+        stream_key: Option<String>,
+    ) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            let settings = RtmpSettings { url, stream_key };
+            stream
+                .add_output(&format!("rtmp:{}", name), |encoder| settings.open(encoder))
+                .map_err(|e| operation_error("start RTMP output", e))
+        }))
+    }
+
+    #[napi]
+    pub fn stop_rtmp_output(&self, mut env: napi::Env, name: String) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.outputs.remove(&format!("rtmp:{}", name)).is_some())
+    }
+}
+
+#[cfg(feature = "segment")]
+#[napi]
+impl SlumpStream {
+    // HLS and DASH can run side by side, each into its own directory
+    #[napi]
+    pub fn start_segment_output(
+        &self,
+        mut env: napi::Env,
+        directory: String,
+        format: Option<String>,
+        segment_secs: Option<u32>,
+        window_size: Option<u32>,
+    ) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let format = format
+            .as_deref()
+            .unwrap_or("hls")
+            .parse::<SegmentFormat>()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+
+        let settings = SegmentSettings {
+            directory,
+            format,
+            segment_secs: segment_secs.unwrap_or(2),
+            window_size: window_size.unwrap_or(6),
+        };
+
+        stream
+            .add_output(format.name(), |encoder| settings.open(encoder))
+            .map_err(|e| {
+                napi::Error::new(
+                    napi::Status::GenericFailure,
+                    format!(
+                        "Failed to start {} output: {}",
+                        format.name().to_uppercase(),
+                        e
+                    ),
+                )
+            })
+    }
+
+    #[napi]
+    pub fn stop_segment_output(
+        &self,
+        mut env: napi::Env,
+        format: Option<String>,
+    ) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let format = format
+            .as_deref()
+            .unwrap_or("hls")
+            .parse::<SegmentFormat>()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+
+        Ok(stream.outputs.remove(format.name()).is_some())
+    }
+}
+
+#[cfg(feature = "udp")]
+#[napi]
+impl SlumpStream {
+    #[napi]
+    pub fn start_udp_output(
+        &self,
+        mut env: napi::Env,
+        address: String,
+        encapsulation: Option<String>,
+        ttl: Option<u32>,
+    ) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let settings = UdpSettings {
+            address,
+            encapsulation: encapsulation.as_deref().unwrap_or("udp").parse().map_err(
+                |e: error::SlumpError| napi::Error::new(napi::Status::InvalidArg, e.to_string()),
+            )?,
+            ttl: ttl.unwrap_or(16),
+        };
+
+        stream
+            .add_output("udp", |encoder| settings.open(encoder))
+            .map_err(|e| operation_error("start UDP output", e))
+    }
+
+    #[napi]
+    pub fn stop_udp_output(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.outputs.remove("udp").is_some())
+    }
+}
+
+// Experimental; only built with the `moq` feature
+#[cfg(feature = "moq")]
+#[napi]
+impl SlumpStream {
+    #[napi]
+    pub fn start_moq_output(
+        &self,
+        mut env: napi::Env,
+        url: String,
+        namespace: String,
+    ) -> napi::Result<AsyncTask<Blocking<bool>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            stream
+                .add_output(MOQ_OUTPUT, |encoder| {
+                    MoqPublisher::connect(&url, &namespace, encoder)
+                })
+                .map_err(|e| operation_error("start MoQ output", e))
+        }))
+    }
+
+    #[napi]
+    pub fn stop_moq_output(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.outputs.remove(MOQ_OUTPUT).is_some())
+    }
+}
+
+#[napi(object)]
+pub struct RecordingInfo {
+    pub path: String,
+    pub duration_secs: f64,
+    pub size_bytes: f64,
+}
+
+impl From<RecordingSummary> for RecordingInfo {
+    fn from(summary: RecordingSummary) -> Self {
+        RecordingInfo {
+            path: summary.path,
+            duration_secs: summary.duration.as_secs_f64(),
+            size_bytes: summary.size_bytes as f64,
+        }
+    }
+}
+
+impl From<RecordingSummary> for StreamEvent {
+    fn from(summary: RecordingSummary) -> Self {
+        StreamEvent::RecordingStopped {
+            path: summary.path,
+            duration_secs: summary.duration.as_secs_f64(),
+            size_bytes: summary.size_bytes as f64,
+        }
+    }
+}
+
+// Unset fields follow the live stream
+#[napi(object)]
+pub struct RecordingEncodeOptions {
+    pub codec: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+    pub quality: Option<u32>,
+    pub bitrate_kbps: Option<u32>,
+}
+
+impl RecordingEncodeOptions {
+    fn into_settings(self, live: VideoParams) -> Result<RecordingEncodeSettings> {
+        Ok(RecordingEncodeSettings {
+            codec: match self.codec {
+                Some(codec) => codec.parse()?,
+                None => Default::default(),
+            },
+            width: self.width.unwrap_or(live.width),
+            height: self.height.unwrap_or(live.height),
+            fps: self.fps.unwrap_or(live.fps),
+            quality: self.quality,
+            bitrate_kbps: self.bitrate_kbps.unwrap_or(live.bitrate_kbps),
+            colorimetry: live.colorimetry,
+            chroma: live.chroma,
+        })
+    }
+}
+
+#[napi(object)]
+pub struct CaptionOptions {
+    pub text: String,
+    // "caption" (the default), "title" or "telemetry"
+    pub kind: Option<String>,
+    // How long it stays up, 4000 by default
+    pub duration_ms: Option<u32>,
+}
+
+impl CaptionOptions {
+    fn into_caption(self) -> Result<Caption> {
+        Ok(Caption {
+            kind: match self.kind {
+                Some(kind) => kind.parse()?,
+                None => Default::default(),
+            },
+            text: self.text,
+            duration: Duration::from_millis(
+                self.duration_ms.unwrap_or(DEFAULT_CAPTION_DURATION_MS) as u64,
+            ),
+        })
+    }
+}
+
+#[napi(object)]
+pub struct RecordingOptions {
+    // May contain `{n}` and `{timestamp}`
+    pub path: String,
+    // "mp4" (default), "fmp4" or "mkv"
+    pub format: Option<String>,
+    // True unless set
+    pub remux_on_stop: Option<bool>,
+    // Limits of 0 are ignored
+    pub segment_minutes: Option<u32>,
+    pub segment_size_mb: Option<u32>,
+    // Records with a separate encoder instead of sharing the live one
+    pub encode: Option<RecordingEncodeOptions>,
+    // Muxes push_caption text as a subtitle track
+    pub captions: Option<bool>,
+}
+
+#[napi]
+impl SlumpStream {
+    #[napi]
+    pub fn start_recording(
+        &self,
+        mut env: napi::Env,
+        options: RecordingOptions,
+    ) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let RecordingOptions {
+            path,
+            format,
+            remux_on_stop,
+            segment_minutes,
+            segment_size_mb,
+            encode,
+            captions,
+        } = options;
+        let format = match format {
+            Some(format) => format
+                .parse::<RecordingFormat>()
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?,
+            None => RecordingFormat::default(),
+        };
+        let encode = encode
+            .map(|options| options.into_settings(stream.output_video_params()))
+            .transpose()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+
+        let started = stream
+            .add_output(RECORDING_OUTPUT, |encoder| {
+                let policy = SegmentPolicy {
+                    max_duration: segment_minutes
+                        .filter(|&minutes| minutes > 0)
+                        .map(|minutes| Duration::from_secs(minutes as u64 * 60)),
+                    max_size_bytes: segment_size_mb
+                        .filter(|&mb| mb > 0)
+                        .map(|mb| mb as u64 * 1024 * 1024),
+                };
+                Recorder::start(
+                    path,
+                    format,
+                    remux_on_stop.unwrap_or(true),
+                    policy,
+                    encode,
+                    captions.unwrap_or(false),
+                    encoder,
+                )
+            })
+            .map_err(|e| operation_error("start recording", e))?;
+        if !started {
+            return Ok(false);
+        }
+
+        // Report the expanded name of the first file rather than the template
+        let path = stream
+            .outputs
+            .get_mut::<Recorder>(RECORDING_OUTPUT)
+            .map(|recorder| recorder.path().to_string())
+            .unwrap_or_default();
+        if let Some(events) = &stream.events {
+            let _ = events.call(
+                StreamEvent::RecordingStarted { path },
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        }
+        Ok(true)
+    }
+}
+
+#[napi(object)]
+pub struct RecordingLimitsOptions {
+    pub warn_free_space_mb: Option<u32>,
+    pub stop_free_space_mb: Option<u32>,
+    pub write_stall_ms: Option<u32>,
+}
+
+impl From<RecordingLimitsOptions> for RecordingLimits {
+    fn from(options: RecordingLimitsOptions) -> Self {
+        let defaults = RecordingLimits::default();
+        let mb = |mb: u32| mb as u64 * 1024 * 1024;
+        Self {
+            warn_free_bytes: options
+                .warn_free_space_mb
+                .map(mb)
+                .unwrap_or(defaults.warn_free_bytes),
+            stop_free_bytes: options
+                .stop_free_space_mb
+                .map(mb)
+                .unwrap_or(defaults.stop_free_bytes),
+            write_stall: options
+                .write_stall_ms
+                .map(|ms| Duration::from_millis(ms as u64))
+                .unwrap_or(defaults.write_stall),
+        }
+    }
+}
+
+#[napi]
+impl SlumpStream {
+    // Thresholds for the disk space and write latency warnings; the recording
+    // is stopped once free space drops below `stop_free_space_mb`
+    #[napi]
+    pub fn set_recording_limits(
+        &self,
+        mut env: napi::Env,
+        options: RecordingLimitsOptions,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let limits = RecordingLimits::from(options);
+        if limits.stop_free_bytes > limits.warn_free_bytes {
+            return Err(napi::Error::new(
+                napi::Status::InvalidArg,
+                "stopFreeSpaceMb must not exceed warnFreeSpaceMb".to_string(),
+            ));
+        }
+        stream.recording_limits = limits;
+        Ok(())
+    }
+
+    #[napi]
+    pub fn stop_recording(&self, mut env: napi::Env) -> napi::Result<Option<RecordingInfo>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let Some(recorder) = stream.outputs.take::<Recorder>(RECORDING_OUTPUT) else {
+            return Ok(None);
+        };
+
+        let summary = recorder
+            .stop()
+            .map_err(|e| operation_error("finalize recording", e))?;
+
+        if let Some(events) = &stream.events {
+            let _ = events.call(
+                summary.clone().into(),
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        }
+        Ok(Some(summary.into()))
+    }
+
+    #[napi]
+    pub fn pause_recording(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream
+            .outputs
+            .get_mut::<Recorder>(RECORDING_OUTPUT)
+            .map(|r| r.pause())
+            .unwrap_or(false))
+    }
+
+    #[napi]
+    pub fn resume_recording(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream
+            .outputs
+            .get_mut::<Recorder>(RECORDING_OUTPUT)
+            .map(|r| r.resume())
+            .unwrap_or(false))
+    }
+
+    // Chapter at the current position of the recording and the replay buffer;
+    // false when neither is running
+    #[napi]
+    pub fn add_marker(&self, mut env: napi::Env, label: String) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let recorded = stream
+            .outputs
+            .get_mut::<Recorder>(RECORDING_OUTPUT)
+            .map(|r| r.add_marker(&label))
+            .is_some();
+        let buffered = stream
+            .outputs
+            .get_mut::<ReplayBuffer>(REPLAY_OUTPUT)
+            .map(|r| r.add_marker(&label))
+            .unwrap_or(false);
+        Ok(recorded || buffered)
+    }
+
+    // Sends timed text to every viewer on the "captions" data channel,
+    // stamped with the frame captured last, RTP timestamp included for
+    // matching it against the received video, and into the recording when it
+    // was started with captions. False when nothing took it.
+    #[napi]
+    pub fn push_caption(&self, mut env: napi::Env, options: CaptionOptions) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let caption = options
+            .into_caption()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let message = caption.message(stream.last_frame);
+        let mut sent = false;
+        for transport in stream.peers.values() {
+            sent |= transport.send_caption(message.clone());
+        }
+        let recorded = match stream.outputs.get_mut::<Recorder>(RECORDING_OUTPUT) {
+            Some(recorder) => recorder
+                .write_text(&caption.text, caption.duration)
+                .map_err(|e| operation_error("record caption", e))?,
+            None => false,
+        };
+        Ok(sent || recorded)
+    }
+
+    #[napi]
+    pub fn enable_replay_buffer(&self, mut env: napi::Env, seconds: u32) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        // Re-enabling with a new window starts a fresh buffer
+        stream.outputs.remove(REPLAY_OUTPUT);
+        let max_bytes = stream.budget.replay_bytes;
+        stream
+            .add_output(REPLAY_OUTPUT, |encoder| {
+                Ok(ReplayBuffer::new(
+                    Duration::from_secs(seconds.max(1) as u64),
+                    max_bytes,
+                    encoder,
+                ))
+            })
+            .map_err(|e| operation_error("start replay buffer", e))
+    }
+
+    #[napi]
+    pub fn disable_replay_buffer(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        Ok(stream.outputs.remove(REPLAY_OUTPUT).is_some())
+    }
+
+    #[napi]
+    pub fn save_replay(
+        &self,
+        mut env: napi::Env,
+        path: String,
+        format: Option<String>,
+    ) -> napi::Result<AsyncTask<Blocking<RecordingInfo>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let mut state = shared.lock();
+            let stream = &mut *state;
+
+            let replay = stream
+                .outputs
+                .get_mut::<ReplayBuffer>(REPLAY_OUTPUT)
+                .ok_or_else(|| {
+                    napi::Error::new(
+                        napi::Status::GenericFailure,
+                        "Replay buffer is not enabled".to_string(),
+                    )
+                })?;
+
+            let format = match format {
+                Some(format) => format
+                    .parse::<RecordingFormat>()
+                    .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?,
+                None => RecordingFormat::default(),
+            };
+
+            let summary = replay
+                .save(&path, format)
+                .map_err(|e| operation_error("save replay", e))?;
+
+            if let Some(events) = &stream.events {
+                let _ = events.call(
+                    StreamEvent::ReplaySaved {
+                        path: summary.path.clone(),
+                        duration_secs: summary.duration.as_secs_f64(),
+                    },
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                );
+            }
+            Ok(summary.into())
+        }))
+    }
+}
+
+#[napi(object)]
+pub struct ClipExportOptions {
+    // Defaults to a timestamped file in the temp directory
+    pub path: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+}
+
+pub struct ExportClipTask {
+    clip: Clip,
+    path: String,
+    settings: ClipSettings,
+    cancel: Cancellation,
+}
+
+impl napi::Task for ExportClipTask {
+    type Output = RecordingSummary;
+    type JsValue = String;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        self.clip
+            .export(&self.path, self.settings, &self.cancel)
+            .map_err(|e| operation_error("export clip", e))
+    }
+
+    fn resolve(&mut self, _env: napi::Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output.path)
+    }
+}
+
+#[napi]
+impl SlumpStream {
+    // Cuts `duration` seconds starting `start_offset` seconds into the replay
+    // buffer; the encode runs on the libuv pool and resolves to the file path
+    #[napi]
+    pub fn export_clip(
+        &self,
+        mut env: napi::Env,
+        start_offset: f64,
+        duration: f64,
+        format: Option<String>,
+        options: Option<ClipExportOptions>,
+        cancel: Option<ClassInstance<CancelHandle>>,
+    ) -> napi::Result<AsyncTask<ExportClipTask>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        if !(start_offset >= 0.0) || !(duration > 0.0) {
+            return Err(napi::Error::new(
+                napi::Status::InvalidArg,
+                "Clip offset and duration must be positive".to_string(),
+            ));
+        }
+        let format = match format {
+            Some(format) => format
+                .parse::<ClipFormat>()
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?,
+            None => ClipFormat::default(),
+        };
+
+        let replay = stream
+            .outputs
+            .get_mut::<ReplayBuffer>(REPLAY_OUTPUT)
+            .ok_or_else(|| {
+                napi::Error::new(
+                    napi::Status::GenericFailure,
+                    "Replay buffer is not enabled".to_string(),
+                )
+            })?;
+        let clip = replay
+            .clip(
+                Duration::from_secs_f64(start_offset),
+                Duration::from_secs_f64(duration),
+            )
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+
+        let options = options.unwrap_or(ClipExportOptions {
+            path: None,
+            width: None,
+            height: None,
+            fps: None,
+        });
+        let path = options.path.unwrap_or_else(|| {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default();
+            std::env::temp_dir()
+                .join(format!("slump-clip-{}.{}", timestamp, format.extension()))
+                .to_string_lossy()
+                .into_owned()
+        });
+
+        Ok(AsyncTask::new(ExportClipTask {
+            clip,
+            path,
+            settings: ClipSettings {
+                format,
+                width: options.width,
+                height: options.height,
+                fps: options.fps,
+            },
+            cancel: cancellation(cancel),
+        }))
+    }
+}
+
+// Raw frame on its way from capture to the encoder
+struct EncodeJob {
+    frame: Frame,
+    keyframe: bool,
+    // When its capture started; latency is measured from here
+    captured_at: Instant,
+    // What it goes to peers with, and what captions refer to it by
+    timestamp: u32,
+    span: tracing::Span,
+}
+
+// Encoded frame and the peers connected when it was encoded
+struct SendJob {
+    data: Vec<u8>,
+    // From the capture time, so peers play it in step with the other tracks
+    timestamp: u32,
+    writers: Vec<TrackWriter>,
+    captured_at: Instant,
+    span: tracing::Span,
+}
+
+// State the capture loop carries from one tick to the next
+#[derive(Default)]
+struct CaptureLoop {
+    frame_index: u64,
+    placeholder_sent: Option<Instant>,
+    last_captured: Option<Instant>,
+}
+
+// What the capture stage reports of the capture it holds, for the control
+// loop's stats, watchdog and adaptation
+#[derive(Default, Clone, Copy)]
+struct CaptureStatus {
+    frame_rate: f64,
+    pool_misses: u64,
+    // Set as the capture goes in, see VideoCapture::is_source_paced
+    source_paced: bool,
+}
+
+// Likewise from the audio stage, of the microphone capture
+#[derive(Default, Clone, Copy)]
+struct AudioStatus {
+    dropped_samples: u64,
+    // 0-1
+    occupancy: f64,
+    buffered: Duration,
+}
+
+// Capture paces itself off the adaptive target, so update_stream and the
+// adaptive controller change the rate without restarting it. It never waits
+// on the runtime, and owns the capture: the state is locked to pick up
+// changes and copy the settings before a frame, and to fan it out after, but
+// not while it is grabbed and drawn on.
+fn spawn_capture_stage(
+    state: Arc<parking_lot::Mutex<StreamState>>,
+    mut device: StageDevice<VideoCapture, StreamState>,
+    frames: StageSender<EncodeJob>,
+    stop: Arc<AtomicBool>,
+    events: ThreadsafeFunction<StreamEvent>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let failure_state = state.clone();
+        let capture = move || {
+            let mut capture_loop = CaptureLoop::default();
+            let (mut pacer, shared) = {
+                let stream = state.lock();
+                (
+                    Pacer::new(stream.adaptive.target().fps),
+                    SharedStats::of(&stream),
+                )
+            };
+            let mut cpu_lap = CpuLap::start();
+            while !stop.load(Ordering::SeqCst) {
+                let ticked = capture_tick(
+                    &state,
+                    &mut device,
+                    &mut capture_loop,
+                    &mut pacer,
+                    &shared,
+                    &frames,
+                    &events,
+                );
+                if !ticked {
+                    break;
+                }
+                shared.cpu.capture.record(cpu_lap.lap());
+
+                if !pacer.wait() {
+                    shared.metrics.lock().unwrap().video_frames_late += 1;
+                }
+                shared.histograms.lock().pacing_error.record(pacer.late());
+                let skipped = pacer.take_skipped();
+                if skipped > 0 {
+                    shared.stats.lock().unwrap().frames.video.dropped_pacing += skipped;
+                }
+            }
+        };
+        if let Err(report) = panic::catch(capture) {
+            fail_stream(&failure_state, "capture", report);
+        }
+    })
+}
+
+// The accounting a stage thread records into, taken once as it starts. None
+// of it is replaced while the stream runs.
+struct SharedStats {
+    stats: Arc<Mutex<StreamStats>>,
+    metrics: Arc<Mutex<Metrics>>,
+    histograms: Arc<parking_lot::Mutex<FrameHistograms>>,
+    timings: Arc<StageTimings>,
+    cpu: Arc<StageCpu>,
+    clock: Arc<MediaClock>,
+}
+
+impl SharedStats {
+    fn of(stream: &StreamState) -> Self {
+        Self {
+            stats: stream.stats.clone(),
+            metrics: stream.metrics.clone(),
+            histograms: stream.histograms.clone(),
+            timings: stream.timings.clone(),
+            cpu: stream.cpu.clone(),
+            clock: stream.clock.clone(),
+        }
+    }
+}
+
+// One frame. False once the stream has failed.
+fn capture_tick(
+    state: &parking_lot::Mutex<StreamState>,
+    device: &mut StageDevice<VideoCapture, StreamState>,
+    capture_loop: &mut CaptureLoop,
+    pacer: &mut Pacer,
+    shared: &SharedStats,
+    frames: &StageSender<EncodeJob>,
+    events: &ThreadsafeFunction<StreamEvent>,
+) -> bool {
+    let mut guard = state.lock();
+    let stream = &mut *guard;
+    if stream.failure.is_some() {
+        return false;
+    }
+    pacer.set_fps(stream.adaptive.target().fps);
+    // Scene switches, rescales and reopened captures land here, between frames
+    device.apply_changes(stream);
+
+    // One span per tick, parenting every stage the frame passes through
+    capture_loop.frame_index += 1;
+    let frame_span = tracing::trace_span!(
+        "frame",
+        index = capture_loop.frame_index,
+        paused = stream.paused
+    );
+
+    // The secure desktop only captures black; say so, and cut to and from
+    // the notice on keyframes
+    if stream.video_source == VideoSource::Screen {
+        if let Some(active) = stream.secure_desktop.poll() {
+            stream.scene_cut = true;
+            let _ = events.call(
+                StreamEvent::SecureDesktop { active },
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        }
+        if stream.secure_desktop.is_active() && stream.secure_desktop_placeholder {
+            let due = capture_loop
+                .placeholder_sent
+                .is_none_or(|sent| sent.elapsed() >= PLACEHOLDER_INTERVAL);
+            if due && stream.video_encoder.is_some() {
+                capture_loop.placeholder_sent = Some(Instant::now());
+                let target = stream.adaptive.target();
+                let mut frame = video::placeholder_frame(target.width, target.height);
+                video::notice(&mut frame, SECURE_DESKTOP_NOTICE);
+                let drawn = Instant::now();
+                frames.push(EncodeJob {
+                    frame,
+                    keyframe: std::mem::take(&mut stream.scene_cut),
+                    captured_at: drawn,
+                    timestamp: shared.clock.timestamp(TrackKind::Video, drawn),
+                    span: frame_span.clone(),
+                });
+            }
+            return true;
+        }
+    }
+
+    // Copies of what the frame is drawn with, so the lock can go for the
+    // grab and the drawing
+    stream.overlay.expire();
+    let paused = stream.paused;
+    let privacy = stream.privacy.clone();
+    let quality_regions = stream.quality_regions.clone();
+    let overlay = stream.overlay.clone();
+    let crossfade = stream.crossfade.clone();
+    let burn_in = stream.burn_in;
+    let target_kbps = stream.adaptive.target().bitrate_kbps;
+    drop(guard);
+
+    // Capture once, then fan out to the outputs and the WebRTC encoder
+    let Some(capture) = device.device.as_mut() else {
+        return true;
+    };
+    let capture_started = Instant::now();
+    let captured = frame_span.in_scope(|| capture.capture_frame().ok().flatten());
+    let Some(mut captured) = captured else {
+        return true;
+    };
+    let capture_elapsed = capture_started.elapsed();
+    let stamp = FrameStamp {
+        index: capture_loop.frame_index,
+        capture_ms: logging::timestamp_ms() - capture_started.elapsed().as_secs_f64() * 1000.0,
+        rtp_timestamp: shared.clock.timestamp(TrackKind::Video, capture_started),
+    };
+
+    // Everything downstream, outputs included, sees the privacy regions and
+    // then the annotations, which may be drawn over them
+    let size = capture.source_size();
+    frame_span.in_scope(|| privacy.apply(&mut captured, size));
+    quality_regions.apply(&mut captured, size);
+    frame_span.in_scope(|| overlay.draw(&mut captured));
+    let fading = crossfade
+        .as_ref()
+        .is_some_and(|crossfade| frame_span.in_scope(|| crossfade.blend(&mut captured)));
+    // On top of everything else. The clock changes every frame, so while it
+    // is on duplicate skipping never hides a dropped frame.
+    if let Some(corner) = burn_in {
+        let sent_kbps = shared.stats.lock().unwrap().sent_kbps.video_kbps;
+        let text = burn_in_text(stamp, target_kbps, sent_kbps);
+        frame_span.in_scope(|| video::burn_in(&mut captured, corner, &text));
+    }
+    let source = capture.source_frame();
+
+    let mut guard = state.lock();
+    let stream = &mut *guard;
+    stream.capture_status.frame_rate = capture.get_frame_rate();
+    stream.capture_status.pool_misses = capture.pool_misses();
+    // A fade that's over is dropped, unless a newer switch replaced it
+    if let Some(done) = crossfade.filter(|_| !fading) {
+        if stream
+            .crossfade
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, &done))
+        {
+            stream.crossfade = None;
+        }
+    }
+
+    shared.timings.capture.record(capture_elapsed);
+    if let Some(last) = capture_loop.last_captured.replace(capture_started) {
+        shared
+            .histograms
+            .lock()
+            .capture_interval
+            .record(capture_started - last);
+    }
+    {
+        let mut metrics = shared.metrics.lock().unwrap();
+        metrics.video_frames_captured += 1;
+        metrics
+            .capture_seconds
+            .observe(capture_elapsed.as_secs_f64());
+    }
+    shared.stats.lock().unwrap().frames.video.captured += 1;
+    if let Some(watchdog) = stream.watchdog.as_mut() {
+        watchdog.video_captured();
+    }
+    stream.last_frame = stamp;
+    // Only a display capture has a pointer to report
+    if stream.video_source == VideoSource::Screen && !paused {
+        let display_index = stream.display_index;
+        if let Some(cursor) = stream
+            .cursor
+            .as_mut()
+            .and_then(|cursor| cursor.poll(display_index))
+        {
+            let message = cursor.message(stamp.index);
+            for transport in stream.peers.values() {
+                transport.send_control(message.clone());
+            }
+        }
+    }
+    if let Some(frame_callback) = stream.frame_callback.as_mut().filter(|c| c.tap.due()) {
+        let tapped = match frame_callback.tap.source() {
+            TapSource::Scaled => Some(&captured),
+            TapSource::Capture => source,
+        };
+        if let Some(tapped) = tapped.and_then(tap::tap_frame) {
+            // A callback that is still busy loses the frame rather than queueing it
+            let _ = frame_callback
+                .callback
+                .call(tapped, ThreadsafeFunctionCallMode::NonBlocking);
+        }
+    }
+    // Sinks that encode the raw capture themselves would get it unobscured.
+    // Outputs are written with the state locked, since they live in it.
+    let output_source = source.filter(|_| privacy.is_empty());
+    for (name, e) in frame_span.in_scope(|| {
+        stream
+            .outputs
+            .write_video(&captured, output_source, capture_started)
+    }) {
+        tracing::error!("Output {} failed: {}", name, e);
+        let _ = events.call(
+            StreamEvent::Warning(format!("Output {} stopped: {}", name, e)),
+            ThreadsafeFunctionCallMode::NonBlocking,
+        );
+    }
+
+    // While paused only the placeholder goes out, at a trickle
+    let outgoing = if !paused {
+        Some(&captured)
+    } else if capture_loop
+        .placeholder_sent
+        .is_none_or(|sent| sent.elapsed() >= PLACEHOLDER_INTERVAL)
+    {
+        stream.placeholder.as_ref()
+    } else {
+        None
+    };
+    if paused && outgoing.is_some() {
+        capture_loop.placeholder_sent = Some(Instant::now());
+    }
+
+    // A static screen skips the encoder; the placeholder is already rate limited
+    let decision = match stream.dedup.as_mut().filter(|_| !paused) {
+        Some(dedup) => frame_span.in_scope(|| dedup.check(&captured)),
+        None => Decision::Encode,
+    };
+    if decision == Decision::Skip {
+        shared.metrics.lock().unwrap().video_frames_skipped += 1;
+        shared.stats.lock().unwrap().frames.video.duplicated += 1;
+        return true;
+    }
+
+    // Hand off to the encode stage; if it is behind, the older frame goes
+    let Some(outgoing) = outgoing else {
+        return true;
+    };
+    if !stream.video_encoder.is_some() {
+        shared
+            .stats
+            .lock()
+            .unwrap()
+            .frames
+            .video
+            .dropped_encoder_busy += 1;
+        return true;
+    }
+    let kept = frames.push(EncodeJob {
+        frame: outgoing.clone(),
+        keyframe: decision == Decision::Keyframe || std::mem::take(&mut stream.scene_cut),
+        captured_at: capture_started,
+        timestamp: stamp.rtp_timestamp,
+        span: frame_span.clone(),
+    });
+    if !kept {
+        shared.stats.lock().unwrap().frames.video.dropped_queue_full += 1;
+    }
+    true
+}
+
+// Wall-clock capture time in UTC to the millisecond, the capture tick, and
+// video sent over the last second against the encoder target
+fn burn_in_text(frame: FrameStamp, target_kbps: u32, sent_kbps: f64) -> String {
+    let ms = frame.capture_ms as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03} UTC\nframe {}\n{:.0}/{} kbps",
+        ms / 3_600_000 % 24,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000,
+        frame.index,
+        sent_kbps,
+        target_kbps
+    )
+}
+
+// The encoder gets its own thread so a slow encode holds up neither capture
+// nor sending. It owns the encoder, so the state is only locked to pick up
+// changes and keyframe requests before an encode and to note the result
+// after it.
+fn spawn_encode_stage(
+    state: Arc<parking_lot::Mutex<StreamState>>,
+    mut device: StageDevice<VideoEncoder, StreamState>,
+    mut jobs: StageReceiver<EncodeJob>,
+    sends: StageSender<SendJob>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let failure_state = state.clone();
+        let encode = move || {
+            let rt = runtime::get().unwrap();
+            let shared = SharedStats::of(&state.lock());
+            rt.block_on(async {
+                let mut keyframe_needed = false;
+                let mut cpu_lap = CpuLap::start();
+                while let Some(job) = jobs.recv().await {
+                    // Charges the previous frame, whichever way it left the loop
+                    shared.cpu.encode.record(cpu_lap.lap());
+                    let keyframe = {
+                        let mut guard = state.lock();
+                        let stream = &mut *guard;
+                        device.apply_changes(stream);
+                        stream.collect_keyframe_requests();
+                        if device.device.is_none() {
+                            None
+                        } else {
+                            let forced = std::mem::take(&mut keyframe_needed) || job.keyframe;
+                            Some(stream.video_keyframes.poll(forced))
+                        }
+                    };
+                    let (Some(encoder), Some(keyframe)) = (device.device.as_mut(), keyframe) else {
+                        shared
+                            .stats
+                            .lock()
+                            .unwrap()
+                            .frames
+                            .video
+                            .dropped_encoder_busy += 1;
+                        continue;
+                    };
+                    if keyframe {
+                        encoder.request_keyframe();
+                    }
+
+                    let started = Instant::now();
+                    let encoded = job.span.in_scope(|| encoder.encode(&job.frame));
+                    let elapsed = started.elapsed();
+                    shared.timings.encode.record(elapsed);
+                    shared.histograms.lock().encode.record(elapsed);
+                    shared
+                        .metrics
+                        .lock()
+                        .unwrap()
+                        .encode_seconds
+                        .observe(elapsed.as_secs_f64());
+
+                    // Peers connected when it was encoded get it
+                    let writers: Vec<TrackWriter> = {
+                        let mut stream = state.lock();
+                        if let Some(watchdog) = stream.watchdog.as_mut() {
+                            watchdog.encoder_fed();
+                            if matches!(encoded, Ok(Some(_))) {
+                                watchdog.encoder_output();
+                            }
+                        }
+                        match &encoded {
+                            Ok(Some(_)) => stream
+                                .peers
+                                .values()
+                                .filter_map(|transport| transport.writer(TrackKind::Video))
+                                .collect(),
+                            _ => Vec::new(),
+                        }
+                    };
+                    let data = match encoded {
+                        Ok(Some(data)) => data,
+                        // Held for now, e.g. lookahead
+                        Ok(None) => continue,
+                        Err(e) => {
+                            tracing::warn!("Failed to encode video frame: {}", e);
+                            shared
+                                .stats
+                                .lock()
+                                .unwrap()
+                                .frames
+                                .video
+                                .dropped_encoder_busy += 1;
+                            continue;
+                        }
+                    };
+                    {
+                        let mut metrics = shared.metrics.lock().unwrap();
+                        metrics.video_frames_encoded += 1;
+                        metrics.video_bytes_encoded += data.len() as u64;
+                    }
+                    shared.stats.lock().unwrap().frames.video.encoded += 1;
+                    shared.timings.to_encoded.record(job.captured_at.elapsed());
+
+                    let send = SendJob {
+                        data,
+                        timestamp: job.timestamp,
+                        writers,
+                        captured_at: job.captured_at,
+                        span: job.span,
+                    };
+                    if !sends.push(send) {
+                        shared.stats.lock().unwrap().frames.video.dropped_queue_full += 1;
+                        keyframe_needed = true;
+                    }
+                }
+            });
+        };
+        if let Err(report) = panic::catch(encode) {
+            fail_stream(&failure_state, "encode", report);
+        }
+    })
+}
+
+// The camera track's capture, on its own thread like the screen's and paced
+// at CAMERA_FPS. It owns the camera, so the state is only locked to pick up
+// changes between frames. Idles while there is no camera.
+fn spawn_camera_capture_stage(
+    state: Arc<parking_lot::Mutex<StreamState>>,
+    mut device: StageDevice<VideoCapture, StreamState>,
+    frames: StageSender<EncodeJob>,
+    stop: Arc<AtomicBool>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let failure_state = state.clone();
+        let capture = move || {
+            let shared = SharedStats::of(&state.lock());
+            let mut pacer = Pacer::new(CAMERA_FPS);
+            while !stop.load(Ordering::SeqCst) {
+                let paused = {
+                    let mut guard = state.lock();
+                    let stream = &mut *guard;
+                    if stream.failure.is_some() {
+                        break;
+                    }
+                    device.apply_changes(stream);
+                    stream.paused
+                };
+                if let Some(camera) = device.device.as_mut() {
+                    let captured_at = Instant::now();
+                    if let Ok(Some(frame)) = camera.capture_frame() {
+                        shared.stats.lock().unwrap().frames.camera.captured += 1;
+                        // Peers get nothing from the camera while paused
+                        if !paused {
+                            let kept = frames.push(EncodeJob {
+                                frame,
+                                keyframe: false,
+                                captured_at,
+                                timestamp: shared.clock.timestamp(TrackKind::Camera, captured_at),
+                                span: tracing::trace_span!("camera frame"),
+                            });
+                            if !kept {
+                                shared
+                                    .stats
+                                    .lock()
+                                    .unwrap()
+                                    .frames
+                                    .camera
+                                    .dropped_queue_full += 1;
+                            }
+                        }
+                    }
+                }
+                pacer.wait();
+            }
+        };
+        if let Err(report) = panic::catch(capture) {
+            fail_stream(&failure_state, "camera capture", report);
+        }
+    })
+}
+
+// Encodes the camera track and writes it to the peers itself; one small
+// stream doesn't need a send stage of its own. Owns the camera encoder like
+// the encode stage owns the screen's.
+fn spawn_camera_encode_stage(
+    state: Arc<parking_lot::Mutex<StreamState>>,
+    mut device: StageDevice<VideoEncoder, StreamState>,
+    mut jobs: StageReceiver<EncodeJob>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let failure_state = state.clone();
+        let encode = move || {
+            let rt = runtime::get().unwrap();
+            let shared = SharedStats::of(&state.lock());
+            rt.block_on(async {
+                // Sends wait here while the A/V offset holds the camera back
+                let mut delayed = DelayLine::default();
+                loop {
+                    let delay = shared.clock.send_delay(TrackKind::Camera);
+                    let job = match delayed.next(&mut jobs, delay).await {
+                        Some(Delayed::Arrived(job)) => job,
+                        Some(Delayed::Due(send)) => {
+                            send_camera_frame(send, &shared).await;
+                            continue;
+                        }
+                        None => break,
+                    };
+                    let keyframe = {
+                        let mut guard = state.lock();
+                        let stream = &mut *guard;
+                        device.apply_changes(stream);
+                        stream.collect_keyframe_requests();
+                        stream.camera_keyframes.poll(false)
+                    };
+                    let Some(encoder) = device.device.as_mut() else {
+                        shared
+                            .stats
+                            .lock()
+                            .unwrap()
+                            .frames
+                            .camera
+                            .dropped_encoder_busy += 1;
+                        continue;
+                    };
+                    if keyframe {
+                        encoder.request_keyframe();
+                    }
+                    let data = match encoder.encode(&job.frame) {
+                        Ok(Some(data)) => data,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            log::warn!("Failed to encode camera frame: {}", e);
+                            shared
+                                .stats
+                                .lock()
+                                .unwrap()
+                                .frames
+                                .camera
+                                .dropped_encoder_busy += 1;
+                            continue;
+                        }
+                    };
+                    shared.stats.lock().unwrap().frames.camera.encoded += 1;
+
+                    // Peers connected when it was encoded get it
+                    let writers: Vec<TrackWriter> = state
+                        .lock()
+                        .peers
+                        .values()
+                        .filter_map(|transport| transport.writer(TrackKind::Camera))
+                        .collect();
+                    delayed.push(
+                        Instant::now(),
+                        SendJob {
+                            data,
+                            timestamp: job.timestamp,
+                            writers,
+                            captured_at: job.captured_at,
+                            span: job.span,
+                        },
+                    );
+                }
+            });
+        };
+        if let Err(report) = panic::catch(encode) {
+            fail_stream(&failure_state, "camera encode", report);
+        }
+    })
+}
+
+async fn send_camera_frame(job: SendJob, shared: &SharedStats) {
+    let mut sent = false;
+    for writer in &job.writers {
+        match writer.write(&job.data, job.timestamp).await {
+            Ok(()) => sent = true,
+            Err(e) => log::error!("Failed to send camera frame to {}: {}", writer.peer_id(), e),
+        }
+    }
+    if sent {
+        shared.stats.lock().unwrap().frames.camera.sent += 1;
+    }
+}
+
+// What the audio stage owns while the stream runs
+struct AudioStageDevices {
+    microphone: StageDevice<AudioCapture, StreamState>,
+    system: StageDevice<AudioCapture, StreamState>,
+    encoder: StageDevice<AudioEncoder, StreamState>,
+}
+
+// A mixed frame for the peers, with who to send it to
+struct AudioSend {
+    samples: usize,
+    captured_at: Instant,
+    writers: Vec<TrackWriter>,
+}
+
+// What the encoder made of one, with the RTP timestamp of each packet
+struct AudioPackets {
+    packets: Vec<(Vec<u8>, u32)>,
+    writers: Vec<TrackWriter>,
+}
+
+// Audio capture, mixing, the outputs that mux audio and the Opus encode for
+// the peers, one frame per tick. Reading a device blocks until it has a
+// packet, so this gets a thread rather than waiting with the state locked. It
+// owns the microphone, system audio and the encoder; the state is locked to
+// pick up changes before a frame and to hand it on after. Idles while there
+// is no microphone.
+fn spawn_audio_stage(
+    state: Arc<parking_lot::Mutex<StreamState>>,
+    mut devices: AudioStageDevices,
+    stop: Arc<AtomicBool>,
+    events: ThreadsafeFunction<StreamEvent>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let failure_state = state.clone();
+        let capture = move || {
+            let rt = runtime::get().unwrap();
+            let shared = SharedStats::of(&state.lock());
+            let mut pacer = Pacer::new(audio::SAMPLE_RATE as u32 / audio::FRAME_SIZE as u32);
+            let mut buffer = vec![0.0f32; audio::FRAME_SIZE * audio::CHANNELS as usize];
+            let mut system_buffer = buffer.clone();
+            let mut stamps = AudioTimestamps::default();
+            // Sends wait here while the A/V offset holds audio back, going
+            // out on the first tick they're due
+            let mut delayed = DelayLine::default();
+            while !stop.load(Ordering::SeqCst) {
+                {
+                    let mut guard = state.lock();
+                    let stream = &mut *guard;
+                    if stream.failure.is_some() {
+                        break;
+                    }
+                    devices.microphone.apply_changes(stream);
+                    devices.system.apply_changes(stream);
+                    devices.encoder.apply_changes(stream);
+                }
+                let send = audio_tick(
+                    &state,
+                    &mut devices,
+                    &mut buffer,
+                    &mut system_buffer,
+                    &shared,
+                    &events,
+                );
+                if let (Some(send), Some(encoder)) = (send, devices.encoder.device.as_mut()) {
+                    let samples = &buffer[..send.samples];
+                    if let Some(packets) =
+                        encode_audio(encoder, &mut stamps, samples, send, &shared)
+                    {
+                        delayed.push(Instant::now(), packets);
+                    }
+                }
+                let delay = shared.clock.send_delay(TrackKind::Audio);
+                while let Some(packets) = delayed.pop_due(Instant::now(), delay) {
+                    rt.block_on(send_audio(packets, &shared));
+                }
+                pacer.wait();
+            }
+        };
+        if let Err(report) = panic::catch(capture) {
+            fail_stream(&failure_state, "audio", report);
+        }
+    })
+}
+
+// One audio frame: the microphone, with system audio mixed under it. What
+// the peers should get of it comes back, left in `buffer`.
+fn audio_tick(
+    state: &parking_lot::Mutex<StreamState>,
+    devices: &mut AudioStageDevices,
+    buffer: &mut [f32],
+    system_buffer: &mut [f32],
+    shared: &SharedStats,
+    events: &ThreadsafeFunction<StreamEvent>,
+) -> Option<AudioSend> {
+    let audio = devices.microphone.device.as_mut()?;
+    if let Err(e) = audio.capture_audio() {
+        log::warn!("Failed to capture audio: {}", e);
+    }
+    let captured_at = Instant::now();
+    let read = audio.read_audio(buffer);
+    let status = AudioStatus {
+        dropped_samples: audio.dropped_samples(),
+        occupancy: audio.occupancy(),
+        buffered: audio.buffered(),
+    };
+    // Short reads mix in silence rather than holding the microphone back
+    let mixing = match devices.system.device.as_mut().filter(|_| read > 0) {
+        Some(system) => {
+            if let Err(e) = system.capture_audio() {
+                log::warn!("Failed to capture system audio: {}", e);
+            }
+            let mixed = system.read_audio(&mut system_buffer[..read]);
+            system_buffer[mixed..read].fill(0.0);
+            true
+        }
+        None => false,
+    };
+
+    let mut guard = state.lock();
+    let stream = &mut *guard;
+    stream.audio_status = status;
+    if read == 0 {
+        return None;
+    }
+    let samples = &mut buffer[..read];
+    if mixing {
+        stream.ducker.mix(samples, &system_buffer[..read]);
+    }
+    stream.audio_meter.measure(samples);
+    if let Some(watchdog) = stream.watchdog.as_mut() {
+        watchdog.audio_captured();
+    }
+    shared.metrics.lock().unwrap().audio_frames_captured += 1;
+    shared.stats.lock().unwrap().frames.audio.captured += 1;
+    for (name, e) in stream.outputs.write_audio(samples) {
+        log::error!("Output {} failed: {}", name, e);
+        let _ = events.call(
+            StreamEvent::Warning(format!("Output {} stopped: {}", name, e)),
+            ThreadsafeFunctionCallMode::NonBlocking,
+        );
+    }
+
+    // Peers get no media while paused, and nothing is encoded for nobody
+    if stream.paused {
+        return None;
+    }
+    let writers: Vec<TrackWriter> = stream
+        .peers
+        .values()
+        .filter_map(|transport| transport.writer(TrackKind::Audio))
+        .collect();
+    (!writers.is_empty()).then_some(AudioSend {
+        samples: read,
+        captured_at,
+        writers,
+    })
+}
+
+// Encodes a frame, stamping the packets it completes; None if there are none
+fn encode_audio(
+    encoder: &mut AudioEncoder,
+    stamps: &mut AudioTimestamps,
+    samples: &[f32],
+    send: AudioSend,
+    shared: &SharedStats,
+) -> Option<AudioPackets> {
+    let packets = match encoder.encode(samples) {
+        Ok(packets) => packets,
+        Err(e) => {
+            log::warn!("Failed to encode audio: {}", e);
+            return None;
+        }
+    };
+    if packets.is_empty() {
+        return None;
+    }
+    shared.stats.lock().unwrap().frames.audio.encoded += packets.len() as u64;
+    let clock = shared.clock.timestamp(TrackKind::Audio, send.captured_at);
+    Some(AudioPackets {
+        packets: packets
+            .into_iter()
+            .map(|packet| (packet, stamps.next(clock, audio::FRAME_SIZE as u32)))
+            .collect(),
+        writers: send.writers,
+    })
+}
+
+async fn send_audio(audio: AudioPackets, shared: &SharedStats) {
+    for (packet, timestamp) in &audio.packets {
+        let mut sent = false;
+        for writer in &audio.writers {
+            match writer.write(packet, *timestamp).await {
+                Ok(()) => sent = true,
+                Err(e) => log::error!("Failed to send audio frame to {}: {}", writer.peer_id(), e),
+            }
+        }
+        if sent {
+            shared.stats.lock().unwrap().frames.audio.sent += 1;
+        }
+    }
+}
+
+// A pipeline thread died. Its queues close behind it, so the other stages wind
+// down; peers and devices stay open until stop().
+fn fail_stream(state: &parking_lot::Mutex<StreamState>, stage: &str, report: panic::PanicReport) {
+    log::error!("{} stage panicked: {}", stage, report.message);
+    let mut stream = state.lock();
+    stream.failure = Some(format!("{} stage panicked: {}", stage, report.message));
+    if let Some(events) = &stream.events {
+        let error = error::SlumpError::Panicked(format!("{} stage panicked: {}", stage, report));
+        let _ = events.call(error.into(), ThreadsafeFunctionCallMode::NonBlocking);
+    }
+}
+
+// Sending gets a thread too; the writes are async, so it drives them itself
+// with block_on rather than queueing them on the shared runtime
+fn spawn_send_stage(
+    state: Arc<parking_lot::Mutex<StreamState>>,
+    jobs: StageReceiver<SendJob>,
+    stats: Arc<Mutex<StreamStats>>,
+    metrics: Arc<Mutex<Metrics>>,
+) -> std::thread::JoinHandle<()> {
+    let (timings, cpu, clock) = {
+        let stream = state.lock();
+        (
+            stream.timings.clone(),
+            stream.cpu.clone(),
+            stream.clock.clone(),
+        )
+    };
+    std::thread::spawn(move || {
+        let send = move || {
+            let rt = runtime::get().unwrap();
+            rt.block_on(run_send_stage(jobs, stats, metrics, timings, cpu, clock));
+        };
+        if let Err(report) = panic::catch(send) {
+            fail_stream(&state, "send", report);
+        }
+    })
+}
+
+// Writes encoded frames to the peers without touching the stream state, so a
+// slow network backs up the send queue instead of stalling capture
+async fn run_send_stage(
+    mut jobs: StageReceiver<SendJob>,
+    stats: Arc<Mutex<StreamStats>>,
+    metrics: Arc<Mutex<Metrics>>,
+    timings: Arc<StageTimings>,
+    cpu: Arc<StageCpu>,
+    clock: Arc<MediaClock>,
+) {
+    let mut cpu_lap = CpuLap::start();
+    // Jobs wait here while the A/V offset holds video back
+    let mut delayed = DelayLine::default();
+    loop {
+        let job = match delayed
+            .next(&mut jobs, clock.send_delay(TrackKind::Video))
+            .await
+        {
+            Some(Delayed::Due(job)) => job,
+            Some(Delayed::Arrived(job)) => {
+                delayed.push(Instant::now(), job);
+                continue;
+            }
+            None => break,
+        };
+        let started = Instant::now();
+        let mut sent = 0;
+        let mut failed = 0;
+        for writer in &job.writers {
+            match writer
+                .write(&job.data, job.timestamp)
+                .instrument(job.span.clone())
+                .await
+            {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    failed += 1;
+                    tracing::error!("Failed to send video frame to {}: {}", writer.peer_id(), e);
+                }
+            }
+        }
+        timings.send.record(started.elapsed());
+        let latency = job.captured_at.elapsed();
+        if sent > 0 {
+            timings.to_sent.record(latency);
+        }
+
+        {
+            let mut metrics = metrics.lock().unwrap();
+            if sent > 0 {
+                metrics.latency_seconds.observe(latency.as_secs_f64());
+            }
+            metrics.video_frames_sent += sent;
+            metrics.video_bytes_sent += sent * job.data.len() as u64;
+            metrics.send_errors += failed;
+        }
+        if sent > 0 {
+            stats.lock().unwrap().frames.video.sent += 1;
+        }
+        cpu.send.record(cpu_lap.lap());
+    }
+}
+
+// Stops the worker loop, then releases the devices and peers; shared by
+// stop() and Drop
+fn stop_stream(shared: &parking_lot::Mutex<StreamState>) -> napi::Result<bool> {
+    let mut state = shared.lock();
+    if !state.running {
+        return Ok(false);
+    }
+
+    // The worker locks the state on every tick, so it has to be joined
+    // without holding the lock
+    state.running = false;
+    let shutdown_tx = state.shutdown_tx.take();
+    let worker = state.worker.take();
+    drop(state);
+    if let Some(shutdown_tx) = shutdown_tx {
+        let _ = shutdown_tx.send(());
+    }
+    if let Some(worker) = worker {
+        if worker.join().is_err() {
+            log::error!("Streaming worker panicked");
+        }
+    }
+
+    let mut state = shared.lock();
+    let stream = &mut *state;
+    stream.paused = false;
+    stream.placeholder = None;
+    stream.watchdog = None;
+    stream.queues.clear();
+    stream.signaling_server = None;
+    stream.signaling_client = None;
+    stream.signaling_tx = None;
+    let recorder = stream.outputs.take::<Recorder>(RECORDING_OUTPUT);
+    stream.outputs.clear();
+    if let Some(recorder) = recorder {
+        match recorder.stop() {
+            Ok(summary) => {
+                if let Some(events) = &stream.events {
+                    let _ = events.call(summary.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Err(e) => log::error!("Failed to finalize recording: {}", e),
+        }
+    }
+
+    // Close every peer before releasing the devices so queued media is flushed
+    stream.presence.clear();
+    let mut peers = std::mem::take(&mut stream.peers);
+    let mut whip = stream.whip.take();
+    runtime::get()
+        .map_err(|e| {
+            napi::Error::new(
+                napi::Status::GenericFailure,
+                format!("Failed to create runtime: {}", e),
+            )
+        })?
+        .block_on(async {
+            if let Some(whip) = whip.as_mut() {
+                if let Err(e) = whip.teardown().await {
+                    log::warn!("Failed to tear down WHIP session: {}", e);
+                }
+            }
+            for transport in peers.values_mut() {
+                if let Err(e) = transport.close().await {
+                    log::warn!("Failed to close peer {}: {}", transport.peer_id(), e);
+                }
+            }
+        });
+    drop(peers);
+
+    stream.video_capture = Owned::default();
+    stream.video_encoder = Owned::default();
+    stream.note_video_capture(None);
+    stream.camera_capture = Owned::default();
+    stream.camera_encoder = Owned::default();
+    stream.audio_capture = Owned::default();
+    stream.audio_encoder = Owned::default();
+    stream.system_audio = Owned::default();
+
+    if let Some(events) = stream.events.take() {
+        let _ = events.call(
+            StreamEvent::Disconnected,
+            ThreadsafeFunctionCallMode::NonBlocking,
+        );
+        // Everything is released, so the same instance can be started again
+        let _ = events.call(
+            StreamEvent::Stopped,
+            ThreadsafeFunctionCallMode::NonBlocking,
+        );
+    }
+
+    Ok(true)
+}
+
+// A handle collected by JS releases its devices and peers
+// Runs on the JS thread during garbage collection, so it only tells the
+// worker to wind down; joining it, closing peers and finalizing a recording
+// happen on a thread of their own
+impl Drop for SlumpStream {
+    fn drop(&mut self) {
+        let shutdown_tx = {
+            let mut state = self.state.lock();
+            if !state.running {
+                return;
+            }
+            state.shutdown_tx.take()
+        };
+        if let Some(shutdown_tx) = shutdown_tx {
+            let _ = shutdown_tx.send(());
+        }
+        let state = Arc::clone(&self.state);
+        std::thread::spawn(move || {
+            if let Err(e) = stop_stream(&state) {
+                log::warn!("Failed to stop stream: {}", e);
+            }
+        });
+    }
+}
+
+// Viewers from the embedded signaling server each get their own peer. The
+// state is only locked between awaits, so a slow peer connection holds up
+// neither the pipeline nor the API.
+async fn handle_signaling_event(state: &parking_lot::Mutex<StreamState>, event: SignalingEvent) {
+    match event {
+        SignalingEvent::ViewerConnected {
+            viewer_id,
+            outgoing,
+        } => {
+            let (stun_servers, turn_servers, track_kinds, rtp_history, impairment, signaling_tx) = {
+                let stream = state.lock();
+                (
+                    stream.stun_servers.clone(),
+                    stream.turn_servers.clone(),
+                    stream.track_kinds(),
+                    stream.budget.rtp_history,
+                    stream.impairment.clone(),
+                    stream.signaling_tx.clone(),
+                )
+            };
+            let transport = match WebRTCTransport::new(
+                viewer_id.clone(),
+                stun_servers,
+                turn_servers,
+                &track_kinds,
+                rtp_history,
+                impairment,
+            )
+            .await
+            {
+                Ok(transport) => transport,
+                Err(e) => {
+                    log::error!("Failed to create transport for {}: {}", viewer_id, e);
+                    return;
+                }
+            };
+
+            transport.forward_local_candidates(outgoing.clone());
+            if let Some(events) = signaling_tx {
+                let viewer = viewer_id.clone();
+                transport.on_control_message(move |message| {
+                    let _ = events.send(SignalingEvent::Control {
+                        viewer_id: viewer.clone(),
+                        message,
+                    });
+                });
+            }
+            match transport.create_offer().await {
+                Ok(sdp) => {
+                    let _ = outgoing.send(SignalMessage::Offer { sdp });
+                    state.lock().peers.insert(viewer_id, transport);
+                }
+                Err(e) => log::error!("Failed to create offer for {}: {}", viewer_id, e),
+            }
+        }
+        SignalingEvent::Message {
+            viewer_id,
+            message: SignalMessage::Capabilities(capabilities),
+        } => {
+            log::debug!("{} advertised {:?}", viewer_id, capabilities);
+            state
+                .lock()
+                .peer_capabilities
+                .insert(viewer_id, capabilities);
+        }
+        SignalingEvent::Message { viewer_id, message } => {
+            if let Some(transport) = state.lock().peers.get(&viewer_id) {
+                if let Err(e) = transport.handle_signal(&message) {
+                    log::warn!("Failed to handle signal from {}: {}", viewer_id, e);
+                }
+            }
+        }
+        SignalingEvent::StateChanged { state: signaling } => {
+            if let Some(events) = &state.lock().events {
+                let _ = events.call(
+                    StreamEvent::SignalingState(signaling.as_str().to_string()),
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                );
+            }
+        }
+        SignalingEvent::Failed { reason } => {
+            if let Some(events) = &state.lock().events {
+                let _ = events.call(
+                    error::SlumpError::SignalingClosed(reason).into(),
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                );
+            }
+        }
+        SignalingEvent::Control { viewer_id, message } => {
+            let mut stream = state.lock();
+            // A viewer that already left has nothing to say, and anything
+            // that is neither presence nor input is ignored
+            if !stream.peers.contains_key(&viewer_id) {
+                return;
+            }
+            if let Ok(presence) = serde_json::from_str::<PresenceMessage>(&message) {
+                let change = stream.presence.handle(&viewer_id, presence);
+                stream.presence_changed(viewer_id, change);
+                return;
+            }
+            let Ok(input) = serde_json::from_str::<InputEvent>(&message) else {
+                return;
+            };
+            let event = match stream.input.check(&viewer_id, &input) {
+                Verdict::Allowed => StreamEvent::from_input(viewer_id, input),
+                Verdict::NeedsApproval => StreamEvent::InputRequested { viewer_id },
+                Verdict::Denied => return,
+            };
+            if let Some(events) = &stream.events {
+                let _ = events.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+            }
+        }
+        SignalingEvent::ViewerDisconnected { viewer_id } => {
+            let transport = {
+                let mut stream = state.lock();
+                stream.input.forget(&viewer_id);
+                let left = stream.presence.leave(&viewer_id, LeaveReason::Disconnected);
+                stream.presence_changed(viewer_id.clone(), left);
+                stream.peer_capabilities.remove(&viewer_id);
+                stream.peers.remove(&viewer_id)
+            };
+            if let Some(mut transport) = transport {
+                if let Err(e) = transport.close().await {
+                    log::warn!("Failed to close peer {}: {}", viewer_id, e);
+                }
+            }
+        }
+    }
+}
+
+#[napi]
+impl SlumpStream {
+    #[napi]
+    pub fn set_degradation_preference(
+        &self,
+        mut env: napi::Env,
+        preference: String,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        let preference = preference
+            .parse::<DegradationPreference>()
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        stream.adaptive.set_preference(preference);
+
+        Ok(())
+    }
+
+    // Takes effect from the next stats window; see StreamOptions::adaptation_priority
+    #[napi]
+    pub fn set_adaptation_priority(
+        &self,
+        mut env: napi::Env,
+        priority: Vec<String>,
+    ) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let priority = adaptive::parse_priority(&priority)
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        self.state.lock().adaptive.set_priority(priority);
+        Ok(())
+    }
+
+    // Moves audio in outputs already running as well as later ones, and for
+    // peers from the next frame. A shift towards earlier drops that much
+    // audio from outputs rather than rewinding timestamps.
+    #[napi]
+    pub fn set_audio_offset(&self, mut env: napi::Env, ms: i32) -> napi::Result<()> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        validate_audio_offset(ms)
+            .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e.to_string()))?;
+        let mut stream = self.state.lock();
+        stream.outputs.set_audio_offset(ms);
+        stream.clock.set_audio_offset(ms);
+        Ok(())
+    }
+
+    // Stops media to peers without tearing anything down, so resume is instant.
+    // With `placeholder`, viewers see a black frame instead of the last one.
+    #[napi]
+    pub fn pause_stream(
+        &self,
+        mut env: napi::Env,
+        placeholder: Option<bool>,
+    ) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        if !stream.running || stream.paused {
+            return Ok(false);
+        }
+
+        let target = stream.adaptive.target();
+        stream.placeholder = placeholder
+            .unwrap_or(false)
+            .then(|| video::placeholder_frame(target.width, target.height));
+        stream.paused = true;
+        if let Some(events) = &stream.events {
+            let _ = events.call(StreamEvent::Paused, ThreadsafeFunctionCallMode::NonBlocking);
+        }
+        Ok(true)
+    }
+
+    #[napi]
+    pub fn resume_stream(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let mut state = self.state.lock();
+        let stream = &mut *state;
+
+        if !stream.paused {
+            return Ok(false);
+        }
+
+        stream.paused = false;
+        stream.placeholder = None;
+        if let Some(events) = &stream.events {
+            let _ = events.call(
+                StreamEvent::Resumed,
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        }
+        Ok(true)
+    }
+
+    #[napi]
+    pub fn is_running(&self, mut env: napi::Env) -> napi::Result<bool> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        Ok(self.state.lock().running)
+    }
+
+    // Why the stream stopped producing media, if a pipeline thread panicked.
+    // Cleared by the next start().
+    #[napi]
+    pub fn get_failure(&self, mut env: napi::Env) -> napi::Result<Option<String>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        Ok(self.state.lock().failure.clone())
+    }
+
+    // Writes a zip for support: recent logs, the start settings, the last ten
+    // minutes of stats and each peer's SDP, candidates and pair states
+    #[napi]
+    pub fn export_diagnostics(
+        &self,
+        mut env: napi::Env,
+        path: String,
+    ) -> napi::Result<AsyncTask<Blocking<()>>> {
+        context::check(&mut env, self.context, "SlumpStream")?;
+        let shared = self.state.clone();
+        Ok(Blocking::spawn(move || {
+            let bundle = {
+                let state = shared.lock();
+                let stream = &*state;
+                let peers = runtime::get()
+                    .map_err(|e| {
+                        napi::Error::new(
+                            napi::Status::GenericFailure,
+                            format!("Failed to create runtime: {}", e),
+                        )
+                    })?
+                    .block_on(async {
+                        let mut peers = Vec::new();
+                        for transport in stream.peers.values() {
+                            peers.push(transport.diagnostics().await);
+                        }
+                        peers
+                    });
+                diagnostics::Bundle {
+                    session: serde_json::json!({
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "os": std::env::consts::OS,
+                        "arch": std::env::consts::ARCH,
+                        "timestamp_ms": logging::timestamp_ms(),
+                        "running": stream.running,
+                        "paused": stream.paused,
+                        "failure": stream.failure,
+                    }),
+                    config: stream.config.clone(),
+                    stats: stream.stats_history.iter().cloned().collect(),
+                    peers,
+                }
+            };
+            diagnostics::write(&path, &bundle).map_err(|e| operation_error("export diagnostics", e))
+        }))
+    }
+}
+
+#[napi(js_name = "StreamEvent")]
+pub enum StreamEvent {
+    Stats {
+        capture_fps: Option<f64>,
+        capture_ms: Option<f64>,
+        fps: Option<f64>,
+        frames: Option<Vec<TrackFrameStats>>,
+        video_kbps: Option<f64>,
+        encode_ms: Option<f64>,
+        quality_limitation: Option<String>,
+        quality_limitation_durations: Option<QualityLimitationDurations>,
+        rtt: Option<f64>,
+        jitter: Option<f64>,
+        fraction_lost: Option<f64>,
+        available_bitrate_kbps: Option<f64>,
+        track_loss: Option<Vec<TrackLossStats>>,
+        send_ms: Option<f64>,
+        encode_latency_ms: Option<f64>,
+        send_latency_ms: Option<f64>,
+        estimated_glass_to_glass_ms: Option<f64>,
+        sent_kbps: Option<TrackBitrates>,
+        rates: Option<WindowedRates>,
+        audio_kbps: Option<f64>,
+        audio_target_kbps: Option<f64>,
+        audio_peak_dbfs: Option<f64>,
+        audio_rms_dbfs: Option<f64>,
+        audio_clipped_samples: Option<f64>,
+        audio_dropped_samples: Option<f64>,
+        audio_buffer_percent: Option<f64>,
+        audio_packets_sent: Option<f64>,
+        buffered_frames: Option<f64>,
+        audio_buffered_ms: Option<f64>,
+        rtp_history_packets: Option<f64>,
+        replay_buffer_bytes: Option<f64>,
+        process_cpu_percent: Option<f64>,
+        capture_cpu_percent: Option<f64>,
+        encode_cpu_percent: Option<f64>,
+        send_cpu_percent: Option<f64>,
+        gpu_encoder_percent: Option<f64>,
+    },
+    PeerQuality {
+        peer_id: String,
+        bitrate_kbps: f64,
+        packet_loss: f64,
+        rtt: f64,
+        selected_candidate: Option<String>,
+    },
+    // A 1 (unusable) to 5 (excellent) score from the peer's loss, RTT and
+    // jitter and whether they are getting worse. Sent when it changes; a
+    // lower score goes out at once, a higher one once it has held for a few
+    // seconds.
+    ConnectionQuality {
+        peer_id: String,
+        score: u32,
+        // "loss", "rtt" or "jitter", whichever pulled the score down; None at 5
+        limited_by: Option<String>,
+    },
+    SignalingState(String),
+    // A viewer without a grant tried to send input; sent once per viewer
+    // until it is granted or leaves
+    InputRequested {
+        viewer_id: String,
+    },
+    // The panic hotkey was pressed; these viewers lost control
+    InputRevoked {
+        viewer_ids: Vec<String>,
+    },
+    // A viewer announced itself on the control channel. `name` is its own,
+    // or the viewer id if it gave none; `count` is everyone present now.
+    ViewerJoined {
+        viewer_id: String,
+        name: String,
+        count: u32,
+    },
+    // `reason` is "left", "timeout" after 15 s without a heartbeat, or
+    // "disconnected"
+    ViewerLeft {
+        viewer_id: String,
+        name: String,
+        reason: String,
+        count: u32,
+    },
+    // Input a viewer is allowed to send, for the app to inject. "mouseMove"
+    // has x and y (0-1 across the capture), "mouseButton" button and down,
+    // "wheel" delta_x and delta_y, "key" code and down.
+    RemoteInput {
+        viewer_id: String,
+        kind: String,
+        x: Option<f64>,
+        y: Option<f64>,
+        button: Option<u32>,
+        delta_x: Option<f64>,
+        delta_y: Option<f64>,
+        code: Option<String>,
+        down: Option<bool>,
+    },
+    RecordingStarted {
+        path: String,
+    },
+    RecordingProgress {
+        path: String,
+        duration_secs: f64,
+        size_bytes: f64,
+        paused: bool,
+    },
+    // A segment was closed and the recording continues in a new file
+    RecordingSegment {
+        path: String,
+        duration_secs: f64,
+        size_bytes: f64,
+    },
+    RecordingStopped {
+        path: String,
+        duration_secs: f64,
+        size_bytes: f64,
+    },
+    ReplaySaved {
+        path: String,
+        duration_secs: f64,
+    },
+    Error {
+        message: String,
+        // Stable code to branch on, e.g. "ERR_AUDIO_CAPTURE"
+        code: String,
+        // Whether trying again unchanged (e.g. stop and start) may work
+        retryable: bool,
+        device: Option<String>,
+        peer_id: Option<String>,
+        // What to suggest to the user, e.g. "grant_permission"
+        hint: Option<String>,
+    },
+    Connected,
+    Disconnected,
+    Paused,
+    Resumed,
+    Stopped,
+    // A UAC prompt, Ctrl+Alt+Del or the lock screen took the display over
+    // (active), or went away again. Windows display capture only.
+    SecureDesktop {
+        active: bool,
+    },
+    // The adaptive controller changed the capture size, frame rate, encoder
+    // bitrate or audio FEC
+    Adaptation {
+        // "loss", "delay", "cpu" or "recovery"
+        trigger: String,
+        old_width: u32,
+        old_height: u32,
+        old_fps: u32,
+        new_width: u32,
+        new_height: u32,
+        new_fps: u32,
+        old_bitrate_kbps: u32,
+        // The encoder's new target
+        bitrate_kbps: u32,
+        // Loss in percent the audio's in-band FEC is now sized for; video has
+        // no FEC
+        fec_percent: u32,
+        // What it saw when it decided: worst peer loss in percent and RTT,
+        // the capture rate, and video kbps measured on the wire
+        packet_loss: f64,
+        rtt: f64,
+        capture_fps: f64,
+        sent_kbps: f64,
+    },
+    // The watchdog found a stage stuck and tried to restart it
+    PipelineStalled {
+        stage: String,
+        stalled_secs: f64,
+        restarted: bool,
+    },
+    Warning(String),
+}
+
+// FFI-safe wrapper for the stream event
+#[napi]
+impl StreamEvent {
+    #[napi(constructor)]
+    pub fn new() -> Self {
         StreamEvent::Stats {
-            video_kbps: 0.0,
-            audio_kbps: 0.0,
-            rtt: 0.0,
-            jitter: 0.0,
-            fps: 0.0,
+            capture_fps: None,
+            capture_ms: None,
+            fps: None,
+            frames: None,
+            video_kbps: None,
+            encode_ms: None,
+            quality_limitation: None,
+            quality_limitation_durations: None,
+            rtt: None,
+            jitter: None,
+            fraction_lost: None,
+            available_bitrate_kbps: None,
+            track_loss: None,
+            send_ms: None,
+            encode_latency_ms: None,
+            send_latency_ms: None,
+            estimated_glass_to_glass_ms: None,
+            sent_kbps: None,
+            rates: None,
+            audio_kbps: None,
+            audio_target_kbps: None,
+            audio_peak_dbfs: None,
+            audio_rms_dbfs: None,
+            audio_clipped_samples: None,
+            audio_dropped_samples: None,
+            audio_buffer_percent: None,
+            audio_packets_sent: None,
+            buffered_frames: None,
+            audio_buffered_ms: None,
+            rtp_history_packets: None,
+            replay_buffer_bytes: None,
+            process_cpu_percent: None,
+            capture_cpu_percent: None,
+            encode_cpu_percent: None,
+            send_cpu_percent: None,
+            gpu_encoder_percent: None,
+        }
+    }
+}
+
+impl StreamEvent {
+    fn from_input(viewer_id: String, input: InputEvent) -> Self {
+        let kind = input.name().to_string();
+        let (x, y) = match input {
+            InputEvent::MouseMove { x, y } => (Some(x), Some(y)),
+            _ => (None, None),
+        };
+        let (delta_x, delta_y) = match input {
+            InputEvent::Wheel { dx, dy } => (Some(dx), Some(dy)),
+            _ => (None, None),
+        };
+        let (button, code, down) = match input {
+            InputEvent::MouseButton { button, down } => (Some(button), None, Some(down)),
+            InputEvent::Key { code, down } => (None, Some(code), Some(down)),
+            _ => (None, None, None),
+        };
+        StreamEvent::RemoteInput {
+            viewer_id,
+            kind,
+            x,
+            y,
+            button,
+            delta_x,
+            delta_y,
+            code,
+            down,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audio_offset_is_bounded_both_ways() {
+        assert!(validate_audio_offset(0).is_ok());
+        assert!(validate_audio_offset(MAX_AUDIO_OFFSET_MS).is_ok());
+        assert!(validate_audio_offset(-MAX_AUDIO_OFFSET_MS).is_ok());
+        assert!(validate_audio_offset(MAX_AUDIO_OFFSET_MS + 1).is_err());
+        assert!(validate_audio_offset(-MAX_AUDIO_OFFSET_MS - 1).is_err());
+        // Has no positive counterpart, so abs() would overflow
+        assert!(validate_audio_offset(i32::MIN).is_err());
+        assert!(validate_audio_offset(i32::MAX).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use crate::context::ContextId;
+use log::{LevelFilter, Log, Metadata, Record};
+use parking_lot::{const_mutex, Mutex};
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::Once,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub type LogCallback = Box<dyn Fn(&Record) + Send + Sync>;
+
+// Lines kept in memory for diagnostics bundles
+const RECENT_LINES: usize = 2000;
+
+// Global `log` backend. Once installed the last RECENT_LINES lines are kept
+// in memory; beyond that each JS context has its own level, callback and
+// file, since one context's function can't be called from another and one
+// worker turning on trace logging shouldn't flood the others. Records are
+// process-wide, panics included, so every context sees all of them at its
+// level. A callback must not log itself, since the sink lock is held while
+// it runs.
+struct Logger {
+    sinks: Mutex<Vec<(ContextId, Sinks)>>,
+    recent: Mutex<VecDeque<String>>,
+}
+
+struct Sinks {
+    level: LevelFilter,
+    callback: Option<LogCallback>,
+    file: Option<RotatingFile>,
+}
+
+impl Default for Sinks {
+    fn default() -> Self {
+        Self {
+            level: DEFAULT_LEVEL,
+            callback: None,
+            file: None,
+        }
+    }
+}
+
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+static LOGGER: Logger = Logger {
+    sinks: const_mutex(Vec::new()),
+    recent: const_mutex(VecDeque::new()),
+};
+
+static INSTALL: Once = Once::new();
+
+pub fn install() {
+    INSTALL.call_once(|| {
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(DEFAULT_LEVEL);
+        }
+    });
+}
+
+pub fn set_level(context: ContextId, level: LevelFilter) {
+    update(context, |sinks| sinks.level = level);
+}
+
+pub fn set_callback(context: ContextId, callback: Option<LogCallback>) {
+    update(context, |sinks| sinks.callback = callback);
+}
+
+pub fn set_file(context: ContextId, file: Option<RotatingFile>) {
+    update(context, |sinks| {
+        if let Some(mut previous) = std::mem::replace(&mut sinks.file, file) {
+            let _ = previous.file.flush();
+        }
+    });
+}
+
+// For when the context goes away; its function must not be called after that
+pub fn remove_context(context: ContextId) {
+    let mut all = LOGGER.sinks.lock();
+    if let Some(position) = all.iter().position(|(owner, _)| *owner == context) {
+        let (_, mut sinks) = all.remove(position);
+        if let Some(file) = sinks.file.as_mut() {
+            let _ = file.file.flush();
+        }
+    }
+    set_max_level(&all);
+}
+
+fn update(context: ContextId, change: impl FnOnce(&mut Sinks)) {
+    install();
+    let mut all = LOGGER.sinks.lock();
+    let position = match all.iter().position(|(owner, _)| *owner == context) {
+        Some(position) => position,
+        None => {
+            all.push((context, Sinks::default()));
+            all.len() - 1
+        }
+    };
+    change(&mut all[position].1);
+    set_max_level(&all);
+}
+
+// The most verbose any context asked for, so `log` filters nothing one of
+// them wants; the rest filter per context
+fn set_max_level(all: &[(ContextId, Sinks)]) {
+    let level = all
+        .iter()
+        .map(|(_, sinks)| sinks.level)
+        .max()
+        .unwrap_or(DEFAULT_LEVEL);
+    log::set_max_level(level);
+}
+
+// Oldest first, each ending in a newline
+pub fn recent_lines() -> Vec<String> {
+    LOGGER.recent.lock().iter().cloned().collect()
+}
+
+pub fn timestamp_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64() * 1000.0)
+        .unwrap_or_default()
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!(
+            "{:.0} {:<5} {} {}\n",
+            timestamp_ms(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        for (_, sinks) in self.sinks.lock().iter_mut() {
+            if record.level() > sinks.level {
+                continue;
+            }
+            if let Some(callback) = &sinks.callback {
+                callback(record);
+            }
+            if let Some(file) = sinks.file.as_mut() {
+                // Nowhere left to report a failing log file
+                let _ = file.write_line(&line);
+            }
+        }
+
+        let mut recent = self.recent.lock();
+        if recent.len() == RECENT_LINES {
+            recent.pop_front();
+        }
+        recent.push_back(line);
+    }
+
+    fn flush(&self) {
+        for (_, sinks) in self.sinks.lock().iter_mut() {
+            if let Some(file) = sinks.file.as_mut() {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+// Appends to `path`; once it would grow past `max_bytes` it becomes path.1,
+// older files shift up and anything beyond `max_files` is deleted
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: u32,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, max_files: u32) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let numbered = |n: u32| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.max_files > 0 {
+            let _ = fs::remove_file(numbered(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let _ = fs::rename(numbered(n), numbered(n + 1));
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+use crate::benchmark::fill_pattern;
+use crate::cancel::Cancellation;
+use crate::error::{Result, SlumpError};
+use crate::pacing::Pacer;
+use crate::video::VideoEncoder;
+use crate::webrtc::{SignalMessage, TrackKind, WebRTCTransport};
+use ffmpeg_next::util::frame;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use webrtc::{
+    api::{
+        interceptor_registry::register_default_interceptors, media_engine::MediaEngine, APIBuilder,
+    },
+    ice_transport::{ice_candidate::RTCIceCandidate, ice_candidate::RTCIceCandidateInit},
+    interceptor::registry::Registry,
+    peer_connection::{
+        configuration::RTCConfiguration, peer_connection_state::RTCPeerConnectionState,
+        sdp::session_description::RTCSessionDescription, RTCPeerConnection,
+    },
+};
+
+const PEER_ID: &str = "loopback";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Frames still in flight when sending stops get this long to arrive
+const DRAIN: Duration = Duration::from_millis(500);
+const VIDEO_CLOCK_RATE: u64 = 90_000;
+
+pub struct LoopbackSettings {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub bitrate_kbps: u32,
+    pub rtp_history: u16,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LoopbackReport {
+    // Offer to both sides reporting connected
+    pub connect_ms: f64,
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    // Measured at the receiving peer
+    pub fps: f64,
+    pub bitrate_kbps: f64,
+    // From the pattern being drawn to its last packet arriving
+    pub latency_ms: f64,
+    pub max_latency_ms: f64,
+}
+
+// What the receiving side has seen, keyed on RTP timestamp so arrivals can
+// be matched to when the frame was drawn
+#[derive(Default)]
+struct Received {
+    drawn: HashMap<u32, Instant>,
+    first: Option<Instant>,
+    last: Option<Instant>,
+    frames: u64,
+    bytes: u64,
+    latency_total: Duration,
+    latency_max: Duration,
+}
+
+// Connects a sending transport to a plain receiving peer connection in this
+// process, streams a moving test pattern between them for `duration` and
+// measures what arrives. Only host candidates are used, so this checks the
+// encode and WebRTC stack, not the network.
+pub fn run(
+    duration: Duration,
+    settings: &LoopbackSettings,
+    cancel: &Cancellation,
+) -> Result<LoopbackReport> {
+    ffmpeg_next::init().map_err(|e| SlumpError::Init(e.to_string()))?;
+    let rt = crate::runtime::get().map_err(|e| SlumpError::Init(e.to_string()))?;
+    let received = Arc::new(Mutex::new(Received::default()));
+
+    let started = Instant::now();
+    let (mut sender, receiver) =
+        rt.block_on(cancel.run(connect(settings, Arc::clone(&received))))?;
+    let mut report = LoopbackReport {
+        connect_ms: started.elapsed().as_secs_f64() * 1000.0,
+        ..Default::default()
+    };
+
+    let result = stream(rt, &sender, duration, settings, &received, cancel);
+
+    // Close both ends whatever happened
+    let _ = rt.block_on(async {
+        let _ = receiver.close().await;
+        sender.close().await
+    });
+    report.frames_sent = result?;
+
+    let received = received.lock().unwrap();
+    report.frames_received = received.frames;
+    if let (Some(first), Some(last)) = (received.first, received.last) {
+        let seconds = last.duration_since(first).as_secs_f64();
+        if seconds > 0.0 {
+            report.fps = received.frames.saturating_sub(1) as f64 / seconds;
+            report.bitrate_kbps = received.bytes as f64 * 8.0 / 1000.0 / seconds;
+        }
+    }
+    if received.frames > 0 {
+        report.latency_ms = received.latency_total.as_secs_f64() * 1000.0 / received.frames as f64;
+        report.max_latency_ms = received.latency_max.as_secs_f64() * 1000.0;
+    }
+    Ok(report)
+}
+
+async fn connect(
+    settings: &LoopbackSettings,
+    received: Arc<Mutex<Received>>,
+) -> Result<(WebRTCTransport, Arc<RTCPeerConnection>)> {
+    let sender = WebRTCTransport::new(
+        PEER_ID.to_string(),
+        Vec::new(),
+        Vec::new(),
+        &[TrackKind::Video],
+        settings.rtp_history,
+        None,
+    )
+    .await?;
+    let receiver = open_receiver(received).await?;
+
+    // Trickle candidates both ways, as signaling would
+    let (outgoing, mut candidates) = mpsc::unbounded_channel();
+    sender.forward_local_candidates(outgoing);
+    let remote = Arc::clone(&receiver);
+    tokio::spawn(async move {
+        while let Some(message) = candidates.recv().await {
+            if let SignalMessage::Ice { candidate } = message {
+                let init = RTCIceCandidateInit {
+                    candidate: candidate.candidate,
+                    sdp_mid: candidate.sdp_mid,
+                    sdp_mline_index: candidate.sdp_m_line_index,
+                    username_fragment: None,
+                };
+                if let Err(e) = remote.add_ice_candidate(init).await {
+                    log::warn!("Loopback receiver rejected a candidate: {}", e);
+                }
+            }
+        }
+    });
+    let (answer_candidates, mut candidates) = mpsc::unbounded_channel();
+    receiver.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+        let answer_candidates = answer_candidates.clone();
+        Box::pin(async move {
+            if let Some(Ok(init)) = candidate.map(|candidate| candidate.to_json()) {
+                let _ = answer_candidates.send(init);
+            }
+        })
+    }));
+
+    let offer = sender.create_offer().await?;
+    receiver
+        .set_remote_description(RTCSessionDescription::offer(offer).map_err(webrtc_error)?)
+        .await
+        .map_err(webrtc_error)?;
+    let answer = receiver.create_answer(None).await.map_err(webrtc_error)?;
+    let sdp = answer.sdp.clone();
+    receiver
+        .set_local_description(answer)
+        .await
+        .map_err(webrtc_error)?;
+    sender.set_remote_answer(sdp).await?;
+
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    loop {
+        while let Ok(init) = candidates.try_recv() {
+            sender.handle_signal(&SignalMessage::Ice {
+                candidate: crate::webrtc::IceCandidate {
+                    candidate: init.candidate,
+                    sdp_mid: init.sdp_mid,
+                    sdp_m_line_index: init.sdp_mline_index,
+                },
+            })?;
+        }
+        match receiver.connection_state() {
+            RTCPeerConnectionState::Connected => break,
+            RTCPeerConnectionState::Failed => {
+                return Err(SlumpError::IceFailed(
+                    "loopback peers could not reach each other".into(),
+                ))
+            }
+            _ if Instant::now() > deadline => {
+                return Err(SlumpError::Webrtc(format!(
+                    "Loopback peers did not connect within {}s",
+                    CONNECT_TIMEOUT.as_secs()
+                )))
+            }
+            _ => tokio::time::sleep(Duration::from_millis(20)).await,
+        }
+    }
+
+    Ok((sender, receiver))
+}
+
+// A bare peer connection standing in for a viewer; it only counts packets,
+// nothing is decoded
+async fn open_receiver(received: Arc<Mutex<Received>>) -> Result<Arc<RTCPeerConnection>> {
+    let mut media_engine = MediaEngine::default();
+    media_engine
+        .register_default_codecs()
+        .map_err(webrtc_error)?;
+    let registry =
+        register_default_interceptors(Registry::new(), &mut media_engine).map_err(webrtc_error)?;
+    let api = APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .build();
+    let peer_connection = Arc::new(
+        api.new_peer_connection(RTCConfiguration::default())
+            .await
+            .map_err(webrtc_error)?,
+    );
+
+    peer_connection.on_track(Box::new(move |track, _, _| {
+        let received = Arc::clone(&received);
+        Box::pin(async move {
+            while let Ok((packet, _)) = track.read_rtp().await {
+                let now = Instant::now();
+                let mut received = received.lock().unwrap();
+                received.bytes += packet.payload.len() as u64;
+                // The marker bit closes a frame
+                if !packet.header.marker {
+                    continue;
+                }
+                received.frames += 1;
+                received.first.get_or_insert(now);
+                received.last = Some(now);
+                if let Some(drawn) = received.drawn.remove(&packet.header.timestamp) {
+                    let latency = now.duration_since(drawn);
+                    received.latency_total += latency;
+                    received.latency_max = received.latency_max.max(latency);
+                }
+            }
+        })
+    }));
+
+    Ok(peer_connection)
+}
+
+// Draws, encodes and sends at the configured rate; returns frames sent
+fn stream(
+    rt: &tokio::runtime::Runtime,
+    sender: &WebRTCTransport,
+    duration: Duration,
+    settings: &LoopbackSettings,
+    received: &Mutex<Received>,
+    cancel: &Cancellation,
+) -> Result<u64> {
+    let mut encoder = VideoEncoder::new(
+        settings.width,
+        settings.height,
+        settings.fps,
+        settings.bitrate_kbps,
+    )?;
+    let mut picture =
+        frame::Video::new(VideoEncoder::PIXEL_FORMAT, settings.width, settings.height);
+    let mut pacer = Pacer::new(settings.fps);
+    let started = Instant::now();
+    let mut index = 0u64;
+    let mut sent = 0u64;
+
+    while started.elapsed() < duration {
+        cancel.check()?;
+        pacer.wait();
+        let drawn = Instant::now();
+        fill_pattern(&mut picture, index);
+        let timestamp = (index * VIDEO_CLOCK_RATE / settings.fps as u64) as u32;
+        index += 1;
+        let Some(data) = encoder.encode(&picture)? else {
+            continue;
+        };
+        received.lock().unwrap().drawn.insert(timestamp, drawn);
+        rt.block_on(sender.send_frame(TrackKind::Video, &data, timestamp))?;
+        sent += 1;
+    }
+
+    std::thread::sleep(DRAIN);
+    Ok(sent)
+}
+
+fn webrtc_error(e: webrtc::Error) -> SlumpError {
+    SlumpError::Webrtc(e.to_string())
+}
@@ -0,0 +1,509 @@
+use crate::error::{Result, SlumpError};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+    task::JoinSet,
+};
+
+// Seconds. Capture and encode should fit well inside a frame; the top buckets
+// catch stages that blow the budget several times over.
+const STAGE_BUCKETS: &[f64] = &[0.001, 0.0025, 0.005, 0.01, 0.02, 0.033, 0.05, 0.1, 0.25];
+const RTT_BUCKETS: &[f64] = &[0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+// Capture to send, which spans every stage and the queues between them
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.02, 0.033, 0.05, 0.075, 0.1, 0.15, 0.25, 0.5, 1.0,
+];
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+// OTLP AggregationTemporality: every point counts from the same start
+const CUMULATIVE: u32 = 2;
+
+pub struct Histogram {
+    bounds: &'static [f64],
+    // Per bucket, not cumulative; render adds them up
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|&bound| value <= bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn otlp(&self, start: u64, now: u64) -> Value {
+        // OTLP counts the overflow bucket too
+        let overflow = self.count - self.counts.iter().sum::<u64>();
+        let counts: Vec<String> = self
+            .counts
+            .iter()
+            .chain(std::iter::once(&overflow))
+            .map(|count| count.to_string())
+            .collect();
+        json!({
+            "startTimeUnixNano": start.to_string(),
+            "timeUnixNano": now.to_string(),
+            "count": self.count.to_string(),
+            "sum": self.sum,
+            "bucketCounts": counts,
+            "explicitBounds": self.bounds,
+        })
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+#[derive(Default)]
+struct PeerMetrics {
+    bytes_sent: u64,
+    packets_sent: u64,
+    rtt_seconds: f64,
+    packet_loss_ratio: f64,
+}
+
+// Counters for one stream. They live as long as the SlumpStream, so they keep
+// counting across stop and start the way Prometheus expects.
+pub struct Metrics {
+    pub video_frames_captured: u64,
+    pub video_frames_encoded: u64,
+    pub video_bytes_encoded: u64,
+    pub video_frames_sent: u64,
+    pub video_bytes_sent: u64,
+    pub send_errors: u64,
+    pub audio_frames_captured: u64,
+    pub video_frames_late: u64,
+    pub video_frames_skipped: u64,
+    // Current capture's count; starts over when the capture is reopened
+    pub frame_pool_misses: u64,
+    pub capture_seconds: Histogram,
+    pub encode_seconds: Histogram,
+    pub rtt_seconds: Histogram,
+    pub latency_seconds: Histogram,
+    peers: HashMap<String, PeerMetrics>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            video_frames_captured: 0,
+            video_frames_encoded: 0,
+            video_bytes_encoded: 0,
+            video_frames_sent: 0,
+            video_bytes_sent: 0,
+            send_errors: 0,
+            audio_frames_captured: 0,
+            video_frames_late: 0,
+            video_frames_skipped: 0,
+            frame_pool_misses: 0,
+            capture_seconds: Histogram::new(STAGE_BUCKETS),
+            encode_seconds: Histogram::new(STAGE_BUCKETS),
+            rtt_seconds: Histogram::new(RTT_BUCKETS),
+            latency_seconds: Histogram::new(LATENCY_BUCKETS),
+            peers: HashMap::new(),
+        }
+    }
+}
+
+impl Metrics {
+    // rtt in milliseconds and loss in percent, as the transport reports them
+    pub fn peer_stats(
+        &mut self,
+        peer_id: &str,
+        bytes_sent: u64,
+        packets_sent: u64,
+        rtt_ms: f64,
+        loss_percent: f64,
+    ) {
+        let rtt_seconds = rtt_ms / 1000.0;
+        if rtt_seconds > 0.0 {
+            self.rtt_seconds.observe(rtt_seconds);
+        }
+        let peer = self.peers.entry(peer_id.to_string()).or_default();
+        peer.bytes_sent = bytes_sent;
+        peer.packets_sent = packets_sent;
+        peer.rtt_seconds = rtt_seconds;
+        peer.packet_loss_ratio = loss_percent / 100.0;
+    }
+
+    pub fn retain_peers(&mut self, keep: impl Fn(&str) -> bool) {
+        self.peers.retain(|peer_id, _| keep(peer_id));
+    }
+
+    fn counters(&self) -> [(&'static str, &'static str, u64); 9] {
+        [
+            (
+                "slump_video_frames_captured_total",
+                "Video frames captured",
+                self.video_frames_captured,
+            ),
+            (
+                "slump_video_frames_encoded_total",
+                "Video frames produced by the encoder",
+                self.video_frames_encoded,
+            ),
+            (
+                "slump_video_encoded_bytes_total",
+                "Bytes produced by the video encoder",
+                self.video_bytes_encoded,
+            ),
+            (
+                "slump_video_frames_sent_total",
+                "Encoded video frames sent, counted once per peer",
+                self.video_frames_sent,
+            ),
+            (
+                "slump_video_sent_bytes_total",
+                "Encoded video bytes sent, counted once per peer",
+                self.video_bytes_sent,
+            ),
+            (
+                "slump_send_errors_total",
+                "Frames a peer failed to accept",
+                self.send_errors,
+            ),
+            (
+                "slump_audio_frames_captured_total",
+                "Audio frames captured",
+                self.audio_frames_captured,
+            ),
+            (
+                "slump_video_frames_late_total",
+                "Capture ticks that started after their deadline had passed",
+                self.video_frames_late,
+            ),
+            (
+                "slump_video_frames_skipped_total",
+                "Captured frames not encoded because they matched the previous one",
+                self.video_frames_skipped,
+            ),
+        ]
+    }
+
+    fn gauges(&self) -> [(&'static str, &'static str, f64); 2] {
+        [
+            (
+                "slump_frame_pool_misses",
+                "Scaled frames allocated because every pooled buffer was still in use",
+                self.frame_pool_misses as f64,
+            ),
+            ("slump_peers", "Connected peers", self.peers.len() as f64),
+        ]
+    }
+
+    fn histograms(&self) -> [(&'static str, &'static str, &Histogram); 4] {
+        [
+            (
+                "slump_capture_seconds",
+                "Time to capture and scale a video frame",
+                &self.capture_seconds,
+            ),
+            (
+                "slump_encode_seconds",
+                "Time to encode a video frame",
+                &self.encode_seconds,
+            ),
+            (
+                "slump_rtt_seconds",
+                "Round trip time samples across all peers",
+                &self.rtt_seconds,
+            ),
+            (
+                "slump_capture_to_send_seconds",
+                "Time from capturing a video frame to sending it to the peers",
+                &self.latency_seconds,
+            ),
+        ]
+    }
+
+    fn sorted_peers(&self) -> Vec<(&String, &PeerMetrics)> {
+        let mut peers: Vec<_> = self.peers.iter().collect();
+        peers.sort_by(|a, b| a.0.cmp(b.0));
+        peers
+    }
+
+    // Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, help, value) in self.counters() {
+            header(&mut out, name, help, "counter");
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        for (name, help, value) in self.gauges() {
+            header(&mut out, name, help, "gauge");
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        for (name, help, histogram) in self.histograms() {
+            histogram.render(&mut out, name, help);
+        }
+
+        let peers = self.sorted_peers();
+        for (name, help, kind, value) in PER_PEER {
+            header(&mut out, name, help, kind.prometheus());
+            for (peer_id, peer) in &peers {
+                let _ = writeln!(
+                    out,
+                    "{}{{peer=\"{}\"}} {}",
+                    name,
+                    escape_label(peer_id),
+                    value(peer)
+                );
+            }
+        }
+        out
+    }
+
+    // The same series as OTLP/JSON metrics, cumulative since `start`; times
+    // are Unix nanoseconds
+    pub fn otlp(&self, start: u64, now: u64) -> Vec<Value> {
+        let point = |value: Value, attributes: Value| {
+            json!({
+                "startTimeUnixNano": start.to_string(),
+                "timeUnixNano": now.to_string(),
+                "asDouble": value,
+                "attributes": attributes,
+            })
+        };
+        let mut metrics = Vec::new();
+        for (name, help, value) in self.counters() {
+            metrics.push(json!({
+                "name": name,
+                "description": help,
+                "sum": {
+                    "dataPoints": [point(json!(value as f64), json!([]))],
+                    "aggregationTemporality": CUMULATIVE,
+                    "isMonotonic": true,
+                },
+            }));
+        }
+        for (name, help, value) in self.gauges() {
+            metrics.push(json!({
+                "name": name,
+                "description": help,
+                "gauge": { "dataPoints": [point(json!(value), json!([]))] },
+            }));
+        }
+        for (name, help, histogram) in self.histograms() {
+            metrics.push(json!({
+                "name": name,
+                "description": help,
+                "unit": "s",
+                "histogram": {
+                    "dataPoints": [histogram.otlp(start, now)],
+                    "aggregationTemporality": CUMULATIVE,
+                },
+            }));
+        }
+
+        let peers = self.sorted_peers();
+        for (name, help, kind, value) in PER_PEER {
+            let points: Vec<Value> = peers
+                .iter()
+                .map(|(peer_id, peer)| {
+                    point(
+                        json!(value(peer)),
+                        json!([{ "key": "peer", "value": { "stringValue": peer_id } }]),
+                    )
+                })
+                .collect();
+            metrics.push(match kind {
+                Kind::Counter => json!({
+                    "name": name,
+                    "description": help,
+                    "sum": {
+                        "dataPoints": points,
+                        "aggregationTemporality": CUMULATIVE,
+                        "isMonotonic": true,
+                    },
+                }),
+                Kind::Gauge => json!({
+                    "name": name,
+                    "description": help,
+                    "gauge": { "dataPoints": points },
+                }),
+            });
+        }
+        metrics
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+impl Kind {
+    fn prometheus(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+        }
+    }
+}
+
+type PeerSeries = (&'static str, &'static str, Kind, fn(&PeerMetrics) -> f64);
+
+const PER_PEER: [PeerSeries; 4] = [
+    (
+        "slump_peer_sent_bytes_total",
+        "Bytes sent to a peer",
+        Kind::Counter,
+        |peer| peer.bytes_sent as f64,
+    ),
+    (
+        "slump_peer_sent_packets_total",
+        "RTP packets sent to a peer",
+        Kind::Counter,
+        |peer| peer.packets_sent as f64,
+    ),
+    (
+        "slump_peer_rtt_seconds",
+        "Latest round trip time to a peer",
+        Kind::Gauge,
+        |peer| peer.rtt_seconds,
+    ),
+    (
+        "slump_peer_packet_loss_ratio",
+        "Fraction of packets the peer reports lost",
+        Kind::Gauge,
+        |peer| peer.packet_loss_ratio,
+    ),
+];
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Answers every request with the current metrics, for Prometheus to scrape
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    pub fn start(host: &str, port: u16, metrics: Arc<Mutex<Metrics>>) -> Result<Self> {
+        let std_listener = std::net::TcpListener::bind((host, port))
+            .map_err(|e| SlumpError::Network(format!("Failed to bind {}:{}: {}", host, port, e)))?;
+        std_listener
+            .set_nonblocking(true)
+            .map_err(|e| SlumpError::Network(e.to_string()))?;
+        let local_addr = std_listener
+            .local_addr()
+            .map_err(|e| SlumpError::Network(e.to_string()))?;
+
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let thread = std::thread::spawn(move || {
+            let rt = match crate::runtime::get() {
+                Ok(rt) => rt,
+                Err(e) => {
+                    log::error!("Failed to create metrics runtime: {}", e);
+                    return;
+                }
+            };
+
+            rt.block_on(async move {
+                let listener = match TcpListener::from_std(std_listener) {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        log::error!("Failed to start metrics listener: {}", e);
+                        return;
+                    }
+                };
+
+                let mut connections = JoinSet::new();
+                loop {
+                    tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        Some(_) = connections.join_next() => {}
+                        accepted = listener.accept() => match accepted {
+                            Ok((socket, _)) => {
+                                connections.spawn(serve_metrics(socket, metrics.clone()));
+                            }
+                            Err(e) => log::warn!("Failed to accept metrics connection: {}", e),
+                        }
+                    }
+                }
+            });
+        });
+
+        Ok(Self {
+            local_addr,
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}/metrics", self.local_addr)
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+async fn serve_metrics(mut socket: TcpStream, metrics: Arc<Mutex<Metrics>>) {
+    // Scrapers only ever GET one path, so the request is not inspected
+    let mut buf = [0u8; 4096];
+    let _ = socket.read(&mut buf).await;
+
+    let body = metrics.lock().unwrap().render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        CONTENT_TYPE,
+        body.len(),
+        body
+    );
+    if let Err(e) = socket.write_all(response.as_bytes()).await {
+        log::debug!("Failed to serve metrics: {}", e);
+        return;
+    }
+    let _ = socket.shutdown().await;
+}
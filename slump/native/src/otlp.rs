@@ -0,0 +1,309 @@
+use crate::{
+    error::{Result, SlumpError},
+    metrics::Metrics,
+    trace::{self, FieldVisitor},
+};
+use parking_lot::{const_mutex, Mutex};
+use reqwest::header;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::oneshot;
+use tracing::{
+    span::{Attributes, Id},
+    subscriber::Interest,
+    Metadata, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+// Spans waiting for the next export; past this they are dropped
+const MAX_PENDING_SPANS: usize = 100_000;
+const JSON_CONTENT_TYPE: &str = "application/json";
+// OTLP SpanKind: internal work, not a request to another service
+const SPAN_KIND_INTERNAL: u32 = 1;
+
+// Exporters running across every stream; spans are only kept while one is
+static EXPORTERS: AtomicUsize = AtomicUsize::new(0);
+static SPANS: Mutex<Vec<FinishedSpan>> = const_mutex(Vec::new());
+static NEXT_SPAN_ID: AtomicU64 = AtomicU64::new(1);
+
+pub fn exporting() -> bool {
+    EXPORTERS.load(Ordering::Relaxed) > 0
+}
+
+pub struct OtlpSettings {
+    // Collector base URL, e.g. http://localhost:4318; /v1/metrics and
+    // /v1/traces are appended
+    pub endpoint: String,
+    pub headers: HashMap<String, String>,
+    pub interval: Duration,
+    pub service_name: String,
+    pub traces: bool,
+}
+
+struct FinishedSpan {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    name: &'static str,
+    target: &'static str,
+    start: u64,
+    end: u64,
+    attributes: Map<String, Value>,
+}
+
+// Per-span bookkeeping kept in the registry's extensions
+struct OpenSpan {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    start: u64,
+    attributes: Map<String, Value>,
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+// Unique within the process, which is what a collector needs to stitch
+// spans together; the process start keeps restarts apart
+fn new_trace_id(span_id: u64) -> u128 {
+    static PROCESS: std::sync::OnceLock<u64> = std::sync::OnceLock::new();
+    let process = *PROCESS.get_or_init(unix_nanos);
+    ((process as u128) << 64) | span_id as u128
+}
+
+pub struct SpanLayer;
+
+impl<S> Layer<S> for SpanLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if metadata.is_span() {
+            Interest::sometimes()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _ctx: Context<'_, S>) -> bool {
+        metadata.is_span() && trace::spans_wanted()
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !exporting() {
+            return;
+        }
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<OpenSpan>()
+                .map(|open| (open.trace_id, open.span_id))
+        });
+        let span_id = NEXT_SPAN_ID.fetch_add(1, Ordering::Relaxed);
+        let mut attributes = Map::new();
+        attrs.record(&mut FieldVisitor(&mut attributes));
+        span.extensions_mut().insert(OpenSpan {
+            trace_id: parent.map_or_else(|| new_trace_id(span_id), |(trace_id, _)| trace_id),
+            span_id,
+            parent_id: parent.map(|(_, parent_id)| parent_id),
+            start: unix_nanos(),
+            attributes,
+        });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(open) = span.extensions_mut().remove::<OpenSpan>() else {
+            return;
+        };
+        if !exporting() {
+            return;
+        }
+        let mut spans = SPANS.lock();
+        if spans.len() < MAX_PENDING_SPANS {
+            spans.push(FinishedSpan {
+                trace_id: open.trace_id,
+                span_id: open.span_id,
+                parent_id: open.parent_id,
+                name: span.metadata().name(),
+                target: span.metadata().target(),
+                start: open.start,
+                end: unix_nanos(),
+                attributes: open.attributes,
+            });
+        }
+    }
+}
+
+// Pushes a stream's metrics, and the process's spans, to an OTLP/HTTP
+// collector as JSON until dropped. Spans are process-wide: with several
+// streams exporting, each span goes out once, with whichever exports next.
+pub struct OtlpExporter {
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    traces: bool,
+}
+
+impl OtlpExporter {
+    pub fn start(settings: OtlpSettings, metrics: Arc<std::sync::Mutex<Metrics>>) -> Result<Self> {
+        let mut headers = header::HeaderMap::new();
+        for (name, value) in &settings.headers {
+            let name = header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| SlumpError::Init(format!("Invalid OTLP header {}: {}", name, e)))?;
+            let value = header::HeaderValue::from_str(value)
+                .map_err(|e| SlumpError::Init(format!("Invalid OTLP header {}: {}", name, e)))?;
+            headers.insert(name, value);
+        }
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(settings.interval)
+            .build()
+            .map_err(|e| SlumpError::Network(format!("Failed to create OTLP client: {}", e)))?;
+
+        if settings.traces {
+            trace::install();
+            EXPORTERS.fetch_add(1, Ordering::SeqCst);
+        }
+        let traces = settings.traces;
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let thread = std::thread::spawn(move || {
+            let rt = match crate::runtime::get() {
+                Ok(rt) => rt,
+                Err(e) => {
+                    log::error!("Failed to create OTLP runtime: {}", e);
+                    return;
+                }
+            };
+            let endpoint = settings.endpoint.trim_end_matches('/').to_string();
+            let resource = json!({
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": settings.service_name } },
+                    { "key": "process.pid", "value": { "intValue": std::process::id().to_string() } },
+                ],
+            });
+            let start = unix_nanos();
+            rt.block_on(async move {
+                let mut interval = tokio::time::interval(settings.interval);
+                // Whatever is left goes out on shutdown too
+                let mut stopping = false;
+                while !stopping {
+                    tokio::select! {
+                        _ = &mut shutdown_rx => stopping = true,
+                        _ = interval.tick() => {}
+                    }
+
+                    let body = json!({
+                        "resourceMetrics": [{
+                            "resource": resource,
+                            "scopeMetrics": [{
+                                "scope": { "name": "slump" },
+                                "metrics": metrics.lock().unwrap().otlp(start, unix_nanos()),
+                            }],
+                        }],
+                    });
+                    post(&client, &format!("{}/v1/metrics", endpoint), body).await;
+
+                    if !settings.traces {
+                        continue;
+                    }
+                    let spans = std::mem::take(&mut *SPANS.lock());
+                    if spans.is_empty() {
+                        continue;
+                    }
+                    let body = json!({
+                        "resourceSpans": [{
+                            "resource": resource,
+                            "scopeSpans": [{
+                                "scope": { "name": "slump" },
+                                "spans": spans.iter().map(span_json).collect::<Vec<_>>(),
+                            }],
+                        }],
+                    });
+                    post(&client, &format!("{}/v1/traces", endpoint), body).await;
+                }
+            });
+        });
+
+        Ok(Self {
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+            traces,
+        })
+    }
+}
+
+impl Drop for OtlpExporter {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if self.traces && EXPORTERS.fetch_sub(1, Ordering::SeqCst) == 1 {
+            SPANS.lock().clear();
+        }
+    }
+}
+
+// A collector that is down loses this batch only; the next one retries
+async fn post(client: &reqwest::Client, url: &str, body: Value) {
+    let result = client
+        .post(url)
+        .header(header::CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .body(body.to_string())
+        .send()
+        .await;
+    match result {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => log::warn!("OTLP export to {} returned {}", url, response.status()),
+        Err(e) => log::warn!("OTLP export to {} failed: {}", url, e),
+    }
+}
+
+fn span_json(span: &FinishedSpan) -> Value {
+    let mut attributes: Vec<Value> = span
+        .attributes
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": any_value(value) }))
+        .collect();
+    attributes.push(json!({ "key": "target", "value": { "stringValue": span.target } }));
+    json!({
+        "traceId": format!("{:032x}", span.trace_id),
+        "spanId": format!("{:016x}", span.span_id),
+        "parentSpanId": span.parent_id.map_or(String::new(), |id| format!("{:016x}", id)),
+        "name": span.name,
+        "kind": SPAN_KIND_INTERNAL,
+        "startTimeUnixNano": span.start.to_string(),
+        "endTimeUnixNano": span.end.to_string(),
+        "attributes": attributes,
+    })
+}
+
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(value) => json!({ "boolValue": value }),
+        Value::Number(number) if number.is_i64() || number.is_u64() => {
+            json!({ "intValue": number.to_string() })
+        }
+        Value::Number(number) => json!({ "doubleValue": number.as_f64() }),
+        Value::String(value) => json!({ "stringValue": value }),
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
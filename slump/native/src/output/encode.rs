@@ -0,0 +1,279 @@
+use super::VideoParams;
+use crate::{
+    error::{Result, SlumpError},
+    video::Resampler,
+};
+use ffmpeg_next::{
+    codec, encoder, ffi, format, format::sample::Sample, frame,
+    util::channel_layout::ChannelLayout, Dictionary, Frame, Packet, Rational,
+};
+use std::time::Instant;
+
+pub const AUDIO_SAMPLE_RATE: i32 = 48000;
+const AUDIO_CHANNELS: usize = 2;
+const AUDIO_BITRATE: usize = 128_000;
+// Beyond this a skew is a broken device rather than latency to correct
+pub const MAX_AUDIO_OFFSET_MS: i32 = 2000;
+// Video is timestamped from capture time, in the usual MPEG clock
+pub const VIDEO_CLOCK_RATE: i32 = 90000;
+
+// Timestamps from capture times against a fixed epoch, so frames the
+// adaptive controller paces slower, the pacer skips or pausing holds back
+// keep their place on the timeline, and video stays in step with audio
+// counted in samples from the same start
+pub struct CaptureTimeline {
+    epoch: Instant,
+    last: Option<i64>,
+}
+
+impl CaptureTimeline {
+    pub fn new(epoch: Instant) -> Self {
+        Self { epoch, last: None }
+    }
+
+    // In 1/VIDEO_CLOCK_RATE. Never repeats or goes back, which encoders and
+    // muxers refuse.
+    pub fn pts(&mut self, captured_at: Instant) -> i64 {
+        let elapsed = captured_at.saturating_duration_since(self.epoch);
+        let pts = (elapsed.as_micros() as i64 * VIDEO_CLOCK_RATE as i64 / 1_000_000)
+            .max(self.last.map_or(0, |last| last + 1));
+        self.last = Some(pts);
+        pts
+    }
+}
+
+// H.264 encoder producing timestamped packets for muxing
+pub struct H264Encoder {
+    encoder: encoder::Video,
+    timeline: CaptureTimeline,
+    keyframe_requested: bool,
+    // For frames that arrive in another layout, like the 4:2:0 pause
+    // placeholder, or at another size once the adaptive controller has
+    // rescaled the capture
+    resampler: Resampler,
+}
+
+impl H264Encoder {
+    pub fn new(video: VideoParams, global_header: bool) -> Result<Self> {
+        let h264 = encoder::find(codec::Id::H264)
+            .ok_or_else(|| SlumpError::Ffmpeg("H.264 encoder not available".into()))?;
+
+        let mut encoder = codec::context::Context::new_with_codec(h264)
+            .encoder()
+            .video()?;
+        encoder.set_width(video.width);
+        encoder.set_height(video.height);
+        // 4:2:2 and 4:4:4 give the High 4:2:2 / 4:4:4 profiles, which
+        // hardware decoders often can't play
+        encoder.set_format(video.chroma.pixel());
+        encoder.set_time_base((1, VIDEO_CLOCK_RATE));
+        encoder.set_frame_rate(Some((video.fps as i32, 1)));
+        encoder.set_bit_rate(video.bitrate_kbps as usize * 1000);
+        encoder.set_max_b_frames(0);
+        encoder.set_gop(video.fps);
+        video.colorimetry.apply(&mut encoder);
+        if global_header {
+            encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let mut options = Dictionary::new();
+        options.set("preset", "veryfast");
+        options.set("tune", "zerolatency");
+        options.set("forced-idr", "1");
+        // Keep SPS/PPS in-band as well, so one encode suits both file
+        // containers and stream formats that join mid-GOP
+        if global_header {
+            options.set("x264-params", "repeat-headers=1");
+        }
+
+        Ok(Self {
+            encoder: encoder.open_with(options)?,
+            timeline: CaptureTimeline::new(Instant::now()),
+            keyframe_requested: false,
+            resampler: Resampler::default(),
+        })
+    }
+
+    // Makes the next encoded frame an IDR, e.g. when a new output joins
+    pub fn force_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    pub fn codec(&self) -> &encoder::Video {
+        &self.encoder
+    }
+
+    pub fn time_base(&self) -> Rational {
+        self.encoder.time_base()
+    }
+
+    // Always at the size it was opened with; sinks were set up for that
+    pub fn encode(&mut self, frame: &Frame, captured_at: Instant) -> Result<Vec<Packet>> {
+        let mut frame = self.resampler.scale(
+            frame,
+            self.encoder.format(),
+            self.encoder.width(),
+            self.encoder.height(),
+        )?;
+        frame.set_pts(Some(self.timeline.pts(captured_at)));
+        if std::mem::take(&mut self.keyframe_requested) {
+            unsafe { (*frame.as_mut_ptr()).pict_type = ffi::AVPictureType::AV_PICTURE_TYPE_I };
+        }
+
+        self.encoder.send_frame(&frame)?;
+        Ok(self.drain())
+    }
+
+    pub fn flush(&mut self) -> Result<Vec<Packet>> {
+        self.encoder.send_eof()?;
+        Ok(self.drain())
+    }
+
+    fn drain(&mut self) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            packets.push(packet.clone());
+        }
+        packets
+    }
+}
+
+// AAC encoder fed with interleaved stereo f32 samples
+pub struct AacEncoder {
+    encoder: encoder::Audio,
+    frame_size: usize,
+    pending: Vec<f32>,
+    samples_written: i64,
+    // Applied A/V offset, and input still to drop for a move towards earlier
+    offset_samples: i64,
+    skip: usize,
+}
+
+impl AacEncoder {
+    pub fn new(global_header: bool) -> Result<Self> {
+        let aac = encoder::find(codec::Id::AAC)
+            .ok_or_else(|| SlumpError::Ffmpeg("AAC encoder not available".into()))?;
+
+        let mut encoder = codec::context::Context::new_with_codec(aac)
+            .encoder()
+            .audio()?;
+        encoder.set_rate(AUDIO_SAMPLE_RATE);
+        encoder.set_channel_layout(ChannelLayout::STEREO);
+        encoder.set_format(Sample::F32(format::sample::Type::Planar));
+        encoder.set_bit_rate(AUDIO_BITRATE);
+        encoder.set_time_base((1, AUDIO_SAMPLE_RATE));
+        if global_header {
+            encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let encoder = encoder.open()?;
+        Ok(Self {
+            frame_size: encoder.frame_size().max(1) as usize,
+            encoder,
+            pending: Vec::new(),
+            samples_written: 0,
+            offset_samples: 0,
+            skip: 0,
+        })
+    }
+
+    pub fn codec(&self) -> &encoder::Audio {
+        &self.encoder
+    }
+
+    pub fn time_base(&self) -> Rational {
+        Rational::new(1, AUDIO_SAMPLE_RATE)
+    }
+
+    // Shifts audio against video; positive plays it later. Timestamps only
+    // move forward, so a later shift leaves a gap and an earlier one drops
+    // that much input.
+    pub fn set_offset_ms(&mut self, ms: i32) {
+        let offset = ms as i64 * AUDIO_SAMPLE_RATE as i64 / 1000;
+        let delta = offset - self.offset_samples;
+        self.offset_samples = offset;
+        if delta >= 0 {
+            // Input not dropped yet covers part of the move
+            let undropped = (delta as usize).min(self.skip / AUDIO_CHANNELS);
+            self.skip -= undropped * AUDIO_CHANNELS;
+            self.samples_written += delta - undropped as i64;
+        } else {
+            self.skip += delta.unsigned_abs() as usize * AUDIO_CHANNELS;
+        }
+    }
+
+    pub fn encode(&mut self, samples: &[f32]) -> Result<Vec<Packet>> {
+        let skipped = self.skip.min(samples.len());
+        self.skip -= skipped;
+        self.pending.extend_from_slice(&samples[skipped..]);
+        let chunk = self.frame_size * AUDIO_CHANNELS;
+
+        let mut packets = Vec::new();
+        while self.pending.len() >= chunk {
+            let mut audio_frame = frame::Audio::new(
+                Sample::F32(format::sample::Type::Planar),
+                self.frame_size,
+                ChannelLayout::STEREO,
+            );
+            audio_frame.set_rate(AUDIO_SAMPLE_RATE as u32);
+            audio_frame.set_pts(Some(self.samples_written));
+
+            // Deinterleave into one plane per channel
+            for channel in 0..AUDIO_CHANNELS {
+                let plane = audio_frame.plane_mut::<f32>(channel);
+                for (i, sample) in plane.iter_mut().enumerate() {
+                    *sample = self.pending[i * AUDIO_CHANNELS + channel];
+                }
+            }
+            self.pending.drain(..chunk);
+            self.samples_written += self.frame_size as i64;
+
+            self.encoder.send_frame(&audio_frame)?;
+            packets.extend(self.drain());
+        }
+
+        Ok(packets)
+    }
+
+    pub fn flush(&mut self) -> Result<Vec<Packet>> {
+        self.encoder.send_eof()?;
+        Ok(self.drain())
+    }
+
+    fn drain(&mut self) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            packets.push(packet.clone());
+        }
+        packets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn timeline_follows_capture_time() {
+        let epoch = Instant::now();
+        let mut timeline = CaptureTimeline::new(epoch);
+        assert_eq!(timeline.pts(epoch), 0);
+        // A frame the pacer skipped leaves a gap rather than closing it up
+        assert_eq!(timeline.pts(epoch + Duration::from_millis(100)), 9_000);
+        assert_eq!(timeline.pts(epoch + Duration::from_secs(1)), 90_000);
+    }
+
+    #[test]
+    fn timeline_never_repeats_or_goes_back() {
+        let epoch = Instant::now() + Duration::from_secs(1);
+        let mut timeline = CaptureTimeline::new(epoch);
+        // From before the epoch
+        assert_eq!(timeline.pts(epoch - Duration::from_millis(10)), 0);
+        assert_eq!(timeline.pts(epoch), 1);
+        assert_eq!(timeline.pts(epoch + Duration::from_millis(1)), 90);
+        assert_eq!(timeline.pts(epoch + Duration::from_micros(500)), 91);
+    }
+}
@@ -0,0 +1,43 @@
+mod encode;
+#[cfg(feature = "moq")]
+mod moq;
+mod muxer;
+#[cfg(feature = "ndi")]
+mod ndi;
+#[cfg(feature = "rist")]
+mod rist;
+#[cfg(feature = "rtmp")]
+mod rtmp;
+#[cfg(feature = "rtsp")]
+mod rtsp;
+#[cfg(feature = "segment")]
+mod segment;
+#[cfg(feature = "srt")]
+mod srt;
+mod tee;
+mod text;
+#[cfg(feature = "udp")]
+mod udp;
+
+pub use encode::{
+    AacEncoder, CaptureTimeline, H264Encoder, MAX_AUDIO_OFFSET_MS, VIDEO_CLOCK_RATE,
+};
+#[cfg(feature = "moq")]
+pub use moq::MoqPublisher;
+pub use muxer::{write_chapters, Marker, Muxer, StreamInfo, StreamLayout, VideoParams};
+#[cfg(feature = "ndi")]
+pub use ndi::NdiSender;
+#[cfg(feature = "rist")]
+pub use rist::{RistProfile, RistSettings};
+#[cfg(feature = "rtmp")]
+pub use rtmp::RtmpSettings;
+#[cfg(feature = "rtsp")]
+pub use rtsp::RtspServer;
+#[cfg(feature = "segment")]
+pub use segment::{SegmentFormat, SegmentSettings};
+#[cfg(feature = "srt")]
+pub use srt::{SrtMode, SrtSettings};
+pub use tee::{OutputSink, SharedEncoder, Tee};
+pub use text::text_stream;
+#[cfg(feature = "udp")]
+pub use udp::{UdpEncapsulation, UdpSettings};
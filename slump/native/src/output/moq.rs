@@ -0,0 +1,477 @@
+use super::{OutputSink, SharedEncoder};
+use crate::error::{Result, SlumpError};
+use bytes::{BufMut, Bytes, BytesMut};
+use ffmpeg_next::Packet;
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+};
+use wtransport::{ClientConfig, Endpoint, SendStream};
+
+// MoQ Transport draft-04, the revision spoken by the moq-rs relays
+const MOQ_VERSION: u64 = 0xff00_0004;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Audio has no keyframes, so start a new group about once a second
+const AUDIO_OBJECTS_PER_GROUP: u64 = 50;
+
+const MSG_SUBSCRIBE: u64 = 0x03;
+const MSG_SUBSCRIBE_OK: u64 = 0x04;
+const MSG_SUBSCRIBE_DONE: u64 = 0x0b;
+const MSG_ANNOUNCE: u64 = 0x06;
+const MSG_ANNOUNCE_OK: u64 = 0x07;
+const MSG_ANNOUNCE_ERROR: u64 = 0x08;
+const MSG_UNSUBSCRIBE: u64 = 0x0a;
+const MSG_GOAWAY: u64 = 0x10;
+const MSG_CLIENT_SETUP: u64 = 0x40;
+const MSG_SERVER_SETUP: u64 = 0x41;
+const STREAM_HEADER_GROUP: u64 = 0x51;
+
+const VIDEO_TRACK: &str = "video";
+const AUDIO_TRACK: &str = "audio";
+
+struct MoqObject {
+    track: &'static str,
+    // First object of a new group
+    group_start: bool,
+    payload: Bytes,
+}
+
+#[derive(Clone, Copy)]
+struct Subscription {
+    subscribe_id: u64,
+    track_alias: u64,
+}
+
+type Subscriptions = Arc<Mutex<HashMap<String, Subscription>>>;
+
+// Experimental: announces a namespace to a MoQ relay over WebTransport and
+// serves subscriptions to the "video" (H.264 Annex B) and "audio" (raw AAC,
+// each group opening with the AudioSpecificConfig) tracks
+pub struct MoqPublisher {
+    audio_config: Bytes,
+    audio_objects: u64,
+    objects: mpsc::UnboundedSender<MoqObject>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl MoqPublisher {
+    pub fn connect(url: &str, namespace: &str, encoder: &SharedEncoder) -> Result<Self> {
+        let audio_config = encoder
+            .audio()
+            .map(|audio| unsafe {
+                let codec = &*audio.codec().as_ptr();
+                if codec.extradata.is_null() {
+                    Bytes::new()
+                } else {
+                    Bytes::copy_from_slice(std::slice::from_raw_parts(
+                        codec.extradata,
+                        codec.extradata_size as usize,
+                    ))
+                }
+            })
+            .unwrap_or_default();
+
+        let (objects_tx, objects_rx) = mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        let url = url.to_string();
+        let namespace = namespace.to_string();
+        let thread = std::thread::spawn(move || {
+            let rt = match crate::runtime::get() {
+                Ok(rt) => rt,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(SlumpError::Network(e.to_string())));
+                    return;
+                }
+            };
+
+            rt.block_on(async move {
+                let session = match MoqSession::connect(&url, &namespace).await {
+                    Ok(session) => {
+                        let _ = ready_tx.send(Ok(()));
+                        session
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                if let Err(e) = session.run(objects_rx, shutdown_rx).await {
+                    log::error!("MoQ session ended: {}", e);
+                }
+            });
+        });
+
+        ready_rx
+            .recv_timeout(CONNECT_TIMEOUT)
+            .map_err(|_| SlumpError::Network("Timed out connecting to MoQ relay".into()))??;
+
+        Ok(Self {
+            audio_config,
+            audio_objects: 0,
+            objects: objects_tx,
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+        })
+    }
+
+    fn send(&self, track: &'static str, group_start: bool, payload: Bytes) -> Result<()> {
+        self.objects
+            .send(MoqObject {
+                track,
+                group_start,
+                payload,
+            })
+            .map_err(|_| SlumpError::Network("MoQ session is closed".into()))
+    }
+}
+
+// Video groups follow the shared encoder's GOPs; SPS/PPS are repeated in-band
+impl OutputSink for MoqPublisher {
+    fn write_video_packet(&mut self, packet: &Packet) -> Result<()> {
+        let Some(data) = packet.data() else {
+            return Ok(());
+        };
+        self.send(VIDEO_TRACK, packet.is_key(), Bytes::copy_from_slice(data))
+    }
+
+    fn write_audio_packet(&mut self, packet: &Packet) -> Result<()> {
+        let Some(data) = packet.data() else {
+            return Ok(());
+        };
+        if self.audio_objects % AUDIO_OBJECTS_PER_GROUP == 0 {
+            self.send(AUDIO_TRACK, true, self.audio_config.clone())?;
+        }
+        self.audio_objects += 1;
+        self.send(AUDIO_TRACK, false, Bytes::copy_from_slice(data))
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Drop for MoqPublisher {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct GroupStream {
+    stream: SendStream,
+    next_object: u64,
+}
+
+struct MoqSession {
+    connection: wtransport::Connection,
+    control: (SendStream, wtransport::RecvStream),
+    subscriptions: Subscriptions,
+}
+
+impl MoqSession {
+    async fn connect(url: &str, namespace: &str) -> Result<Self> {
+        let network = |e: &dyn std::fmt::Display| SlumpError::Network(e.to_string());
+
+        let config = ClientConfig::builder()
+            .with_bind_default()
+            .with_native_certs()
+            .build();
+        let connection = Endpoint::client(config)
+            .map_err(|e| network(&e))?
+            .connect(url)
+            .await
+            .map_err(|e| network(&e))?;
+
+        let (mut send, mut recv) = connection
+            .open_bi()
+            .await
+            .map_err(|e| network(&e))?
+            .await
+            .map_err(|e| network(&e))?;
+
+        // CLIENT_SETUP: one version, role parameter = publisher
+        let mut setup = BytesMut::new();
+        put_varint(&mut setup, MSG_CLIENT_SETUP);
+        put_varint(&mut setup, 1);
+        put_varint(&mut setup, MOQ_VERSION);
+        put_varint(&mut setup, 1);
+        put_varint(&mut setup, 0x00);
+        put_varint(&mut setup, 1);
+        put_varint(&mut setup, 0x01);
+        send.write_all(&setup).await.map_err(|e| network(&e))?;
+
+        if read_varint(&mut recv).await? != MSG_SERVER_SETUP {
+            return Err(SlumpError::Network(
+                "MoQ relay did not send SERVER_SETUP".into(),
+            ));
+        }
+        let version = read_varint(&mut recv).await?;
+        if version != MOQ_VERSION {
+            return Err(SlumpError::Network(format!(
+                "MoQ relay selected unsupported version {:#x}",
+                version
+            )));
+        }
+        skip_parameters(&mut recv).await?;
+
+        let mut announce = BytesMut::new();
+        put_varint(&mut announce, MSG_ANNOUNCE);
+        put_string(&mut announce, namespace);
+        put_varint(&mut announce, 0);
+        send.write_all(&announce).await.map_err(|e| network(&e))?;
+
+        Ok(Self {
+            connection,
+            control: (send, recv),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    async fn run(
+        self,
+        mut objects: mpsc::UnboundedReceiver<MoqObject>,
+        mut shutdown: oneshot::Receiver<()>,
+    ) -> Result<()> {
+        let (mut control_send, mut control_recv) = self.control;
+        let subscriptions = self.subscriptions.clone();
+        let control = async move {
+            loop {
+                match read_varint(&mut control_recv).await? {
+                    MSG_SUBSCRIBE => {
+                        let subscribe_id = read_varint(&mut control_recv).await?;
+                        let track_alias = read_varint(&mut control_recv).await?;
+                        let _namespace = read_string(&mut control_recv).await?;
+                        let track = read_string(&mut control_recv).await?;
+                        // Filter: latest group/object carry nothing, absolute start
+                        // adds a location and absolute range adds two
+                        let locations = match read_varint(&mut control_recv).await? {
+                            3 => 1,
+                            4 => 2,
+                            _ => 0,
+                        };
+                        for _ in 0..locations * 2 {
+                            read_varint(&mut control_recv).await?;
+                        }
+                        skip_parameters(&mut control_recv).await?;
+
+                        let mut reply = BytesMut::new();
+                        if track == VIDEO_TRACK || track == AUDIO_TRACK {
+                            subscriptions.lock().unwrap().insert(
+                                track,
+                                Subscription {
+                                    subscribe_id,
+                                    track_alias,
+                                },
+                            );
+                            put_varint(&mut reply, MSG_SUBSCRIBE_OK);
+                            put_varint(&mut reply, subscribe_id);
+                            put_varint(&mut reply, 0);
+                            put_varint(&mut reply, 0);
+                        } else {
+                            // SUBSCRIBE_ERROR: track does not exist
+                            put_varint(&mut reply, 0x05);
+                            put_varint(&mut reply, subscribe_id);
+                            put_varint(&mut reply, 0x04);
+                            put_string(&mut reply, "unknown track");
+                            put_varint(&mut reply, track_alias);
+                        }
+                        control_send
+                            .write_all(&reply)
+                            .await
+                            .map_err(|e| SlumpError::Network(e.to_string()))?;
+                    }
+                    MSG_UNSUBSCRIBE => {
+                        let subscribe_id = read_varint(&mut control_recv).await?;
+                        subscriptions
+                            .lock()
+                            .unwrap()
+                            .retain(|_, s| s.subscribe_id != subscribe_id);
+
+                        // SUBSCRIBE_DONE: unsubscribed, no final group
+                        let mut reply = BytesMut::new();
+                        put_varint(&mut reply, MSG_SUBSCRIBE_DONE);
+                        put_varint(&mut reply, subscribe_id);
+                        put_varint(&mut reply, 0);
+                        put_string(&mut reply, "");
+                        put_varint(&mut reply, 0);
+                        control_send
+                            .write_all(&reply)
+                            .await
+                            .map_err(|e| SlumpError::Network(e.to_string()))?;
+                    }
+                    MSG_ANNOUNCE_OK => {
+                        let namespace = read_string(&mut control_recv).await?;
+                        log::info!("MoQ relay accepted namespace {}", namespace);
+                    }
+                    MSG_ANNOUNCE_ERROR => {
+                        let _namespace = read_string(&mut control_recv).await?;
+                        let code = read_varint(&mut control_recv).await?;
+                        let reason = read_string(&mut control_recv).await?;
+                        return Err(SlumpError::Network(format!(
+                            "MoQ relay rejected announce ({}): {}",
+                            code, reason
+                        )));
+                    }
+                    MSG_GOAWAY => {
+                        return Err(SlumpError::Network("MoQ relay is going away".into()));
+                    }
+                    other => {
+                        // draft-04 control messages carry no length, so an
+                        // unknown one cannot be skipped
+                        return Err(SlumpError::Network(format!(
+                            "Unsupported MoQ control message {:#x}",
+                            other
+                        )));
+                    }
+                }
+            }
+        };
+
+        let connection = self.connection;
+        let subscriptions = self.subscriptions;
+        let media = async move {
+            let mut groups: HashMap<&'static str, (u64, GroupStream)> = HashMap::new();
+            let mut next_group: HashMap<&'static str, u64> = HashMap::new();
+
+            while let Some(object) = objects.recv().await {
+                let subscription = subscriptions.lock().unwrap().get(object.track).copied();
+                let Some(subscription) = subscription else {
+                    groups.remove(object.track);
+                    continue;
+                };
+
+                if object.group_start {
+                    if let Some((_, mut previous)) = groups.remove(object.track) {
+                        let _ = previous.stream.finish().await;
+                    }
+
+                    let group_id = next_group.entry(object.track).or_insert(0);
+                    let mut stream = connection
+                        .open_uni()
+                        .await
+                        .map_err(|e| SlumpError::Network(e.to_string()))?
+                        .await
+                        .map_err(|e| SlumpError::Network(e.to_string()))?;
+
+                    let mut header = BytesMut::new();
+                    put_varint(&mut header, STREAM_HEADER_GROUP);
+                    put_varint(&mut header, subscription.subscribe_id);
+                    put_varint(&mut header, subscription.track_alias);
+                    put_varint(&mut header, *group_id);
+                    // Publisher priority: audio ahead of video
+                    header.put_u8(if object.track == AUDIO_TRACK { 0 } else { 1 });
+                    stream
+                        .write_all(&header)
+                        .await
+                        .map_err(|e| SlumpError::Network(e.to_string()))?;
+
+                    groups.insert(
+                        object.track,
+                        (
+                            *group_id,
+                            GroupStream {
+                                stream,
+                                next_object: 0,
+                            },
+                        ),
+                    );
+                    *group_id += 1;
+                }
+
+                // Objects before the first group start have nothing to join
+                let Some((_, group)) = groups.get_mut(object.track) else {
+                    continue;
+                };
+
+                let mut frame = BytesMut::new();
+                put_varint(&mut frame, group.next_object);
+                put_varint(&mut frame, object.payload.len() as u64);
+                frame.extend_from_slice(&object.payload);
+                group.next_object += 1;
+
+                if let Err(e) = group.stream.write_all(&frame).await {
+                    // The relay may reset a group it no longer needs
+                    log::debug!("MoQ group stream closed: {}", e);
+                    groups.remove(object.track);
+                }
+            }
+            Ok::<(), SlumpError>(())
+        };
+
+        tokio::select! {
+            _ = &mut shutdown => Ok(()),
+            result = control => result,
+            result = media => result,
+        }
+    }
+}
+
+fn put_varint(buf: &mut BytesMut, value: u64) {
+    // QUIC variable-length integer (RFC 9000 section 16)
+    if value < 1 << 6 {
+        buf.put_u8(value as u8);
+    } else if value < 1 << 14 {
+        buf.put_u16(0x4000 | value as u16);
+    } else if value < 1 << 30 {
+        buf.put_u32(0x8000_0000 | value as u32);
+    } else {
+        buf.put_u64(0xc000_0000_0000_0000 | value);
+    }
+}
+
+fn put_string(buf: &mut BytesMut, value: &str) {
+    put_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+async fn read_varint<R: AsyncRead + Unpin>(reader: &mut R) -> Result<u64> {
+    let network = |e: std::io::Error| SlumpError::Network(e.to_string());
+    let first = reader.read_u8().await.map_err(network)?;
+    let len = 1usize << (first >> 6);
+    let mut value = (first & 0x3f) as u64;
+    for _ in 1..len {
+        value = (value << 8) | reader.read_u8().await.map_err(network)? as u64;
+    }
+    Ok(value)
+}
+
+async fn read_string<R: AsyncRead + Unpin>(reader: &mut R) -> Result<String> {
+    let len = read_varint(reader).await? as usize;
+    let mut bytes = vec![0u8; len];
+    reader
+        .read_exact(&mut bytes)
+        .await
+        .map_err(|e| SlumpError::Network(e.to_string()))?;
+    String::from_utf8(bytes).map_err(|e| SlumpError::Network(e.to_string()))
+}
+
+async fn skip_parameters<R: AsyncRead + Unpin>(reader: &mut R) -> Result<()> {
+    let count = read_varint(reader).await?;
+    for _ in 0..count {
+        let _key = read_varint(reader).await?;
+        let len = read_varint(reader).await? as usize;
+        let mut value = vec![0u8; len];
+        reader
+            .read_exact(&mut value)
+            .await
+            .map_err(|e| SlumpError::Network(e.to_string()))?;
+    }
+    Ok(())
+}
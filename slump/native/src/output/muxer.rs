@@ -0,0 +1,314 @@
+use super::{text::text_packet, OutputSink, SharedEncoder};
+use crate::{
+    error::Result,
+    video::{Chroma, Colorimetry},
+};
+use ffmpeg_next::{codec, format, Dictionary, Packet, Rational, Rescale};
+use std::{any::Any, time::Duration};
+
+#[derive(Debug, Clone, Copy)]
+pub struct VideoParams {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub bitrate_kbps: u32,
+    pub colorimetry: Colorimetry,
+    pub chroma: Chroma,
+}
+
+// Codec parameters of the shared encode, kept so files can be (re)opened
+// without access to the encoder itself
+#[derive(Clone)]
+pub struct StreamInfo {
+    pub parameters: codec::Parameters,
+    pub time_base: Rational,
+    // Nominal, for video; its timestamps follow capture time, not the rate
+    pub frame_rate: Option<Rational>,
+}
+
+#[derive(Clone)]
+pub struct StreamLayout {
+    pub video: StreamInfo,
+    pub audio: Option<StreamInfo>,
+    // Captions, for recordings started with them
+    pub text: Option<StreamInfo>,
+}
+
+// Named position on the output timeline, written as a chapter start
+#[derive(Debug, Clone)]
+pub struct Marker {
+    pub time: Duration,
+    pub label: String,
+}
+
+// Chapters run from each marker to the next one, the last to the end of the file
+pub fn write_chapters(
+    output: &mut format::context::Output,
+    markers: &[Marker],
+    end: Duration,
+) -> Result<()> {
+    let millis = |d: Duration| d.as_millis() as i64;
+    for (i, marker) in markers.iter().enumerate() {
+        let start = millis(marker.time);
+        let chapter_end = markers
+            .get(i + 1)
+            .map_or(millis(end), |next| millis(next.time));
+        output.add_chapter(
+            i as i64,
+            Rational::new(1, 1000),
+            start,
+            chapter_end.max(start),
+            &marker.label,
+        )?;
+    }
+    Ok(())
+}
+
+struct MuxedStream {
+    index: usize,
+    // Encoder time base in, muxer time base out
+    source: Rational,
+    target: Rational,
+}
+
+// Writes the shared H.264 + AAC packets to an ffmpeg output context (file or network URL)
+pub struct Muxer {
+    output: format::context::Output,
+    video: MuxedStream,
+    audio: Option<MuxedStream>,
+    text: Option<(MuxedStream, codec::Id)>,
+    // Video/audio pts offsets, set at the first keyframe after open or resync
+    offsets: Option<(i64, i64)>,
+    next_video_pts: i64,
+    // One frame at the nominal rate, where the timeline picks up after a gap
+    frame_duration: i64,
+    bytes_written: u64,
+    markers: Vec<Marker>,
+    finished: bool,
+}
+
+impl Muxer {
+    pub fn open(
+        url: &str,
+        format_name: &str,
+        options: Dictionary,
+        encoder: &SharedEncoder,
+    ) -> Result<Self> {
+        Self::open_layout(url, format_name, options, &encoder.layout())
+    }
+
+    pub fn open_layout(
+        url: &str,
+        format_name: &str,
+        options: Dictionary,
+        layout: &StreamLayout,
+    ) -> Result<Self> {
+        // Protocol options are taken when the URL is opened, muxer options
+        // (movflags, hls_time, ...) when the header is written
+        let mut output = format::output_as_with(&url, format_name, options.clone())?;
+
+        let mut video_ost = output.add_stream(layout.video.parameters.id())?;
+        video_ost.set_parameters(layout.video.parameters.clone());
+        let video_index = video_ost.index();
+
+        let audio_index = match &layout.audio {
+            Some(audio) => {
+                let mut audio_ost = output.add_stream(audio.parameters.id())?;
+                audio_ost.set_parameters(audio.parameters.clone());
+                Some(audio_ost.index())
+            }
+            None => None,
+        };
+
+        let text_index = match &layout.text {
+            Some(text) => {
+                let mut text_ost = output.add_stream(text.parameters.id())?;
+                text_ost.set_parameters(text.parameters.clone());
+                Some(text_ost.index())
+            }
+            None => None,
+        };
+
+        output.write_header_with(options)?;
+
+        // The muxer may pick its own stream time bases in write_header
+        let time_base = |index: usize| {
+            output
+                .stream(index)
+                .map(|s| s.time_base())
+                .unwrap_or(Rational::new(1, 90000))
+        };
+        let video = MuxedStream {
+            index: video_index,
+            source: layout.video.time_base,
+            target: time_base(video_index),
+        };
+        let audio = audio_index
+            .zip(layout.audio.as_ref())
+            .map(|(index, audio)| MuxedStream {
+                index,
+                source: audio.time_base,
+                target: time_base(index),
+            });
+        let text = text_index.zip(layout.text.as_ref()).map(|(index, text)| {
+            (
+                MuxedStream {
+                    index,
+                    source: text.time_base,
+                    target: time_base(index),
+                },
+                text.parameters.id(),
+            )
+        });
+
+        Ok(Self {
+            output,
+            video,
+            audio,
+            text,
+            offsets: None,
+            next_video_pts: 0,
+            frame_duration: layout.video.frame_rate.map_or(1, |rate| {
+                1i64.rescale(rate.invert(), layout.video.time_base).max(1)
+            }),
+            bytes_written: 0,
+            markers: Vec::new(),
+            finished: false,
+        })
+    }
+
+    // Media time written so far, excluding any gaps closed by resync
+    pub fn duration(&self) -> Duration {
+        let time_base = self.video.source;
+        Duration::from_secs_f64(
+            self.next_video_pts.max(0) as f64 * time_base.numerator() as f64
+                / time_base.denominator() as f64,
+        )
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    // Starts a chapter at the current position; returns where it landed
+    pub fn add_marker(&mut self, label: &str) -> Duration {
+        let time = self.duration();
+        self.markers.push(Marker {
+            time,
+            label: label.to_string(),
+        });
+        time
+    }
+
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    // Drops input until the next keyframe, then continues the timeline
+    // where it left off (used to close the gap after a pause)
+    pub fn resync(&mut self) {
+        self.offsets = None;
+    }
+
+    fn write_packet(
+        output: &mut format::context::Output,
+        packet: &Packet,
+        stream: &MuxedStream,
+        offset: i64,
+    ) -> Result<()> {
+        let mut packet = packet.clone();
+        packet.set_pts(packet.pts().map(|pts| pts - offset));
+        packet.set_dts(packet.dts().map(|dts| dts - offset));
+        packet.set_stream(stream.index);
+        packet.rescale_ts(stream.source, stream.target);
+        packet.write_interleaved(output)?;
+        Ok(())
+    }
+
+    pub fn write_video(&mut self, packet: &Packet) -> Result<()> {
+        let pts = packet.pts().unwrap_or(0);
+        let (video_offset, _) = match self.offsets {
+            Some(offsets) => offsets,
+            // Output has to start on a keyframe to be decodable
+            None if !packet.is_key() => return Ok(()),
+            None => {
+                let video_offset = pts - self.next_video_pts;
+                let audio_offset = self
+                    .audio
+                    .as_ref()
+                    .map(|audio| video_offset.rescale(self.video.source, audio.source))
+                    .unwrap_or(0);
+                self.offsets = Some((video_offset, audio_offset));
+                (video_offset, audio_offset)
+            }
+        };
+
+        self.next_video_pts = pts - video_offset + self.frame_duration;
+        self.bytes_written += packet.size() as u64;
+        Self::write_packet(&mut self.output, packet, &self.video, video_offset)
+    }
+
+    pub fn write_audio(&mut self, packet: &Packet) -> Result<()> {
+        let (Some(audio), Some((_, audio_offset))) = (self.audio.as_ref(), self.offsets) else {
+            return Ok(());
+        };
+        // Audio from before the video start would land at negative time
+        if packet.pts().unwrap_or(0) < audio_offset {
+            return Ok(());
+        }
+
+        self.bytes_written += packet.size() as u64;
+        Self::write_packet(&mut self.output, packet, audio, audio_offset)
+    }
+
+    // A cue starting at the current position; false without a text track or
+    // before the first keyframe, when there is no position yet
+    pub fn write_text(&mut self, text: &str, duration: Duration) -> Result<bool> {
+        let Some((stream, id)) = self.text.as_ref() else {
+            return Ok(false);
+        };
+        if self.offsets.is_none() {
+            return Ok(false);
+        }
+        let packet = text_packet(*id, text, self.duration(), duration);
+        self.bytes_written += packet.size() as u64;
+        Self::write_packet(&mut self.output, &packet, stream, 0)?;
+        Ok(true)
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        write_chapters(&mut self.output, &self.markers, self.duration())?;
+        self.output.write_trailer()?;
+        Ok(())
+    }
+}
+
+impl OutputSink for Muxer {
+    fn write_video_packet(&mut self, packet: &Packet) -> Result<()> {
+        self.write_video(packet)
+    }
+
+    fn write_audio_packet(&mut self, packet: &Packet) -> Result<()> {
+        self.write_audio(packet)
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Drop for Muxer {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::warn!("Failed to finalize output: {}", e);
+        }
+    }
+}
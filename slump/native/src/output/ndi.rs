@@ -0,0 +1,278 @@
+use super::OutputSink;
+use crate::audio::{CHANNELS, SAMPLE_RATE};
+use crate::error::{Result, SlumpError};
+use crate::video::Resampler;
+use ffmpeg_next::{format::Pixel, Frame};
+use libloading::Library;
+use std::{
+    any::Any,
+    ffi::{c_char, c_void, CString},
+    ptr,
+    time::Instant,
+};
+
+// Subset of the NDI SDK ABI (Processing.NDI.Lib.h); the runtime is loaded
+// at start so slump runs without it installed
+#[repr(C)]
+struct SendCreate {
+    p_ndi_name: *const c_char,
+    p_groups: *const c_char,
+    clock_video: bool,
+    clock_audio: bool,
+}
+
+#[repr(C)]
+struct VideoFrameV2 {
+    xres: i32,
+    yres: i32,
+    four_cc: u32,
+    frame_rate_n: i32,
+    frame_rate_d: i32,
+    picture_aspect_ratio: f32,
+    frame_format_type: i32,
+    timecode: i64,
+    p_data: *const u8,
+    line_stride_in_bytes: i32,
+    p_metadata: *const c_char,
+    timestamp: i64,
+}
+
+#[repr(C)]
+struct AudioFrameV2 {
+    sample_rate: i32,
+    no_channels: i32,
+    no_samples: i32,
+    timecode: i64,
+    p_data: *const f32,
+    channel_stride_in_bytes: i32,
+    p_metadata: *const c_char,
+    timestamp: i64,
+}
+
+const FOURCC_I420: u32 = u32::from_le_bytes(*b"I420");
+const FRAME_FORMAT_PROGRESSIVE: i32 = 1;
+const TIMECODE_SYNTHESIZE: i64 = i64::MAX;
+
+type InitializeFn = unsafe extern "C" fn() -> bool;
+type SendCreateFn = unsafe extern "C" fn(*const SendCreate) -> *mut c_void;
+type SendDestroyFn = unsafe extern "C" fn(*mut c_void);
+type SendVideoFn = unsafe extern "C" fn(*mut c_void, *const VideoFrameV2);
+type SendAudioFn = unsafe extern "C" fn(*mut c_void, *const AudioFrameV2);
+type GetConnectionsFn = unsafe extern "C" fn(*mut c_void, u32) -> i32;
+
+fn library_candidates() -> Vec<String> {
+    let mut candidates = Vec::new();
+    if cfg!(target_os = "windows") {
+        for var in ["NDI_RUNTIME_DIR_V6", "NDI_RUNTIME_DIR_V5"] {
+            if let Ok(dir) = std::env::var(var) {
+                candidates.push(format!("{}\\Processing.NDI.Lib.x64.dll", dir));
+            }
+        }
+        candidates.push("Processing.NDI.Lib.x64.dll".into());
+    } else if cfg!(target_os = "macos") {
+        candidates.push("/usr/local/lib/libndi.dylib".into());
+        candidates.push("libndi.dylib".into());
+    } else {
+        candidates.push("libndi.so.6".into());
+        candidates.push("libndi.so.5".into());
+    }
+    candidates
+}
+
+// Publishes uncompressed frames as an NDI source discoverable on the LAN
+pub struct NdiSender {
+    instance: *mut c_void,
+    send_video: SendVideoFn,
+    send_audio: SendAudioFn,
+    send_destroy: SendDestroyFn,
+    get_connections: GetConnectionsFn,
+    fps: u32,
+    video_buffer: Vec<u8>,
+    audio_buffer: Vec<f32>,
+    resampler: Resampler,
+    // Keeps the name alive for the lifetime of the sender
+    _name: CString,
+    _library: Library,
+}
+
+// The NDI send instance is thread safe per the SDK documentation
+unsafe impl Send for NdiSender {}
+
+impl NdiSender {
+    pub fn new(name: &str, fps: u32) -> Result<Self> {
+        let library = library_candidates()
+            .iter()
+            .find_map(|path| unsafe { Library::new(path) }.ok())
+            .ok_or_else(|| SlumpError::Init("NDI runtime is not installed".into()))?;
+
+        let symbol_error =
+            |e: libloading::Error| SlumpError::Init(format!("Invalid NDI runtime: {}", e));
+        let (initialize, send_create, send_destroy, send_video, send_audio, get_connections) = unsafe {
+            (
+                *library
+                    .get::<InitializeFn>(b"NDIlib_initialize\0")
+                    .map_err(symbol_error)?,
+                *library
+                    .get::<SendCreateFn>(b"NDIlib_send_create\0")
+                    .map_err(symbol_error)?,
+                *library
+                    .get::<SendDestroyFn>(b"NDIlib_send_destroy\0")
+                    .map_err(symbol_error)?,
+                *library
+                    .get::<SendVideoFn>(b"NDIlib_send_send_video_v2\0")
+                    .map_err(symbol_error)?,
+                *library
+                    .get::<SendAudioFn>(b"NDIlib_send_send_audio_v2\0")
+                    .map_err(symbol_error)?,
+                *library
+                    .get::<GetConnectionsFn>(b"NDIlib_send_get_no_connections\0")
+                    .map_err(symbol_error)?,
+            )
+        };
+
+        if !unsafe { initialize() } {
+            return Err(SlumpError::Init("NDI is not supported on this CPU".into()));
+        }
+
+        let name = CString::new(name).map_err(|e| SlumpError::Init(e.to_string()))?;
+        let settings = SendCreate {
+            p_ndi_name: name.as_ptr(),
+            p_groups: ptr::null(),
+            // The worker loop already paces frames
+            clock_video: false,
+            clock_audio: false,
+        };
+        let instance = unsafe { send_create(&settings) };
+        if instance.is_null() {
+            return Err(SlumpError::Init("Failed to create NDI sender".into()));
+        }
+
+        Ok(Self {
+            instance,
+            send_video,
+            send_audio,
+            send_destroy,
+            get_connections,
+            fps,
+            video_buffer: Vec::new(),
+            audio_buffer: Vec::new(),
+            resampler: Resampler::default(),
+            _name: name,
+            _library: library,
+        })
+    }
+
+    pub fn connections(&self) -> u32 {
+        unsafe { (self.get_connections)(self.instance, 0) }.max(0) as u32
+    }
+
+    pub fn write_video(&mut self, frame: &Frame) -> Result<()> {
+        let (width, height) = unsafe {
+            let raw = &*frame.as_ptr();
+            (raw.width as usize, raw.height as usize)
+        };
+        if self.connections() == 0 {
+            return Ok(());
+        }
+        // Capture frames are already scaled, but may be 4:2:2 or 4:4:4
+        let frame = self.resampler.convert(frame, Pixel::YUV420P)?;
+
+        // NDI wants the three I420 planes packed back to back
+        self.video_buffer.clear();
+        unsafe {
+            let raw = &*frame.as_ptr();
+            for plane in 0..3 {
+                let (plane_width, plane_height) = if plane == 0 {
+                    (width, height)
+                } else {
+                    (width / 2, height / 2)
+                };
+                let stride = raw.linesize[plane] as usize;
+                for row in 0..plane_height {
+                    let line =
+                        std::slice::from_raw_parts(raw.data[plane].add(row * stride), plane_width);
+                    self.video_buffer.extend_from_slice(line);
+                }
+            }
+        }
+
+        let ndi_frame = VideoFrameV2 {
+            xres: width as i32,
+            yres: height as i32,
+            four_cc: FOURCC_I420,
+            frame_rate_n: self.fps as i32,
+            frame_rate_d: 1,
+            picture_aspect_ratio: 0.0,
+            frame_format_type: FRAME_FORMAT_PROGRESSIVE,
+            timecode: TIMECODE_SYNTHESIZE,
+            p_data: self.video_buffer.as_ptr(),
+            line_stride_in_bytes: width as i32,
+            p_metadata: ptr::null(),
+            timestamp: 0,
+        };
+        unsafe { (self.send_video)(self.instance, &ndi_frame) };
+        Ok(())
+    }
+
+    pub fn write_audio(&mut self, samples: &[f32]) -> Result<()> {
+        if self.connections() == 0 {
+            return Ok(());
+        }
+
+        // Interleaved capture samples to NDI's planar layout
+        let channels = CHANNELS as usize;
+        let frames = samples.len() / channels;
+        self.audio_buffer.clear();
+        self.audio_buffer.resize(frames * channels, 0.0);
+        for (i, sample) in samples[..frames * channels].iter().enumerate() {
+            self.audio_buffer[(i % channels) * frames + i / channels] = *sample;
+        }
+
+        let ndi_frame = AudioFrameV2 {
+            sample_rate: SAMPLE_RATE as i32,
+            no_channels: channels as i32,
+            no_samples: frames as i32,
+            timecode: TIMECODE_SYNTHESIZE,
+            p_data: self.audio_buffer.as_ptr(),
+            channel_stride_in_bytes: (frames * std::mem::size_of::<f32>()) as i32,
+            p_metadata: ptr::null(),
+            timestamp: 0,
+        };
+        unsafe { (self.send_audio)(self.instance, &ndi_frame) };
+        Ok(())
+    }
+}
+
+// NDI carries uncompressed video, so it bypasses the shared encode
+impl OutputSink for NdiSender {
+    fn encoded(&self) -> bool {
+        false
+    }
+
+    fn write_video_frame(
+        &mut self,
+        frame: &Frame,
+        _source: Option<&Frame>,
+        _captured_at: Instant,
+    ) -> Result<()> {
+        self.write_video(frame)
+    }
+
+    fn write_audio_samples(&mut self, samples: &[f32]) -> Result<()> {
+        self.write_audio(samples)
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Drop for NdiSender {
+    fn drop(&mut self) {
+        unsafe { (self.send_destroy)(self.instance) };
+    }
+}
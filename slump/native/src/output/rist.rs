@@ -0,0 +1,64 @@
+use super::{Muxer, SharedEncoder};
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::Dictionary;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RistProfile {
+    Simple,
+    Main,
+}
+
+impl FromStr for RistProfile {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "simple" => Ok(RistProfile::Simple),
+            "main" => Ok(RistProfile::Main),
+            other => Err(SlumpError::Network(format!(
+                "Unknown RIST profile: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RistSettings {
+    pub address: String,
+    pub profile: RistProfile,
+    // Retransmission buffer; should cover a few round trips on the link
+    pub buffer_ms: u32,
+    // Main profile only (PSK encryption)
+    pub secret: Option<String>,
+}
+
+impl RistSettings {
+    // MPEG-TS over RIST through ffmpeg's librist protocol
+    pub fn open(&self, encoder: &SharedEncoder) -> Result<Muxer> {
+        let profile = match self.profile {
+            RistProfile::Simple => "simple",
+            RistProfile::Main => "main",
+        };
+
+        if self.secret.is_some() && self.profile == RistProfile::Simple {
+            return Err(SlumpError::Network(
+                "RIST encryption requires the main profile".into(),
+            ));
+        }
+
+        let url = format!("rist://{}", self.address);
+
+        let mut options = Dictionary::new();
+        options.set("rist_profile", profile);
+        options.set("buffer_size", &self.buffer_ms.to_string());
+        options.set("pkt_size", "1316");
+        if let Some(secret) = &self.secret {
+            options.set("secret", secret);
+            options.set("encryption", "128");
+        }
+
+        Muxer::open(&url, "mpegts", options, encoder)
+    }
+}
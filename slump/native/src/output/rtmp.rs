@@ -0,0 +1,34 @@
+use super::{Muxer, SharedEncoder};
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::Dictionary;
+
+#[derive(Debug, Clone)]
+pub struct RtmpSettings {
+    // rtmp:// or rtmps:// ingest URL
+    pub url: String,
+    // Appended to the URL path when the service hands it out separately
+    pub stream_key: Option<String>,
+}
+
+impl RtmpSettings {
+    // FLV over RTMP through ffmpeg's rtmp protocol
+    pub fn open(&self, encoder: &SharedEncoder) -> Result<Muxer> {
+        if !self.url.starts_with("rtmp://") && !self.url.starts_with("rtmps://") {
+            return Err(SlumpError::Network(format!(
+                "Invalid RTMP URL: {}",
+                self.url
+            )));
+        }
+
+        let url = match &self.stream_key {
+            Some(key) => format!("{}/{}", self.url.trim_end_matches('/'), key),
+            None => self.url.clone(),
+        };
+
+        let mut options = Dictionary::new();
+        options.set("rtmp_live", "live");
+        options.set("flvflags", "no_duration_filesize");
+
+        Muxer::open(&url, "flv", options, encoder)
+    }
+}
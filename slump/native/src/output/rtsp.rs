@@ -0,0 +1,441 @@
+use super::OutputSink;
+use crate::error::{Result, SlumpError};
+use crate::signaling::lan_address;
+use bytes::Bytes;
+use ffmpeg_next::{Packet, Rational, Rescale};
+use std::{any::Any, net::SocketAddr};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, oneshot},
+    task::JoinSet,
+};
+use webrtc::{
+    rtp::{
+        codecs::h264::H264Payloader,
+        packetizer::{new_packetizer, Packetizer},
+        sequence::new_random_sequencer,
+    },
+    util::Marshal,
+};
+
+const RTP_MTU: usize = 1400;
+const RTP_PAYLOAD_TYPE: u8 = 96;
+const RTP_CLOCK_RATE: u32 = 90000;
+// Packets buffered per client before a slow reader starts skipping
+const CLIENT_BACKLOG: usize = 1024;
+// The server listens on every interface, so a client that sends this much
+// without ending a request is dropped rather than buffered forever
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+// Minimal RTSP server publishing the H.264 stream with interleaved RTP over
+// TCP, which VLC, ffmpeg and most NVRs fall back to when UDP is refused
+pub struct RtspServer {
+    local_addr: SocketAddr,
+    path: String,
+    packetizer: Box<dyn Packetizer + Send + Sync>,
+    // The shared encode's, which RTP timestamps are taken from
+    time_base: Rational,
+    last_timestamp: Option<i64>,
+    packets: broadcast::Sender<Bytes>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl RtspServer {
+    pub fn start(port: u16, path: &str, time_base: Rational) -> Result<Self> {
+        let std_listener = std::net::TcpListener::bind(("0.0.0.0", port))
+            .map_err(|e| SlumpError::Network(format!("Failed to bind port {}: {}", port, e)))?;
+        std_listener
+            .set_nonblocking(true)
+            .map_err(|e| SlumpError::Network(e.to_string()))?;
+        let local_addr = std_listener
+            .local_addr()
+            .map_err(|e| SlumpError::Network(e.to_string()))?;
+
+        let packetizer = new_packetizer(
+            RTP_MTU,
+            RTP_PAYLOAD_TYPE,
+            random_ssrc(),
+            Box::new(H264Payloader::default()),
+            Box::new(new_random_sequencer()),
+            RTP_CLOCK_RATE,
+        );
+
+        let (packets, _) = broadcast::channel(CLIENT_BACKLOG);
+        let path = format!("/{}", path.trim_matches('/'));
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let session_packets = packets.clone();
+        let session_path = path.clone();
+        let thread = std::thread::spawn(move || {
+            let rt = match crate::runtime::get() {
+                Ok(rt) => rt,
+                Err(e) => {
+                    log::error!("Failed to create RTSP runtime: {}", e);
+                    return;
+                }
+            };
+
+            rt.block_on(async move {
+                let listener = match TcpListener::from_std(std_listener) {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        log::error!("Failed to start RTSP listener: {}", e);
+                        return;
+                    }
+                };
+
+                // Sessions live on the shared runtime, so they are aborted when the set drops
+                let mut sessions = JoinSet::new();
+                let mut next_session = 0u64;
+                loop {
+                    tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        Some(_) = sessions.join_next() => {}
+                        accepted = listener.accept() => match accepted {
+                            Ok((socket, addr)) => {
+                                next_session += 1;
+                                log::debug!("RTSP client {} connected", addr);
+                                let session = RtspSession {
+                                    socket,
+                                    session_id: format!("{:08x}", next_session),
+                                    path: session_path.clone(),
+                                    packets: session_packets.clone(),
+                                };
+                                sessions.spawn(async move {
+                                    if let Err(e) = session.run().await {
+                                        log::debug!("RTSP client {} closed: {}", addr, e);
+                                    }
+                                });
+                            }
+                            Err(e) => log::warn!("Failed to accept RTSP connection: {}", e),
+                        }
+                    }
+                }
+            });
+        });
+
+        Ok(Self {
+            local_addr,
+            path,
+            packetizer,
+            time_base,
+            last_timestamp: None,
+            packets,
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn url(&self) -> String {
+        let host = lan_address().unwrap_or_else(|| self.local_addr.ip());
+        format!("rtsp://{}:{}{}", host, self.local_addr.port(), self.path)
+    }
+
+    pub fn viewer_count(&self) -> usize {
+        self.packets.receiver_count()
+    }
+}
+
+// The shared encode repeats SPS/PPS on every keyframe, so clients joining
+// mid-stream can start decoding at the next GOP
+impl OutputSink for RtspServer {
+    fn write_video_packet(&mut self, packet: &Packet) -> Result<()> {
+        if self.packets.receiver_count() == 0 {
+            return Ok(());
+        }
+        let Some(data) = packet.data() else {
+            return Ok(());
+        };
+
+        let _span =
+            tracing::trace_span!("packetize", output = "rtsp", bytes = data.len()).entered();
+        // Each frame goes out at its own capture time, however far the
+        // previous one was, so players keep real time through rate changes
+        // and skipped frames
+        let timestamp = packet
+            .pts()
+            .unwrap_or(0)
+            .rescale(self.time_base, Rational::new(1, RTP_CLOCK_RATE as i32));
+        if let Some(last) = self.last_timestamp {
+            self.packetizer
+                .skip_samples(timestamp.saturating_sub(last).max(0) as u32);
+        }
+        self.last_timestamp = Some(timestamp);
+        let rtp_packets = self
+            .packetizer
+            .packetize(&Bytes::copy_from_slice(data), 0)
+            .map_err(|e| SlumpError::Network(e.to_string()))?;
+        for rtp in rtp_packets {
+            let bytes = rtp
+                .marshal()
+                .map_err(|e| SlumpError::Network(e.to_string()))?;
+            let _ = self.packets.send(bytes);
+        }
+        Ok(())
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Drop for RtspServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct RtspRequest {
+    method: String,
+    uri: String,
+    cseq: String,
+    transport: Option<String>,
+}
+
+struct RtspSession {
+    socket: TcpStream,
+    session_id: String,
+    path: String,
+    packets: broadcast::Sender<Bytes>,
+}
+
+impl RtspSession {
+    async fn run(mut self) -> Result<()> {
+        let mut buffer = Vec::new();
+        let mut playing: Option<broadcast::Receiver<Bytes>> = None;
+
+        loop {
+            while let Some(request) = take_request(&mut buffer)? {
+                match request.method.as_str() {
+                    "OPTIONS" => {
+                        self.respond(
+                            &request,
+                            "200 OK",
+                            "Public: OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN, GET_PARAMETER\r\n",
+                            "",
+                        )
+                        .await?
+                    }
+                    "DESCRIBE" if !request.uri.ends_with(&self.path) => {
+                        self.respond(&request, "404 Not Found", "", "").await?
+                    }
+                    "DESCRIBE" => {
+                        let sdp = format!(
+                            "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=slump\r\nc=IN IP4 0.0.0.0\r\nt=0 0\r\n\
+                             m=video 0 RTP/AVP {pt}\r\na=rtpmap:{pt} H264/{clock}\r\n\
+                             a=fmtp:{pt} packetization-mode=1\r\na=control:trackID=0\r\n",
+                            pt = RTP_PAYLOAD_TYPE,
+                            clock = RTP_CLOCK_RATE,
+                        );
+                        let headers = format!(
+                            "Content-Base: {}/\r\nContent-Type: application/sdp\r\n",
+                            request.uri.trim_end_matches('/')
+                        );
+                        self.respond(&request, "200 OK", &headers, &sdp).await?
+                    }
+                    "SETUP" => {
+                        let transport = request.transport.as_deref().unwrap_or_default();
+                        if transport.contains("TCP") {
+                            let headers = format!(
+                                "Transport: RTP/AVP/TCP;unicast;interleaved=0-1\r\nSession: {}\r\n",
+                                self.session_id
+                            );
+                            self.respond(&request, "200 OK", &headers, "").await?
+                        } else {
+                            // Clients retry with interleaved TCP on 461
+                            self.respond(&request, "461 Unsupported Transport", "", "")
+                                .await?
+                        }
+                    }
+                    "PLAY" => {
+                        let headers = format!("Session: {}\r\n", self.session_id);
+                        self.respond(&request, "200 OK", &headers, "").await?;
+                        playing = Some(self.packets.subscribe());
+                    }
+                    "GET_PARAMETER" => {
+                        let headers = format!("Session: {}\r\n", self.session_id);
+                        self.respond(&request, "200 OK", &headers, "").await?
+                    }
+                    "TEARDOWN" => {
+                        self.respond(&request, "200 OK", "", "").await?;
+                        return Ok(());
+                    }
+                    _ => {
+                        self.respond(&request, "501 Not Implemented", "", "")
+                            .await?
+                    }
+                }
+            }
+
+            let mut chunk = [0u8; 4096];
+            tokio::select! {
+                read = self.socket.read(&mut chunk) => {
+                    let n = read.map_err(|e| SlumpError::Network(e.to_string()))?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    buffer.extend_from_slice(&chunk[..n]);
+                }
+                packet = recv_packet(&mut playing) => match packet {
+                    Ok(packet) => {
+                        // Interleaved frame: '$', channel, 16-bit length, RTP packet
+                        let mut frame = Vec::with_capacity(packet.len() + 4);
+                        frame.push(b'$');
+                        frame.push(0);
+                        frame.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+                        frame.extend_from_slice(&packet);
+                        self.socket
+                            .write_all(&frame)
+                            .await
+                            .map_err(|e| SlumpError::Network(e.to_string()))?;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!("RTSP client fell behind, skipped {} packets", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                }
+            }
+        }
+    }
+
+    async fn respond(
+        &mut self,
+        request: &RtspRequest,
+        status: &str,
+        headers: &str,
+        body: &str,
+    ) -> Result<()> {
+        let response = format!(
+            "RTSP/1.0 {}\r\nCSeq: {}\r\nServer: slump\r\n{}Content-Length: {}\r\n\r\n{}",
+            status,
+            request.cseq,
+            headers,
+            body.len(),
+            body
+        );
+        self.socket
+            .write_all(response.as_bytes())
+            .await
+            .map_err(|e| SlumpError::Network(e.to_string()))
+    }
+}
+
+fn random_ssrc() -> u32 {
+    let rng = ring::rand::SystemRandom::new();
+    ring::rand::generate::<[u8; 4]>(&rng)
+        .map(|bytes| u32::from_be_bytes(bytes.expose()))
+        .unwrap_or(0x5173_0001)
+}
+
+async fn recv_packet(
+    playing: &mut Option<broadcast::Receiver<Bytes>>,
+) -> std::result::Result<Bytes, broadcast::error::RecvError> {
+    match playing {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Pops the next complete request off the buffer, skipping any interleaved
+// RTCP the client sends back. Fails once a request head runs past
+// MAX_REQUEST_HEAD.
+fn take_request(buffer: &mut Vec<u8>) -> Result<Option<RtspRequest>> {
+    loop {
+        if buffer.first() == Some(&b'$') {
+            if buffer.len() < 4 {
+                return Ok(None);
+            }
+            let len = u16::from_be_bytes([buffer[2], buffer[3]]) as usize;
+            if buffer.len() < 4 + len {
+                return Ok(None);
+            }
+            buffer.drain(..4 + len);
+            continue;
+        }
+
+        let searched = buffer.len().min(MAX_REQUEST_HEAD + 4);
+        let Some(end) = buffer[..searched].windows(4).position(|w| w == b"\r\n\r\n") else {
+            if buffer.len() > MAX_REQUEST_HEAD {
+                return Err(SlumpError::Network(format!(
+                    "RTSP request head over {} bytes",
+                    MAX_REQUEST_HEAD
+                )));
+            }
+            return Ok(None);
+        };
+        let head = String::from_utf8_lossy(&buffer[..end]).into_owned();
+        buffer.drain(..end + 4);
+
+        // A head without a request line is skipped
+        if let Some(request) = parse_request(&head) {
+            return Ok(Some(request));
+        }
+    }
+}
+
+fn parse_request(head: &str) -> Option<RtspRequest> {
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let uri = request_line.next().unwrap_or_default().to_string();
+
+    let mut cseq = String::from("0");
+    let mut transport = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "cseq" => cseq = value.trim().to_string(),
+                "transport" => transport = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    Some(RtspRequest {
+        method,
+        uri,
+        cseq,
+        transport,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_requests_and_skips_interleaved_rtcp() {
+        let mut buffer = b"$\x01\x00\x02ab".to_vec();
+        buffer.extend_from_slice(b"OPTIONS rtsp://host/live RTSP/1.0\r\nCSeq: 2\r\n\r\nPLAY");
+        let request = take_request(&mut buffer).unwrap().unwrap();
+        assert_eq!(request.method, "OPTIONS");
+        assert_eq!(request.uri, "rtsp://host/live");
+        assert_eq!(request.cseq, "2");
+        // The next request isn't complete yet
+        assert!(take_request(&mut buffer).unwrap().is_none());
+        assert_eq!(buffer, b"PLAY");
+    }
+
+    #[test]
+    fn refuses_a_request_head_past_the_cap() {
+        let mut buffer = vec![b'A'; MAX_REQUEST_HEAD];
+        assert!(take_request(&mut buffer).unwrap().is_none());
+        buffer.push(b'A');
+        assert!(take_request(&mut buffer).is_err());
+
+        // Also when the end does arrive, but only past the cap
+        let mut buffer = vec![b'A'; MAX_REQUEST_HEAD + 1];
+        buffer.extend_from_slice(b"\r\n\r\n");
+        assert!(take_request(&mut buffer).is_err());
+    }
+}
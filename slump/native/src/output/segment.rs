@@ -0,0 +1,92 @@
+use super::{Muxer, SharedEncoder};
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::Dictionary;
+use std::{path::Path, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SegmentFormat {
+    Hls,
+    Dash,
+}
+
+impl SegmentFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SegmentFormat::Hls => "hls",
+            SegmentFormat::Dash => "dash",
+        }
+    }
+
+    fn manifest(&self) -> &'static str {
+        match self {
+            SegmentFormat::Hls => "index.m3u8",
+            SegmentFormat::Dash => "manifest.mpd",
+        }
+    }
+}
+
+impl FromStr for SegmentFormat {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hls" => Ok(SegmentFormat::Hls),
+            "dash" => Ok(SegmentFormat::Dash),
+            other => Err(SlumpError::Init(format!(
+                "Unknown segment format: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SegmentSettings {
+    // Directory receiving the manifest and media segments
+    pub directory: String,
+    pub format: SegmentFormat,
+    // Rounded up to whole GOPs (one keyframe per second)
+    pub segment_secs: u32,
+    // Segments kept in the live manifest; older ones are deleted
+    pub window_size: u32,
+}
+
+impl SegmentSettings {
+    pub fn manifest_path(&self) -> String {
+        Path::new(&self.directory)
+            .join(self.format.manifest())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // Both formats write CMAF (fragmented MP4) segments so the same
+    // encode settings serve HLS and DASH players
+    pub fn open(&self, encoder: &SharedEncoder) -> Result<Muxer> {
+        std::fs::create_dir_all(&self.directory)
+            .map_err(|e| SlumpError::Init(format!("Failed to create {}: {}", self.directory, e)))?;
+
+        let segment_secs = self.segment_secs.max(1).to_string();
+        let window_size = self.window_size.max(1).to_string();
+
+        let mut options = Dictionary::new();
+        match self.format {
+            SegmentFormat::Hls => {
+                options.set("hls_time", &segment_secs);
+                options.set("hls_list_size", &window_size);
+                options.set("hls_segment_type", "fmp4");
+                options.set("hls_flags", "delete_segments+independent_segments");
+            }
+            SegmentFormat::Dash => {
+                options.set("seg_duration", &segment_secs);
+                options.set("window_size", &window_size);
+                options.set("extra_window_size", "2");
+                options.set("streaming", "1");
+                options.set("use_template", "1");
+                options.set("use_timeline", "1");
+                options.set("remove_at_exit", "1");
+            }
+        }
+
+        Muxer::open(&self.manifest_path(), self.format.name(), options, encoder)
+    }
+}
@@ -0,0 +1,62 @@
+use super::{Muxer, SharedEncoder};
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::Dictionary;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SrtMode {
+    Caller,
+    Listener,
+}
+
+impl FromStr for SrtMode {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "caller" => Ok(SrtMode::Caller),
+            "listener" => Ok(SrtMode::Listener),
+            other => Err(SlumpError::Network(format!("Unknown SRT mode: {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SrtSettings {
+    // host:port to call, or local bind address when listening
+    pub address: String,
+    pub mode: SrtMode,
+    pub latency_ms: u32,
+    pub passphrase: Option<String>,
+}
+
+impl SrtSettings {
+    // MPEG-TS over SRT through ffmpeg's libsrt protocol
+    pub fn open(&self, encoder: &SharedEncoder) -> Result<Muxer> {
+        if let Some(passphrase) = &self.passphrase {
+            // libsrt rejects passphrases outside 10..=79 characters
+            if !(10..=79).contains(&passphrase.len()) {
+                return Err(SlumpError::Network(
+                    "SRT passphrase must be 10 to 79 characters".into(),
+                ));
+            }
+        }
+
+        let mode = match self.mode {
+            SrtMode::Caller => "caller",
+            SrtMode::Listener => "listener",
+        };
+        let url = format!("srt://{}?mode={}&transtype=live", self.address, mode);
+
+        let mut options = Dictionary::new();
+        // ffmpeg's srt protocol takes latency in microseconds
+        options.set("latency", &(self.latency_ms as u64 * 1000).to_string());
+        options.set("pkt_size", "1316");
+        if let Some(passphrase) = &self.passphrase {
+            options.set("passphrase", passphrase);
+            options.set("pbkeylen", "16");
+        }
+
+        Muxer::open(&url, "mpegts", options, encoder)
+    }
+}
@@ -0,0 +1,268 @@
+use super::{AacEncoder, H264Encoder, StreamInfo, StreamLayout, VideoParams};
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::{codec, Frame, Packet, Rational};
+use std::{any::Any, collections::HashMap, time::Instant};
+
+// One destination fed by the tee. Sinks that consume encoded media share a
+// single H.264/AAC encode; raw sinks (e.g. NDI) get the captured frames.
+pub trait OutputSink: Any + Send {
+    fn encoded(&self) -> bool {
+        true
+    }
+
+    // Packets arrive in the shared encoder's time bases
+    fn write_video_packet(&mut self, _packet: &Packet) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_audio_packet(&mut self, _packet: &Packet) -> Result<()> {
+        Ok(())
+    }
+
+    // `source` is the full-resolution capture, when the sink scales on its
+    // own, and `captured_at` when it was grabbed, for sinks that timestamp
+    fn write_video_frame(
+        &mut self,
+        _frame: &Frame,
+        _source: Option<&Frame>,
+        _captured_at: Instant,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_audio_samples(&mut self, _samples: &[f32]) -> Result<()> {
+        Ok(())
+    }
+
+    // For sinks that timestamp audio from their own encode
+    fn set_audio_offset(&mut self, _ms: i32) {}
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+// The H.264/AAC encode shared by every encoded sink
+pub struct SharedEncoder {
+    video: H264Encoder,
+    audio: Option<AacEncoder>,
+    params: VideoParams,
+}
+
+impl SharedEncoder {
+    fn new(params: VideoParams, with_audio: bool) -> Result<Self> {
+        // Global headers for MP4/MKV/DASH; in-band headers are repeated too
+        Ok(Self {
+            video: H264Encoder::new(params, true)?,
+            audio: if with_audio {
+                Some(AacEncoder::new(true)?)
+            } else {
+                None
+            },
+            params,
+        })
+    }
+
+    pub fn video(&self) -> &H264Encoder {
+        &self.video
+    }
+
+    pub fn audio(&self) -> Option<&AacEncoder> {
+        self.audio.as_ref()
+    }
+
+    pub fn params(&self) -> VideoParams {
+        self.params
+    }
+
+    pub fn layout(&self) -> StreamLayout {
+        StreamLayout {
+            video: StreamInfo {
+                parameters: codec::Parameters::from(self.video.codec()),
+                time_base: self.video.time_base(),
+                frame_rate: Some(Rational::new(self.params.fps as i32, 1)),
+            },
+            audio: self.audio.as_ref().map(|audio| StreamInfo {
+                parameters: codec::Parameters::from(audio.codec()),
+                time_base: audio.time_base(),
+                frame_rate: None,
+            }),
+            text: None,
+        }
+    }
+}
+
+// Fans one capture out to any number of sinks. Sinks are added and removed
+// by name without touching the others; a sink that fails is dropped alone.
+#[derive(Default)]
+pub struct Tee {
+    encoder: Option<SharedEncoder>,
+    sinks: HashMap<String, Box<dyn OutputSink>>,
+    audio_offset_ms: i32,
+}
+
+impl Tee {
+    // Starts the shared encode on first use
+    pub fn encoder(&mut self, params: VideoParams, with_audio: bool) -> Result<&SharedEncoder> {
+        if self.encoder.is_none() {
+            let mut encoder = SharedEncoder::new(params, with_audio)?;
+            if let Some(audio) = encoder.audio.as_mut() {
+                audio.set_offset_ms(self.audio_offset_ms);
+            }
+            self.encoder = Some(encoder);
+        }
+        Ok(self.encoder.as_ref().unwrap())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sinks.contains_key(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.sinks.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn insert(&mut self, name: &str, mut sink: Box<dyn OutputSink>) -> bool {
+        if self.sinks.contains_key(name) {
+            return false;
+        }
+        sink.set_audio_offset(self.audio_offset_ms);
+
+        // Start a fresh GOP so the new sink doesn't wait for the next keyframe
+        if sink.encoded() {
+            if let Some(encoder) = self.encoder.as_mut() {
+                encoder.video.force_keyframe();
+            }
+        }
+        self.sinks.insert(name.to_string(), sink);
+        true
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn OutputSink>> {
+        self.sinks.remove(name)
+    }
+
+    pub fn get_mut<T: OutputSink>(&mut self, name: &str) -> Option<&mut T> {
+        self.sinks
+            .get_mut(name)
+            .and_then(|sink| sink.as_any_mut().downcast_mut::<T>())
+    }
+
+    pub fn take<T: OutputSink>(&mut self, name: &str) -> Option<T> {
+        if self.get_mut::<T>(name).is_none() {
+            return None;
+        }
+        self.sinks
+            .remove(name)
+            .and_then(|sink| sink.into_any().downcast::<T>().ok())
+            .map(|sink| *sink)
+    }
+
+    // Positive delays audio against video; kept for sinks added later
+    pub fn set_audio_offset(&mut self, ms: i32) {
+        self.audio_offset_ms = ms;
+        if let Some(audio) = self
+            .encoder
+            .as_mut()
+            .and_then(|encoder| encoder.audio.as_mut())
+        {
+            audio.set_offset_ms(ms);
+        }
+        for sink in self.sinks.values_mut() {
+            sink.set_audio_offset(ms);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sinks.clear();
+        self.encoder = None;
+    }
+
+    // Returns the sinks that failed; they have already been removed
+    pub fn write_video(
+        &mut self,
+        frame: &Frame,
+        source: Option<&Frame>,
+        captured_at: Instant,
+    ) -> Vec<(String, SlumpError)> {
+        let _span = tracing::trace_span!("outputs", sinks = self.sinks.len()).entered();
+        let mut failed = Vec::new();
+
+        let packets = if self.sinks.values().any(|sink| sink.encoded()) {
+            match self.encoder.as_mut().map(|encoder| {
+                tracing::trace_span!("encode", codec = "h264")
+                    .in_scope(|| encoder.video.encode(frame, captured_at))
+            }) {
+                Some(Ok(packets)) => packets,
+                Some(Err(e)) => {
+                    tracing::error!("Failed to encode output video: {}", e);
+                    Vec::new()
+                }
+                None => Vec::new(),
+            }
+        } else {
+            // Nothing left to encode for; the next encoded sink starts over
+            self.encoder = None;
+            Vec::new()
+        };
+
+        for (name, sink) in self.sinks.iter_mut() {
+            let _span = tracing::trace_span!("sink", name = %name).entered();
+            let result = if sink.encoded() {
+                packets
+                    .iter()
+                    .try_for_each(|packet| sink.write_video_packet(packet))
+            } else {
+                sink.write_video_frame(frame, source, captured_at)
+            };
+            if let Err(e) = result {
+                failed.push((name.clone(), e));
+            }
+        }
+
+        for (name, _) in &failed {
+            self.sinks.remove(name);
+        }
+        failed
+    }
+
+    pub fn write_audio(&mut self, samples: &[f32]) -> Vec<(String, SlumpError)> {
+        let mut failed = Vec::new();
+
+        let packets = match self
+            .encoder
+            .as_mut()
+            .and_then(|encoder| encoder.audio.as_mut())
+        {
+            Some(audio) => match audio.encode(samples) {
+                Ok(packets) => packets,
+                Err(e) => {
+                    tracing::error!("Failed to encode output audio: {}", e);
+                    Vec::new()
+                }
+            },
+            None => Vec::new(),
+        };
+
+        for (name, sink) in self.sinks.iter_mut() {
+            let _span = tracing::trace_span!("sink", name = %name).entered();
+            let result = if sink.encoded() {
+                packets
+                    .iter()
+                    .try_for_each(|packet| sink.write_audio_packet(packet))
+            } else {
+                sink.write_audio_samples(samples)
+            };
+            if let Err(e) = result {
+                failed.push((name.clone(), e));
+            }
+        }
+
+        for (name, _) in &failed {
+            self.sinks.remove(name);
+        }
+        failed
+    }
+}
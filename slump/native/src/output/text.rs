@@ -0,0 +1,70 @@
+use super::StreamInfo;
+use ffmpeg_next::{codec, ffi, Packet, Rational};
+use std::time::Duration;
+
+// Cues are timed in milliseconds
+const TEXT_TIME_BASE: Rational = Rational(1, 1000);
+// mov_text samples carry their length in 16 bits
+const MAX_MOV_TEXT_BYTES: usize = u16::MAX as usize;
+
+// The default 3GPP text sample entry FFmpeg's mov_text encoder writes:
+// bottom-centred white Serif on a transparent background
+const TX3G_SAMPLE_ENTRY: [u8; 48] = [
+    0x00, 0x00, 0x00, 0x00, // display flags
+    0x01, 0xFF, // horizontal and vertical justification
+    0x00, 0x00, 0x00, 0x00, // background colour
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // text box
+    0x00, 0x00, 0x00, 0x00, // style: start and end char
+    0x00, 0x01, 0x00, 0x12, // font id, face, size
+    0xFF, 0xFF, 0xFF, 0xFF, // text colour
+    0x00, 0x00, 0x00, 0x12, b'f', b't', b'a', b'b', // font table
+    0x00, 0x01, 0x00, 0x01, 0x05, b'S', b'e', b'r', b'i', b'f',
+];
+
+// A subtitle track: SubRip for Matroska, 3GPP timed text for MP4
+pub fn text_stream(id: codec::Id) -> StreamInfo {
+    let mut parameters = codec::Parameters::new();
+    unsafe {
+        let par = &mut *parameters.as_mut_ptr();
+        par.codec_type = ffi::AVMediaType::AVMEDIA_TYPE_SUBTITLE;
+        par.codec_id = id.into();
+        if id == codec::Id::MOV_TEXT {
+            // Freed with the parameters, so it has to come from av_malloc
+            let size = TX3G_SAMPLE_ENTRY.len();
+            let extradata =
+                ffi::av_mallocz(size + ffi::AV_INPUT_BUFFER_PADDING_SIZE as usize) as *mut u8;
+            if !extradata.is_null() {
+                std::ptr::copy_nonoverlapping(TX3G_SAMPLE_ENTRY.as_ptr(), extradata, size);
+                par.extradata = extradata;
+                par.extradata_size = size as i32;
+            }
+        }
+    }
+    StreamInfo {
+        parameters,
+        time_base: TEXT_TIME_BASE,
+        frame_rate: None,
+    }
+}
+
+// One cue, in TEXT_TIME_BASE
+pub fn text_packet(id: codec::Id, text: &str, start: Duration, duration: Duration) -> Packet {
+    let mut data = Vec::with_capacity(text.len() + 2);
+    if id == codec::Id::MOV_TEXT {
+        let mut end = text.len().min(MAX_MOV_TEXT_BYTES);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        data.extend_from_slice(&(end as u16).to_be_bytes());
+        data.extend_from_slice(&text.as_bytes()[..end]);
+    } else {
+        data.extend_from_slice(text.as_bytes());
+    }
+
+    let mut packet = Packet::copy(&data);
+    let start = start.as_millis() as i64;
+    packet.set_pts(Some(start));
+    packet.set_dts(Some(start));
+    packet.set_duration(duration.as_millis() as i64);
+    packet
+}
@@ -0,0 +1,63 @@
+use super::{Muxer, SharedEncoder};
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::Dictionary;
+use std::{net::SocketAddr, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpEncapsulation {
+    // Raw TS packets, seven per datagram (ffplay udp://@:port)
+    Udp,
+    // RTP/MP2T (payload type 33) as expected by most IRDs
+    Rtp,
+}
+
+impl FromStr for UdpEncapsulation {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "udp" => Ok(UdpEncapsulation::Udp),
+            "rtp" => Ok(UdpEncapsulation::Rtp),
+            other => Err(SlumpError::Network(format!(
+                "Unknown UDP encapsulation: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UdpSettings {
+    // Unicast or multicast host:port
+    pub address: String,
+    pub encapsulation: UdpEncapsulation,
+    // Only applied to multicast destinations
+    pub ttl: u32,
+}
+
+impl UdpSettings {
+    pub fn open(&self, encoder: &SharedEncoder) -> Result<Muxer> {
+        let address: SocketAddr = self
+            .address
+            .parse()
+            .map_err(|_| SlumpError::Network(format!("Invalid UDP address: {}", self.address)))?;
+
+        let mut options = Dictionary::new();
+        options.set("pkt_size", "1316");
+        if address.ip().is_multicast() {
+            options.set("ttl", &self.ttl.to_string());
+        }
+
+        match self.encapsulation {
+            UdpEncapsulation::Udp => {
+                Muxer::open(&format!("udp://{}", address), "mpegts", options, encoder)
+            }
+            UdpEncapsulation::Rtp => Muxer::open(
+                &format!("rtp://{}", address),
+                "rtp_mpegts",
+                options,
+                encoder,
+            ),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::{Duration, Instant};
+
+// How far ahead of a deadline sleeping stops and spinning takes over. Sleeps
+// overshoot by up to a scheduler tick, which is much coarser on Windows.
+#[cfg(windows)]
+const SPIN_WINDOW: Duration = Duration::from_millis(2);
+#[cfg(not(windows))]
+const SPIN_WINDOW: Duration = Duration::from_micros(500);
+
+// Paces a loop to a frame rate. Deadlines are computed from the frame count
+// since the last reset rather than by adding up intervals, so 1/fps rounding
+// never accumulates into drift.
+pub struct Pacer {
+    fps: u32,
+    epoch: Instant,
+    frames: u64,
+    // Frames given up on when starting over, until taken
+    skipped: u64,
+    // How far past its deadline the last wait returned
+    late: Duration,
+}
+
+impl Pacer {
+    pub fn new(fps: u32) -> Self {
+        Self {
+            fps: fps.max(1),
+            epoch: Instant::now(),
+            frames: 0,
+            skipped: 0,
+            late: Duration::ZERO,
+        }
+    }
+
+    // Takes effect from the next frame, counted from the current deadline
+    pub fn set_fps(&mut self, fps: u32) {
+        let fps = fps.max(1);
+        if fps != self.fps {
+            self.epoch = self.deadline();
+            self.frames = 0;
+            self.fps = fps;
+        }
+    }
+
+    // Blocks until the next frame is due; false if it was already overdue. A
+    // loop that has fallen a whole frame behind starts over from now instead
+    // of bursting to catch up.
+    pub fn wait(&mut self) -> bool {
+        self.frames += 1;
+        let deadline = self.deadline();
+        let now = Instant::now();
+        if deadline <= now {
+            self.late = now - deadline;
+            if now - deadline >= self.interval() {
+                self.skipped += ((now - deadline).as_nanos() / self.interval().as_nanos()) as u64;
+                self.epoch = now;
+                self.frames = 0;
+            }
+            return false;
+        }
+
+        if deadline - now > SPIN_WINDOW {
+            std::thread::sleep(deadline - now - SPIN_WINDOW);
+        }
+        let mut now = Instant::now();
+        while now < deadline {
+            std::hint::spin_loop();
+            now = Instant::now();
+        }
+        self.late = now - deadline;
+        true
+    }
+
+    pub fn late(&self) -> Duration {
+        self.late
+    }
+
+    // Frames skipped by starting over since the last call
+    pub fn take_skipped(&mut self) -> u64 {
+        std::mem::take(&mut self.skipped)
+    }
+
+    fn deadline(&self) -> Instant {
+        self.epoch + Duration::from_nanos(self.frames * 1_000_000_000 / self.fps as u64)
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_nanos(1_000_000_000 / self.fps as u64)
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::RefCell,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+static INSTALL: Once = Once::new();
+
+thread_local! {
+    // Filled by the hook, where the backtrace still points at the panic, and
+    // taken by catch() once the stack has unwound
+    static LAST_PANIC: RefCell<Option<PanicReport>> = RefCell::new(None);
+}
+
+#[derive(Debug, Clone)]
+pub struct PanicReport {
+    pub message: String,
+    pub backtrace: String,
+}
+
+impl fmt::Display for PanicReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n{}", self.message, self.backtrace)
+    }
+}
+
+// Chains onto whatever hook was there, so panics still reach stderr. There
+// is only one hook per process; the record it logs reaches each JS context
+// through that context's own log sinks.
+pub fn install_hook() {
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let message = payload_message(info.payload());
+            let message = match info.location() {
+                Some(location) => format!("{} at {}", message, location),
+                None => message,
+            };
+            log::error!("Panic in {}: {}", thread_name(), message);
+            LAST_PANIC.with(|last| {
+                *last.borrow_mut() = Some(PanicReport {
+                    message,
+                    backtrace: Backtrace::force_capture().to_string(),
+                });
+            });
+            previous(info);
+        }));
+    });
+}
+
+// Runs `work`, turning a panic into a report instead of unwinding further
+pub fn catch<T>(work: impl FnOnce() -> T) -> Result<T, PanicReport> {
+    panic::catch_unwind(AssertUnwindSafe(work)).map_err(from_payload)
+}
+
+// Only has the backtrace when called on the thread that panicked; a tokio
+// task's panic surfaces on the thread that joins it, with the message alone
+pub fn from_payload(payload: Box<dyn Any + Send>) -> PanicReport {
+    LAST_PANIC
+        .with(|last| last.borrow_mut().take())
+        .unwrap_or_else(|| PanicReport {
+            message: payload_message(payload.as_ref()),
+            backtrace: String::new(),
+        })
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+fn thread_name() -> String {
+    std::thread::current()
+        .name()
+        .unwrap_or("unnamed thread")
+        .to_string()
+}
@@ -0,0 +1,213 @@
+use crate::error::SlumpError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ScreenRecording,
+    Microphone,
+    Camera,
+}
+
+impl Permission {
+    fn label(&self) -> &'static str {
+        match self {
+            Permission::ScreenRecording => "Screen Recording",
+            Permission::Microphone => "Microphone",
+            Permission::Camera => "Camera",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermissionState {
+    Granted,
+    Denied,
+    // Blocked by policy, e.g. parental controls or MDM; the user can't grant it
+    Restricted,
+    // Not asked yet; opening the device or request() shows the prompt
+    NotDetermined,
+    // The platform has no such gate, or won't say; starting is the test
+    Unknown,
+}
+
+impl PermissionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PermissionState::Granted => "granted",
+            PermissionState::Denied => "denied",
+            PermissionState::Restricted => "restricted",
+            PermissionState::NotDetermined => "not_determined",
+            PermissionState::Unknown => "unknown",
+        }
+    }
+}
+
+// A device that failed to open because the OS refused access says so only
+// as a generic I/O error; this turns it into PermissionDenied when the OS
+// reports the permission missing
+pub fn explain(err: SlumpError, permission: Permission) -> SlumpError {
+    if matches!(err, SlumpError::PermissionDenied(_)) {
+        return err;
+    }
+    match check(permission) {
+        PermissionState::Denied | PermissionState::Restricted => SlumpError::PermissionDenied(
+            format!("{} access is not granted ({})", permission.label(), err),
+        ),
+        _ => err,
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub use macos::{check, request};
+
+#[cfg(not(target_os = "macos"))]
+pub fn check(_permission: Permission) -> PermissionState {
+    PermissionState::Unknown
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn request(_permission: Permission) {}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use super::{Permission, PermissionState};
+    use std::{
+        ffi::{c_char, c_void},
+        sync::OnceLock,
+    };
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGPreflightScreenCaptureAccess() -> bool;
+        fn CGRequestScreenCaptureAccess() -> bool;
+    }
+
+    #[link(name = "AVFoundation", kind = "framework")]
+    extern "C" {
+        static AVMediaTypeAudio: *const c_void;
+        static AVMediaTypeVideo: *const c_void;
+    }
+
+    #[link(name = "objc")]
+    extern "C" {
+        fn objc_getClass(name: *const c_char) -> *const c_void;
+        fn sel_registerName(name: *const c_char) -> *const c_void;
+        fn objc_msgSend();
+    }
+
+    extern "C" {
+        // The isa of blocks that capture nothing, from libSystem
+        static _NSConcreteGlobalBlock: u8;
+    }
+
+    // Just enough of the blocks ABI for a completion handler that ignores
+    // its answer
+    #[repr(C)]
+    struct BlockDescriptor {
+        reserved: usize,
+        size: usize,
+    }
+
+    #[repr(C)]
+    struct Block {
+        isa: *const c_void,
+        flags: i32,
+        reserved: i32,
+        invoke: unsafe extern "C" fn(*const Block, bool),
+        descriptor: *const BlockDescriptor,
+    }
+
+    struct Handler(*const Block);
+
+    // Never written after it's built
+    unsafe impl Send for Handler {}
+    unsafe impl Sync for Handler {}
+
+    const BLOCK_IS_GLOBAL: i32 = 1 << 28;
+    static DESCRIPTOR: BlockDescriptor = BlockDescriptor {
+        reserved: 0,
+        size: std::mem::size_of::<Block>(),
+    };
+
+    unsafe extern "C" fn ignore_answer(_block: *const Block, _granted: bool) {}
+
+    pub fn check(permission: Permission) -> PermissionState {
+        match permission {
+            // macOS only says whether access is granted, not whether it has
+            // asked yet, so anything else reads as denied
+            Permission::ScreenRecording => {
+                if unsafe { CGPreflightScreenCaptureAccess() } {
+                    PermissionState::Granted
+                } else {
+                    PermissionState::Denied
+                }
+            }
+            Permission::Microphone | Permission::Camera => unsafe {
+                let Some(class) = capture_device() else {
+                    return PermissionState::Unknown;
+                };
+                let status: unsafe extern "C" fn(
+                    *const c_void,
+                    *const c_void,
+                    *const c_void,
+                ) -> isize = std::mem::transmute(objc_msgSend as unsafe extern "C" fn());
+                let selector =
+                    sel_registerName(b"authorizationStatusForMediaType:\0".as_ptr() as _);
+                match status(class, selector, media_type(permission)) {
+                    0 => PermissionState::NotDetermined,
+                    1 => PermissionState::Restricted,
+                    2 => PermissionState::Denied,
+                    3 => PermissionState::Granted,
+                    _ => PermissionState::Unknown,
+                }
+            },
+        }
+    }
+
+    // Shows the system prompt and returns without waiting for the answer.
+    // macOS prompts once per app; after that the user has to change it in
+    // System Settings, and Screen Recording only takes effect on relaunch.
+    pub fn request(permission: Permission) {
+        match permission {
+            Permission::ScreenRecording => {
+                unsafe { CGRequestScreenCaptureAccess() };
+            }
+            Permission::Microphone | Permission::Camera => unsafe {
+                let Some(class) = capture_device() else {
+                    return;
+                };
+                static HANDLER: OnceLock<Handler> = OnceLock::new();
+                let handler = HANDLER.get_or_init(|| {
+                    Handler(Box::leak(Box::new(Block {
+                        isa: &_NSConcreteGlobalBlock as *const u8 as *const c_void,
+                        flags: BLOCK_IS_GLOBAL,
+                        reserved: 0,
+                        invoke: ignore_answer,
+                        descriptor: &DESCRIPTOR,
+                    })))
+                });
+                let request: unsafe extern "C" fn(
+                    *const c_void,
+                    *const c_void,
+                    *const c_void,
+                    *const Block,
+                ) = std::mem::transmute(objc_msgSend as unsafe extern "C" fn());
+                let selector = sel_registerName(
+                    b"requestAccessForMediaType:completionHandler:\0".as_ptr() as _,
+                );
+                request(class, selector, media_type(permission), handler.0);
+            },
+        }
+    }
+
+    unsafe fn capture_device() -> Option<*const c_void> {
+        let class = objc_getClass(b"AVCaptureDevice\0".as_ptr() as _);
+        (!class.is_null()).then_some(class)
+    }
+
+    unsafe fn media_type(permission: Permission) -> *const c_void {
+        match permission {
+            Permission::Microphone => AVMediaTypeAudio,
+            _ => AVMediaTypeVideo,
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+// What a full queue does with the next item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    // Evict the oldest item; for frames where only the latest matters
+    DropOldest,
+    // Refuse the new item and keep what is queued
+    DropNewest,
+}
+
+impl DropPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DropPolicy::DropOldest => "drop_oldest",
+            DropPolicy::DropNewest => "drop_newest",
+        }
+    }
+}
+
+// Counters for one queue, readable while the stages run
+pub struct QueueStats {
+    pub stage: &'static str,
+    pub capacity: usize,
+    pub policy: DropPolicy,
+    depth: AtomicUsize,
+    high_water: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+impl QueueStats {
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    pub fn high_water(&self) -> usize {
+        self.high_water.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+struct Shared<T> {
+    items: Mutex<VecDeque<T>>,
+    closed: AtomicBool,
+    notify: Notify,
+    stats: Arc<QueueStats>,
+}
+
+// Single-producer, single-consumer queue between two pipeline stages. Unlike
+// a tokio channel a full queue never makes the producer wait: the policy
+// decides what is thrown away, and every drop is counted.
+pub fn bounded<T>(
+    stage: &'static str,
+    capacity: usize,
+    policy: DropPolicy,
+) -> (StageSender<T>, StageReceiver<T>) {
+    let shared = Arc::new(Shared {
+        items: Mutex::new(VecDeque::with_capacity(capacity)),
+        closed: AtomicBool::new(false),
+        notify: Notify::new(),
+        stats: Arc::new(QueueStats {
+            stage,
+            capacity,
+            policy,
+            depth: AtomicUsize::new(0),
+            high_water: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        }),
+    });
+    (
+        StageSender {
+            shared: shared.clone(),
+        },
+        StageReceiver { shared },
+    )
+}
+
+pub struct StageSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> StageSender<T> {
+    // False if something was dropped to make room, or the item itself was
+    pub fn push(&self, item: T) -> bool {
+        let stats = &self.shared.stats;
+        let mut items = self.shared.items.lock().unwrap();
+        let mut kept = true;
+        if items.len() >= stats.capacity {
+            kept = false;
+            stats.dropped.fetch_add(1, Ordering::Relaxed);
+            match stats.policy {
+                DropPolicy::DropOldest => {
+                    items.pop_front();
+                }
+                DropPolicy::DropNewest => return false,
+            }
+        }
+        items.push_back(item);
+        stats.depth.store(items.len(), Ordering::Relaxed);
+        stats.high_water.fetch_max(items.len(), Ordering::Relaxed);
+        drop(items);
+
+        self.shared.notify.notify_one();
+        kept
+    }
+
+    pub fn stats(&self) -> Arc<QueueStats> {
+        self.shared.stats.clone()
+    }
+}
+
+// Dropping the sender ends the stage once it has drained the queue
+impl<T> Drop for StageSender<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.shared.notify.notify_one();
+    }
+}
+
+pub struct StageReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> StageReceiver<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            // notify_one leaves a permit when nobody waits, so a push between
+            // the check and the await is not lost
+            let notified = self.shared.notify.notified();
+            {
+                let mut items = self.shared.items.lock().unwrap();
+                if let Some(item) = items.pop_front() {
+                    self.shared
+                        .stats
+                        .depth
+                        .store(items.len(), Ordering::Relaxed);
+                    return Some(item);
+                }
+            }
+            if self.shared.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            notified.await;
+        }
+    }
+}
+
+// Items held back by a delay that can change while they wait, such as sends
+// under the A/V offset. Kept by the stage itself rather than by waiting in
+// front of its queue, which would fill the queue and drop what it holds.
+pub struct DelayLine<T> {
+    items: VecDeque<(Instant, T)>,
+}
+
+// What a stage with a delay line waits on next
+pub enum Delayed<T, U> {
+    // An item that has waited long enough
+    Due(T),
+    // A new one from the stage's queue
+    Arrived(U),
+}
+
+impl<T> Default for DelayLine<T> {
+    fn default() -> Self {
+        Self {
+            items: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> DelayLine<T> {
+    // `at` is when its wait starts, normally now
+    pub fn push(&mut self, at: Instant, item: T) {
+        self.items.push_back((at, item));
+    }
+
+    // The oldest item, if it's been held `delay` by `now`
+    pub fn pop_due(&mut self, now: Instant, delay: Duration) -> Option<T> {
+        let (at, _) = self.items.front()?;
+        if *at + delay > now {
+            return None;
+        }
+        self.items.pop_front().map(|(_, item)| item)
+    }
+
+    pub fn next_due(&self, delay: Duration) -> Option<Instant> {
+        self.items.front().map(|(at, _)| *at + delay)
+    }
+
+    // Whichever comes first, an item falling due or another arriving; None
+    // once the queue has closed, dropping whatever is still held
+    pub async fn next<U>(
+        &mut self,
+        queue: &mut StageReceiver<U>,
+        delay: Duration,
+    ) -> Option<Delayed<T, U>> {
+        loop {
+            if let Some(item) = self.pop_due(Instant::now(), delay) {
+                return Some(Delayed::Due(item));
+            }
+            let due = self.next_due(delay);
+            let wait = tokio::time::sleep_until(due.unwrap_or_else(Instant::now).into());
+            tokio::select! {
+                item = queue.recv() => return item.map(Delayed::Arrived),
+                _ = wait, if due.is_some() => {}
+            }
+        }
+    }
+}
+
+// A change to a staged device, run by the stage thread with its context
+type Change<T, C> = Box<dyn FnOnce(&mut Option<T>, &mut C) + Send>;
+
+// A device that belongs to one stage thread while the stream runs, such as
+// the video capture or encoder. Until start hands it over it is used in
+// place. After that the stage holds it without any lock, and everyone else
+// queues changes that the stage applies between items. Only this side opens
+// and closes it, so whether there is one is known without asking the stage.
+pub enum Owned<T, C> {
+    Local(Option<T>),
+    Staged {
+        open: bool,
+        changes: mpsc::Sender<Change<T, C>>,
+    },
+}
+
+impl<T, C> Default for Owned<T, C> {
+    fn default() -> Self {
+        Owned::Local(None)
+    }
+}
+
+impl<T: Send + 'static, C: 'static> Owned<T, C> {
+    pub fn is_some(&self) -> bool {
+        match self {
+            Owned::Local(device) => device.is_some(),
+            Owned::Staged { open, .. } => *open,
+        }
+    }
+
+    // The device itself, while no stage has it
+    pub fn local(&mut self) -> Option<&mut Option<T>> {
+        match self {
+            Owned::Local(device) => Some(device),
+            Owned::Staged { .. } => None,
+        }
+    }
+
+    // Opens, replaces or, with None, closes it
+    pub fn set(&mut self, device: Option<T>) {
+        match self {
+            Owned::Local(slot) => *slot = device,
+            Owned::Staged { open, changes } => {
+                *open = device.is_some();
+                let _ = changes.send(Box::new(move |slot, _| *slot = device));
+            }
+        }
+    }
+
+    // Skipped if there is none by the time it runs. Errors can't come back
+    // from the stage, so `change` deals with its own.
+    pub fn change(&mut self, change: impl FnOnce(&mut T) + Send + 'static) {
+        match self {
+            Owned::Local(slot) => {
+                if let Some(device) = slot.as_mut() {
+                    change(device);
+                }
+            }
+            Owned::Staged { changes, .. } => {
+                let _ = changes.send(Box::new(move |slot, _| {
+                    if let Some(device) = slot.as_mut() {
+                        change(device);
+                    }
+                }));
+            }
+        }
+    }
+
+    // Replaces it on the stage thread, where `retire` gets the one it
+    // replaced along with the stage's context. False, doing nothing, while
+    // it is local; swap through local() then.
+    pub fn swap(
+        &mut self,
+        device: Option<T>,
+        retire: impl FnOnce(Option<T>, &mut C) + Send + 'static,
+    ) -> bool {
+        let Owned::Staged { open, changes } = self else {
+            return false;
+        };
+        *open = device.is_some();
+        let _ = changes.send(Box::new(move |slot, context| {
+            let previous = std::mem::replace(slot, device);
+            retire(previous, context);
+        }));
+        true
+    }
+
+    // Moves it to a stage thread along with the queue its changes arrive on.
+    // Changes queued after the stage exits are dropped with it.
+    pub fn hand_off(&mut self) -> StageDevice<T, C> {
+        let (changes, queued) = mpsc::channel();
+        let device = match std::mem::replace(
+            self,
+            Owned::Staged {
+                open: false,
+                changes,
+            },
+        ) {
+            Owned::Local(device) => device,
+            Owned::Staged { .. } => None,
+        };
+        if let Owned::Staged { open, .. } = self {
+            *open = device.is_some();
+        }
+        StageDevice { device, queued }
+    }
+}
+
+// The stage thread's side of an Owned device
+pub struct StageDevice<T, C> {
+    pub device: Option<T>,
+    queued: mpsc::Receiver<Change<T, C>>,
+}
+
+impl<T, C> StageDevice<T, C> {
+    // Runs what was queued since the last call, in order
+    pub fn apply_changes(&mut self, context: &mut C) {
+        while let Ok(change) = self.queued.try_recv() {
+            change(&mut self.device, context);
+        }
+    }
+}
+
+// Time one stage spends per item, summed until the reader takes the average
+#[derive(Default)]
+pub struct StageTiming {
+    total_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl StageTiming {
+    pub fn record(&self, elapsed: Duration) {
+        self.total_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Milliseconds per item since the last call; 0 if nothing went through
+    pub fn take_average_ms(&self) -> f64 {
+        let count = self.count.swap(0, Ordering::Relaxed);
+        let total = self.total_nanos.swap(0, Ordering::Relaxed);
+        if count == 0 {
+            return 0.0;
+        }
+        total as f64 / count as f64 / 1_000_000.0
+    }
+}
+
+#[derive(Default)]
+pub struct StageTimings {
+    pub capture: StageTiming,
+    pub encode: StageTiming,
+    pub send: StageTiming,
+    // From the start of capture to the frame leaving the encoder, and to the
+    // last peer write returning
+    pub to_encoded: StageTiming,
+    pub to_sent: StageTiming,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owned_changes_in_place_until_handed_off() {
+        let mut owned: Owned<u32, Vec<u32>> = Owned::default();
+        assert!(!owned.is_some());
+        owned.set(Some(1));
+        owned.change(|device| *device += 1);
+        assert_eq!(owned.local(), Some(&mut Some(2)));
+        assert!(!owned.swap(Some(5), |_, _| {}));
+        assert_eq!(owned.local(), Some(&mut Some(2)));
+    }
+
+    #[test]
+    fn owned_queues_changes_for_the_stage_once_handed_off() {
+        let mut owned: Owned<u32, Vec<u32>> = Owned::default();
+        owned.set(Some(1));
+        let mut stage = owned.hand_off();
+        assert_eq!(stage.device, Some(1));
+        assert!(owned.is_some());
+        assert!(owned.local().is_none());
+
+        owned.change(|device| *device += 1);
+        assert!(owned.swap(Some(10), |previous, retired: &mut Vec<u32>| {
+            retired.extend(previous)
+        }));
+        owned.set(None);
+        // Known on this side before the stage has seen any of it
+        assert!(!owned.is_some());
+        assert_eq!(stage.device, Some(1));
+
+        let mut retired = Vec::new();
+        stage.apply_changes(&mut retired);
+        assert_eq!(retired, vec![2]);
+        assert_eq!(stage.device, None);
+        // Changes to a device that's gone are skipped
+        owned.change(|device| *device += 1);
+        stage.apply_changes(&mut retired);
+        assert_eq!(stage.device, None);
+    }
+
+    #[test]
+    fn delay_line_holds_items_back_in_order() {
+        let start = Instant::now();
+        let mut line = DelayLine::default();
+        let delay = Duration::from_millis(80);
+        assert_eq!(line.next_due(delay), None);
+        line.push(start, 1);
+        line.push(start + Duration::from_millis(20), 2);
+        assert_eq!(line.next_due(delay), Some(start + delay));
+        assert_eq!(line.pop_due(start + Duration::from_millis(79), delay), None);
+        assert_eq!(line.pop_due(start + delay, delay), Some(1));
+        assert_eq!(line.pop_due(start + delay, delay), None);
+        // A shorter delay lets the rest go sooner
+        assert_eq!(line.pop_due(start + delay, Duration::ZERO), Some(2));
+        assert_eq!(line.pop_due(start + delay, Duration::ZERO), None);
+    }
+}
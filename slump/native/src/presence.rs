@@ -0,0 +1,126 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+// A viewer that stops sending heartbeats is taken to have gone
+const PRESENCE_TIMEOUT: Duration = Duration::from_secs(15);
+const MAX_NAME_CHARS: usize = 64;
+
+// Sent by viewers on the control channel alongside input
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum PresenceMessage {
+    Join { name: Option<String> },
+    Leave,
+    Heartbeat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaveReason {
+    Left,
+    TimedOut,
+    Disconnected,
+}
+
+impl LeaveReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LeaveReason::Left => "left",
+            LeaveReason::TimedOut => "timeout",
+            LeaveReason::Disconnected => "disconnected",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Joined { name: String },
+    Left { name: String, reason: LeaveReason },
+}
+
+struct Viewer {
+    name: String,
+    last_seen: Instant,
+}
+
+// Who has said they are watching. Connected viewers that never join aren't
+// counted, so a broadcast UI can tell an audience from stray connections.
+#[derive(Default)]
+pub struct Presence {
+    viewers: HashMap<String, Viewer>,
+}
+
+impl Presence {
+    // A join from a viewer already present only renames it, and a heartbeat
+    // before joining is ignored
+    pub fn handle(&mut self, viewer_id: &str, message: PresenceMessage) -> Option<Change> {
+        match message {
+            PresenceMessage::Join { name } => {
+                let name = name
+                    .map(|name| name.trim().chars().take(MAX_NAME_CHARS).collect::<String>())
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| viewer_id.to_string());
+                let viewer = Viewer {
+                    name: name.clone(),
+                    last_seen: Instant::now(),
+                };
+                self.viewers
+                    .insert(viewer_id.to_string(), viewer)
+                    .is_none()
+                    .then_some(Change::Joined { name })
+            }
+            PresenceMessage::Leave => self.leave(viewer_id, LeaveReason::Left),
+            PresenceMessage::Heartbeat => {
+                if let Some(viewer) = self.viewers.get_mut(viewer_id) {
+                    viewer.last_seen = Instant::now();
+                }
+                None
+            }
+        }
+    }
+
+    pub fn leave(&mut self, viewer_id: &str, reason: LeaveReason) -> Option<Change> {
+        self.viewers.remove(viewer_id).map(|viewer| Change::Left {
+            name: viewer.name,
+            reason,
+        })
+    }
+
+    // Viewers silent for longer than the timeout, now removed
+    pub fn expire(&mut self) -> Vec<(String, Change)> {
+        let expired: Vec<String> = self
+            .viewers
+            .iter()
+            .filter(|(_, viewer)| viewer.last_seen.elapsed() > PRESENCE_TIMEOUT)
+            .map(|(viewer_id, _)| viewer_id.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|viewer_id| {
+                let change = self.leave(&viewer_id, LeaveReason::TimedOut)?;
+                Some((viewer_id, change))
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.viewers.clear();
+    }
+
+    pub fn count(&self) -> usize {
+        self.viewers.len()
+    }
+
+    // (viewer id, name), sorted by name
+    pub fn viewers(&self) -> Vec<(String, String)> {
+        let mut viewers: Vec<(String, String)> = self
+            .viewers
+            .iter()
+            .map(|(viewer_id, viewer)| (viewer_id.clone(), viewer.name.clone()))
+            .collect();
+        viewers.sort_by(|a, b| a.1.cmp(&b.1));
+        viewers
+    }
+}
@@ -0,0 +1,122 @@
+use crate::error::{Result, SlumpError};
+use crate::video::{self, DisplayInfo};
+use ffmpeg_next::{codec, encoder, format::pixel::Pixel};
+
+// Hardware encoders worth offering, limited to the ones this build enables;
+// being compiled into FFmpeg says nothing about the GPU and driver actually
+// present, so each one is test-opened. VAAPI needs a hardware frames context
+// and is left to the software check.
+const HARDWARE_ENCODERS: &[&str] = &[
+    #[cfg(feature = "nvenc")]
+    "h264_nvenc",
+    #[cfg(feature = "nvenc")]
+    "hevc_nvenc",
+    #[cfg(feature = "nvenc")]
+    "av1_nvenc",
+    #[cfg(feature = "qsv")]
+    "h264_qsv",
+    #[cfg(feature = "qsv")]
+    "hevc_qsv",
+    #[cfg(feature = "qsv")]
+    "av1_qsv",
+    #[cfg(feature = "amf")]
+    "h264_amf",
+    #[cfg(feature = "amf")]
+    "hevc_amf",
+    #[cfg(feature = "amf")]
+    "av1_amf",
+    #[cfg(feature = "videotoolbox")]
+    "h264_videotoolbox",
+    #[cfg(feature = "videotoolbox")]
+    "hevc_videotoolbox",
+];
+
+// Software fallback per codec, named the way codec options take them
+const SOFTWARE_ENCODERS: &[(&str, &str)] = &[
+    ("vp8", "libvpx"),
+    ("h264", "libx264"),
+    ("hevc", "libx265"),
+    ("av1", "libsvtav1"),
+];
+
+const PROBE_WIDTH: u32 = 256;
+const PROBE_HEIGHT: u32 = 144;
+const PROBE_FPS: i32 = 30;
+
+#[derive(Debug, Clone, Default)]
+pub struct MachineCapabilities {
+    pub video_capture_backends: Vec<String>,
+    pub audio_capture_backends: Vec<String>,
+    pub hardware_encoders: Vec<String>,
+    // Codecs with at least one encoder that opened
+    pub codecs: Vec<String>,
+    pub displays: Vec<DisplayInfo>,
+}
+
+pub fn probe() -> Result<MachineCapabilities> {
+    ffmpeg_next::init().map_err(|e| SlumpError::Init(e.to_string()))?;
+
+    let hardware_encoders: Vec<String> = HARDWARE_ENCODERS
+        .iter()
+        .filter(|name| encoder_opens(name, Pixel::NV12))
+        .map(|name| name.to_string())
+        .collect();
+
+    let mut codecs = Vec::new();
+    for (codec, software) in SOFTWARE_ENCODERS {
+        let hardware = hardware_encoders
+            .iter()
+            .any(|name| name.split('_').next() == Some(*codec));
+        if hardware || encoder_opens(software, Pixel::YUV420P) {
+            codecs.push(codec.to_string());
+        }
+    }
+
+    let (video_capture_backends, audio_capture_backends) = capture_backends();
+    Ok(MachineCapabilities {
+        video_capture_backends,
+        audio_capture_backends,
+        hardware_encoders,
+        codecs,
+        displays: video::list_displays(),
+    })
+}
+
+#[cfg(feature = "device")]
+fn capture_backends() -> (Vec<String>, Vec<String>) {
+    use ffmpeg_next::device;
+
+    (
+        device::input::video()
+            .map(|format| format.name().to_string())
+            .collect(),
+        device::input::audio()
+            .map(|format| format.name().to_string())
+            .collect(),
+    )
+}
+
+// Built without libavdevice, so nothing can capture
+#[cfg(not(feature = "device"))]
+fn capture_backends() -> (Vec<String>, Vec<String>) {
+    (Vec::new(), Vec::new())
+}
+
+fn encoder_opens(name: &str, format: Pixel) -> bool {
+    let Some(codec) = encoder::find_by_name(name) else {
+        return false;
+    };
+    let Ok(mut video) = codec::context::Context::new_with_codec(codec)
+        .encoder()
+        .video()
+    else {
+        return false;
+    };
+
+    video.set_width(PROBE_WIDTH);
+    video.set_height(PROBE_HEIGHT);
+    video.set_format(format);
+    video.set_time_base((1, PROBE_FPS));
+    video.set_frame_rate(Some((PROBE_FPS, 1)));
+    video.open().is_ok()
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+// Upper bounds for scores 5 to 2; anything worse is a 1
+const LOSS_PERCENT_STEPS: [f64; 4] = [1.0, 2.5, 5.0, 10.0];
+const RTT_MS_STEPS: [f64; 4] = [100.0, 200.0, 300.0, 500.0];
+const JITTER_MS_STEPS: [f64; 4] = [10.0, 30.0, 50.0, 100.0];
+// Weights of each one-second sample in the short and long averages. The
+// short one is what gets scored; running well above the long one is a trend.
+const SHORT_SMOOTHING: f64 = 0.5;
+const LONG_SMOOTHING: f64 = 0.1;
+// How far above its long average a metric has to be to count as worsening
+const WORSENING_RATIO: f64 = 1.5;
+// A score drops at once but only rises after holding this many samples, so
+// the bars don't flicker
+const RAISE_AFTER: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Factor {
+    Loss,
+    Rtt,
+    Jitter,
+}
+
+impl Factor {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Factor::Loss => "loss",
+            Factor::Rtt => "rtt",
+            Factor::Jitter => "jitter",
+        }
+    }
+}
+
+// 1 (unusable) to 5 (excellent), and what kept it below 5
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QualityScore {
+    pub score: u32,
+    pub limited_by: Option<Factor>,
+}
+
+#[derive(Clone, Copy)]
+struct Average {
+    short: f64,
+    long: f64,
+}
+
+impl Average {
+    fn new(value: f64) -> Self {
+        Self {
+            short: value,
+            long: value,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.short += (value - self.short) * SHORT_SMOOTHING;
+        self.long += (value - self.long) * LONG_SMOOTHING;
+    }
+
+    // Worse by the level it is at, and one step more if it keeps climbing.
+    // Below the first step a rise is noise, not a trend.
+    fn score(&self, steps: &[f64; 4]) -> u32 {
+        let level = 5 - steps.iter().take_while(|&&step| self.short >= step).count() as u32;
+        let worsening = self.short > steps[0] && self.short > self.long * WORSENING_RATIO;
+        if worsening {
+            level.saturating_sub(1).max(1)
+        } else {
+            level
+        }
+    }
+}
+
+struct PeerScore {
+    loss: Average,
+    rtt: Average,
+    jitter: Average,
+    reported: QualityScore,
+    // A better score waiting out RAISE_AFTER
+    pending: Option<(QualityScore, u32)>,
+}
+
+impl PeerScore {
+    fn current(&self) -> QualityScore {
+        let scores = [
+            (Factor::Loss, self.loss.score(&LOSS_PERCENT_STEPS)),
+            (Factor::Rtt, self.rtt.score(&RTT_MS_STEPS)),
+            (Factor::Jitter, self.jitter.score(&JITTER_MS_STEPS)),
+        ];
+        let (factor, score) = scores
+            .into_iter()
+            .min_by_key(|&(_, score)| score)
+            .unwrap_or((Factor::Loss, 5));
+        QualityScore {
+            score,
+            limited_by: (score < 5).then_some(factor),
+        }
+    }
+}
+
+// Turns each peer's loss, RTT and jitter into a signal-bars score
+#[derive(Default)]
+pub struct ConnectionScores {
+    peers: HashMap<String, PeerScore>,
+}
+
+impl ConnectionScores {
+    // Feed one sample per second; returns the score when it changes, and the
+    // first time a peer is seen
+    pub fn update(
+        &mut self,
+        peer_id: &str,
+        loss_percent: f64,
+        rtt_ms: f64,
+        jitter_ms: f64,
+    ) -> Option<QualityScore> {
+        let Some(peer) = self.peers.get_mut(peer_id) else {
+            let mut peer = PeerScore {
+                loss: Average::new(loss_percent),
+                rtt: Average::new(rtt_ms),
+                jitter: Average::new(jitter_ms),
+                reported: QualityScore {
+                    score: 5,
+                    limited_by: None,
+                },
+                pending: None,
+            };
+            peer.reported = peer.current();
+            let reported = peer.reported;
+            self.peers.insert(peer_id.to_string(), peer);
+            return Some(reported);
+        };
+
+        peer.loss.add(loss_percent);
+        peer.rtt.add(rtt_ms);
+        peer.jitter.add(jitter_ms);
+        let current = peer.current();
+        if current.score < peer.reported.score
+            || (current.score == peer.reported.score && current != peer.reported)
+        {
+            peer.pending = None;
+            peer.reported = current;
+            return Some(current);
+        }
+        if current.score == peer.reported.score {
+            peer.pending = None;
+            return None;
+        }
+        let held = match peer.pending {
+            Some((pending, held)) if pending.score == current.score => held + 1,
+            _ => 1,
+        };
+        if held < RAISE_AFTER {
+            peer.pending = Some((current, held));
+            return None;
+        }
+        peer.pending = None;
+        peer.reported = current;
+        Some(current)
+    }
+
+    pub fn retain_peers(&mut self, keep: impl Fn(&str) -> bool) {
+        self.peers.retain(|peer_id, _| keep(peer_id));
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+// Counters that only grow while the stream runs
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Totals {
+    // On the wire, all peers, media and overhead
+    pub bytes_sent: u64,
+    pub packets_sent: u64,
+    // As the peers report them
+    pub packets_lost: u64,
+    // Video track
+    pub frames_captured: u64,
+    pub frames_encoded: u64,
+    pub frames_sent: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct PeerCounts {
+    bytes_sent: u64,
+    packets_sent: u64,
+    packets_lost: u64,
+}
+
+// A peer's own counters start over when it reconnects and vanish when it
+// leaves, so only what each one added since the last look goes into the
+// totals
+#[derive(Default)]
+pub struct PeerTotals {
+    last: HashMap<String, PeerCounts>,
+}
+
+impl PeerTotals {
+    pub fn add(
+        &mut self,
+        totals: &mut Totals,
+        peer_id: &str,
+        bytes_sent: u64,
+        packets_sent: u64,
+        packets_lost: u64,
+    ) {
+        let now = PeerCounts {
+            bytes_sent,
+            packets_sent,
+            packets_lost,
+        };
+        let last = self
+            .last
+            .insert(peer_id.to_string(), now)
+            .unwrap_or_default();
+        // Counters that went backwards belong to a new connection
+        let added = |now: u64, last: u64| if now >= last { now - last } else { now };
+        totals.bytes_sent += added(now.bytes_sent, last.bytes_sent);
+        totals.packets_sent += added(now.packets_sent, last.packets_sent);
+        totals.packets_lost += added(now.packets_lost, last.packets_lost);
+    }
+
+    pub fn retain_peers(&mut self, keep: impl Fn(&str) -> bool) {
+        self.last.retain(|peer_id, _| keep(peer_id));
+    }
+}
+
+// Per second over the window, or over what there is of it so far
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rates {
+    // What the samples actually span
+    pub window: Duration,
+    pub bitrate_kbps: f64,
+    pub packets_per_sec: f64,
+    pub lost_per_sec: f64,
+    // Lost for every hundred sent in the window
+    pub loss_percent: f64,
+    pub capture_fps: f64,
+    pub encode_fps: f64,
+    pub send_fps: f64,
+}
+
+// Rates over a sliding window of samples, so one late or early sample moves
+// them by a fraction instead of swinging a one-interval diff
+pub struct RateWindow {
+    window: Duration,
+    samples: VecDeque<(Instant, Totals)>,
+}
+
+impl RateWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    pub fn push(&mut self, now: Instant, totals: Totals) -> Rates {
+        self.samples.push_back((now, totals));
+        // Keep the newest sample at or before the window's start as the base
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|&(at, _)| now.duration_since(at) >= self.window)
+        {
+            self.samples.pop_front();
+        }
+        let Some(&(since, base)) = self.samples.front() else {
+            return Rates::default();
+        };
+        let span = now.duration_since(since);
+        if span.is_zero() {
+            return Rates::default();
+        }
+        let seconds = span.as_secs_f64();
+        let rate = |now: u64, base: u64| now.saturating_sub(base) as f64 / seconds;
+        let packets = totals.packets_sent.saturating_sub(base.packets_sent);
+        let lost = totals.packets_lost.saturating_sub(base.packets_lost);
+        Rates {
+            window: span,
+            bitrate_kbps: rate(totals.bytes_sent, base.bytes_sent) * 8.0 / 1000.0,
+            packets_per_sec: rate(totals.packets_sent, base.packets_sent),
+            lost_per_sec: rate(totals.packets_lost, base.packets_lost),
+            loss_percent: if packets > 0 {
+                (lost as f64 * 100.0 / packets as f64).min(100.0)
+            } else {
+                0.0
+            },
+            capture_fps: rate(totals.frames_captured, base.frames_captured),
+            encode_fps: rate(totals.frames_encoded, base.frames_encoded),
+            send_fps: rate(totals.frames_sent, base.frames_sent),
+        }
+    }
+}
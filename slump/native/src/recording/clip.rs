@@ -0,0 +1,415 @@
+use super::replay::{BufferedPacket, PacketKind};
+use super::RecordingSummary;
+use crate::cancel::Cancellation;
+use crate::error::{Result, SlumpError};
+use crate::output::StreamInfo;
+use ffmpeg_next::{
+    codec, decoder, encoder, ffi, filter, format, format::pixel::Pixel, frame, Dictionary, Packet,
+    Rational,
+};
+use std::{str::FromStr, time::Duration};
+
+// Animated formats get a lower default rate to keep files small
+const ANIMATED_FPS: u32 = 15;
+// For a buffer that doesn't say what rate it was encoded at
+const DEFAULT_SOURCE_FPS: u32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipFormat {
+    #[default]
+    Mp4,
+    WebP,
+    Gif,
+}
+
+impl ClipFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ClipFormat::Mp4 => "mp4",
+            ClipFormat::WebP => "webp",
+            ClipFormat::Gif => "gif",
+        }
+    }
+}
+
+impl FromStr for ClipFormat {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mp4" => Ok(ClipFormat::Mp4),
+            "webp" => Ok(ClipFormat::WebP),
+            "gif" => Ok(ClipFormat::Gif),
+            other => Err(SlumpError::Init(format!("Unknown clip format: {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClipSettings {
+    pub format: ClipFormat,
+    // Setting only one dimension keeps the aspect ratio
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+}
+
+impl ClipSettings {
+    // MP4 at the source size and rate can be cut without re-encoding
+    fn is_copy(&self) -> bool {
+        self.format == ClipFormat::Mp4
+            && self.width.is_none()
+            && self.height.is_none()
+            && self.fps.is_none()
+    }
+
+    fn output_size(&self, source_width: u32, source_height: u32) -> (u32, u32) {
+        let (width, height) = match (self.width, self.height) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, source_height * width / source_width.max(1)),
+            (None, Some(height)) => (source_width * height / source_height.max(1), height),
+            (None, None) => (source_width, source_height),
+        };
+        // 4:2:0 encoders need even dimensions
+        (width.max(2) & !1, height.max(2) & !1)
+    }
+}
+
+fn seconds_to_ts(seconds: f64, time_base: Rational) -> i64 {
+    (seconds * time_base.denominator() as f64 / time_base.numerator() as f64) as i64
+}
+
+// A range of the replay buffer, copied out so it can be exported off the
+// capture thread
+pub struct Clip {
+    video: StreamInfo,
+    audio: Option<StreamInfo>,
+    // Starts at the keyframe before `start` so the first frames decode
+    packets: Vec<BufferedPacket>,
+    // Encoder-clock seconds
+    start: f64,
+    end: f64,
+}
+
+impl Clip {
+    pub(super) fn new(
+        video: StreamInfo,
+        audio: Option<StreamInfo>,
+        packets: Vec<BufferedPacket>,
+        start: f64,
+        end: f64,
+    ) -> Self {
+        Self {
+            video,
+            audio,
+            packets,
+            start,
+            end,
+        }
+    }
+
+    // A cancelled or failed export leaves no partial file behind
+    pub fn export(
+        &self,
+        path: &str,
+        settings: ClipSettings,
+        cancel: &Cancellation,
+    ) -> Result<RecordingSummary> {
+        let written = if settings.is_copy() {
+            self.copy(path, cancel)
+        } else {
+            self.transcode(path, settings, cancel).map(|()| self.start)
+        };
+        let start = match written {
+            Ok(start) => start,
+            Err(e) => {
+                let _ = std::fs::remove_file(path);
+                return Err(e);
+            }
+        };
+
+        Ok(RecordingSummary {
+            path: path.to_string(),
+            duration: Duration::from_secs_f64((self.end - start).max(0.0)),
+            size_bytes: std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        })
+    }
+
+    // Stream copy, which can only cut on a keyframe; returns where the clip
+    // actually starts
+    fn copy(&self, path: &str, cancel: &Cancellation) -> Result<f64> {
+        let mut output = format::output_as(&path, "mp4")?;
+
+        let mut video_ost = output.add_stream(self.video.parameters.id())?;
+        video_ost.set_parameters(self.video.parameters.clone());
+        let video_stream = video_ost.index();
+
+        let audio_stream = match &self.audio {
+            Some(audio) => {
+                let mut audio_ost = output.add_stream(audio.parameters.id())?;
+                audio_ost.set_parameters(audio.parameters.clone());
+                Some(audio_ost.index())
+            }
+            None => None,
+        };
+
+        let mut options = Dictionary::new();
+        options.set("movflags", "+faststart");
+        output.write_header_with(options)?;
+
+        let start = self
+            .packets
+            .iter()
+            .find(|p| p.kind == PacketKind::Video)
+            .map(|p| p.time)
+            .unwrap_or(self.start);
+        for buffered in &self.packets {
+            cancel.check()?;
+            let (stream, encoder_time_base) = match (buffered.kind, audio_stream, &self.audio) {
+                (PacketKind::Video, _, _) => (video_stream, self.video.time_base),
+                (PacketKind::Audio, Some(stream), Some(audio)) => (stream, audio.time_base),
+                (PacketKind::Audio, _, _) => continue,
+            };
+            if buffered.time < start {
+                continue;
+            }
+
+            let offset = seconds_to_ts(start, encoder_time_base);
+            let mut packet = buffered.packet.clone();
+            packet.set_pts(packet.pts().map(|pts| pts - offset));
+            packet.set_dts(packet.dts().map(|dts| dts - offset));
+            packet.set_stream(stream);
+
+            let stream_time_base = output
+                .stream(stream)
+                .map(|s| s.time_base())
+                .unwrap_or(encoder_time_base);
+            packet.rescale_ts(encoder_time_base, stream_time_base);
+            packet.write_interleaved(&mut output)?;
+        }
+
+        output.write_trailer()?;
+        Ok(start)
+    }
+
+    // Decode, cut on the exact frame, then scale and re-encode
+    fn transcode(&self, path: &str, settings: ClipSettings, cancel: &Cancellation) -> Result<()> {
+        let mut decoder = codec::context::Context::from_parameters(self.video.parameters.clone())?
+            .decoder()
+            .video()?;
+        let (width, height) = settings.output_size(decoder.width(), decoder.height());
+        let source_fps = self.video.frame_rate.map_or(DEFAULT_SOURCE_FPS, |rate| {
+            rate.numerator().max(1) as u32 / rate.denominator().max(1) as u32
+        });
+        let fps = settings.fps.unwrap_or(match settings.format {
+            ClipFormat::Mp4 => source_fps,
+            ClipFormat::WebP | ClipFormat::Gif => ANIMATED_FPS.min(source_fps),
+        });
+
+        let mut graph = self.filter_graph(&decoder, settings.format, width, height, fps)?;
+
+        let (codec, pixel, muxer_name) = match settings.format {
+            ClipFormat::Mp4 => (encoder::find(codec::Id::H264), Pixel::YUV420P, "mp4"),
+            ClipFormat::WebP => (
+                encoder::find_by_name("libwebp_anim"),
+                Pixel::YUV420P,
+                "webp",
+            ),
+            ClipFormat::Gif => (encoder::find(codec::Id::GIF), Pixel::PAL8, "gif"),
+        };
+        let codec = codec.ok_or_else(|| {
+            SlumpError::Ffmpeg(format!(
+                "No encoder available for {:?} clips",
+                settings.format
+            ))
+        })?;
+
+        let mut output = format::output_as(&path, muxer_name)?;
+
+        let mut video = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()?;
+        video.set_width(width);
+        video.set_height(height);
+        video.set_format(pixel);
+        video.set_time_base((1, fps as i32));
+        video.set_frame_rate(Some((fps as i32, 1)));
+        if output
+            .format()
+            .flags()
+            .contains(format::Flags::GLOBAL_HEADER)
+        {
+            video.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let mut codec_options = Dictionary::new();
+        let mut muxer_options = Dictionary::new();
+        match settings.format {
+            ClipFormat::Mp4 => {
+                codec_options.set("preset", "veryfast");
+                codec_options.set("crf", "20");
+                muxer_options.set("movflags", "+faststart");
+            }
+            ClipFormat::WebP => {
+                codec_options.set("quality", "75");
+                muxer_options.set("loop", "0");
+            }
+            ClipFormat::Gif => muxer_options.set("loop", "0"),
+        }
+        let mut video = video.open_with(codec_options)?;
+        let time_base = Rational::new(1, fps as i32);
+
+        let mut video_ost = output.add_stream(codec)?;
+        video_ost.set_parameters(&video);
+        let video_stream = video_ost.index();
+
+        // Audio is only kept for MP4, and copied as is
+        let audio_stream = match (&self.audio, settings.format) {
+            (Some(audio), ClipFormat::Mp4) => {
+                let mut audio_ost = output.add_stream(audio.parameters.id())?;
+                audio_ost.set_parameters(audio.parameters.clone());
+                Some(audio_ost.index())
+            }
+            _ => None,
+        };
+
+        output.write_header_with(muxer_options)?;
+
+        let range = (
+            seconds_to_ts(self.start, self.video.time_base),
+            seconds_to_ts(self.end, self.video.time_base),
+        );
+        for buffered in self.packets.iter().filter(|p| p.kind == PacketKind::Video) {
+            cancel.check()?;
+            decoder.send_packet(&buffered.packet)?;
+            push_decoded(&mut decoder, &mut graph, range)?;
+            pull_filtered(&mut graph, &mut video, &mut output, video_stream, time_base)?;
+        }
+        decoder.send_eof()?;
+        push_decoded(&mut decoder, &mut graph, range)?;
+        graph.get("in").expect("buffer source").source().flush()?;
+        pull_filtered(&mut graph, &mut video, &mut output, video_stream, time_base)?;
+        video.send_eof()?;
+        write_encoded(&mut video, &mut output, video_stream, time_base)?;
+
+        if let (Some(stream), Some(audio)) = (audio_stream, &self.audio) {
+            let offset = seconds_to_ts(self.start, audio.time_base);
+            let stream_time_base = output
+                .stream(stream)
+                .map(|s| s.time_base())
+                .unwrap_or(audio.time_base);
+            for buffered in self.packets.iter().filter(|p| {
+                p.kind == PacketKind::Audio && p.time >= self.start && p.time <= self.end
+            }) {
+                let mut packet = buffered.packet.clone();
+                packet.set_pts(packet.pts().map(|pts| pts - offset));
+                packet.set_dts(packet.dts().map(|dts| dts - offset));
+                packet.set_stream(stream);
+                packet.rescale_ts(audio.time_base, stream_time_base);
+                packet.write_interleaved(&mut output)?;
+            }
+        }
+
+        output.write_trailer()?;
+        Ok(())
+    }
+
+    fn filter_graph(
+        &self,
+        decoder: &decoder::Video,
+        format: ClipFormat,
+        width: u32,
+        height: u32,
+        fps: u32,
+    ) -> Result<filter::Graph> {
+        let buffer = filter::find("buffer")
+            .ok_or_else(|| SlumpError::Ffmpeg("buffer filter not available".into()))?;
+        let buffersink = filter::find("buffersink")
+            .ok_or_else(|| SlumpError::Ffmpeg("buffersink filter not available".into()))?;
+
+        let mut graph = filter::Graph::new();
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect=1/1",
+            decoder.width(),
+            decoder.height(),
+            ffi::AVPixelFormat::from(decoder.format()) as i32,
+            self.video.time_base.numerator(),
+            self.video.time_base.denominator(),
+        );
+        graph.add(&buffer, "in", &args)?;
+        graph.add(&buffersink, "out", "")?;
+
+        let scale = format!("fps={},scale={}:{}:flags=lanczos", fps, width, height);
+        let spec = match format {
+            ClipFormat::Mp4 | ClipFormat::WebP => format!("{},format=yuv420p", scale),
+            // A palette built from the whole clip looks far better than the
+            // fixed GIF palette
+            ClipFormat::Gif => format!("{},split[a][b];[a]palettegen[p];[b][p]paletteuse", scale),
+        };
+        graph.output("in", 0)?.input("out", 0)?.parse(&spec)?;
+        graph.validate()?;
+        Ok(graph)
+    }
+}
+
+// Frames outside the clip range are decoder pre-roll and are dropped
+fn push_decoded(
+    decoder: &mut decoder::Video,
+    graph: &mut filter::Graph,
+    (start, end): (i64, i64),
+) -> Result<()> {
+    let mut decoded = frame::Video::empty();
+    while decoder.receive_frame(&mut decoded).is_ok() {
+        let pts = decoded.pts().unwrap_or(0);
+        if pts < start || pts > end {
+            continue;
+        }
+        decoded.set_pts(Some(pts - start));
+        graph
+            .get("in")
+            .expect("buffer source")
+            .source()
+            .add(&decoded)?;
+    }
+    Ok(())
+}
+
+fn pull_filtered(
+    graph: &mut filter::Graph,
+    encoder: &mut encoder::Video,
+    output: &mut format::context::Output,
+    stream: usize,
+    time_base: Rational,
+) -> Result<()> {
+    let mut filtered = frame::Video::empty();
+    while graph
+        .get("out")
+        .expect("buffer sink")
+        .sink()
+        .frame(&mut filtered)
+        .is_ok()
+    {
+        encoder.send_frame(&filtered)?;
+        write_encoded(encoder, output, stream, time_base)?;
+    }
+    Ok(())
+}
+
+fn write_encoded(
+    encoder: &mut encoder::Video,
+    output: &mut format::context::Output,
+    stream: usize,
+    time_base: Rational,
+) -> Result<()> {
+    let stream_time_base = output
+        .stream(stream)
+        .map(|s| s.time_base())
+        .unwrap_or(time_base);
+    let mut packet = Packet::empty();
+    while encoder.receive_packet(&mut packet).is_ok() {
+        packet.set_stream(stream);
+        packet.rescale_ts(time_base, stream_time_base);
+        packet.write_interleaved(output)?;
+    }
+    Ok(())
+}
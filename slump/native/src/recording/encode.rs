@@ -0,0 +1,215 @@
+use crate::error::{Result, SlumpError};
+use crate::output::{AacEncoder, CaptureTimeline, StreamInfo, StreamLayout, VIDEO_CLOCK_RATE};
+use crate::video::{Chroma, Colorimetry};
+use ffmpeg_next::{
+    codec, encoder, format::pixel::Pixel, software::scaling, Dictionary, Frame, Packet, Rational,
+};
+use std::{str::FromStr, time::Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordingCodec {
+    #[default]
+    H264,
+    Hevc,
+}
+
+impl RecordingCodec {
+    // Hardware encoders first when built with them, software as fallback
+    fn encoder_names(&self) -> &'static [&'static str] {
+        match self {
+            RecordingCodec::H264 if cfg!(feature = "nvenc") => &["h264_nvenc", "libx264"],
+            RecordingCodec::H264 => &["libx264"],
+            RecordingCodec::Hevc if cfg!(feature = "nvenc") => &["hevc_nvenc", "libx265"],
+            RecordingCodec::Hevc => &["libx265"],
+        }
+    }
+}
+
+impl FromStr for RecordingCodec {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "h264" => Ok(RecordingCodec::H264),
+            "hevc" | "h265" => Ok(RecordingCodec::Hevc),
+            other => Err(SlumpError::Init(format!(
+                "Unknown recording codec: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordingEncodeSettings {
+    pub codec: RecordingCodec,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    // Constant quality (CRF / CQ) takes precedence over a bitrate
+    pub quality: Option<u32>,
+    pub bitrate_kbps: u32,
+    // Always the live stream's, so both encodes look the same
+    pub colorimetry: Colorimetry,
+    pub chroma: Chroma,
+}
+
+const ENOMEM: i32 = 12;
+
+// Second encode for recordings that don't match the live settings, scaling
+// from the full-resolution capture rather than the live frame
+pub struct RecordingEncoder {
+    video: encoder::Video,
+    audio: Option<AacEncoder>,
+    scaler: Option<(scaling::Context, (Pixel, u32, u32))>,
+    settings: RecordingEncodeSettings,
+    timeline: CaptureTimeline,
+}
+
+impl RecordingEncoder {
+    pub fn new(settings: RecordingEncodeSettings, with_audio: bool) -> Result<Self> {
+        let (name, codec) = settings
+            .codec
+            .encoder_names()
+            .iter()
+            .find_map(|name| encoder::find_by_name(name).map(|codec| (*name, codec)))
+            .ok_or_else(|| {
+                SlumpError::Ffmpeg(format!("No {:?} encoder available", settings.codec))
+            })?;
+
+        let mut video = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()?;
+        video.set_width(settings.width);
+        video.set_height(settings.height);
+        video.set_format(settings.chroma.pixel());
+        // Timestamped from capture time, like the shared encode
+        video.set_time_base((1, VIDEO_CLOCK_RATE));
+        video.set_frame_rate(Some((settings.fps as i32, 1)));
+        video.set_max_b_frames(0);
+        video.set_gop(settings.fps);
+        settings.colorimetry.apply(&mut video);
+        // Recordings always go to MP4/MKV
+        video.set_flags(codec::Flags::GLOBAL_HEADER);
+
+        let mut options = Dictionary::new();
+        let nvenc = name.ends_with("_nvenc");
+        match settings.quality {
+            Some(quality) if nvenc => {
+                options.set("rc", "vbr");
+                options.set("cq", &quality.to_string());
+                video.set_bit_rate(0);
+            }
+            Some(quality) => {
+                options.set("crf", &quality.to_string());
+            }
+            None => video.set_bit_rate(settings.bitrate_kbps as usize * 1000),
+        }
+        options.set("preset", if nvenc { "p5" } else { "veryfast" });
+
+        let video = video.open_with(options).map_err(|e| match e {
+            // Consumer GPUs cap concurrent NVENC sessions and report hitting
+            // the cap as an allocation failure
+            ffmpeg_next::Error::Other { errno: ENOMEM } | ffmpeg_next::Error::External if nvenc => {
+                SlumpError::EncoderSessionLimit(e.to_string())
+            }
+            e => SlumpError::from(e),
+        })?;
+
+        Ok(Self {
+            video,
+            audio: if with_audio {
+                Some(AacEncoder::new(true)?)
+            } else {
+                None
+            },
+            scaler: None,
+            settings,
+            timeline: CaptureTimeline::new(Instant::now()),
+        })
+    }
+
+    pub fn layout(&self) -> StreamLayout {
+        StreamLayout {
+            video: StreamInfo {
+                parameters: codec::Parameters::from(&self.video),
+                time_base: Rational::new(1, VIDEO_CLOCK_RATE),
+                frame_rate: Some(Rational::new(self.settings.fps as i32, 1)),
+            },
+            audio: self.audio.as_ref().map(|audio| StreamInfo {
+                parameters: codec::Parameters::from(audio.codec()),
+                time_base: audio.time_base(),
+                frame_rate: None,
+            }),
+            text: None,
+        }
+    }
+
+    pub fn encode_video(&mut self, frame: &Frame, captured_at: Instant) -> Result<Vec<Packet>> {
+        let (format, width, height) = unsafe {
+            let raw = &*frame.as_ptr();
+            (
+                Pixel::from(std::mem::transmute::<i32, ffmpeg_next::ffi::AVPixelFormat>(
+                    raw.format,
+                )),
+                raw.width as u32,
+                raw.height as u32,
+            )
+        };
+
+        // The capture size can change under adaptation, so rebuild on demand
+        let source = (format, width, height);
+        if self.scaler.as_ref().map(|(_, s)| *s) != Some(source) {
+            let scaler = self.settings.colorimetry.scaler(
+                source,
+                self.settings.chroma.pixel(),
+                self.settings.width,
+                self.settings.height,
+                scaling::Flags::BICUBIC,
+            )?;
+            self.scaler = Some((scaler, source));
+        }
+
+        let mut scaled = Frame::empty();
+        if let Some((scaler, _)) = self.scaler.as_mut() {
+            scaler.run(frame, &mut scaled)?;
+        }
+        self.settings.colorimetry.tag(&mut scaled);
+        scaled.set_pts(Some(self.timeline.pts(captured_at)));
+
+        self.video.send_frame(&scaled)?;
+        Ok(drain(&mut self.video))
+    }
+
+    pub fn encode_audio(&mut self, samples: &[f32]) -> Result<Vec<Packet>> {
+        match self.audio.as_mut() {
+            Some(audio) => audio.encode(samples),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn set_audio_offset(&mut self, ms: i32) {
+        if let Some(audio) = self.audio.as_mut() {
+            audio.set_offset_ms(ms);
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(Vec<Packet>, Vec<Packet>)> {
+        self.video.send_eof()?;
+        let video = drain(&mut self.video);
+        let audio = match self.audio.as_mut() {
+            Some(audio) => audio.flush()?,
+            None => Vec::new(),
+        };
+        Ok((video, audio))
+    }
+}
+
+fn drain(encoder: &mut encoder::Video) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut packet = Packet::empty();
+    while encoder.receive_packet(&mut packet).is_ok() {
+        packets.push(packet.clone());
+    }
+    packets
+}
@@ -0,0 +1,60 @@
+use std::{fmt, path::Path, time::Duration};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordingLimits {
+    // Warn once free space on the recording volume drops below this
+    pub warn_free_bytes: u64,
+    // Stop the recording below this, leaving room to write the trailer
+    pub stop_free_bytes: u64,
+    // A single muxer write taking longer than this counts as a stall
+    pub write_stall: Duration,
+}
+
+impl Default for RecordingLimits {
+    fn default() -> Self {
+        Self {
+            warn_free_bytes: 2048 * 1024 * 1024,
+            stop_free_bytes: 256 * 1024 * 1024,
+            write_stall: Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingAlert {
+    LowDiskSpace { free_bytes: u64 },
+    DiskFull { free_bytes: u64 },
+    WriteStall { latency: Duration },
+}
+
+impl fmt::Display for RecordingAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mb = |bytes: u64| bytes / (1024 * 1024);
+        match self {
+            RecordingAlert::LowDiskSpace { free_bytes } => {
+                write!(
+                    f,
+                    "Recording disk is low on space ({} MB free)",
+                    mb(*free_bytes)
+                )
+            }
+            RecordingAlert::DiskFull { free_bytes } => write!(
+                f,
+                "Recording stopped, disk is almost full ({} MB free)",
+                mb(*free_bytes)
+            ),
+            RecordingAlert::WriteStall { latency } => {
+                write!(f, "Recording write stalled for {} ms", latency.as_millis())
+            }
+        }
+    }
+}
+
+// Free space on the volume the recording is written to
+pub fn free_space(path: &str) -> Option<u64> {
+    let dir = Path::new(path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    fs2::available_space(dir).ok()
+}
@@ -0,0 +1,446 @@
+mod clip;
+mod encode;
+mod health;
+mod remux;
+mod replay;
+
+pub use clip::{Clip, ClipFormat, ClipSettings};
+pub use encode::{RecordingCodec, RecordingEncodeSettings, RecordingEncoder};
+pub use health::{RecordingAlert, RecordingLimits};
+pub use replay::ReplayBuffer;
+
+use crate::error::{Result, SlumpError};
+use crate::output::{text_stream, Muxer, OutputSink, SharedEncoder, StreamLayout};
+use ffmpeg_next::{codec, Dictionary, Frame, Packet};
+use std::{
+    any::Any,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordingFormat {
+    #[default]
+    Mp4,
+    // MP4 written as self-contained fragments, so a crash only loses the
+    // fragment in progress
+    FragmentedMp4,
+    Mkv,
+}
+
+impl RecordingFormat {
+    fn muxer_name(&self) -> &'static str {
+        match self {
+            RecordingFormat::Mp4 | RecordingFormat::FragmentedMp4 => "mp4",
+            RecordingFormat::Mkv => "matroska",
+        }
+    }
+
+    fn muxer_options(&self) -> Dictionary<'static> {
+        let mut options = Dictionary::new();
+        match self {
+            RecordingFormat::Mp4 => options.set("movflags", "+faststart"),
+            RecordingFormat::FragmentedMp4 => {
+                // One fragment per GOP, each pushed to disk as it closes
+                options.set("movflags", "+frag_keyframe+empty_moov+default_base_moof");
+                options.set("flush_packets", "1");
+            }
+            RecordingFormat::Mkv => {
+                options.set("cluster_time_limit", "2000");
+                options.set("flush_packets", "1");
+            }
+        }
+        options
+    }
+
+    fn text_codec(&self) -> codec::Id {
+        match self {
+            RecordingFormat::Mp4 | RecordingFormat::FragmentedMp4 => codec::Id::MOV_TEXT,
+            RecordingFormat::Mkv => codec::Id::SUBRIP,
+        }
+    }
+}
+
+impl FromStr for RecordingFormat {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mp4" => Ok(RecordingFormat::Mp4),
+            "fmp4" | "fragmented-mp4" => Ok(RecordingFormat::FragmentedMp4),
+            "mkv" | "matroska" => Ok(RecordingFormat::Mkv),
+            other => Err(SlumpError::Init(format!("Unknown recording format: {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordingSummary {
+    pub path: String,
+    pub duration: Duration,
+    pub size_bytes: u64,
+}
+
+// When to roll over to a new file; either limit triggers a split at the
+// next keyframe
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SegmentPolicy {
+    pub max_duration: Option<Duration>,
+    pub max_size_bytes: Option<u64>,
+}
+
+impl SegmentPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_duration.is_some() || self.max_size_bytes.is_some()
+    }
+}
+
+// Expands `{n}` (segment number) and `{timestamp}` (unix seconds). With
+// segmenting on and no `{n}`, the number goes before the extension.
+fn segment_path(template: &str, index: u32, segmented: bool) -> String {
+    let mut template = template.to_string();
+    if segmented && !template.contains("{n}") {
+        let path = Path::new(&template);
+        template = match (path.file_stem(), path.extension()) {
+            (Some(stem), Some(ext)) => path
+                .with_file_name(format!(
+                    "{}_{{n}}.{}",
+                    stem.to_string_lossy(),
+                    ext.to_string_lossy()
+                ))
+                .to_string_lossy()
+                .into_owned(),
+            _ => format!("{}_{{n}}", template),
+        };
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    template
+        .replace("{n}", &format!("{:03}", index))
+        .replace("{timestamp}", &timestamp.to_string())
+}
+
+// Writes the stream to disk alongside the live send
+pub struct Recorder {
+    muxer: Muxer,
+    path: String,
+    template: String,
+    segment_index: u32,
+    policy: SegmentPolicy,
+    rotate_pending: bool,
+    // Finished segments not yet reported to JS
+    completed: Vec<RecordingSummary>,
+    layout: StreamLayout,
+    // Own encode when the recording settings differ from the live stream
+    encoder: Option<RecordingEncoder>,
+    format: RecordingFormat,
+    // Rewrite a fragmented recording as a regular MP4 on clean stop
+    remux_on_stop: bool,
+    started: Instant,
+    paused_at: Option<Instant>,
+    paused_total: Duration,
+    // Longest muxer write since the last health check
+    slowest_write: Duration,
+    stalled: bool,
+    low_space_warned: bool,
+}
+
+impl Recorder {
+    pub fn start(
+        template: String,
+        format: RecordingFormat,
+        remux_on_stop: bool,
+        policy: SegmentPolicy,
+        encode: Option<RecordingEncodeSettings>,
+        captions: bool,
+        shared: &SharedEncoder,
+    ) -> Result<Self> {
+        let encoder = encode
+            .map(|settings| RecordingEncoder::new(settings, shared.audio().is_some()))
+            .transpose()?;
+        let mut layout = match &encoder {
+            Some(encoder) => encoder.layout(),
+            None => shared.layout(),
+        };
+        // Opt-in: an empty text track holds back interleaving until the
+        // muxer gives up waiting on it
+        if captions {
+            layout.text = Some(text_stream(format.text_codec()));
+        }
+        let path = segment_path(&template, 1, policy.is_enabled());
+        let muxer =
+            Muxer::open_layout(&path, format.muxer_name(), format.muxer_options(), &layout)?;
+
+        Ok(Self {
+            muxer,
+            path,
+            template,
+            segment_index: 1,
+            policy,
+            rotate_pending: false,
+            completed: Vec::new(),
+            layout,
+            encoder,
+            format,
+            remux_on_stop,
+            started: Instant::now(),
+            paused_at: None,
+            paused_total: Duration::ZERO,
+            slowest_write: Duration::ZERO,
+            stalled: false,
+            low_space_warned: false,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    // Input is dropped while paused; on resume the muxer picks up at the
+    // next keyframe without leaving a gap in the file
+    pub fn pause(&mut self) -> bool {
+        if self.paused_at.is_some() {
+            return false;
+        }
+        self.paused_at = Some(Instant::now());
+        true
+    }
+
+    pub fn resume(&mut self) -> bool {
+        match self.paused_at.take() {
+            Some(paused_at) => {
+                self.paused_total += paused_at.elapsed();
+                self.muxer.resync();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        let paused = self.paused_total + self.paused_at.map(|p| p.elapsed()).unwrap_or_default();
+        self.started.elapsed().saturating_sub(paused)
+    }
+
+    // Chapter in the current segment at its current position
+    pub fn add_marker(&mut self, label: &str) -> Duration {
+        self.muxer.add_marker(label)
+    }
+
+    // A cue in the current segment at its current position; false without
+    // a text track, or while paused
+    pub fn write_text(&mut self, text: &str, duration: Duration) -> Result<bool> {
+        if self.is_paused() {
+            return Ok(false);
+        }
+        self.muxer.write_text(text, duration)
+    }
+
+    pub fn size_bytes(&self) -> u64 {
+        std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0)
+    }
+
+    // Called periodically; each condition is reported once when it starts
+    pub fn check_health(&mut self, limits: &RecordingLimits) -> Vec<RecordingAlert> {
+        let mut alerts = Vec::new();
+
+        if let Some(free_bytes) = health::free_space(&self.path) {
+            if free_bytes < limits.stop_free_bytes {
+                alerts.push(RecordingAlert::DiskFull { free_bytes });
+            } else if free_bytes < limits.warn_free_bytes {
+                if !self.low_space_warned {
+                    alerts.push(RecordingAlert::LowDiskSpace { free_bytes });
+                }
+                self.low_space_warned = true;
+            } else {
+                self.low_space_warned = false;
+            }
+        }
+
+        let latency = std::mem::take(&mut self.slowest_write);
+        let stalled = latency > limits.write_stall;
+        if stalled && !self.stalled {
+            alerts.push(RecordingAlert::WriteStall { latency });
+        }
+        self.stalled = stalled;
+
+        alerts
+    }
+
+    pub fn take_completed_segments(&mut self) -> Vec<RecordingSummary> {
+        std::mem::take(&mut self.completed)
+    }
+
+    fn segment_full(&self) -> bool {
+        self.policy
+            .max_duration
+            .is_some_and(|max| self.muxer.duration() >= max)
+            || self
+                .policy
+                .max_size_bytes
+                .is_some_and(|max| self.muxer.bytes_written() >= max)
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let next_path = segment_path(&self.template, self.segment_index + 1, true);
+        let next = Muxer::open_layout(
+            &next_path,
+            self.format.muxer_name(),
+            self.format.muxer_options(),
+            &self.layout,
+        )?;
+        self.segment_index += 1;
+        self.rotate_pending = false;
+
+        let mut previous = std::mem::replace(&mut self.muxer, next);
+        let previous_path = std::mem::replace(&mut self.path, next_path);
+        previous.finish()?;
+        let duration = previous.duration();
+        let markers = previous.markers().to_vec();
+        drop(previous);
+
+        let size_bytes = std::fs::metadata(&previous_path)
+            .map(|m| m.len())
+            .unwrap_or(0);
+        if self.format == RecordingFormat::FragmentedMp4 && self.remux_on_stop {
+            // Off the capture thread; the file is already complete
+            let path = previous_path.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = remux::remux_to_mp4(&path, &markers) {
+                    log::warn!("Failed to remux {}: {}", path, e);
+                }
+            });
+        }
+
+        self.completed.push(RecordingSummary {
+            path: previous_path,
+            duration,
+            size_bytes,
+        });
+        Ok(())
+    }
+
+    fn write_video(&mut self, packet: &Packet) -> Result<()> {
+        if self.is_paused() {
+            return Ok(());
+        }
+
+        // Segments split on keyframes so each file plays on its own
+        if self.policy.is_enabled() && !self.rotate_pending && self.segment_full() {
+            self.rotate_pending = true;
+        }
+        if self.rotate_pending && packet.is_key() {
+            self.rotate()?;
+        }
+
+        let started = Instant::now();
+        self.muxer.write_video(packet)?;
+        self.slowest_write = self.slowest_write.max(started.elapsed());
+        Ok(())
+    }
+
+    fn write_audio(&mut self, packet: &Packet) -> Result<()> {
+        if self.is_paused() {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        self.muxer.write_audio(packet)?;
+        self.slowest_write = self.slowest_write.max(started.elapsed());
+        Ok(())
+    }
+
+    pub fn stop(mut self) -> Result<RecordingSummary> {
+        self.resume();
+        if let Some(encoder) = self.encoder.as_mut() {
+            let (video, audio) = encoder.flush()?;
+            for packet in &video {
+                self.muxer.write_video(packet)?;
+            }
+            for packet in &audio {
+                self.muxer.write_audio(packet)?;
+            }
+        }
+        self.muxer.finish()?;
+        let duration = self.duration();
+
+        let markers = self.muxer.markers().to_vec();
+
+        // The file has to be closed before it can be rewritten
+        drop(self.muxer);
+        if self.format == RecordingFormat::FragmentedMp4 && self.remux_on_stop {
+            // The fragmented file is still playable, so a failed remux is not fatal
+            if let Err(e) = remux::remux_to_mp4(&self.path, &markers) {
+                log::warn!("Failed to remux {}: {}", self.path, e);
+            }
+        }
+
+        Ok(RecordingSummary {
+            duration,
+            size_bytes: std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0),
+            path: self.path,
+        })
+    }
+}
+
+impl OutputSink for Recorder {
+    fn encoded(&self) -> bool {
+        self.encoder.is_none()
+    }
+
+    fn write_video_packet(&mut self, packet: &Packet) -> Result<()> {
+        self.write_video(packet)
+    }
+
+    fn write_audio_packet(&mut self, packet: &Packet) -> Result<()> {
+        self.write_audio(packet)
+    }
+
+    fn write_video_frame(
+        &mut self,
+        frame: &Frame,
+        source: Option<&Frame>,
+        captured_at: Instant,
+    ) -> Result<()> {
+        // Keep encoding while paused so the GOP cadence carries on
+        let Some(encoder) = self.encoder.as_mut() else {
+            return Ok(());
+        };
+        for packet in encoder.encode_video(source.unwrap_or(frame), captured_at)? {
+            self.write_video(&packet)?;
+        }
+        Ok(())
+    }
+
+    fn write_audio_samples(&mut self, samples: &[f32]) -> Result<()> {
+        let Some(encoder) = self.encoder.as_mut() else {
+            return Ok(());
+        };
+        for packet in encoder.encode_audio(samples)? {
+            self.write_audio(&packet)?;
+        }
+        Ok(())
+    }
+
+    fn set_audio_offset(&mut self, ms: i32) {
+        if let Some(encoder) = self.encoder.as_mut() {
+            encoder.set_audio_offset(ms);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
@@ -0,0 +1,63 @@
+use crate::error::Result;
+use crate::output::{write_chapters, Marker};
+use ffmpeg_next::{codec, encoder, format, media, Dictionary};
+use std::time::Duration;
+
+// Stream-copies a finished recording into a regular MP4 with the index up
+// front, replacing the original only once the copy is complete. Markers are
+// passed in because a fragmented file has no room for chapters.
+pub fn remux_to_mp4(path: &str, markers: &[Marker]) -> Result<()> {
+    let temp = format!("{}.remux", path);
+
+    {
+        let mut input = format::input(&path)?;
+        let mut output = format::output_as(&temp, "mp4")?;
+
+        let mut mapping = vec![None; input.nb_streams() as usize];
+        for ist in input.streams() {
+            let medium = ist.parameters().medium();
+            if !matches!(
+                medium,
+                media::Type::Video | media::Type::Audio | media::Type::Subtitle
+            ) {
+                continue;
+            }
+
+            let mut ost = output.add_stream(encoder::find(codec::Id::None))?;
+            ost.set_parameters(ist.parameters());
+            // Let the mp4 muxer pick its own codec tag
+            unsafe {
+                (*ost.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+            mapping[ist.index()] = Some(ost.index());
+        }
+
+        let mut options = Dictionary::new();
+        options.set("movflags", "+faststart");
+        output.write_header_with(options)?;
+
+        for (ist, mut packet) in input.packets() {
+            let Some(index) = mapping.get(ist.index()).copied().flatten() else {
+                continue;
+            };
+            let time_base = match output.stream(index) {
+                Some(ost) => ost.time_base(),
+                None => continue,
+            };
+
+            packet.rescale_ts(ist.time_base(), time_base);
+            packet.set_position(-1);
+            packet.set_stream(index);
+            packet.write_interleaved(&mut output)?;
+        }
+
+        let end = Duration::from_micros(input.duration().max(0) as u64);
+        write_chapters(&mut output, markers, end)?;
+        output.write_trailer()?;
+    }
+
+    std::fs::rename(&temp, path).map_err(|e| {
+        let _ = std::fs::remove_file(&temp);
+        crate::error::SlumpError::Init(format!("Failed to replace {}: {}", path, e))
+    })
+}
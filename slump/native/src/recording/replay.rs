@@ -0,0 +1,259 @@
+use super::{Clip, RecordingFormat, RecordingSummary};
+use crate::error::{Result, SlumpError};
+use crate::output::{write_chapters, Marker, OutputSink, SharedEncoder, StreamInfo};
+use ffmpeg_next::{format, Dictionary, Packet, Rational};
+use std::{any::Any, collections::VecDeque, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum PacketKind {
+    Video,
+    Audio,
+}
+
+#[derive(Clone)]
+pub(super) struct BufferedPacket {
+    pub(super) kind: PacketKind,
+    pub(super) packet: Packet,
+    // Presentation time in seconds on the encoder clock
+    pub(super) time: f64,
+}
+
+fn seconds(ts: Option<i64>, time_base: Rational) -> f64 {
+    ts.unwrap_or(0) as f64 * time_base.numerator() as f64 / time_base.denominator() as f64
+}
+
+// Rolling window of encoded media that can be flushed to a clip on demand
+pub struct ReplayBuffer {
+    video: StreamInfo,
+    audio: Option<StreamInfo>,
+    packets: VecDeque<BufferedPacket>,
+    // Labels at encoder-clock seconds, trimmed along with the packets
+    markers: VecDeque<(f64, String)>,
+    window: Duration,
+    // Packet bytes held, and the cap that may cut the window short
+    bytes: usize,
+    max_bytes: Option<usize>,
+    // Packets dropped while still inside the window, to stay under max_bytes
+    evicted: u64,
+}
+
+impl ReplayBuffer {
+    pub fn new(window: Duration, max_bytes: Option<usize>, encoder: &SharedEncoder) -> Self {
+        let layout = encoder.layout();
+        Self {
+            video: layout.video,
+            audio: layout.audio,
+            packets: VecDeque::new(),
+            markers: VecDeque::new(),
+            window,
+            bytes: 0,
+            max_bytes,
+            evicted: 0,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn buffered(&self) -> Duration {
+        match (self.packets.front(), self.packets.back()) {
+            (Some(first), Some(last)) => Duration::from_secs_f64((last.time - first.time).max(0.0)),
+            _ => Duration::ZERO,
+        }
+    }
+
+    // Marks the newest buffered frame; saved clips carry it as a chapter
+    pub fn add_marker(&mut self, label: &str) -> bool {
+        let Some(newest) = self.packets.back().map(|p| p.time) else {
+            return false;
+        };
+        self.markers.push_back((newest, label.to_string()));
+        true
+    }
+
+    // Drop media older than the window, then advance to a keyframe so the
+    // buffer always starts decodable
+    fn trim(&mut self) {
+        let Some(newest) = self.packets.back().map(|p| p.time) else {
+            return;
+        };
+
+        let cutoff = newest - self.window.as_secs_f64();
+        while self.packets.front().is_some_and(|p| p.time < cutoff) {
+            self.pop_front();
+        }
+        // Over the cap, the oldest packets go until it fits, and the keyframe
+        // search below takes the rest of their GOP
+        while self.max_bytes.is_some_and(|max| self.bytes > max) && self.packets.len() > 1 {
+            self.pop_front();
+            self.evicted += 1;
+        }
+        while self
+            .packets
+            .front()
+            .is_some_and(|p| p.kind != PacketKind::Video || !p.packet.is_key())
+        {
+            self.pop_front();
+        }
+
+        let start = self.packets.front().map_or(f64::MAX, |p| p.time);
+        while self.markers.front().is_some_and(|(time, _)| *time < start) {
+            self.markers.pop_front();
+        }
+    }
+
+    fn push_back(&mut self, kind: PacketKind, time: f64, packet: &Packet) {
+        self.bytes += packet.size();
+        self.packets.push_back(BufferedPacket {
+            kind,
+            packet: packet.clone(),
+            time,
+        });
+    }
+
+    fn pop_front(&mut self) {
+        if let Some(p) = self.packets.pop_front() {
+            self.bytes -= p.packet.size();
+        }
+    }
+
+    // Copies out `duration` starting `start_offset` after the oldest buffered
+    // frame, plus the preceding GOP needed to decode it
+    pub fn clip(&self, start_offset: Duration, duration: Duration) -> Result<Clip> {
+        let (Some(first), Some(last)) = (self.packets.front(), self.packets.back()) else {
+            return Err(SlumpError::Init("Replay buffer is empty".into()));
+        };
+
+        let start = first.time + start_offset.as_secs_f64();
+        let end = (start + duration.as_secs_f64()).min(last.time);
+        if start >= end {
+            return Err(SlumpError::Init(
+                "Clip lies outside the replay buffer".into(),
+            ));
+        }
+
+        let preroll = self
+            .packets
+            .iter()
+            .filter(|p| p.kind == PacketKind::Video && p.packet.is_key() && p.time <= start)
+            .map(|p| p.time)
+            .last()
+            .unwrap_or(first.time);
+        let packets = self
+            .packets
+            .iter()
+            .filter(|p| p.time >= preroll && p.time <= end)
+            .cloned()
+            .collect();
+
+        Ok(Clip::new(
+            self.video.clone(),
+            self.audio.clone(),
+            packets,
+            start,
+            end,
+        ))
+    }
+
+    pub fn save(&self, path: &str, format: RecordingFormat) -> Result<RecordingSummary> {
+        let mut output = format::output_as_with(&path, format.muxer_name(), Dictionary::new())?;
+
+        let mut video_ost = output.add_stream(self.video.parameters.id())?;
+        video_ost.set_parameters(self.video.parameters.clone());
+        let video_stream = video_ost.index();
+
+        let audio_stream = match &self.audio {
+            Some(audio) => {
+                let mut audio_ost = output.add_stream(audio.parameters.id())?;
+                audio_ost.set_parameters(audio.parameters.clone());
+                Some(audio_ost.index())
+            }
+            None => None,
+        };
+
+        output.write_header()?;
+
+        let start = self.packets.front().map(|p| p.time).unwrap_or(0.0);
+        for buffered in &self.packets {
+            let (stream, encoder_time_base) = match (buffered.kind, audio_stream) {
+                (PacketKind::Video, _) => (video_stream, self.video.time_base),
+                (PacketKind::Audio, Some(stream)) => (
+                    stream,
+                    self.audio.as_ref().map(|a| a.time_base).unwrap_or_default(),
+                ),
+                (PacketKind::Audio, None) => continue,
+            };
+            if buffered.time < start {
+                continue;
+            }
+
+            // Rebase so the clip starts at zero
+            let offset = (start * encoder_time_base.denominator() as f64
+                / encoder_time_base.numerator() as f64) as i64;
+            let mut packet = buffered.packet.clone();
+            packet.set_pts(packet.pts().map(|pts| pts - offset));
+            packet.set_dts(packet.dts().map(|dts| dts - offset));
+            packet.set_stream(stream);
+
+            let stream_time_base = output
+                .stream(stream)
+                .map(|s| s.time_base())
+                .unwrap_or(encoder_time_base);
+            packet.rescale_ts(encoder_time_base, stream_time_base);
+            packet.write_interleaved(&mut output)?;
+        }
+
+        let markers: Vec<Marker> = self
+            .markers
+            .iter()
+            .map(|(time, label)| Marker {
+                time: Duration::from_secs_f64((time - start).max(0.0)),
+                label: label.clone(),
+            })
+            .collect();
+        write_chapters(&mut output, &markers, self.buffered())?;
+        output.write_trailer()?;
+
+        Ok(RecordingSummary {
+            path: path.to_string(),
+            duration: self.buffered(),
+            size_bytes: std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        })
+    }
+}
+
+impl OutputSink for ReplayBuffer {
+    fn write_video_packet(&mut self, packet: &Packet) -> Result<()> {
+        let time = seconds(packet.pts(), self.video.time_base);
+        self.push_back(PacketKind::Video, time, packet);
+        self.trim();
+        Ok(())
+    }
+
+    fn write_audio_packet(&mut self, packet: &Packet) -> Result<()> {
+        let Some(audio) = &self.audio else {
+            return Ok(());
+        };
+
+        let time = seconds(packet.pts(), audio.time_base);
+        self.push_back(PacketKind::Audio, time, packet);
+        Ok(())
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
@@ -0,0 +1,21 @@
+use std::{io, sync::OnceLock};
+
+use tokio::runtime::{Builder, Runtime};
+
+// One multi-thread runtime for the whole addon, built on first use. Blocking
+// calls and worker threads block_on it, and anything they spawn (per-connection
+// tasks, best-effort peer cleanup) runs on its pool instead of whichever
+// runtime happens to be current. It holds no JS values, so worker threads and
+// Electron contexts loading the addon can all share it.
+static RUNTIME: OnceLock<io::Result<Runtime>> = OnceLock::new();
+
+pub fn get() -> Result<&'static Runtime, &'static io::Error> {
+    RUNTIME
+        .get_or_init(|| {
+            Builder::new_multi_thread()
+                .enable_all()
+                .thread_name("slump-runtime")
+                .build()
+        })
+        .as_ref()
+}
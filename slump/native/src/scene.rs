@@ -0,0 +1,160 @@
+use crate::{
+    error::{Result, SlumpError},
+    video::{Chroma, Overlay, PrivacyRegions, VideoCapture, VideoSource},
+};
+use ffmpeg_next::Frame;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+// What the stream was started with, before any switch
+pub const DEFAULT_SCENE: &str = "default";
+
+// The parts of the stream a switch swaps out. Only the live scene's are in
+// StreamState; the rest wait here with their capture already open.
+pub struct Scene {
+    pub video_source: VideoSource,
+    pub display_index: usize,
+    pub capture_cursor: bool,
+    // None for a scene parked while the stream was stopped
+    pub capture: Option<VideoCapture>,
+    pub overlay: Overlay,
+    pub privacy: PrivacyRegions,
+}
+
+pub struct Scenes {
+    idle: BTreeMap<String, Scene>,
+    active: String,
+}
+
+impl Default for Scenes {
+    fn default() -> Self {
+        Self {
+            idle: BTreeMap::new(),
+            active: DEFAULT_SCENE.to_string(),
+        }
+    }
+}
+
+impl Scenes {
+    pub fn active(&self) -> &str {
+        &self.active
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.idle.keys().cloned().collect();
+        names.push(self.active.clone());
+        names.sort();
+        names
+    }
+
+    // Replaces an idle scene of the same name; the live one can't be
+    pub fn add(&mut self, name: String, scene: Scene) -> Result<()> {
+        if name == self.active {
+            return Err(SlumpError::Init(format!(
+                "Scene {} is live; switch away from it first",
+                name
+            )));
+        }
+        self.idle.insert(name, scene);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        if name == self.active {
+            return Err(SlumpError::Init(format!(
+                "Scene {} is live; switch away from it first",
+                name
+            )));
+        }
+        Ok(self.idle.remove(name).is_some())
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Scene> {
+        self.idle.get_mut(name)
+    }
+
+    pub fn take(&mut self, name: &str) -> Option<Scene> {
+        self.idle.remove(name)
+    }
+
+    // Parks what was live under its name, and records `name` as live
+    pub fn park(&mut self, previous: Scene, name: String) {
+        let previous_name = std::mem::replace(&mut self.active, name);
+        self.idle.insert(previous_name, previous);
+    }
+
+    // Gives a parked scene back the capture the capture stage let go of. A
+    // scene that went live again or was removed meanwhile doesn't want it.
+    pub fn restore(&mut self, name: &str, capture: VideoCapture) {
+        if let Some(scene) = self
+            .idle
+            .get_mut(name)
+            .filter(|scene| scene.capture.is_none())
+        {
+            scene.capture = Some(capture);
+        }
+    }
+}
+
+// Blends the last frame of the old scene into the new one's over `duration`
+pub struct Crossfade {
+    from: Frame,
+    started: Instant,
+    duration: Duration,
+}
+
+impl Crossfade {
+    pub fn new(from: Frame, duration: Duration) -> Self {
+        Self {
+            from,
+            started: Instant::now(),
+            duration,
+        }
+    }
+
+    // False once the fade is over, or if the frames no longer match after a
+    // rescale; either way the new scene shows on its own from then on
+    pub fn blend(&self, to: &mut Frame) -> bool {
+        let progress = self.started.elapsed().as_secs_f64() / self.duration.as_secs_f64();
+        if progress >= 1.0 {
+            return false;
+        }
+        let weight = (progress.max(0.0) * 256.0) as u32;
+        unsafe {
+            let from = &*self.from.as_ptr();
+            let to = &mut *to.as_mut_ptr();
+            let Some(chroma) = Chroma::of(from.format) else {
+                return false;
+            };
+            if to.format != from.format
+                || (to.width, to.height) != (from.width, from.height)
+                || to.width <= 0
+                || to.height <= 0
+            {
+                return false;
+            }
+            let (width, height) = (to.width as i64, to.height as i64);
+            let (chroma_width, chroma_height) = chroma.plane_size(width, height);
+            for (plane, (plane_width, plane_height)) in [
+                (width as usize, height as usize),
+                (chroma_width as usize, chroma_height as usize),
+                (chroma_width as usize, chroma_height as usize),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                for y in 0..plane_height {
+                    let old = from.data[plane].offset(y as isize * from.linesize[plane] as isize);
+                    let new = to.data[plane].offset(y as isize * to.linesize[plane] as isize);
+                    for x in 0..plane_width {
+                        let (old, new) = (*old.add(x) as u32, new.add(x));
+                        *new = ((old * (256 - weight) + *new as u32 * weight + 128) >> 8) as u8;
+                    }
+                }
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,61 @@
+use super::TlsSettings;
+use crate::error::{Result, SlumpError};
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest,
+    handshake::client::Request,
+    http::{header::AUTHORIZATION, HeaderName, HeaderValue},
+};
+
+// Credentials attached to signaling WebSocket and WHIP requests
+#[derive(Debug, Clone, Default)]
+pub struct SignalingAuth {
+    pub bearer_token: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub query: Vec<(String, String)>,
+    pub tls: TlsSettings,
+}
+
+impl SignalingAuth {
+    pub fn apply_query(&self, url: &str) -> Result<String> {
+        let mut url = reqwest::Url::parse(url)
+            .map_err(|e| SlumpError::Network(format!("Invalid signaling URL: {}", e)))?;
+        if !self.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(self.query.iter());
+        }
+        Ok(url.to_string())
+    }
+
+    pub fn websocket_request(&self, url: &str) -> Result<Request> {
+        let mut request = self
+            .apply_query(url)?
+            .into_client_request()
+            .map_err(|e| SlumpError::Network(e.to_string()))?;
+
+        let headers = request.headers_mut();
+        if let Some(token) = &self.bearer_token {
+            let value = HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|e| SlumpError::Network(format!("Invalid bearer token: {}", e)))?;
+            headers.insert(AUTHORIZATION, value);
+        }
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| SlumpError::Network(format!("Invalid header name {}: {}", name, e)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| SlumpError::Network(format!("Invalid header value: {}", e)))?;
+            headers.insert(name, value);
+        }
+
+        Ok(request)
+    }
+
+    pub fn apply(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let mut request = request.query(&self.query);
+        if let Some(token) = &self.bearer_token {
+            request = request.bearer_auth(token);
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        request
+    }
+}
@@ -0,0 +1,236 @@
+use super::{SignalingAuth, SignalingEvent};
+use crate::webrtc::{
+    new_session_id, Capabilities, SignalEnvelope, SignalMessage, SIGNAL_PROTOCOL_VERSION,
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::{
+    connect_async_tls_with_config, tungstenite::protocol::Message, Connector,
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+const LIVENESS_TIMEOUT: Duration = Duration::from_secs(15);
+// Messages that failed mid-send and are retried after reconnecting
+const MAX_PENDING: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalingState {
+    Connecting,
+    Connected,
+    Reconnecting,
+    Closed,
+}
+
+impl SignalingState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignalingState::Connecting => "connecting",
+            SignalingState::Connected => "connected",
+            SignalingState::Reconnecting => "reconnecting",
+            SignalingState::Closed => "closed",
+        }
+    }
+}
+
+// WebSocket signaling client that reconnects with exponential backoff and
+// queues outgoing messages while the connection is down
+pub struct SignalingClient {
+    outgoing: mpsc::UnboundedSender<SignalMessage>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl SignalingClient {
+    pub fn connect(
+        url: String,
+        auth: SignalingAuth,
+        peer_id: String,
+        events: mpsc::UnboundedSender<SignalingEvent>,
+    ) -> Self {
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+
+        let thread = std::thread::spawn(move || {
+            let rt = match crate::runtime::get() {
+                Ok(rt) => rt,
+                Err(e) => {
+                    log::error!("Failed to create signaling runtime: {}", e);
+                    return;
+                }
+            };
+            rt.block_on(run(url, auth, peer_id, events, outgoing_rx, shutdown_rx));
+        });
+
+        Self {
+            outgoing,
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn sender(&self) -> mpsc::UnboundedSender<SignalMessage> {
+        self.outgoing.clone()
+    }
+
+    pub fn send(&self, message: SignalMessage) {
+        let _ = self.outgoing.send(message);
+    }
+}
+
+impl Drop for SignalingClient {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn set_state(events: &mpsc::UnboundedSender<SignalingEvent>, state: SignalingState) {
+    let _ = events.send(SignalingEvent::StateChanged { state });
+}
+
+fn fail(events: &mpsc::UnboundedSender<SignalingEvent>, reason: String) {
+    log::error!("{}", reason);
+    let _ = events.send(SignalingEvent::Failed { reason });
+    set_state(events, SignalingState::Closed);
+}
+
+async fn run(
+    url: String,
+    auth: SignalingAuth,
+    peer_id: String,
+    events: mpsc::UnboundedSender<SignalingEvent>,
+    mut outgoing_rx: mpsc::UnboundedReceiver<SignalMessage>,
+    mut shutdown_rx: oneshot::Receiver<()>,
+) {
+    let connector = if auth.tls.is_default() {
+        None
+    } else {
+        match auth.tls.client_config() {
+            Ok(config) => Some(Connector::Rustls(config)),
+            Err(e) => {
+                fail(&events, e.to_string());
+                return;
+            }
+        }
+    };
+
+    let session_id = new_session_id();
+    let encode = |message: SignalMessage| {
+        serde_json::to_string(&SignalEnvelope::new(message, &peer_id, &session_id)).unwrap_or_default()
+    };
+
+    let mut pending: VecDeque<SignalMessage> = VecDeque::new();
+    let mut backoff = INITIAL_BACKOFF;
+    set_state(&events, SignalingState::Connecting);
+
+    loop {
+        // Rebuilt every attempt since the handshake request is consumed
+        let request = match auth.websocket_request(&url) {
+            Ok(request) => request,
+            Err(e) => {
+                fail(&events, e.to_string());
+                return;
+            }
+        };
+
+        match connect_async_tls_with_config(request, None, false, connector.clone()).await {
+            Ok((ws, _)) => {
+                backoff = INITIAL_BACKOFF;
+                set_state(&events, SignalingState::Connected);
+
+                let (mut sink, mut source) = ws.split();
+                let mut ping = tokio::time::interval(PING_INTERVAL);
+                let mut last_pong = Instant::now();
+
+                // Advertise capabilities, then retry whatever failed on the previous connection
+                let hello = encode(SignalMessage::Capabilities(Capabilities::local()));
+                let _ = sink.send(Message::Text(hello)).await;
+                while let Some(message) = pending.pop_front() {
+                    let text = encode(message.clone());
+                    if sink.send(Message::Text(text)).await.is_err() {
+                        pending.push_front(message);
+                        break;
+                    }
+                }
+
+                loop {
+                    tokio::select! {
+                        _ = &mut shutdown_rx => {
+                            let _ = sink.close().await;
+                            set_state(&events, SignalingState::Closed);
+                            return;
+                        }
+                        Some(message) = outgoing_rx.recv() => {
+                            let text = encode(message.clone());
+                            if let Err(e) = sink.send(Message::Text(text)).await {
+                                log::warn!("Signaling send failed: {}", e);
+                                if pending.len() < MAX_PENDING {
+                                    pending.push_back(message);
+                                }
+                                break;
+                            }
+                        }
+                        _ = ping.tick() => {
+                            if last_pong.elapsed() > LIVENESS_TIMEOUT {
+                                log::warn!("Signaling connection timed out");
+                                break;
+                            }
+                            if sink.send(Message::Ping(Vec::new())).await.is_err() {
+                                break;
+                            }
+                        }
+                        incoming = source.next() => match incoming {
+                            Some(Ok(Message::Text(text))) => {
+                                last_pong = Instant::now();
+                                match serde_json::from_str::<SignalEnvelope>(&text) {
+                                    Ok(envelope) => {
+                                        if envelope.version > SIGNAL_PROTOCOL_VERSION {
+                                            log::debug!("Server speaks newer signaling v{}", envelope.version);
+                                        }
+                                        let _ = events.send(SignalingEvent::Message {
+                                            viewer_id: peer_id.clone(),
+                                            message: envelope.message,
+                                        });
+                                    }
+                                    Err(e) => log::warn!("Ignoring malformed signal: {}", e),
+                                }
+                            }
+                            Some(Ok(Message::Pong(_))) | Some(Ok(Message::Ping(_))) => {
+                                last_pong = Instant::now();
+                            }
+                            Some(Ok(Message::Close(_))) | None => break,
+                            Some(Ok(_)) => {}
+                            Some(Err(e)) => {
+                                log::warn!("Signaling socket error: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => log::warn!("Failed to connect to signaling server: {}", e),
+        }
+
+        set_state(&events, SignalingState::Reconnecting);
+
+        // Outgoing messages stay queued in the channel until the next connection
+        tokio::select! {
+            _ = &mut shutdown_rx => {
+                set_state(&events, SignalingState::Closed);
+                return;
+            }
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
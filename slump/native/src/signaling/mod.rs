@@ -0,0 +1,240 @@
+mod auth;
+mod client;
+mod tls;
+mod whip;
+
+pub use auth::SignalingAuth;
+pub use client::{SignalingClient, SignalingState};
+pub use tls::TlsSettings;
+pub use whip::WhipClient;
+
+use crate::error::{Result, SlumpError};
+use crate::webrtc::{new_session_id, Capabilities, SignalEnvelope, SignalMessage, SIGNAL_PROTOCOL_VERSION};
+use futures_util::{SinkExt, StreamExt};
+use std::net::{IpAddr, SocketAddr};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+    task::JoinSet,
+};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+const VIEWER_PAGE: &str = include_str!("viewer.html");
+
+pub enum SignalingEvent {
+    ViewerConnected {
+        viewer_id: String,
+        outgoing: mpsc::UnboundedSender<SignalMessage>,
+    },
+    Message {
+        viewer_id: String,
+        message: SignalMessage,
+    },
+    ViewerDisconnected {
+        viewer_id: String,
+    },
+    // Sent by a viewer over its WebRTC control channel rather than signaling
+    Control {
+        viewer_id: String,
+        message: String,
+    },
+    StateChanged {
+        state: SignalingState,
+    },
+    // The client gave up without being asked to; it won't reconnect
+    Failed {
+        reason: String,
+    },
+}
+
+// Serves the viewer page over HTTP and signaling over WebSocket on the same port
+pub struct SignalingServer {
+    local_addr: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl SignalingServer {
+    pub fn start(port: u16, events: mpsc::UnboundedSender<SignalingEvent>) -> Result<Self> {
+        let std_listener = std::net::TcpListener::bind(("0.0.0.0", port))
+            .map_err(|e| SlumpError::Network(format!("Failed to bind port {}: {}", port, e)))?;
+        std_listener
+            .set_nonblocking(true)
+            .map_err(|e| SlumpError::Network(e.to_string()))?;
+        let local_addr = std_listener
+            .local_addr()
+            .map_err(|e| SlumpError::Network(e.to_string()))?;
+
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let thread = std::thread::spawn(move || {
+            let rt = match crate::runtime::get() {
+                Ok(rt) => rt,
+                Err(e) => {
+                    log::error!("Failed to create signaling runtime: {}", e);
+                    return;
+                }
+            };
+
+            rt.block_on(async move {
+                let listener = match TcpListener::from_std(std_listener) {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        log::error!("Failed to start signaling listener: {}", e);
+                        return;
+                    }
+                };
+
+                // Connections live on the shared runtime, so they are aborted when the set drops
+                let mut connections = JoinSet::new();
+                let mut next_viewer = 0u64;
+                loop {
+                    tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        Some(_) = connections.join_next() => {}
+                        accepted = listener.accept() => match accepted {
+                            Ok((socket, addr)) => {
+                                next_viewer += 1;
+                                let viewer_id = format!("viewer-{}", next_viewer);
+                                log::debug!("Signaling connection {} from {}", viewer_id, addr);
+                                connections.spawn(handle_connection(socket, viewer_id, events.clone()));
+                            }
+                            Err(e) => log::warn!("Failed to accept signaling connection: {}", e),
+                        }
+                    }
+                }
+            });
+        });
+
+        Ok(Self {
+            local_addr,
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+        })
+    }
+
+    // URL viewers on the LAN can open directly
+    pub fn url(&self) -> String {
+        let host = lan_address().unwrap_or_else(|| self.local_addr.ip());
+        format!("http://{}:{}/", host, self.local_addr.port())
+    }
+}
+
+impl Drop for SignalingServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Routing trick: connecting a UDP socket picks the outbound interface without sending anything
+pub(crate) fn lan_address() -> Option<IpAddr> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+async fn handle_connection(
+    socket: TcpStream,
+    viewer_id: String,
+    events: mpsc::UnboundedSender<SignalingEvent>,
+) {
+    let mut buf = [0u8; 2048];
+    let n = match socket.peek(&mut buf).await {
+        Ok(n) => n,
+        Err(_) => return,
+    };
+
+    let head = String::from_utf8_lossy(&buf[..n]).to_ascii_lowercase();
+    let result = if head.contains("upgrade: websocket") {
+        serve_viewer(socket, viewer_id, events).await
+    } else {
+        serve_page(socket).await
+    };
+
+    if let Err(e) = result {
+        log::warn!("Signaling connection failed: {}", e);
+    }
+}
+
+async fn serve_page(mut socket: TcpStream) -> Result<()> {
+    // Only one page is served, so the request itself is not inspected
+    let mut buf = [0u8; 4096];
+    let _ = socket.read(&mut buf).await;
+
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        VIEWER_PAGE.len(),
+        VIEWER_PAGE
+    );
+    socket
+        .write_all(response.as_bytes())
+        .await
+        .map_err(|e| SlumpError::Network(e.to_string()))?;
+    socket
+        .shutdown()
+        .await
+        .map_err(|e| SlumpError::Network(e.to_string()))
+}
+
+async fn serve_viewer(
+    socket: TcpStream,
+    viewer_id: String,
+    events: mpsc::UnboundedSender<SignalingEvent>,
+) -> Result<()> {
+    let ws = tokio_tungstenite::accept_async(socket)
+        .await
+        .map_err(|e| SlumpError::Network(e.to_string()))?;
+    let (mut sink, mut source) = ws.split();
+
+    let session_id = new_session_id();
+    let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel::<SignalMessage>();
+    let _ = outgoing_tx.send(SignalMessage::Capabilities(Capabilities::local()));
+    events
+        .send(SignalingEvent::ViewerConnected {
+            viewer_id: viewer_id.clone(),
+            outgoing: outgoing_tx,
+        })
+        .map_err(|_| SlumpError::Network("Stream is not running".into()))?;
+
+    loop {
+        tokio::select! {
+            Some(message) = outgoing_rx.recv() => {
+                let envelope = SignalEnvelope::new(message, &viewer_id, &session_id);
+                let text = serde_json::to_string(&envelope)
+                    .map_err(|e| SlumpError::Network(e.to_string()))?;
+                if let Err(e) = sink.send(Message::Text(text)).await {
+                    log::warn!("Failed to send signal to {}: {}", viewer_id, e);
+                    break;
+                }
+            }
+            incoming = source.next() => match incoming {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<SignalEnvelope>(&text) {
+                    Ok(envelope) => {
+                        if envelope.version > SIGNAL_PROTOCOL_VERSION {
+                            log::debug!("{} speaks newer signaling v{}", viewer_id, envelope.version);
+                        }
+                        let _ = events.send(SignalingEvent::Message {
+                            viewer_id: viewer_id.clone(),
+                            message: envelope.message,
+                        });
+                    }
+                    Err(e) => log::warn!("Ignoring malformed signal from {}: {}", viewer_id, e),
+                },
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    log::warn!("Signaling socket error for {}: {}", viewer_id, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    let _ = events.send(SignalingEvent::ViewerDisconnected { viewer_id });
+    Ok(())
+}
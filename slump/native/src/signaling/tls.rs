@@ -0,0 +1,153 @@
+use crate::error::{Result, SlumpError};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName,
+};
+use std::{fs::File, io::BufReader, sync::Arc, time::SystemTime};
+
+// TLS overrides for self-hosted signaling behind private CAs
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    pub ca_file: Option<String>,
+    // SHA-256 fingerprints of accepted leaf certificates, hex with optional colons
+    pub pinned_certificates: Vec<String>,
+    pub insecure_skip_verify: bool,
+}
+
+impl TlsSettings {
+    pub fn is_default(&self) -> bool {
+        self.ca_file.is_none() && self.pinned_certificates.is_empty() && !self.insecure_skip_verify
+    }
+
+    pub fn client_config(&self) -> Result<Arc<ClientConfig>> {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+
+        if let Some(path) = &self.ca_file {
+            let file = File::open(path)
+                .map_err(|e| SlumpError::Network(format!("Failed to open CA bundle {}: {}", path, e)))?;
+            let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+                .map_err(|e| SlumpError::Network(format!("Invalid CA bundle {}: {}", path, e)))?;
+            for cert in certs {
+                roots
+                    .add(&Certificate(cert))
+                    .map_err(|e| SlumpError::Network(format!("Invalid CA certificate: {}", e)))?;
+            }
+        }
+
+        let pins = self
+            .pinned_certificates
+            .iter()
+            .map(|pin| parse_fingerprint(pin))
+            .collect::<Result<Vec<_>>>()?;
+
+        if self.insecure_skip_verify {
+            log::warn!("TLS certificate verification is disabled for signaling");
+        }
+
+        let verifier = PinningVerifier {
+            inner: WebPkiVerifier::new(roots.clone(), None),
+            pins,
+            insecure: self.insecure_skip_verify,
+        };
+
+        let mut config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+
+        Ok(Arc::new(config))
+    }
+}
+
+fn parse_fingerprint(pin: &str) -> Result<Vec<u8>> {
+    let hex: String = pin.chars().filter(|c| *c != ':').collect();
+    // Checked before slicing by byte, which would split a multi-byte char
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(SlumpError::Network(format!(
+            "Certificate pin must be a SHA-256 fingerprint: {}",
+            pin
+        )));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| SlumpError::Network(format!("Invalid certificate pin: {}", pin)))
+        })
+        .collect()
+}
+
+struct PinningVerifier {
+    inner: WebPkiVerifier,
+    pins: Vec<Vec<u8>>,
+    insecure: bool,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        if !self.insecure {
+            self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            )?;
+        }
+
+        if !self.pins.is_empty() {
+            let digest = ring::digest::digest(&ring::digest::SHA256, &end_entity.0);
+            if !self.pins.iter().any(|pin| pin.as_slice() == digest.as_ref()) {
+                return Err(rustls::Error::General(
+                    "Server certificate does not match any pinned fingerprint".into(),
+                ));
+            }
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_fingerprint;
+
+    #[test]
+    fn parses_colon_separated_fingerprints() {
+        let pin = vec!["ab"; 32].join(":");
+        assert_eq!(parse_fingerprint(&pin).unwrap(), vec![0xab; 32]);
+    }
+
+    #[test]
+    fn rejects_non_ascii_pins_of_the_right_byte_length() {
+        // 31 pairs of hex plus one two-byte char: 64 bytes, 63 chars
+        let pin = format!("{}é", "ab".repeat(31));
+        assert_eq!(pin.len(), 64);
+        assert!(parse_fingerprint(&pin).is_err());
+    }
+
+    #[test]
+    fn rejects_short_pins() {
+        assert!(parse_fingerprint("abcd").is_err());
+    }
+}
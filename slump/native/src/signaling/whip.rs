@@ -0,0 +1,153 @@
+use super::SignalingAuth;
+use crate::error::{Result, SlumpError};
+use crate::webrtc::IceCandidate;
+use reqwest::{header, StatusCode};
+
+const SDP_CONTENT_TYPE: &str = "application/sdp";
+const TRICKLE_CONTENT_TYPE: &str = "application/trickle-ice-sdpfrag";
+
+// WebRTC-HTTP ingestion: POST offer, PATCH trickle candidates, DELETE to tear down
+#[derive(Clone)]
+pub struct WhipClient {
+    client: reqwest::Client,
+    endpoint: String,
+    auth: SignalingAuth,
+    resource_url: Option<String>,
+    etag: Option<String>,
+    ice_ufrag: String,
+    ice_pwd: String,
+}
+
+impl WhipClient {
+    pub fn new(endpoint: String, auth: SignalingAuth) -> Result<Self> {
+        let mut builder = reqwest::Client::builder();
+        if !auth.tls.is_default() {
+            builder = builder.use_preconfigured_tls((*auth.tls.client_config()?).clone());
+        }
+        let client = builder
+            .build()
+            .map_err(|e| SlumpError::Network(format!("Failed to create WHIP client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            endpoint,
+            auth,
+            resource_url: None,
+            etag: None,
+            ice_ufrag: String::new(),
+            ice_pwd: String::new(),
+        })
+    }
+
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        self.auth.apply(self.client.request(method, url))
+    }
+
+    // Returns the SDP answer from the WHIP endpoint
+    pub async fn publish(&mut self, offer_sdp: &str) -> Result<String> {
+        let response = self
+            .request(reqwest::Method::POST, &self.endpoint)
+            .header(header::CONTENT_TYPE, SDP_CONTENT_TYPE)
+            .body(offer_sdp.to_string())
+            .send()
+            .await
+            .map_err(|e| SlumpError::Network(format!("WHIP request failed: {}", e)))?;
+
+        if response.status() != StatusCode::CREATED && response.status() != StatusCode::OK {
+            return Err(SlumpError::Network(format!(
+                "WHIP endpoint returned {}",
+                response.status()
+            )));
+        }
+
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| SlumpError::Network("WHIP response missing Location".into()))?;
+        let resource_url = reqwest::Url::parse(&self.endpoint)
+            .and_then(|base| base.join(location))
+            .map_err(|e| SlumpError::Network(format!("Invalid WHIP resource URL: {}", e)))?;
+
+        self.resource_url = Some(resource_url.to_string());
+        self.etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        self.ice_ufrag = sdp_attribute(offer_sdp, "ice-ufrag").unwrap_or_default();
+        self.ice_pwd = sdp_attribute(offer_sdp, "ice-pwd").unwrap_or_default();
+
+        response
+            .text()
+            .await
+            .map_err(|e| SlumpError::Network(format!("Failed to read WHIP answer: {}", e)))
+    }
+
+    pub async fn trickle(&self, candidate: &IceCandidate) -> Result<()> {
+        let Some(resource_url) = &self.resource_url else {
+            return Err(SlumpError::Network("WHIP session not established".into()));
+        };
+
+        let fragment = format!(
+            "a=ice-ufrag:{}\r\na=ice-pwd:{}\r\nm=audio 9 UDP/TLS/RTP/SAVPF 0\r\na=mid:{}\r\na={}\r\n",
+            self.ice_ufrag,
+            self.ice_pwd,
+            candidate.sdp_mid.as_deref().unwrap_or("0"),
+            candidate.candidate
+        );
+
+        let mut request = self
+            .request(reqwest::Method::PATCH, resource_url)
+            .header(header::CONTENT_TYPE, TRICKLE_CONTENT_TYPE)
+            .body(fragment);
+        if let Some(etag) = &self.etag {
+            request = request.header(header::IF_MATCH, etag);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| SlumpError::Network(format!("WHIP trickle failed: {}", e)))?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            // Servers may not support trickle; candidates then only travel in the offer
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => {
+                log::debug!("WHIP endpoint does not support trickle ICE");
+                Ok(())
+            }
+            status => Err(SlumpError::Network(format!(
+                "WHIP trickle returned {}",
+                status
+            ))),
+        }
+    }
+
+    pub async fn teardown(&mut self) -> Result<()> {
+        let Some(resource_url) = self.resource_url.take() else {
+            return Ok(());
+        };
+
+        let response = self
+            .request(reqwest::Method::DELETE, &resource_url)
+            .send()
+            .await
+            .map_err(|e| SlumpError::Network(format!("WHIP teardown failed: {}", e)))?;
+
+        if !response.status().is_success() && response.status() != StatusCode::NOT_FOUND {
+            return Err(SlumpError::Network(format!(
+                "WHIP teardown returned {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn sdp_attribute(sdp: &str, name: &str) -> Option<String> {
+    let prefix = format!("a={}:", name);
+    sdp.lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .map(|value| value.trim().to_string())
+}
@@ -0,0 +1,58 @@
+use crate::error::{Result, SlumpError};
+use std::str::FromStr;
+
+// When a capture device is replaced by a generated test source, so slump
+// runs on machines with no display, camera or sound card
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Substitute {
+    #[default]
+    Never,
+    // Try the real device first
+    Missing,
+    // Don't touch the real device at all
+    Always,
+}
+
+impl FromStr for Substitute {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "never" => Ok(Substitute::Never),
+            "missing" => Ok(Substitute::Missing),
+            "always" => Ok(Substitute::Always),
+            other => Err(SlumpError::Init(format!(
+                "Unknown substitute mode: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl Substitute {
+    // Any failure counts as missing: a headless box reports no display in
+    // as many ways as there are capture backends
+    pub fn open<T>(
+        self,
+        what: &str,
+        real: impl FnOnce() -> Result<T>,
+        synthetic: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        match self {
+            Substitute::Never => real(),
+            Substitute::Always => synthetic(),
+            Substitute::Missing => real().or_else(|e| {
+                log::warn!("{} unavailable, using a test source: {}", what, e);
+                synthetic()
+            }),
+        }
+    }
+}
+
+// Per device kind, as set in the start options
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Substitutes {
+    pub video: Substitute,
+    pub audio: Substitute,
+    pub camera: Substitute,
+}
@@ -0,0 +1,209 @@
+use crate::error::{Result, SlumpError};
+use crate::logging;
+use ffmpeg_next::{ffi, Frame};
+use std::{
+    ffi::CStr,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+// Which frame the tap sees
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TapSource {
+    // Scaled to the output size in the encoder's format, as viewers get it
+    #[default]
+    Scaled,
+    // Straight from the capture device, at its own size and pixel format
+    Capture,
+}
+
+impl FromStr for TapSource {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "scaled" => Ok(TapSource::Scaled),
+            "capture" => Ok(TapSource::Capture),
+            other => Err(SlumpError::Init(format!("Unknown frame source: {}", other))),
+        }
+    }
+}
+
+// Rate limit for handing frames to JS
+pub struct FrameTap {
+    source: TapSource,
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl FrameTap {
+    pub fn new(source: TapSource, max_fps: u32) -> Self {
+        Self {
+            source,
+            interval: Duration::from_secs(1) / max_fps.max(1),
+            last: None,
+        }
+    }
+
+    pub fn source(&self) -> TapSource {
+        self.source
+    }
+
+    // True at most once per interval; the caller copies the frame only then
+    pub fn due(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .last
+            .is_some_and(|last| now.duration_since(last) < self.interval)
+        {
+            return false;
+        }
+        self.last = Some(now);
+        true
+    }
+}
+
+// Where a tapped frame's bytes live
+pub enum Backing {
+    // A reference to the frame itself, whose planes share one buffer. Handing
+    // it to JS keeps that buffer (and its pool slot) until the Buffer is GC'd.
+    Shared(Frame),
+    Copied(Vec<u8>),
+}
+
+pub struct TappedFrame {
+    pub backing: Backing,
+    // FFmpeg's pixel format name, e.g. "yuv420p" or "bgra"
+    pub format: String,
+    pub width: u32,
+    pub height: u32,
+    pub strides: Vec<u32>,
+    pub offsets: Vec<u32>,
+    pub timestamp_ms: f64,
+}
+
+// Every byte of a shared frame's single buffer, which its offsets index into
+pub fn frame_bytes(frame: &Frame) -> &[u8] {
+    unsafe {
+        let buf = &*(*frame.as_ptr()).buf[0];
+        std::slice::from_raw_parts(buf.data, buf.size as usize)
+    }
+}
+
+// Another reference to the same buffers, not a copy of the picture
+pub fn share(frame: &Frame) -> Option<Frame> {
+    let mut shared = Frame::empty();
+    let status = unsafe { ffi::av_frame_ref(shared.as_mut_ptr(), frame.as_ptr()) };
+    (status >= 0).then_some(shared)
+}
+
+// Rows per plane, or None for layouts that can't be described as offsets and
+// strides into one buffer
+struct Layout {
+    format: String,
+    rows: Vec<usize>,
+}
+
+unsafe fn layout(raw: &ffi::AVFrame) -> Option<Layout> {
+    if raw.format < 0 {
+        return None;
+    }
+    let format: ffi::AVPixelFormat = std::mem::transmute(raw.format);
+    let desc = ffi::av_pix_fmt_desc_get(format);
+    let name = ffi::av_get_pix_fmt_name(format);
+    let planes = ffi::av_pix_fmt_count_planes(format);
+    if desc.is_null() || name.is_null() || planes <= 0 {
+        return None;
+    }
+
+    let height = raw.height.max(0) as usize;
+    // Planes 1 and 2 carry chroma; luma and alpha are full height
+    let chroma_height = -((-(height as i64)) >> (*desc).log2_chroma_h) as usize;
+    let mut rows = Vec::with_capacity(planes as usize);
+    for plane in 0..planes as usize {
+        // Bottom-up layouts would need a row by row copy
+        if raw.linesize[plane] <= 0 || raw.data[plane].is_null() {
+            return None;
+        }
+        rows.push(if plane == 1 || plane == 2 {
+            chroma_height
+        } else {
+            height
+        });
+    }
+
+    Some(Layout {
+        format: CStr::from_ptr(name).to_string_lossy().into_owned(),
+        rows,
+    })
+}
+
+// Takes the frame without copying when every plane sits in its first buffer,
+// as with frames from the capture pool; otherwise copies
+pub fn tap_frame(frame: &Frame) -> Option<TappedFrame> {
+    share_planes(frame).or_else(|| copy_planes(frame))
+}
+
+fn share_planes(frame: &Frame) -> Option<TappedFrame> {
+    unsafe {
+        let raw = &*frame.as_ptr();
+        let layout = layout(raw)?;
+        if raw.buf[0].is_null() || !raw.buf[1].is_null() {
+            return None;
+        }
+        let buf = &*raw.buf[0];
+        let start = buf.data as usize;
+        let end = start + buf.size as usize;
+
+        let mut strides = Vec::with_capacity(layout.rows.len());
+        let mut offsets = Vec::with_capacity(layout.rows.len());
+        for (plane, rows) in layout.rows.iter().enumerate() {
+            let stride = raw.linesize[plane] as usize;
+            let plane_start = raw.data[plane] as usize;
+            if plane_start < start || plane_start + stride * rows > end {
+                return None;
+            }
+            offsets.push((plane_start - start) as u32);
+            strides.push(stride as u32);
+        }
+
+        Some(TappedFrame {
+            backing: Backing::Shared(share(frame)?),
+            format: layout.format,
+            width: raw.width.max(0) as u32,
+            height: raw.height.max(0) as u32,
+            strides,
+            offsets,
+            timestamp_ms: logging::timestamp_ms(),
+        })
+    }
+}
+
+// Copies every plane into one buffer, one memcpy per plane. Rows keep their
+// original stride, so consumers index with strides[plane] rather than width.
+fn copy_planes(frame: &Frame) -> Option<TappedFrame> {
+    unsafe {
+        let raw = &*frame.as_ptr();
+        let layout = layout(raw)?;
+
+        let mut data = Vec::new();
+        let mut strides = Vec::with_capacity(layout.rows.len());
+        let mut offsets = Vec::with_capacity(layout.rows.len());
+        for (plane, rows) in layout.rows.iter().enumerate() {
+            let stride = raw.linesize[plane] as usize;
+            offsets.push(data.len() as u32);
+            strides.push(stride as u32);
+            data.extend_from_slice(std::slice::from_raw_parts(raw.data[plane], stride * rows));
+        }
+
+        Some(TappedFrame {
+            backing: Backing::Copied(data),
+            format: layout.format,
+            width: raw.width.max(0) as u32,
+            height: raw.height.max(0) as u32,
+            strides,
+            offsets,
+            timestamp_ms: logging::timestamp_ms(),
+        })
+    }
+}
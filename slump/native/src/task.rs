@@ -0,0 +1,92 @@
+use crate::error::ErrorInfo;
+use napi::{
+    bindgen_prelude::{AsyncTask, ToNapiValue, TypeName},
+    Env, Task,
+};
+use std::cell::RefCell;
+
+thread_local! {
+    // Set by failure() on the pool thread running the call, taken by compute()
+    // once the call returns, the same way panic::catch picks up its report
+    static FAILURE: RefCell<Option<(String, ErrorInfo)>> = RefCell::new(None);
+}
+
+// The error for a failed call, remembering its code and context so the
+// promise rejects with them attached. Errors thrown synchronously have no
+// task to carry them and keep napi's status as their code.
+pub fn failure(status: napi::Status, reason: String, info: ErrorInfo) -> napi::Error {
+    FAILURE.with(|failure| *failure.borrow_mut() = Some((reason.clone(), info)));
+    napi::Error::new(status, reason)
+}
+
+// Runs a blocking call on the libuv thread pool, so opening devices and
+// network round trips don't stall the JS thread; the promise resolves to
+// the call's result
+pub struct Blocking<T> {
+    work: Option<Box<dyn FnOnce() -> napi::Result<T> + Send>>,
+    info: Option<ErrorInfo>,
+}
+
+impl<T: ToNapiValue + TypeName + Send + 'static> Blocking<T> {
+    pub fn spawn(work: impl FnOnce() -> napi::Result<T> + Send + 'static) -> AsyncTask<Self> {
+        AsyncTask::new(Self {
+            work: Some(Box::new(work)),
+            info: None,
+        })
+    }
+}
+
+impl<T: ToNapiValue + TypeName + Send + 'static> Task for Blocking<T> {
+    type Output = T;
+    type JsValue = T;
+
+    fn compute(&mut self) -> napi::Result<T> {
+        let result = match self.work.take() {
+            Some(work) => work(),
+            None => Err(napi::Error::new(
+                napi::Status::GenericFailure,
+                "Task already ran".to_string(),
+            )),
+        };
+        // Only if it describes the error actually returned, not an earlier one
+        let recorded = FAILURE.with(|failure| failure.borrow_mut().take());
+        if let (Err(e), Some((reason, info))) = (&result, recorded) {
+            self.info = (e.reason == reason).then_some(info);
+        }
+        result
+    }
+
+    fn resolve(&mut self, _env: Env, output: T) -> napi::Result<T> {
+        Ok(output)
+    }
+
+    fn reject(&mut self, env: Env, err: napi::Error) -> napi::Result<T> {
+        let Some(info) = self.info.take() else {
+            return Err(err);
+        };
+        let mut error = env.create_error(err)?;
+        error.set_named_property("code", env.create_string(info.code)?)?;
+        error.set_named_property("retryable", env.get_boolean(info.retryable)?)?;
+        if let Some(device) = info.device {
+            error.set_named_property("device", env.create_string(&device)?)?;
+        }
+        if let Some(peer_id) = info.peer_id {
+            error.set_named_property("peerId", env.create_string(&peer_id)?)?;
+        }
+        if let Some(hint) = info.hint {
+            error.set_named_property("hint", env.create_string(hint.as_str())?)?;
+        }
+        // The same shape validate_stream_options returns
+        if !info.violations.is_empty() {
+            let mut violations = env.create_array_with_length(info.violations.len())?;
+            for (index, violation) in info.violations.iter().enumerate() {
+                let mut entry = env.create_object()?;
+                entry.set_named_property("field", env.create_string(&violation.field)?)?;
+                entry.set_named_property("message", env.create_string(&violation.message)?)?;
+                violations.set_element(index as u32, entry)?;
+            }
+            error.set_named_property("violations", violations)?;
+        }
+        Err(napi::Error::from(error.into_unknown()))
+    }
+}
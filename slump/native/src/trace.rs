@@ -0,0 +1,213 @@
+use crate::error::{Result, SlumpError};
+use parking_lot::{const_mutex, Mutex};
+use serde_json::{json, Map, Value};
+use std::{
+    cell::Cell,
+    fmt,
+    fs::File,
+    io::BufWriter,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Once,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id},
+    subscriber::Interest,
+    Metadata, Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+// Bounds a long window on a busy pipeline; later spans are dropped
+const MAX_TRACE_EVENTS: usize = 1_000_000;
+
+// Spans are only recorded while a window is open; the rest of the time the
+// callsites are disabled and cost next to nothing
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+static EVENTS: Mutex<Vec<TraceEvent>> = const_mutex(Vec::new());
+static INSTALL: Once = Once::new();
+
+struct TraceEvent {
+    name: &'static str,
+    target: &'static str,
+    start: Instant,
+    duration: Duration,
+    thread: u64,
+    args: Map<String, Value>,
+}
+
+// Per-span bookkeeping kept in the registry's extensions
+struct SpanStart {
+    start: Instant,
+    thread: u64,
+    args: Map<String, Value>,
+}
+
+pub struct FieldVisitor<'a>(pub &'a mut Map<String, Value>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+// Chrome trace wants small numeric thread ids
+fn thread_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static ID: Cell<u64> = Cell::new(0);
+    }
+    ID.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT.fetch_add(1, Ordering::Relaxed));
+        }
+        id.get()
+    })
+}
+
+struct TraceLayer;
+
+impl<S> Layer<S> for TraceLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if metadata.is_span() {
+            Interest::sometimes()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _ctx: Context<'_, S>) -> bool {
+        metadata.is_span() && spans_wanted()
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut args = Map::new();
+        attrs.record(&mut FieldVisitor(&mut args));
+        span.extensions_mut().insert(SpanStart {
+            start: Instant::now(),
+            thread: thread_id(),
+            args,
+        });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(started) = span.extensions_mut().remove::<SpanStart>() else {
+            return;
+        };
+        if !CAPTURING.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut events = EVENTS.lock();
+        if events.len() < MAX_TRACE_EVENTS {
+            events.push(TraceEvent {
+                name: span.metadata().name(),
+                target: span.metadata().target(),
+                start: started.start,
+                duration: started.start.elapsed(),
+                thread: started.thread,
+                args: started.args,
+            });
+        }
+    }
+}
+
+// Each layer's `enabled` filters for all of them, so both ask the same
+pub fn spans_wanted() -> bool {
+    #[cfg(feature = "otlp")]
+    if crate::otlp::exporting() {
+        return true;
+    }
+    CAPTURING.load(Ordering::Relaxed)
+}
+
+pub fn install() {
+    INSTALL.call_once(|| {
+        let subscriber = tracing_subscriber::registry().with(TraceLayer);
+        #[cfg(feature = "otlp")]
+        let subscriber = subscriber.with(crate::otlp::SpanLayer);
+        if tracing::subscriber::set_global_default(subscriber).is_err() {
+            log::warn!("A tracing subscriber is already installed; traces will be empty");
+        }
+    });
+}
+
+// Records spans for `window`, then writes them to `path` in the Chrome trace
+// format, which Perfetto and chrome://tracing both open. Blocks for the window.
+pub fn record(path: &str, window: Duration) -> Result<usize> {
+    install();
+    if CAPTURING.swap(true, Ordering::SeqCst) {
+        return Err(SlumpError::Init("A trace is already being recorded".into()));
+    }
+
+    let origin = Instant::now();
+    EVENTS.lock().clear();
+    std::thread::sleep(window);
+    CAPTURING.store(false, Ordering::SeqCst);
+    let events = std::mem::take(&mut *EVENTS.lock());
+
+    let pid = std::process::id();
+    let trace_events: Vec<Value> = events
+        .into_iter()
+        // Spans opened before the window started are cut off rather than
+        // drawn with a negative start
+        .filter(|event| event.start >= origin)
+        .map(|event| {
+            json!({
+                "name": event.name,
+                "cat": event.target,
+                "ph": "X",
+                "ts": event.start.duration_since(origin).as_secs_f64() * 1e6,
+                "dur": event.duration.as_secs_f64() * 1e6,
+                "pid": pid,
+                "tid": event.thread,
+                "args": event.args,
+            })
+        })
+        .collect();
+    let count = trace_events.len();
+
+    let file = File::create(path)
+        .map_err(|e| SlumpError::Init(format!("Failed to create {}: {}", path, e)))?;
+    serde_json::to_writer(
+        BufWriter::new(file),
+        &json!({
+            "traceEvents": trace_events,
+            "displayTimeUnit": "ms",
+        }),
+    )
+    .map_err(|e| SlumpError::Init(format!("Failed to write {}: {}", path, e)))?;
+    Ok(count)
+}
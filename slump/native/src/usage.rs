@@ -0,0 +1,282 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "nvenc")]
+use libloading::Library;
+#[cfg(feature = "nvenc")]
+use std::os::raw::{c_int, c_uint, c_void};
+
+// CPU time the calling thread has used, user and kernel together
+#[cfg(unix)]
+pub fn thread_cpu_time() -> Option<Duration> {
+    clock_time(libc::CLOCK_THREAD_CPUTIME_ID)
+}
+
+// CPU time the whole process has used, summed over every core
+#[cfg(unix)]
+pub fn process_cpu_time() -> Option<Duration> {
+    clock_time(libc::CLOCK_PROCESS_CPUTIME_ID)
+}
+
+#[cfg(unix)]
+fn clock_time(clock: libc::clockid_t) -> Option<Duration> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(clock, &mut time) } != 0 {
+        return None;
+    }
+    Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+#[cfg(windows)]
+pub fn thread_cpu_time() -> Option<Duration> {
+    use windows::Win32::{
+        Foundation::FILETIME,
+        System::Threading::{GetCurrentThread, GetThreadTimes},
+    };
+
+    let [mut created, mut exited, mut kernel, mut user] = [FILETIME::default(); 4];
+    let ok = unsafe {
+        GetThreadTimes(
+            GetCurrentThread(),
+            &mut created,
+            &mut exited,
+            &mut kernel,
+            &mut user,
+        )
+    };
+    ok.as_bool()
+        .then(|| filetime_duration(kernel) + filetime_duration(user))
+}
+
+#[cfg(windows)]
+pub fn process_cpu_time() -> Option<Duration> {
+    use windows::Win32::{
+        Foundation::FILETIME,
+        System::Threading::{GetCurrentProcess, GetProcessTimes},
+    };
+
+    let [mut created, mut exited, mut kernel, mut user] = [FILETIME::default(); 4];
+    let ok = unsafe {
+        GetProcessTimes(
+            GetCurrentProcess(),
+            &mut created,
+            &mut exited,
+            &mut kernel,
+            &mut user,
+        )
+    };
+    ok.as_bool()
+        .then(|| filetime_duration(kernel) + filetime_duration(user))
+}
+
+// FILETIME counts 100 ns ticks
+#[cfg(windows)]
+fn filetime_duration(time: windows::Win32::Foundation::FILETIME) -> Duration {
+    let ticks = ((time.dwHighDateTime as u64) << 32) | time.dwLowDateTime as u64;
+    Duration::from_nanos(ticks * 100)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn process_cpu_time() -> Option<Duration> {
+    None
+}
+
+// CPU time one stage thread spends, summed until the reader takes it
+#[derive(Default)]
+pub struct ThreadCpu {
+    nanos: AtomicU64,
+}
+
+impl ThreadCpu {
+    pub fn record(&self, used: Duration) {
+        self.nanos
+            .fetch_add(used.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    // Percent of one core used over `elapsed`, since the last call
+    pub fn take_percent(&self, elapsed: Duration) -> f64 {
+        let nanos = self.nanos.swap(0, Ordering::Relaxed);
+        if elapsed.is_zero() {
+            return 0.0;
+        }
+        nanos as f64 / elapsed.as_nanos() as f64 * 100.0
+    }
+}
+
+// Only the stage threads themselves; threads the encoder starts internally
+// show up in the process figure alone
+#[derive(Default)]
+pub struct StageCpu {
+    pub capture: ThreadCpu,
+    pub encode: ThreadCpu,
+    pub send: ThreadCpu,
+}
+
+// Kept by a stage thread, which reads its own clock after each item
+pub struct CpuLap {
+    last: Option<Duration>,
+}
+
+impl CpuLap {
+    // Must be created on the thread it measures
+    pub fn start() -> Self {
+        Self {
+            last: thread_cpu_time(),
+        }
+    }
+
+    // CPU time used since the previous lap
+    pub fn lap(&mut self) -> Duration {
+        let now = thread_cpu_time();
+        let used = match (self.last, now) {
+            (Some(last), Some(now)) => now.saturating_sub(last),
+            _ => Duration::ZERO,
+        };
+        self.last = now;
+        used
+    }
+}
+
+// The whole process as a share of every core, so 100 means the machine is
+// busy with nothing but us
+pub struct ProcessCpu {
+    cores: f64,
+    last: Option<(Instant, Duration)>,
+}
+
+impl ProcessCpu {
+    pub fn new() -> Self {
+        let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+        Self {
+            cores: cores as f64,
+            last: process_cpu_time().map(|used| (Instant::now(), used)),
+        }
+    }
+
+    // None until two readings are apart, or where the OS can't tell
+    pub fn sample(&mut self) -> Option<f64> {
+        let now = (Instant::now(), process_cpu_time()?);
+        let previous = self.last.replace(now)?;
+        let elapsed = now.0.duration_since(previous.0);
+        if elapsed.is_zero() {
+            return None;
+        }
+        let used = now.1.saturating_sub(previous.1);
+        Some(used.as_secs_f64() / elapsed.as_secs_f64() / self.cores * 100.0)
+    }
+}
+
+#[cfg(feature = "nvenc")]
+const NVML_SUCCESS: c_int = 0;
+
+#[cfg(feature = "nvenc")]
+type NvmlDevice = *mut c_void;
+#[cfg(feature = "nvenc")]
+type NvmlInit = unsafe extern "C" fn() -> c_int;
+#[cfg(feature = "nvenc")]
+type NvmlShutdown = unsafe extern "C" fn() -> c_int;
+#[cfg(feature = "nvenc")]
+type NvmlDeviceGetHandleByIndex = unsafe extern "C" fn(c_uint, *mut NvmlDevice) -> c_int;
+#[cfg(feature = "nvenc")]
+type NvmlDeviceGetEncoderUtilization =
+    unsafe extern "C" fn(NvmlDevice, *mut c_uint, *mut c_uint) -> c_int;
+
+// NVENC load, read through NVML. The library comes with the NVIDIA driver and
+// is loaded at runtime, so a machine without one simply reports nothing.
+#[cfg(feature = "nvenc")]
+pub struct GpuEncoder {
+    device: NvmlDevice,
+    get_utilization: NvmlDeviceGetEncoderUtilization,
+    shutdown: NvmlShutdown,
+    // Keeps the function pointers above valid
+    _library: Library,
+}
+
+#[cfg(feature = "nvenc")]
+impl GpuEncoder {
+    // The first GPU, which is also the one FFmpeg's NVENC picks by default
+    pub fn open() -> Option<Self> {
+        let library = nvml_candidates()
+            .iter()
+            .find_map(|path| unsafe { Library::new(path) }.ok())?;
+        unsafe {
+            let init = *library.get::<NvmlInit>(b"nvmlInit_v2\0").ok()?;
+            let shutdown = *library.get::<NvmlShutdown>(b"nvmlShutdown\0").ok()?;
+            let get_handle = *library
+                .get::<NvmlDeviceGetHandleByIndex>(b"nvmlDeviceGetHandleByIndex_v2\0")
+                .ok()?;
+            let get_utilization = *library
+                .get::<NvmlDeviceGetEncoderUtilization>(b"nvmlDeviceGetEncoderUtilization\0")
+                .ok()?;
+            if init() != NVML_SUCCESS {
+                return None;
+            }
+            let mut device = std::ptr::null_mut();
+            if get_handle(0, &mut device) != NVML_SUCCESS {
+                shutdown();
+                return None;
+            }
+            Some(Self {
+                device,
+                get_utilization,
+                shutdown,
+                _library: library,
+            })
+        }
+    }
+
+    // Percent of NVML's last sampling period the encoder engine was busy,
+    // whoever was using it
+    pub fn utilization(&self) -> Option<f64> {
+        let mut utilization = 0;
+        let mut period_us = 0;
+        let status =
+            unsafe { (self.get_utilization)(self.device, &mut utilization, &mut period_us) };
+        (status == NVML_SUCCESS).then_some(utilization as f64)
+    }
+}
+
+#[cfg(feature = "nvenc")]
+impl Drop for GpuEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            (self.shutdown)();
+        }
+    }
+}
+
+#[cfg(feature = "nvenc")]
+fn nvml_candidates() -> &'static [&'static str] {
+    if cfg!(target_os = "windows") {
+        &["nvml.dll"]
+    } else if cfg!(target_os = "linux") {
+        &["libnvidia-ml.so.1", "libnvidia-ml.so"]
+    } else {
+        &[]
+    }
+}
+
+// Without NVENC there is no encoder engine to read
+#[cfg(not(feature = "nvenc"))]
+pub struct GpuEncoder;
+
+#[cfg(not(feature = "nvenc"))]
+impl GpuEncoder {
+    pub fn open() -> Option<Self> {
+        None
+    }
+
+    pub fn utilization(&self) -> Option<f64> {
+        None
+    }
+}
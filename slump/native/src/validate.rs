@@ -0,0 +1,66 @@
+use crate::error::{Result, SlumpError};
+use std::fmt;
+
+// One option that can't be used, named the way StreamOptions spells it
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+// Collects every problem with a set of options, so a caller fixes them in
+// one go rather than one failed start() at a time
+#[derive(Default)]
+pub struct Violations(Vec<Violation>);
+
+impl Violations {
+    pub fn add(&mut self, field: &str, message: impl Into<String>) {
+        self.0.push(Violation {
+            field: field.to_string(),
+            message: message.into(),
+        });
+    }
+
+    // Records a failed check under `field`; the value if it passed
+    pub fn check<T>(&mut self, field: &str, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.add(field, message(e));
+                None
+            }
+        }
+    }
+
+    pub fn into_result(self) -> Result<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(SlumpError::InvalidOptions(self.0))
+        }
+    }
+}
+
+// The checks report through Init; its "Initialization error" prefix says
+// nothing next to a field name
+fn message(e: SlumpError) -> String {
+    match e {
+        SlumpError::Init(message) => message,
+        SlumpError::InvalidOptions(violations) => describe(&violations),
+        e => e.to_string(),
+    }
+}
+
+pub fn describe(violations: &[Violation]) -> String {
+    violations
+        .iter()
+        .map(Violation::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
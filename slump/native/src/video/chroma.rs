@@ -0,0 +1,136 @@
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::{ffi, format::pixel::Pixel, software::scaling, Frame};
+use std::str::FromStr;
+
+// Chroma subsampling the capture chain runs in. 4:2:0 halves colour both
+// ways, which smears small coloured text such as syntax highlighting;
+// 4:2:2 keeps full vertical colour and 4:4:4 keeps all of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Chroma {
+    #[default]
+    Yuv420,
+    Yuv422,
+    Yuv444,
+}
+
+impl Chroma {
+    const ALL: [Chroma; 3] = [Chroma::Yuv420, Chroma::Yuv422, Chroma::Yuv444];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Chroma::Yuv420 => "420",
+            Chroma::Yuv422 => "422",
+            Chroma::Yuv444 => "444",
+        }
+    }
+
+    pub fn pixel(&self) -> Pixel {
+        self.av_format().into()
+    }
+
+    // None for anything but the three planar 8-bit layouts above
+    pub fn of(format: i32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|chroma| chroma.av_format() as i32 == format)
+    }
+
+    // log2 of how much smaller the chroma planes are, across and down
+    pub fn shift(&self) -> (u32, u32) {
+        match self {
+            Chroma::Yuv420 => (1, 1),
+            Chroma::Yuv422 => (1, 0),
+            Chroma::Yuv444 => (0, 0),
+        }
+    }
+
+    // Size of the chroma planes of a frame this size
+    pub fn plane_size(&self, width: i64, height: i64) -> (i64, i64) {
+        let (x, y) = self.shift();
+        ((width + (1 << x) - 1) >> x, (height + (1 << y) - 1) >> y)
+    }
+
+    fn av_format(&self) -> ffi::AVPixelFormat {
+        match self {
+            Chroma::Yuv420 => ffi::AVPixelFormat::AV_PIX_FMT_YUV420P,
+            Chroma::Yuv422 => ffi::AVPixelFormat::AV_PIX_FMT_YUV422P,
+            Chroma::Yuv444 => ffi::AVPixelFormat::AV_PIX_FMT_YUV444P,
+        }
+    }
+}
+
+impl FromStr for Chroma {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "420" => Ok(Chroma::Yuv420),
+            "422" => Ok(Chroma::Yuv422),
+            "444" => Ok(Chroma::Yuv444),
+            other => Err(SlumpError::Init(format!(
+                "Unknown chroma subsampling: {}",
+                other
+            ))),
+        }
+    }
+}
+
+// Conversion for encoders and sinks that only take one layout, such as VP8
+// and NDI's I420, when the capture runs in another, and for encodes that
+// keep the size they were opened with while the capture is rescaled. The
+// colorimetry is left as it is.
+#[derive(Default)]
+pub struct Resampler {
+    scaler: Option<(scaling::Context, (i32, u32, u32, u32, u32))>,
+}
+
+impl Resampler {
+    // A copy of `frame` in `format`, converted only if it isn't already
+    pub fn convert(&mut self, frame: &Frame, format: Pixel) -> Result<Frame> {
+        let (width, height) = unsafe { ((*frame.as_ptr()).width, (*frame.as_ptr()).height) };
+        self.scale(frame, format, width as u32, height as u32)
+    }
+
+    // A copy of `frame` in `format` at `width` x `height`, converted only if
+    // it isn't already
+    pub fn scale(
+        &mut self,
+        frame: &Frame,
+        format: Pixel,
+        width: u32,
+        height: u32,
+    ) -> Result<Frame> {
+        let (source, source_width, source_height) = unsafe {
+            let raw = &*frame.as_ptr();
+            (raw.format, raw.width as u32, raw.height as u32)
+        };
+        let target = ffi::AVPixelFormat::from(format) as i32;
+        if source == target && (source_width, source_height) == (width, height) {
+            return Ok(frame.clone());
+        }
+
+        let key = (source, source_width, source_height, width, height);
+        if self.scaler.as_ref().map(|(_, key)| *key) != Some(key) {
+            let source =
+                Pixel::from(unsafe { std::mem::transmute::<i32, ffi::AVPixelFormat>(source) });
+            let scaler = scaling::Context::get(
+                source,
+                source_width,
+                source_height,
+                format,
+                width,
+                height,
+                scaling::Flags::BILINEAR,
+            )?;
+            self.scaler = Some((scaler, key));
+        }
+
+        let mut converted = Frame::empty();
+        if let Some((scaler, _)) = self.scaler.as_mut() {
+            scaler.run(frame, &mut converted)?;
+        }
+        // Keeps the colorimetry tags along with the timestamps
+        unsafe { ffi::av_frame_copy_props(converted.as_mut_ptr(), frame.as_ptr()) };
+        Ok(converted)
+    }
+}
@@ -0,0 +1,199 @@
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::{encoder, ffi, format::pixel::Pixel, software::scaling, Frame};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    // What swscale and the SIMD path produce unasked, and what VP8 decoders assume
+    #[default]
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+impl ColorSpace {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorSpace::Bt601 => "bt601",
+            ColorSpace::Bt709 => "bt709",
+            ColorSpace::Bt2020 => "bt2020",
+        }
+    }
+
+    fn sws(&self) -> i32 {
+        (match self {
+            ColorSpace::Bt601 => ffi::SWS_CS_ITU601,
+            ColorSpace::Bt709 => ffi::SWS_CS_ITU709,
+            ColorSpace::Bt2020 => ffi::SWS_CS_BT2020,
+        }) as i32
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bt601" => Ok(ColorSpace::Bt601),
+            "bt709" => Ok(ColorSpace::Bt709),
+            "bt2020" => Ok(ColorSpace::Bt2020),
+            other => Err(SlumpError::Init(format!("Unknown color space: {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorRange {
+    // 16-235 luma, what players expect of video unless told otherwise
+    #[default]
+    Limited,
+    Full,
+}
+
+impl ColorRange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorRange::Limited => "limited",
+            ColorRange::Full => "full",
+        }
+    }
+}
+
+impl FromStr for ColorRange {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "limited" => Ok(ColorRange::Limited),
+            "full" => Ok(ColorRange::Full),
+            other => Err(SlumpError::Init(format!("Unknown color range: {}", other))),
+        }
+    }
+}
+
+// The matrix and range captures are converted to, and what encoders that
+// can carry it are told. Washed-out or crushed colours come from one side
+// assuming a different pair than the other used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Colorimetry {
+    pub space: ColorSpace,
+    pub range: ColorRange,
+}
+
+impl Colorimetry {
+    // A scaler from `source` into `format`, converting to this colorimetry.
+    // RGB sources are full range and carry no matrix; YUV ones are taken as
+    // BT.601, as webcams and pushed I420 usually are.
+    pub fn scaler(
+        &self,
+        source: (Pixel, u32, u32),
+        format: Pixel,
+        width: u32,
+        height: u32,
+        flags: scaling::Flags,
+    ) -> Result<scaling::Context> {
+        let (source_format, source_width, source_height) = source;
+        let mut scaler = scaling::Context::get(
+            source_format,
+            source_width,
+            source_height,
+            format,
+            width,
+            height,
+            flags,
+        )?;
+        unsafe {
+            let ret = ffi::sws_setColorspaceDetails(
+                scaler.as_mut_ptr(),
+                ffi::sws_getCoefficients(ColorSpace::Bt601.sws()),
+                is_full_range(source_format) as i32,
+                ffi::sws_getCoefficients(self.space.sws()),
+                (self.range == ColorRange::Full) as i32,
+                0,
+                1 << 16,
+                1 << 16,
+            );
+            if ret < 0 {
+                return Err(SlumpError::Video(format!(
+                    "Scaler can't convert to {} {} range",
+                    self.space.as_str(),
+                    self.range.as_str()
+                )));
+            }
+        }
+        Ok(scaler)
+    }
+
+    // Marks a converted frame, so raw sinks and encoders fed from it know
+    pub fn tag(&self, frame: &mut Frame) {
+        let (space, primaries, transfer) = self.tags();
+        unsafe {
+            let frame = &mut *frame.as_mut_ptr();
+            frame.colorspace = space;
+            frame.color_primaries = primaries;
+            frame.color_trc = transfer;
+            frame.color_range = self.av_range();
+        }
+    }
+
+    // Before open, so H.264/HEVC write it into the VUI and muxers into the
+    // container. libvpx's VP8 has nowhere to put it and ignores this.
+    pub fn apply(&self, encoder: &mut encoder::Video) {
+        let (space, primaries, transfer) = self.tags();
+        unsafe {
+            let context = &mut *encoder.as_mut_ptr();
+            context.colorspace = space;
+            context.color_primaries = primaries;
+            context.color_trc = transfer;
+            context.color_range = self.av_range();
+        }
+    }
+
+    fn tags(
+        &self,
+    ) -> (
+        ffi::AVColorSpace,
+        ffi::AVColorPrimaries,
+        ffi::AVColorTransferCharacteristic,
+    ) {
+        match self.space {
+            ColorSpace::Bt601 => (
+                ffi::AVColorSpace::AVCOL_SPC_SMPTE170M,
+                ffi::AVColorPrimaries::AVCOL_PRI_SMPTE170M,
+                ffi::AVColorTransferCharacteristic::AVCOL_TRC_SMPTE170M,
+            ),
+            ColorSpace::Bt709 => (
+                ffi::AVColorSpace::AVCOL_SPC_BT709,
+                ffi::AVColorPrimaries::AVCOL_PRI_BT709,
+                ffi::AVColorTransferCharacteristic::AVCOL_TRC_BT709,
+            ),
+            // SDR in the wide gamut; 8-bit output uses the 10-bit curve
+            ColorSpace::Bt2020 => (
+                ffi::AVColorSpace::AVCOL_SPC_BT2020_NCL,
+                ffi::AVColorPrimaries::AVCOL_PRI_BT2020,
+                ffi::AVColorTransferCharacteristic::AVCOL_TRC_BT2020_10,
+            ),
+        }
+    }
+
+    fn av_range(&self) -> ffi::AVColorRange {
+        match self.range {
+            ColorRange::Limited => ffi::AVColorRange::AVCOL_RANGE_MPEG,
+            ColorRange::Full => ffi::AVColorRange::AVCOL_RANGE_JPEG,
+        }
+    }
+}
+
+// RGB, and the deprecated yuvj formats MJPEG webcams decode to
+fn is_full_range(format: Pixel) -> bool {
+    if matches!(
+        format,
+        Pixel::YUVJ420P | Pixel::YUVJ422P | Pixel::YUVJ444P | Pixel::YUVJ440P | Pixel::YUVJ411P
+    ) {
+        return true;
+    }
+    unsafe {
+        let descriptor = ffi::av_pix_fmt_desc_get(format.into());
+        !descriptor.is_null() && (*descriptor).flags & ffi::AV_PIX_FMT_FLAG_RGB as u64 != 0
+    }
+}
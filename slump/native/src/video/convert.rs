@@ -0,0 +1,435 @@
+// Nothing below runs on other architectures, where available() is false
+#![cfg_attr(
+    not(any(target_arch = "x86_64", target_arch = "aarch64")),
+    allow(dead_code)
+)]
+
+use ffmpeg_next::{ffi, Frame};
+
+// BGRA to I420 without scaling, the conversion every same-size screen capture
+// needs. BT.601 limited range, matching what swscale produces by default, so
+// switching paths doesn't visibly shift colours. Chroma is taken from the sum
+// of each 2x2 block.
+//
+// Only used when a SIMD kernel is available; plain Rust is no faster than
+// swscale's own fallback.
+
+pub fn available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx2")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("neon")
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
+
+// Converts into `dst` when both frames fit this path; false leaves the work to swscale
+pub fn bgra_to_i420(src: &Frame, dst: &mut Frame) -> bool {
+    if !available() {
+        return false;
+    }
+    unsafe {
+        let s = &*src.as_ptr();
+        let d = &mut *dst.as_mut_ptr();
+        if s.format != ffi::AVPixelFormat::AV_PIX_FMT_BGRA as i32
+            || d.format != ffi::AVPixelFormat::AV_PIX_FMT_YUV420P as i32
+            || s.width != d.width
+            || s.height != d.height
+            || s.width <= 0
+            || s.height <= 0
+            || s.linesize[0] <= 0
+            || d.linesize[0] <= 0
+            || d.linesize[1] <= 0
+            || d.linesize[2] <= 0
+        {
+            return false;
+        }
+
+        let planes = Planes {
+            src: s.data[0],
+            src_stride: s.linesize[0] as usize,
+            y: d.data[0],
+            y_stride: d.linesize[0] as usize,
+            u: d.data[1],
+            u_stride: d.linesize[1] as usize,
+            v: d.data[2],
+            v_stride: d.linesize[2] as usize,
+            width: s.width as usize,
+            height: s.height as usize,
+        };
+
+        #[cfg(target_arch = "x86_64")]
+        avx2::convert(&planes);
+        #[cfg(target_arch = "aarch64")]
+        neon::convert(&planes);
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        let _ = planes;
+    }
+    true
+}
+
+struct Planes {
+    src: *const u8,
+    src_stride: usize,
+    y: *mut u8,
+    y_stride: usize,
+    u: *mut u8,
+    u_stride: usize,
+    v: *mut u8,
+    v_stride: usize,
+    width: usize,
+    height: usize,
+}
+
+#[inline]
+fn luma(b: i32, g: i32, r: i32) -> u8 {
+    (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8
+}
+
+// Sums over a 2x2 block, so the shift is two bits wider than for luma
+#[inline]
+fn chroma(b: i32, g: i32, r: i32) -> (u8, u8) {
+    let u = ((-38 * r - 74 * g + 112 * b + 512) >> 10) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 512) >> 10) + 128;
+    (u as u8, v as u8)
+}
+
+// Columns from `x` to the end of a row pair, for whatever the kernels leave
+// over. An odd last row is paired with itself.
+unsafe fn scalar_tail(p: &Planes, row: usize, x: usize) {
+    let next = (row + 1).min(p.height - 1);
+    let src0 = p.src.add(row * p.src_stride);
+    let src1 = p.src.add(next * p.src_stride);
+    let y0 = p.y.add(row * p.y_stride);
+    let y1 = p.y.add(next * p.y_stride);
+    let u = p.u.add(row / 2 * p.u_stride);
+    let v = p.v.add(row / 2 * p.v_stride);
+
+    let pixel = |src: *const u8, x: usize| {
+        let px = src.add(x * 4);
+        (*px as i32, *px.add(1) as i32, *px.add(2) as i32)
+    };
+    for x in x..p.width {
+        let (b, g, r) = pixel(src0, x);
+        *y0.add(x) = luma(b, g, r);
+        let (b, g, r) = pixel(src1, x);
+        *y1.add(x) = luma(b, g, r);
+    }
+    for cx in (x / 2)..p.width.div_ceil(2) {
+        let right = (cx * 2 + 1).min(p.width - 1);
+        let (mut b, mut g, mut r) = (0, 0, 0);
+        for (src, px) in [(src0, cx * 2), (src0, right), (src1, cx * 2), (src1, right)] {
+            let (pb, pg, pr) = pixel(src, px);
+            b += pb;
+            g += pg;
+            r += pr;
+        }
+        let (cu, cv) = chroma(b, g, r);
+        *u.add(cx) = cu;
+        *v.add(cx) = cv;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use super::{scalar_tail, Planes};
+    use std::arch::x86_64::*;
+
+    // Each 32-bit lane holds one BGRA pixel. Masking splits it into (B, R) and
+    // (G, A) pairs of 16-bit halves, so one madd applies two coefficients.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn pairs(pixels: __m256i) -> (__m256i, __m256i) {
+        let low_bytes = _mm256_set1_epi32(0x00ff_00ff);
+        (
+            _mm256_and_si256(pixels, low_bytes),
+            _mm256_and_si256(_mm256_srli_epi32(pixels, 8), low_bytes),
+        )
+    }
+
+    // Coefficients for (B, R) and (G, A), low half first
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn coefficients(b: i16, r: i16, g: i16) -> (__m256i, __m256i) {
+        (
+            _mm256_set1_epi32(((r as u16 as i32) << 16) | b as u16 as i32),
+            _mm256_set1_epi32(g as u16 as i32),
+        )
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn luma(pixels: __m256i) -> __m256i {
+        let (br, ga) = pairs(pixels);
+        let (cbr, cga) = coefficients(25, 66, 129);
+        let sum = _mm256_add_epi32(_mm256_madd_epi16(br, cbr), _mm256_madd_epi16(ga, cga));
+        _mm256_add_epi32(
+            _mm256_srai_epi32(_mm256_add_epi32(sum, _mm256_set1_epi32(128)), 8),
+            _mm256_set1_epi32(16),
+        )
+    }
+
+    // 16 luma values from two vectors of eight
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn store_luma(dst: *mut u8, a: __m256i, b: __m256i) {
+        // packs works per 128-bit lane; the permute puts the groups of four back in order
+        let packed = _mm256_packus_epi16(_mm256_packs_epi32(a, b), _mm256_setzero_si256());
+        let packed = _mm256_permutevar8x32_epi32(packed, _mm256_setr_epi32(0, 4, 1, 5, 2, 6, 3, 7));
+        _mm_storeu_si128(dst as *mut __m128i, _mm256_castsi256_si128(packed));
+    }
+
+    // Channel sums over each 2x2 block, in the even lanes
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn block_sums(top: __m256i, bottom: __m256i) -> (__m256i, __m256i) {
+        let (top_br, top_ga) = pairs(top);
+        let (bottom_br, bottom_ga) = pairs(bottom);
+        let br = _mm256_add_epi16(top_br, bottom_br);
+        let ga = _mm256_add_epi16(top_ga, bottom_ga);
+        (
+            _mm256_add_epi16(br, _mm256_shuffle_epi32(br, 0b10_11_00_01)),
+            _mm256_add_epi16(ga, _mm256_shuffle_epi32(ga, 0b10_11_00_01)),
+        )
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn chroma(sums: (__m256i, __m256i), b: i16, g: i16, r: i16) -> __m256i {
+        let (cbr, cga) = coefficients(b, r, g);
+        let sum = _mm256_add_epi32(
+            _mm256_madd_epi16(sums.0, cbr),
+            _mm256_madd_epi16(sums.1, cga),
+        );
+        _mm256_add_epi32(
+            _mm256_srai_epi32(_mm256_add_epi32(sum, _mm256_set1_epi32(512)), 10),
+            _mm256_set1_epi32(128),
+        )
+    }
+
+    // 8 chroma values from the even lanes of two vectors
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn store_chroma(dst: *mut u8, a: __m256i, b: __m256i) {
+        let evens = _mm256_setr_epi32(0, 2, 4, 6, 0, 2, 4, 6);
+        let joined = _mm256_permute2x128_si256(
+            _mm256_permutevar8x32_epi32(a, evens),
+            _mm256_permutevar8x32_epi32(b, evens),
+            0x20,
+        );
+        let packed =
+            _mm256_packus_epi16(_mm256_packs_epi32(joined, joined), _mm256_setzero_si256());
+        (dst as *mut i32).write_unaligned(_mm256_extract_epi32(packed, 0));
+        (dst.add(4) as *mut i32).write_unaligned(_mm256_extract_epi32(packed, 4));
+    }
+
+    // 16 pixels of two rows per step
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn convert(p: &Planes) {
+        let full = p.width / 16 * 16;
+        for row in (0..p.height).step_by(2) {
+            if row + 1 >= p.height {
+                scalar_tail(p, row, 0);
+                break;
+            }
+            let src0 = p.src.add(row * p.src_stride);
+            let src1 = p.src.add((row + 1) * p.src_stride);
+            let y0 = p.y.add(row * p.y_stride);
+            let y1 = p.y.add((row + 1) * p.y_stride);
+            let u = p.u.add(row / 2 * p.u_stride);
+            let v = p.v.add(row / 2 * p.v_stride);
+
+            for x in (0..full).step_by(16) {
+                let t0 = _mm256_loadu_si256(src0.add(x * 4) as *const __m256i);
+                let t1 = _mm256_loadu_si256(src0.add(x * 4 + 32) as *const __m256i);
+                let b0 = _mm256_loadu_si256(src1.add(x * 4) as *const __m256i);
+                let b1 = _mm256_loadu_si256(src1.add(x * 4 + 32) as *const __m256i);
+
+                store_luma(y0.add(x), luma(t0), luma(t1));
+                store_luma(y1.add(x), luma(b0), luma(b1));
+
+                let (sums0, sums1) = (block_sums(t0, b0), block_sums(t1, b1));
+                store_chroma(
+                    u.add(x / 2),
+                    chroma(sums0, 112, -74, -38),
+                    chroma(sums1, 112, -74, -38),
+                );
+                store_chroma(
+                    v.add(x / 2),
+                    chroma(sums0, -18, -94, 112),
+                    chroma(sums1, -18, -94, 112),
+                );
+            }
+            scalar_tail(p, row, full);
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::{scalar_tail, Planes};
+    use std::arch::aarch64::*;
+
+    // 16 pixels per step, deinterleaved into one register per channel
+    #[target_feature(enable = "neon")]
+    pub unsafe fn convert(p: &Planes) {
+        let luma = |px: uint8x16x4_t| {
+            let half = |b: uint8x8_t, g: uint8x8_t, r: uint8x8_t| {
+                let sum = vmull_u8(r, vdup_n_u8(66));
+                let sum = vmlal_u8(sum, g, vdup_n_u8(129));
+                let sum = vmlal_u8(sum, b, vdup_n_u8(25));
+                vrshrn_n_u16(sum, 8)
+            };
+            let low = half(vget_low_u8(px.0), vget_low_u8(px.1), vget_low_u8(px.2));
+            let high = half(vget_high_u8(px.0), vget_high_u8(px.1), vget_high_u8(px.2));
+            vaddq_u8(vcombine_u8(low, high), vdupq_n_u8(16))
+        };
+        let chroma = |b: int16x8_t, g: int16x8_t, r: int16x8_t, cb: i16, cg: i16, cr: i16| {
+            // Sums reach 1020, so the products are widened to 32 bits
+            let half = |b: int16x4_t, g: int16x4_t, r: int16x4_t| {
+                let sum = vmull_n_s16(b, cb);
+                let sum = vmlal_n_s16(sum, g, cg);
+                let sum = vmlal_n_s16(sum, r, cr);
+                vshrq_n_s32(vaddq_s32(sum, vdupq_n_s32(512)), 10)
+            };
+            let low = half(vget_low_s16(b), vget_low_s16(g), vget_low_s16(r));
+            let high = half(vget_high_s16(b), vget_high_s16(g), vget_high_s16(r));
+            let wide = vaddq_s16(
+                vcombine_s16(vmovn_s32(low), vmovn_s32(high)),
+                vdupq_n_s16(128),
+            );
+            vqmovun_s16(wide)
+        };
+
+        let full = p.width / 16 * 16;
+        for row in (0..p.height).step_by(2) {
+            if row + 1 >= p.height {
+                scalar_tail(p, row, 0);
+                break;
+            }
+            let src0 = p.src.add(row * p.src_stride);
+            let src1 = p.src.add((row + 1) * p.src_stride);
+            let y0 = p.y.add(row * p.y_stride);
+            let y1 = p.y.add((row + 1) * p.y_stride);
+            let u = p.u.add(row / 2 * p.u_stride);
+            let v = p.v.add(row / 2 * p.v_stride);
+
+            for x in (0..full).step_by(16) {
+                let top = vld4q_u8(src0.add(x * 4));
+                let bottom = vld4q_u8(src1.add(x * 4));
+                vst1q_u8(y0.add(x), luma(top));
+                vst1q_u8(y1.add(x), luma(bottom));
+
+                // Pairwise adds give the sum of each 2x2 block
+                let sum = |a: uint8x16_t, b: uint8x16_t| {
+                    vreinterpretq_s16_u16(vpadalq_u8(vpaddlq_u8(a), b))
+                };
+                let (b, g, r) = (
+                    sum(top.0, bottom.0),
+                    sum(top.1, bottom.1),
+                    sum(top.2, bottom.2),
+                );
+                vst1_u8(u.add(x / 2), chroma(b, g, r, 112, -74, -38));
+                vst1_u8(v.add(x / 2), chroma(b, g, r, -18, -94, 112));
+            }
+            scalar_tail(p, row, full);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scalar_tail, Planes};
+
+    struct Buffers {
+        src: Vec<u8>,
+        y: Vec<u8>,
+        u: Vec<u8>,
+        v: Vec<u8>,
+    }
+
+    impl Buffers {
+        // Strides padded past the row, as ffmpeg's are
+        fn new(width: usize, height: usize, seed: u32) -> Self {
+            let mut state = seed;
+            let src = (0..(width * 4 + 12) * height)
+                .map(|_| {
+                    // xorshift32
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                })
+                .collect();
+            let chroma = (width.div_ceil(2) + 5) * height.div_ceil(2);
+            Self {
+                src,
+                y: vec![0; (width + 7) * height],
+                u: vec![0; chroma],
+                v: vec![0; chroma],
+            }
+        }
+
+        fn planes(&mut self, width: usize, height: usize) -> Planes {
+            Planes {
+                src: self.src.as_ptr(),
+                src_stride: width * 4 + 12,
+                y: self.y.as_mut_ptr(),
+                y_stride: width + 7,
+                u: self.u.as_mut_ptr(),
+                u_stride: width.div_ceil(2) + 5,
+                v: self.v.as_mut_ptr(),
+                v_stride: width.div_ceil(2) + 5,
+                width,
+                height,
+            }
+        }
+    }
+
+    // Every size mixes full 16-pixel steps, a scalar tail or an odd last row
+    const SIZES: [(usize, usize); 7] =
+        [(17, 3), (33, 1), (16, 2), (1, 1), (15, 4), (64, 5), (47, 9)];
+
+    fn check(kernel: unsafe fn(&Planes)) {
+        for (seed, &(width, height)) in SIZES.iter().enumerate() {
+            let seed = 0x9e37_79b9 ^ seed as u32;
+            let mut expected = Buffers::new(width, height, seed);
+            let mut actual = Buffers::new(width, height, seed);
+            unsafe {
+                let p = expected.planes(width, height);
+                for row in (0..height).step_by(2) {
+                    scalar_tail(&p, row, 0);
+                }
+                kernel(&actual.planes(width, height));
+            }
+            let size = format!("{}x{}", width, height);
+            assert_eq!(actual.y, expected.y, "luma differs at {}", size);
+            assert_eq!(actual.u, expected.u, "U differs at {}", size);
+            assert_eq!(actual.v, expected.v, "V differs at {}", size);
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn avx2_matches_scalar() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        check(super::avx2::convert);
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn neon_matches_scalar() {
+        if !std::arch::is_aarch64_feature_detected!("neon") {
+            return;
+        }
+        check(super::neon::convert);
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant};
+
+// An unchanged cursor is sent again this often, for viewers that joined
+// since or lost a message
+const CURSOR_REFRESH: Duration = Duration::from_secs(1);
+
+// Where the pointer is on the captured display, for viewers that draw their
+// own cursor instead of waiting for it to show up in the video
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CursorState {
+    // 0-1 across and down the display
+    pub x: f64,
+    pub y: f64,
+    // False while hidden, e.g. during video playback, or on another display
+    pub visible: bool,
+    // A CSS cursor name, so browsers can show it as is
+    pub shape: &'static str,
+}
+
+impl CursorState {
+    // What goes out on the control channel; `frame` is the capture tick it
+    // was read on, matching captions and the burn-in
+    pub fn message(&self, frame: u64) -> String {
+        serde_json::json!({
+            "type": "cursor",
+            "x": self.x,
+            "y": self.y,
+            "visible": self.visible,
+            "shape": self.shape,
+            "frame": frame,
+        })
+        .to_string()
+    }
+}
+
+// Polled once per capture tick; only changes are worth sending
+#[derive(Debug, Default)]
+pub struct CursorTracker {
+    last: Option<(CursorState, Instant)>,
+}
+
+impl CursorTracker {
+    pub fn poll(&mut self, display_index: usize) -> Option<CursorState> {
+        let state = cursor_state(display_index)?;
+        let unchanged = self
+            .last
+            .is_some_and(|(last, sent)| last == state && sent.elapsed() < CURSOR_REFRESH);
+        if unchanged {
+            return None;
+        }
+        self.last = Some((state, Instant::now()));
+        Some(state)
+    }
+}
+
+#[cfg(windows)]
+fn cursor_state(display_index: usize) -> Option<CursorState> {
+    use windows::Win32::UI::WindowsAndMessaging::{GetCursorInfo, CURSORINFO, CURSOR_SHOWING};
+
+    let mut info = CURSORINFO {
+        cbSize: std::mem::size_of::<CURSORINFO>() as u32,
+        ..Default::default()
+    };
+    if !unsafe { GetCursorInfo(&mut info) }.as_bool() {
+        return None;
+    }
+    let (left, top, width, height) = super::display_bounds(display_index)?;
+    let x = (info.ptScreenPos.x - left) as f64 / width.max(1) as f64;
+    let y = (info.ptScreenPos.y - top) as f64 / height.max(1) as f64;
+    let on_display = (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y);
+    Some(CursorState {
+        x: x.clamp(0.0, 1.0),
+        y: y.clamp(0.0, 1.0),
+        visible: info.flags.0 & CURSOR_SHOWING.0 != 0 && on_display,
+        shape: shape_name(info.hCursor.0),
+    })
+}
+
+// Custom cursors an application draws itself have no CSS equivalent and
+// show as the arrow
+#[cfg(windows)]
+fn shape_name(cursor: isize) -> &'static str {
+    use std::sync::OnceLock;
+    use windows::Win32::UI::WindowsAndMessaging::{
+        LoadCursorW, IDC_APPSTARTING, IDC_ARROW, IDC_CROSS, IDC_HAND, IDC_HELP, IDC_IBEAM, IDC_NO,
+        IDC_SIZEALL, IDC_SIZENESW, IDC_SIZENS, IDC_SIZENWSE, IDC_SIZEWE, IDC_WAIT,
+    };
+
+    static SYSTEM: OnceLock<Vec<(isize, &'static str)>> = OnceLock::new();
+    let system = SYSTEM.get_or_init(|| {
+        [
+            (IDC_ARROW, "default"),
+            (IDC_IBEAM, "text"),
+            (IDC_HAND, "pointer"),
+            (IDC_WAIT, "wait"),
+            (IDC_APPSTARTING, "progress"),
+            (IDC_CROSS, "crosshair"),
+            (IDC_SIZEALL, "move"),
+            (IDC_SIZEWE, "ew-resize"),
+            (IDC_SIZENS, "ns-resize"),
+            (IDC_SIZENWSE, "nwse-resize"),
+            (IDC_SIZENESW, "nesw-resize"),
+            (IDC_NO, "not-allowed"),
+            (IDC_HELP, "help"),
+        ]
+        .into_iter()
+        .filter_map(|(id, name)| Some((unsafe { LoadCursorW(None, id) }.ok()?.0, name)))
+        .collect()
+    });
+    system
+        .iter()
+        .find(|(handle, _)| *handle == cursor)
+        .map_or("default", |(_, name)| name)
+}
+
+// No cursor query on other platforms yet, so nothing is sent there
+#[cfg(not(windows))]
+fn cursor_state(_display_index: usize) -> Option<CursorState> {
+    None
+}
@@ -0,0 +1,114 @@
+use ffmpeg_next::{ffi, Frame};
+use std::time::{Duration, Instant};
+
+// Multiplier from FxHash; the hash only has to tell consecutive frames apart
+const MIX: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Encode,
+    Skip,
+    // A refresh of static content that should be coded as a keyframe
+    Keyframe,
+}
+
+// Spots frames identical to the one before, so a static screen stops costing
+// encode time and bitrate. Every `refresh` a frame goes out regardless, which
+// keeps the encoder's rate control and the viewers' jitter buffers ticking.
+// With a keyframe interval, a refresh is made a keyframe once the content has
+// been static that long, so late joiners and receivers that lost packets
+// recover without waiting for the screen to change.
+pub struct FrameDedup {
+    refresh: Duration,
+    keyframe_interval: Option<Duration>,
+    last_hash: Option<u64>,
+    last_sent: Instant,
+    // Since the content stopped changing, or since the last static keyframe
+    static_since: Option<Instant>,
+}
+
+impl FrameDedup {
+    pub fn new(refresh: Duration, keyframe_interval: Option<Duration>) -> Self {
+        Self {
+            refresh,
+            keyframe_interval,
+            last_hash: None,
+            last_sent: Instant::now(),
+            static_since: None,
+        }
+    }
+
+    // Frames that can't be hashed are always encoded
+    pub fn check(&mut self, frame: &Frame) -> Decision {
+        let hash = unsafe { hash(frame) };
+        let now = Instant::now();
+        if hash.is_none() || hash != self.last_hash {
+            self.last_hash = hash;
+            self.last_sent = now;
+            self.static_since = None;
+            return Decision::Encode;
+        }
+
+        let static_since = *self.static_since.get_or_insert(now);
+        if now.duration_since(self.last_sent) < self.refresh {
+            return Decision::Skip;
+        }
+        self.last_sent = now;
+        match self.keyframe_interval {
+            Some(interval) if now.duration_since(static_since) >= interval => {
+                self.static_since = Some(now);
+                Decision::Keyframe
+            }
+            _ => Decision::Encode,
+        }
+    }
+}
+
+// Hashes the visible bytes of every plane, leaving out row padding, which
+// FFmpeg does not keep stable between frames
+unsafe fn hash(frame: &Frame) -> Option<u64> {
+    let raw = &*frame.as_ptr();
+    if raw.format < 0 || raw.width <= 0 || raw.height <= 0 {
+        return None;
+    }
+    let format: ffi::AVPixelFormat = std::mem::transmute(raw.format);
+    let desc = ffi::av_pix_fmt_desc_get(format);
+    let mut row_bytes = [0i32; 4];
+    if desc.is_null() || ffi::av_image_fill_linesizes(row_bytes.as_mut_ptr(), format, raw.width) < 0
+    {
+        return None;
+    }
+
+    let height = raw.height as usize;
+    let chroma_height = -((-(height as i64)) >> (*desc).log2_chroma_h) as usize;
+    let mut hash = mix(
+        raw.width as u64,
+        raw.height as u64 ^ (raw.format as u64) << 32,
+    );
+    for plane in 0..4 {
+        if raw.data[plane].is_null() || row_bytes[plane] <= 0 || raw.linesize[plane] <= 0 {
+            break;
+        }
+        let rows = if plane == 1 || plane == 2 {
+            chroma_height
+        } else {
+            height
+        };
+        for row in 0..rows {
+            let start = raw.data[plane].add(row * raw.linesize[plane] as usize);
+            let bytes = std::slice::from_raw_parts(start, row_bytes[plane] as usize);
+            let mut words = bytes.chunks_exact(8);
+            for word in &mut words {
+                hash = mix(hash, u64::from_le_bytes(word.try_into().unwrap()));
+            }
+            for &byte in words.remainder() {
+                hash = mix(hash, byte as u64);
+            }
+        }
+    }
+    Some(hash)
+}
+
+fn mix(hash: u64, word: u64) -> u64 {
+    (hash.rotate_left(5) ^ word).wrapping_mul(MIX)
+}
@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+// Cheap to ask, but there's no need to every frame
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+// UAC prompts, Ctrl+Alt+Del and the lock screen switch input to a desktop
+// that only SYSTEM can capture; until it goes away, display capture returns
+// black frames or fails outright
+#[derive(Debug, Default)]
+pub struct SecureDesktop {
+    active: bool,
+    checked: Option<Instant>,
+}
+
+impl SecureDesktop {
+    // Some when the secure desktop came up or went away since the last call
+    pub fn poll(&mut self) -> Option<bool> {
+        if self
+            .checked
+            .is_some_and(|checked| checked.elapsed() < CHECK_INTERVAL)
+        {
+            return None;
+        }
+        self.checked = Some(Instant::now());
+        let active = secure_desktop_active();
+        (std::mem::replace(&mut self.active, active) != active).then_some(active)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+#[cfg(windows)]
+fn secure_desktop_active() -> bool {
+    use windows::Win32::{
+        Foundation::HANDLE,
+        System::StationsAndDesktops::{
+            CloseDesktop, GetUserObjectInformationW, OpenInputDesktop, DESKTOP_CONTROL_FLAGS,
+            DESKTOP_READOBJECTS, UOI_NAME,
+        },
+    };
+
+    unsafe {
+        // Outside SYSTEM the secure desktop can't even be opened
+        let Ok(desktop) = OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_READOBJECTS)
+        else {
+            return true;
+        };
+        let mut name = [0u16; 64];
+        let read = GetUserObjectInformationW(
+            HANDLE(desktop.0),
+            UOI_NAME,
+            Some(name.as_mut_ptr() as *mut _),
+            std::mem::size_of_val(&name) as u32,
+            None,
+        )
+        .as_bool();
+        let _ = CloseDesktop(desktop);
+        if !read {
+            return false;
+        }
+        // The user's own desktop is "Default"; the secure one is "Winlogon"
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        !String::from_utf16_lossy(&name[..len]).eq_ignore_ascii_case("Default")
+    }
+}
+
+// Only Windows has a secure desktop to capture around
+#[cfg(not(windows))]
+fn secure_desktop_active() -> bool {
+    false
+}
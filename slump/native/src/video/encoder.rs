@@ -0,0 +1,159 @@
+use super::Resampler;
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::{codec, format::pixel::Pixel, Dictionary, Frame, Packet};
+use std::str::FromStr;
+
+// Codecs the live WebRTC track can be negotiated with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoCodec {
+    #[default]
+    Vp8,
+}
+
+impl FromStr for VideoCodec {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "vp8" => Ok(VideoCodec::Vp8),
+            other => Err(SlumpError::Init(format!("Unsupported video codec: {}", other))),
+        }
+    }
+}
+
+impl VideoCodec {
+    pub fn encoder_name(&self) -> &'static str {
+        match self {
+            VideoCodec::Vp8 => "libvpx",
+        }
+    }
+
+    // Whether this FFmpeg build has the encoder; whether it opens is for probe()
+    pub fn is_available(&self) -> bool {
+        ffmpeg_next::encoder::find_by_name(self.encoder_name()).is_some()
+    }
+}
+
+pub struct VideoEncoder {
+    encoder: codec::encoder::Video,
+    width: u32,
+    height: u32,
+    fps: u32,
+    bitrate_kbps: u32,
+    frame_index: i64,
+    keyframe_requested: bool,
+    // Size of the last encoded frame, so the next buffer rarely has to grow
+    last_size: usize,
+    // VP8 is 4:2:0 only; captures in 4:2:2 or 4:4:4 are brought down here
+    resampler: Resampler,
+}
+
+// Like VideoCapture, only ever used by the one thread holding it
+unsafe impl Send for VideoEncoder {}
+
+impl VideoEncoder {
+    // Pixel format captures are scaled to unless a chroma option says otherwise
+    pub const PIXEL_FORMAT: Pixel = Pixel::YUV420P;
+
+    pub fn new(width: u32, height: u32, fps: u32, bitrate_kbps: u32) -> Result<Self> {
+        let encoder = Self::open(width, height, fps, bitrate_kbps)?;
+
+        Ok(Self {
+            encoder,
+            width,
+            height,
+            fps,
+            bitrate_kbps,
+            frame_index: 0,
+            keyframe_requested: false,
+            last_size: 0,
+            resampler: Resampler::default(),
+        })
+    }
+
+    fn open(width: u32, height: u32, fps: u32, bitrate_kbps: u32) -> Result<codec::encoder::Video> {
+        let codec = ffmpeg_next::encoder::find_by_name(VideoCodec::Vp8.encoder_name())
+            .ok_or_else(|| SlumpError::Video("VP8 encoder not available".into()))?;
+
+        let mut encoder = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()?;
+        encoder.set_width(width);
+        encoder.set_height(height);
+        encoder.set_format(Self::PIXEL_FORMAT);
+        encoder.set_time_base((1, fps as i32));
+        encoder.set_frame_rate(Some((fps as i32, 1)));
+        encoder.set_bit_rate(bitrate_kbps as usize * 1000);
+        encoder.set_max_bit_rate(bitrate_kbps as usize * 1000);
+        encoder.set_gop(fps * 2);
+        encoder.set_max_b_frames(0);
+
+        // Realtime settings: no lookahead, fastest preset
+        let mut options = Dictionary::new();
+        options.set("deadline", "realtime");
+        options.set("cpu-used", "8");
+        options.set("lag-in-frames", "0");
+        options.set("error-resilient", "1");
+
+        Ok(encoder.open_with(options)?)
+    }
+
+    pub fn bitrate_kbps(&self) -> u32 {
+        self.bitrate_kbps
+    }
+
+    // libvpx can't retarget a running context, so reopen with the new budget
+    pub fn set_bitrate(&mut self, bitrate_kbps: u32) -> Result<()> {
+        if bitrate_kbps == self.bitrate_kbps {
+            return Ok(());
+        }
+
+        self.encoder = Self::open(self.width, self.height, self.fps, bitrate_kbps)?;
+        self.bitrate_kbps = bitrate_kbps;
+        Ok(())
+    }
+
+    // The next frame is coded as a keyframe, so receivers that lost data can resync
+    pub fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    pub fn encode(&mut self, frame: &Frame) -> Result<Option<Vec<u8>>> {
+        let _span = tracing::trace_span!("encode", codec = "vp8", bitrate_kbps = self.bitrate_kbps).entered();
+        if frame.width() != self.width || frame.height() != self.height {
+            self.encoder = Self::open(frame.width(), frame.height(), self.fps, self.bitrate_kbps)?;
+            self.width = frame.width();
+            self.height = frame.height();
+        }
+
+        let mut frame = self.resampler.convert(frame, Self::PIXEL_FORMAT)?;
+        frame.set_pts(Some(self.frame_index));
+        self.frame_index += 1;
+        if std::mem::take(&mut self.keyframe_requested) {
+            unsafe {
+                (*frame.as_mut_ptr()).pict_type = ffmpeg_next::ffi::AVPictureType::AV_PICTURE_TYPE_I;
+            }
+        }
+
+        self.encoder.send_frame(&frame)?;
+
+        let mut encoded = Vec::with_capacity(self.last_size);
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            if let Some(data) = packet.data() {
+                encoded.extend_from_slice(data);
+            }
+        }
+
+        if !encoded.is_empty() {
+            self.last_size = encoded.len();
+        }
+        Ok((!encoded.is_empty()).then_some(encoded))
+    }
+}
+
+impl Drop for VideoEncoder {
+    fn drop(&mut self) {
+        let _ = self.encoder.send_eof();
+    }
+}
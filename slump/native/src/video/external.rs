@@ -0,0 +1,124 @@
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::{ffi, format::Pixel, util::frame};
+use std::{ptr, str::FromStr};
+
+// Where the video track's frames come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoSource {
+    #[default]
+    Screen,
+    // Frames the application pushes itself, e.g. a canvas or game engine render
+    External,
+    // A game's own frames, taken from its present call by an injected hook;
+    // lower latency than display capture and sees exclusive fullscreen
+    Game,
+}
+
+impl FromStr for VideoSource {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "screen" => Ok(VideoSource::Screen),
+            "external" => Ok(VideoSource::External),
+            "game" => Ok(VideoSource::Game),
+            other => Err(SlumpError::Init(format!("Unknown video source: {}", other))),
+        }
+    }
+}
+
+// Layouts an application is likely to have at hand; names match FFmpeg's
+fn parse_format(name: &str) -> Result<Pixel> {
+    match name {
+        "rgba" => Ok(Pixel::RGBA),
+        "bgra" => Ok(Pixel::BGRA),
+        "rgb24" => Ok(Pixel::RGB24),
+        "yuv420p" => Ok(Pixel::YUV420P),
+        "nv12" => Ok(Pixel::NV12),
+        other => Err(SlumpError::Video(format!(
+            "Unsupported pixel format: {}",
+            other
+        ))),
+    }
+}
+
+// Holds the newest pushed frame until the capture tick picks it up. Pushing
+// faster than the stream's fps replaces frames that were never encoded.
+#[derive(Default)]
+pub struct ExternalFrames {
+    pending: Option<frame::Video>,
+    last_timestamp: Option<i64>,
+}
+
+impl ExternalFrames {
+    // data is tightly packed, planes back to back with no row padding. False
+    // if the frame is older than one already accepted.
+    pub fn push(
+        &mut self,
+        data: &[u8],
+        format: &str,
+        width: u32,
+        height: u32,
+        timestamp_ms: i64,
+    ) -> Result<bool> {
+        let pixel = parse_format(format)?;
+        if width == 0 || height == 0 {
+            return Err(SlumpError::Video(format!(
+                "Frame size must be non-zero, got {}x{}",
+                width, height
+            )));
+        }
+        if self.last_timestamp.is_some_and(|last| timestamp_ms < last) {
+            return Ok(false);
+        }
+
+        let pix_fmt = ffi::AVPixelFormat::from(pixel);
+        let expected =
+            unsafe { ffi::av_image_get_buffer_size(pix_fmt, width as i32, height as i32, 1) };
+        if expected < 0 || data.len() != expected as usize {
+            return Err(SlumpError::Video(format!(
+                "Expected {} bytes for a {}x{} {} frame, got {}",
+                expected,
+                width,
+                height,
+                format,
+                data.len()
+            )));
+        }
+
+        // The frame's own planes are aligned, so rows are copied across
+        let mut frame = frame::Video::new(pixel, width, height);
+        unsafe {
+            let mut src_data = [ptr::null_mut(); 4];
+            let mut src_linesize = [0; 4];
+            ffi::av_image_fill_arrays(
+                src_data.as_mut_ptr(),
+                src_linesize.as_mut_ptr(),
+                data.as_ptr(),
+                pix_fmt,
+                width as i32,
+                height as i32,
+                1,
+            );
+            let raw = &mut *frame.as_mut_ptr();
+            ffi::av_image_copy(
+                raw.data.as_mut_ptr(),
+                raw.linesize.as_mut_ptr(),
+                src_data.as_mut_ptr() as *mut *const u8,
+                src_linesize.as_ptr(),
+                pix_fmt,
+                width as i32,
+                height as i32,
+            );
+        }
+        frame.set_pts(Some(timestamp_ms));
+
+        self.pending = Some(frame);
+        self.last_timestamp = Some(timestamp_ms);
+        Ok(true)
+    }
+
+    pub fn take(&mut self) -> Option<frame::Video> {
+        self.pending.take()
+    }
+}
@@ -0,0 +1,344 @@
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::util::frame;
+use std::path::PathBuf;
+
+// Which game to hook, and with what. Experimental, behind the game-capture
+// feature: the hook library isn't part of this crate, it's built separately
+// against the protocol below and has to match the game's bitness.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameTarget {
+    // A process id, or an executable name such as "game.exe"
+    pub process: String,
+    pub hook: PathBuf,
+}
+
+// Frames from a hooked game's present call. They arrive at the game's own
+// pace, and not at all while it is minimised or loading.
+pub struct GameFrames {
+    #[cfg(all(windows, feature = "game-capture"))]
+    inner: windows_impl::Hooked,
+}
+
+impl GameFrames {
+    // Loads the hook into the game; frames follow once it next presents
+    #[cfg(all(windows, feature = "game-capture"))]
+    pub fn attach(target: &GameTarget) -> Result<Self> {
+        Ok(Self {
+            inner: windows_impl::Hooked::attach(target)?,
+        })
+    }
+
+    #[cfg(not(all(windows, feature = "game-capture")))]
+    pub fn attach(_target: &GameTarget) -> Result<Self> {
+        Err(SlumpError::NotImplemented(
+            "Game capture needs a Windows build with the game-capture feature".into(),
+        ))
+    }
+
+    // The newest frame the game presented since the last call
+    #[cfg(all(windows, feature = "game-capture"))]
+    pub fn take(&mut self) -> Result<Option<frame::Video>> {
+        self.inner.take()
+    }
+
+    #[cfg(not(all(windows, feature = "game-capture")))]
+    pub fn take(&mut self) -> Result<Option<frame::Video>> {
+        Ok(None)
+    }
+}
+
+#[cfg(all(windows, feature = "game-capture"))]
+mod windows_impl {
+    use super::GameTarget;
+    use crate::error::{Result, SlumpError};
+    use ffmpeg_next::{format::Pixel, util::frame};
+    use std::{
+        os::windows::ffi::OsStrExt,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+    use windows::{
+        core::{PCSTR, PCWSTR},
+        Win32::{
+            Foundation::{CloseHandle, ERROR_ACCESS_DENIED, HANDLE},
+            System::{
+                Diagnostics::{
+                    Debug::WriteProcessMemory,
+                    ToolHelp::{
+                        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+                        TH32CS_SNAPPROCESS,
+                    },
+                },
+                LibraryLoader::{GetModuleHandleW, GetProcAddress},
+                Memory::{
+                    MapViewOfFile, OpenFileMappingW, UnmapViewOfFile, VirtualAllocEx,
+                    VirtualFreeEx, FILE_MAP_READ, MEMORYMAPPEDVIEW_ADDRESS, MEM_COMMIT,
+                    MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE,
+                },
+                Threading::{
+                    CreateRemoteThread, GetExitCodeThread, OpenProcess, WaitForSingleObject,
+                    PROCESS_CREATE_THREAD, PROCESS_QUERY_INFORMATION, PROCESS_VM_OPERATION,
+                    PROCESS_VM_WRITE,
+                },
+            },
+        },
+    };
+
+    // LoadLibraryW runs the hook's DllMain; anything slower is stuck
+    const LOAD_TIMEOUT_MS: u32 = 5000;
+
+    // What the hook library and this side agree on. Once loaded into the
+    // game, the hook patches the present call of whichever API it renders
+    // with (IDXGISwapChain::Present for D3D11 and D3D12, vkQueuePresentKHR,
+    // wglSwapBuffers), copies the back buffer out before composition, and
+    // publishes it in a file mapping named `Local\slump-game-<pid>`: a
+    // Header followed by `capacity` bytes of pixels, `pitch` bytes per row.
+    const MAPPING_PREFIX: &str = "Local\\slump-game-";
+    const MAGIC: u32 = u32::from_le_bytes(*b"SLGH");
+    const VERSION: u32 = 1;
+
+    #[repr(C)]
+    struct Header {
+        magic: u32,
+        version: u32,
+        // Odd while the hook is writing a frame, bumped again when it's done
+        sequence: AtomicU32,
+        width: u32,
+        height: u32,
+        pitch: u32,
+        // 0 for BGRA, 1 for RGBA
+        format: u32,
+        capacity: u32,
+    }
+
+    pub struct Hooked {
+        pid: u32,
+        mapping: Option<(HANDLE, MEMORYMAPPEDVIEW_ADDRESS)>,
+        last_sequence: u32,
+    }
+
+    // The view is only read from the capture thread that owns it
+    unsafe impl Send for Hooked {}
+
+    impl Hooked {
+        pub fn attach(target: &GameTarget) -> Result<Self> {
+            let pid = find_process(&target.process)?;
+            inject(pid, target)?;
+            tracing::info!("Hooked {} (pid {}) for game capture", target.process, pid);
+            Ok(Self {
+                pid,
+                mapping: None,
+                last_sequence: 0,
+            })
+        }
+
+        pub fn take(&mut self) -> Result<Option<frame::Video>> {
+            // The hook makes the mapping on the game's first present after
+            // it loads, so it may not be there yet
+            if self.mapping.is_none() {
+                self.open_mapping();
+            }
+            let Some((_, view)) = self.mapping else {
+                return Ok(None);
+            };
+            unsafe {
+                let header = &*(view.Value as *const Header);
+                if header.magic != MAGIC || header.version != VERSION {
+                    return Err(SlumpError::Video(
+                        "Game hook speaks a different protocol version".into(),
+                    ));
+                }
+                let sequence = header.sequence.load(Ordering::Acquire);
+                if sequence % 2 == 1 || sequence == self.last_sequence {
+                    return Ok(None);
+                }
+                let (width, height, pitch) = (header.width, header.height, header.pitch as usize);
+                let row = width as usize * 4;
+                if width == 0
+                    || height == 0
+                    || pitch < row
+                    || pitch * height as usize > header.capacity as usize
+                {
+                    return Ok(None);
+                }
+                let pixel = if header.format == 1 {
+                    Pixel::RGBA
+                } else {
+                    Pixel::BGRA
+                };
+                let mut frame = frame::Video::new(pixel, width, height);
+                let stride = frame.stride(0);
+                let pixels = (view.Value as *const u8).add(std::mem::size_of::<Header>());
+                let data = frame.data_mut(0);
+                for y in 0..height as usize {
+                    std::ptr::copy_nonoverlapping(
+                        pixels.add(y * pitch),
+                        data.as_mut_ptr().add(y * stride),
+                        row,
+                    );
+                }
+                // The hook started another frame while this one was copied
+                if header.sequence.load(Ordering::Acquire) != sequence {
+                    return Ok(None);
+                }
+                self.last_sequence = sequence;
+                Ok(Some(frame))
+            }
+        }
+
+        fn open_mapping(&mut self) {
+            let name = wide(&format!("{}{}", MAPPING_PREFIX, self.pid));
+            unsafe {
+                let Ok(handle) = OpenFileMappingW(FILE_MAP_READ.0, false, PCWSTR(name.as_ptr()))
+                else {
+                    return;
+                };
+                let view = MapViewOfFile(handle, FILE_MAP_READ, 0, 0, 0);
+                if view.Value.is_null() {
+                    let _ = CloseHandle(handle);
+                    return;
+                }
+                self.mapping = Some((handle, view));
+            }
+        }
+    }
+
+    impl Drop for Hooked {
+        fn drop(&mut self) {
+            if let Some((handle, view)) = self.mapping.take() {
+                unsafe {
+                    let _ = UnmapViewOfFile(view);
+                    let _ = CloseHandle(handle);
+                }
+            }
+        }
+    }
+
+    fn find_process(process: &str) -> Result<u32> {
+        if let Ok(pid) = process.parse::<u32>() {
+            return Ok(pid);
+        }
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)
+                .map_err(|e| SlumpError::Video(format!("Failed to list processes: {}", e)))?;
+            let mut entry = PROCESSENTRY32W {
+                dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+                ..Default::default()
+            };
+            let mut found = None;
+            let mut more = Process32FirstW(snapshot, &mut entry).as_bool();
+            while more {
+                let len = entry
+                    .szExeFile
+                    .iter()
+                    .position(|&c| c == 0)
+                    .unwrap_or(entry.szExeFile.len());
+                if String::from_utf16_lossy(&entry.szExeFile[..len]).eq_ignore_ascii_case(process) {
+                    found = Some(entry.th32ProcessID);
+                    break;
+                }
+                more = Process32NextW(snapshot, &mut entry).as_bool();
+            }
+            let _ = CloseHandle(snapshot);
+            found.ok_or_else(|| {
+                SlumpError::DeviceNotFound(format!("No running process {}", process))
+            })
+        }
+    }
+
+    // The usual LoadLibraryW-on-a-remote-thread: kernel32 sits at the same
+    // address in every process of a session, so its LoadLibraryW is the
+    // game's too
+    fn inject(pid: u32, target: &GameTarget) -> Result<()> {
+        let path = target.hook.canonicalize().map_err(|e| {
+            SlumpError::DeviceNotFound(format!("Game hook {}: {}", target.hook.display(), e))
+        })?;
+        let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let size = path.len() * 2;
+
+        unsafe {
+            let process = OpenProcess(
+                PROCESS_CREATE_THREAD
+                    | PROCESS_QUERY_INFORMATION
+                    | PROCESS_VM_OPERATION
+                    | PROCESS_VM_WRITE,
+                false,
+                pid,
+            )
+            .map_err(|e| {
+                if e.code() == ERROR_ACCESS_DENIED.to_hresult() {
+                    SlumpError::PermissionDenied(format!(
+                        "Can't hook process {}; it runs elevated or protected (anti-cheat)",
+                        pid
+                    ))
+                } else {
+                    SlumpError::Video(format!("Failed to open process {}: {}", pid, e))
+                }
+            })?;
+
+            let result = (|| {
+                let remote = VirtualAllocEx(
+                    process,
+                    None,
+                    size,
+                    MEM_COMMIT | MEM_RESERVE,
+                    PAGE_READWRITE,
+                );
+                if remote.is_null() {
+                    return Err(SlumpError::Video(format!(
+                        "Failed to allocate in process {}",
+                        pid
+                    )));
+                }
+                let loaded = (|| {
+                    if !WriteProcessMemory(process, remote, path.as_ptr() as _, size, None)
+                        .as_bool()
+                    {
+                        return Err(SlumpError::Video(format!(
+                            "Failed to write to process {}",
+                            pid
+                        )));
+                    }
+                    let kernel32 = GetModuleHandleW(PCWSTR(wide("kernel32.dll").as_ptr()))
+                        .map_err(|e| SlumpError::Video(e.to_string()))?;
+                    let load_library = GetProcAddress(kernel32, PCSTR(b"LoadLibraryW\0".as_ptr()))
+                        .ok_or_else(|| SlumpError::Video("LoadLibraryW not found".into()))?;
+                    let thread = CreateRemoteThread(
+                        process,
+                        None,
+                        0,
+                        Some(std::mem::transmute(load_library)),
+                        Some(remote),
+                        0,
+                        None,
+                    )
+                    .map_err(|e| {
+                        SlumpError::Video(format!(
+                            "Failed to start the hook in process {}: {}",
+                            pid, e
+                        ))
+                    })?;
+                    WaitForSingleObject(thread, LOAD_TIMEOUT_MS);
+                    // The low half of the module handle; 0 means it didn't load
+                    let mut module = 0;
+                    let _ = GetExitCodeThread(thread, &mut module);
+                    let _ = CloseHandle(thread);
+                    if module == 0 {
+                        return Err(SlumpError::Video(format!(
+                            "Game hook failed to load in process {}; check it matches the game's bitness",
+                            pid
+                        )));
+                    }
+                    Ok(())
+                })();
+                let _ = VirtualFreeEx(process, remote, 0, MEM_RELEASE);
+                loaded
+            })();
+            let _ = CloseHandle(process);
+            result
+        }
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(Some(0)).collect()
+    }
+}
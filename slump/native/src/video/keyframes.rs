@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+
+// Folds keyframe requests from every viewer of a track into at most one
+// keyframe per interval, so one receiver stuck sending PLIs can't fill the
+// stream with keyframes for everyone. A request inside the interval is held
+// rather than dropped, so whoever sent it still gets a keyframe once the
+// interval is up.
+#[derive(Debug, Default)]
+pub struct KeyframeLimiter {
+    interval: Duration,
+    last: Option<Instant>,
+    pending: bool,
+}
+
+impl KeyframeLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+            pending: false,
+        }
+    }
+
+    // Returns how many of the `count` requests were folded into a keyframe
+    // already pending
+    pub fn request(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        if std::mem::replace(&mut self.pending, true) {
+            count
+        } else {
+            count - 1
+        }
+    }
+
+    // Whether the next frame should be a keyframe. `forced` is for the
+    // encoder's own reasons, such as a scene cut or a dropped frame; those
+    // always go through, and satisfy whatever was pending.
+    pub fn poll(&mut self, forced: bool) -> bool {
+        let due = self.pending && self.last.is_none_or(|last| last.elapsed() >= self.interval);
+        if forced || due {
+            self.pending = false;
+            self.last = Some(Instant::now());
+        }
+        forced || due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_several_requests_into_one_keyframe() {
+        let mut limiter = KeyframeLimiter::new(Duration::from_secs(60));
+        assert_eq!(limiter.request(3), 2);
+        assert_eq!(limiter.request(2), 2);
+        assert!(limiter.poll(false));
+        // All five were answered by that one
+        assert!(!limiter.poll(false));
+        assert_eq!(limiter.request(0), 0);
+        assert!(!limiter.poll(false));
+    }
+
+    #[test]
+    fn holds_a_request_inside_the_interval() {
+        let mut limiter = KeyframeLimiter::new(Duration::from_millis(50));
+        limiter.request(1);
+        assert!(limiter.poll(false));
+        assert_eq!(limiter.request(1), 0);
+        assert!(!limiter.poll(false));
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(limiter.poll(false));
+        assert!(!limiter.poll(false));
+    }
+
+    #[test]
+    fn forced_keyframes_go_through_and_satisfy_what_was_pending() {
+        let mut limiter = KeyframeLimiter::new(Duration::from_secs(60));
+        assert!(limiter.poll(true));
+        // Inside the interval of the forced one, but forced again
+        assert!(limiter.poll(true));
+        limiter.request(1);
+        assert!(limiter.poll(true));
+        assert!(!limiter.poll(false));
+    }
+}
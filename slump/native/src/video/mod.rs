@@ -1,3 +1,34 @@
+mod chroma;
+mod color;
+mod convert;
+mod cursor;
+mod dedup;
+mod desktop;
+mod encoder;
+mod external;
+mod game;
+mod keyframes;
+mod overlay;
+mod pool;
+mod privacy;
+mod roi;
+
+pub use chroma::{Chroma, Resampler};
+pub use color::{ColorRange, ColorSpace, Colorimetry};
+pub use cursor::CursorTracker;
+pub use dedup::{Decision, FrameDedup};
+pub use desktop::SecureDesktop;
+pub use encoder::{VideoCodec, VideoEncoder};
+pub use external::{ExternalFrames, VideoSource};
+pub use game::GameTarget;
+pub use keyframes::KeyframeLimiter;
+pub use overlay::{burn_in, notice, Annotation, Color, Corner, Overlay, Shape};
+pub use privacy::{Obscure, PrivacyRegions, Region};
+pub use roi::{QualityRegion, QualityRegions};
+
+use game::GameFrames;
+use pool::FramePool;
+
 use crate::error::{Result, SlumpError};
 use ffmpeg_next::{
     codec,
@@ -7,27 +38,44 @@ use ffmpeg_next::{
     Dictionary,
     Frame,
 };
+use parking_lot::Mutex;
 use std::{
     sync::Arc,
     time::{Duration, Instant},
 };
 
+enum Input {
+    Device {
+        input_ctx: ffmpeg_next::format::context::Input,
+        stream_index: usize,
+        decoder: codec::decoder::Video,
+    },
+    External(Arc<Mutex<ExternalFrames>>),
+    Game(GameFrames),
+}
+
 pub struct VideoCapture {
-    input_ctx: ffmpeg_next::format::context::Input,
-    stream_index: usize,
-    decoder: codec::decoder::Video,
+    input: Input,
     scaler: scaling::Context,
+    colorimetry: Colorimetry,
+    chroma: Chroma,
+    // Scaled frames are drawn from here rather than allocated every tick
+    pool: FramePool,
     last_frame: Option<Frame>,
+    // Decoded capture before scaling, for outputs with their own resolution
+    last_source: Option<Frame>,
     last_pts: Option<i64>,
     frame_rate: f64,
     frame_count: u64,
     start_time: Instant,
 }
 
-impl VideoCapture {
-    pub fn new(display_index: usize, width: u32, height: u32) -> Result<Self> {
-        ffmpeg_next::init().map_err(|e| SlumpError::Init(e.to_string()))?;
+// The FFmpeg contexts are only used by whichever thread holds the capture:
+// the capture stage while the stream runs, under the state lock otherwise
+unsafe impl Send for VideoCapture {}
 
+impl VideoCapture {
+    pub fn new(display_index: usize, width: u32, height: u32, capture_cursor: bool) -> Result<Self> {
         // Setup display capture
         let input_format = if cfg!(windows) {
             "gdigrab"
@@ -40,21 +88,76 @@ impl VideoCapture {
         let input_url = if cfg!(windows) {
             format!("desktop")
         } else if cfg!(target_os = "macos") {
-            format!("1:0")
+            format!("Capture screen {}:none", display_index)
         } else {
-            format!(":0.0+0,0")
+            format!(":0.{}+0,0", display_index)
         };
 
         let mut options = Dictionary::new();
         options.set("framerate", "120");
+        options.set("draw_mouse", if capture_cursor { "1" } else { "0" });
+        // gdigrab sees one virtual desktop, so a monitor is a region of it
+        match display_bounds(display_index) {
+            Some((x, y, display_width, display_height)) => {
+                options.set("offset_x", &x.to_string());
+                options.set("offset_y", &y.to_string());
+                options.set("video_size", &format!("{}x{}", display_width, display_height));
+            }
+            None if display_index > 0 && cfg!(windows) => {
+                return Err(SlumpError::Video(format!("Display {} not found", display_index)));
+            }
+            None => options.set("video_size", &format!("{}x{}", width, height)),
+        }
+
+        Self::open(input_format, &input_url, options, width, height)
+    }
+
+    pub fn new_camera(device: &str, width: u32, height: u32) -> Result<Self> {
+        let input_format = if cfg!(windows) {
+            "dshow"
+        } else if cfg!(target_os = "macos") {
+            "avfoundation"
+        } else {
+            "v4l2"
+        };
+
+        let input_url = if cfg!(windows) {
+            format!("video={}", device)
+        } else {
+            device.to_string()
+        };
+
+        let mut options = Dictionary::new();
+        options.set("framerate", "30");
         options.set("video_size", &format!("{}x{}", width, height));
-        options.set("draw_mouse", "0");
+
+        Self::open(input_format, &input_url, options, width, height)
+    }
+
+    // FFmpeg's lavfi test pattern in place of a display or camera, with a
+    // moving counter so frames are never duplicates
+    pub fn synthetic(width: u32, height: u32, fps: u32) -> Result<Self> {
+        let source = format!("testsrc2=size={}x{}:rate={}", width, height, fps.max(1));
+        Self::open("lavfi", &source, Dictionary::new(), width, height)
+    }
+
+    fn open(
+        input_format: &str,
+        input_url: &str,
+        options: Dictionary,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        if !cfg!(feature = "device") {
+            return Err(SlumpError::Init("Built without the device feature; capture is unavailable".into()));
+        }
+        ffmpeg_next::init().map_err(|e| SlumpError::Init(e.to_string()))?;
 
         let mut input_ctx = ffmpeg_next::format::input_with_dictionary(
             &input_format,
             &input_url,
             options,
-        )?;
+        ).map_err(SlumpError::from_device_open)?;
 
         let stream = input_ctx
             .streams()
@@ -75,18 +178,24 @@ impl VideoCapture {
             decoder.format(),
             decoder.width(),
             decoder.height(),
-            ffmpeg_next::format::pixel::Pixel::NV12,
+            VideoEncoder::PIXEL_FORMAT,
             width,
             height,
             scaling::Flags::BILINEAR,
         )?;
 
         Ok(Self {
-            input_ctx,
-            stream_index,
-            decoder,
+            input: Input::Device {
+                input_ctx,
+                stream_index,
+                decoder,
+            },
             scaler,
+            colorimetry: Colorimetry::default(),
+            chroma: Chroma::default(),
+            pool: FramePool::new(VideoEncoder::PIXEL_FORMAT, width, height),
             last_frame: None,
+            last_source: None,
             last_pts: None,
             frame_rate: 90.0,
             frame_count: 0,
@@ -94,36 +203,175 @@ impl VideoCapture {
         })
     }
 
+    // Fed by push() instead of a device; starts out expecting frames at the
+    // output size, and follows whatever size and format actually arrive
+    pub fn external(width: u32, height: u32) -> Result<Self> {
+        ffmpeg_next::init().map_err(|e| SlumpError::Init(e.to_string()))?;
+        let scaler = scaling::Context::get(
+            VideoEncoder::PIXEL_FORMAT,
+            width,
+            height,
+            VideoEncoder::PIXEL_FORMAT,
+            width,
+            height,
+            scaling::Flags::BILINEAR,
+        )?;
+
+        Ok(Self {
+            input: Input::External(Arc::default()),
+            scaler,
+            colorimetry: Colorimetry::default(),
+            chroma: Chroma::default(),
+            pool: FramePool::new(VideoEncoder::PIXEL_FORMAT, width, height),
+            last_frame: None,
+            last_source: None,
+            last_pts: None,
+            frame_rate: 0.0,
+            frame_count: 0,
+            start_time: Instant::now(),
+        })
+    }
+
+    // Hooks the game's present call; like external(), follows whatever size
+    // the game renders at
+    pub fn game(target: &GameTarget, width: u32, height: u32) -> Result<Self> {
+        let frames = GameFrames::attach(target)?;
+        let mut capture = Self::external(width, height)?;
+        capture.input = Input::Game(frames);
+        Ok(capture)
+    }
+
+    // Frames come when the application or game has one, not at a device's
+    // rate, so a gap is no sign of trouble
+    pub fn is_source_paced(&self) -> bool {
+        matches!(self.input, Input::External(_) | Input::Game(_))
+    }
+
+    // Where application frames are queued for the next capture tick. Shared,
+    // so they can be pushed while the capture stage has the capture.
+    pub fn external_frames(&self) -> Option<Arc<Mutex<ExternalFrames>>> {
+        match &self.input {
+            Input::External(frames) => Some(frames.clone()),
+            Input::Device { .. } | Input::Game(_) => None,
+        }
+    }
+
     pub fn capture_frame(&mut self) -> Result<Option<Frame>> {
-        let mut packet = match self.input_ctx.packets().next() {
-            Some((_, packet)) => packet,
-            None => return Ok(None),
+        let _span = tracing::trace_span!("capture").entered();
+        let decoded = match &mut self.input {
+            Input::Device { input_ctx, stream_index, decoder } => {
+                let mut packet = match input_ctx.packets().next() {
+                    Some((_, packet)) => packet,
+                    None => return Ok(None),
+                };
+
+                if packet.stream() != *stream_index {
+                    return Ok(None);
+                }
+
+                let decode = tracing::trace_span!("decode").entered();
+                decoder.send_packet(&packet)?;
+
+                let mut decoded = Frame::empty();
+                let received = decoder.receive_frame(&mut decoded).is_ok();
+                drop(decode);
+                if !received {
+                    return Ok(None);
+                }
+                decoded
+            }
+            Input::External(frames) => {
+                let Some(frame) = frames.lock().take() else {
+                    return Ok(None);
+                };
+                self.follow_input(&frame)?;
+                (*frame).clone()
+            }
+            Input::Game(frames) => {
+                let Some(frame) = frames.take()? else {
+                    return Ok(None);
+                };
+                self.follow_input(&frame)?;
+                (*frame).clone()
+            }
         };
 
-        if packet.stream() != self.stream_index {
-            return Ok(None);
+        let output = *self.scaler.output();
+        let mut scaled = self.pool.get(output.format, output.width, output.height);
+        tracing::trace_span!("scale", width = self.scaler.output().width, height = self.scaler.output().height)
+            .in_scope(|| {
+                // Same-size BGRA captures skip swscale when the CPU has a SIMD
+                // kernel, which only does BT.601 limited range into 4:2:0
+                if self.colorimetry == Colorimetry::default() && convert::bgra_to_i420(&decoded, &mut scaled) {
+                    Ok(())
+                } else {
+                    self.scaler.run(&decoded, &mut scaled)
+                }
+            })?;
+        self.colorimetry.tag(&mut scaled);
+        self.last_frame = Some(scaled.clone());
+        self.frame_count += 1;
+        self.last_pts = decoded.pts().map(|p| p as i64);
+        self.last_source = Some(decoded);
+
+        // Calculate actual frame rate
+        let elapsed = self.start_time.elapsed();
+        if elapsed.as_secs() > 0 {
+            self.frame_rate = self.frame_count as f64 / elapsed.as_secs_f64();
         }
 
-        self.decoder.send_packet(&packet)?;
-        
-        let mut decoded = Frame::empty();
-        if self.decoder.receive_frame(&mut decoded).is_ok() {
-            let mut scaled = Frame::empty();
-            self.scaler.run(&decoded, &mut scaled)?;
-            self.last_frame = Some(scaled);
-            self.frame_count += 1;
-            self.last_pts = decoded.pts().map(|p| p as i64);
-            
-            // Calculate actual frame rate
-            let elapsed = self.start_time.elapsed();
-            if elapsed.as_secs() > 0 {
-                self.frame_rate = self.frame_count as f64 / elapsed.as_secs_f64();
-            }
-            
-            Ok(Some(decoded))
-        } else {
-            Ok(None)
+        Ok(Some(scaled))
+    }
+
+    // Pushed and game frames can change size or format from one to the next
+    fn follow_input(&mut self, frame: &frame::Video) -> Result<()> {
+        let (input, output) = (*self.scaler.input(), *self.scaler.output());
+        if (input.format, input.width, input.height) != (frame.format(), frame.width(), frame.height()) {
+            self.scaler = self.colorimetry.scaler(
+                (frame.format(), frame.width(), frame.height()),
+                self.chroma.pixel(),
+                output.width,
+                output.height,
+                scaling::Flags::BILINEAR,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn set_output_size(&mut self, width: u32, height: u32) -> Result<()> {
+        let input = *self.scaler.input();
+        self.scaler = self.colorimetry.scaler(
+            (input.format, input.width, input.height),
+            self.chroma.pixel(),
+            width,
+            height,
+            scaling::Flags::BILINEAR,
+        )?;
+        Ok(())
+    }
+
+    pub fn set_colorimetry(&mut self, colorimetry: Colorimetry) -> Result<()> {
+        if colorimetry == self.colorimetry {
+            return Ok(());
+        }
+        self.colorimetry = colorimetry;
+        let output = *self.scaler.output();
+        self.set_output_size(output.width, output.height)
+    }
+
+    // Encoders that can't take it downsample on their side, so raw outputs
+    // and recordings still get the full colour
+    pub fn set_chroma(&mut self, chroma: Chroma) -> Result<()> {
+        if chroma == self.chroma {
+            return Ok(());
         }
+        self.chroma = chroma;
+        let output = *self.scaler.output();
+        self.set_output_size(output.width, output.height)
+    }
+
+    pub fn pool_misses(&self) -> u64 {
+        self.pool.misses()
     }
 
     pub fn get_frame_rate(&self) -> f64 {
@@ -133,10 +381,120 @@ impl VideoCapture {
     pub fn get_last_frame(&self) -> Option<&Frame> {
         self.last_frame.as_ref()
     }
+
+    pub fn source_frame(&self) -> Option<&Frame> {
+        self.last_source.as_ref()
+    }
+
+    // What the scaler takes in, i.e. the capture's own size
+    pub fn source_size(&self) -> (u32, u32) {
+        let input = self.scaler.input();
+        (input.width, input.height)
+    }
+}
+
+// Black frame in the encoder's format, sent to viewers while the stream is paused
+pub fn placeholder_frame(width: u32, height: u32) -> Frame {
+    let mut frame = frame::Video::new(VideoEncoder::PIXEL_FORMAT, width, height);
+    frame.data_mut(0).fill(16);
+    frame.data_mut(1).fill(128);
+    frame.data_mut(2).fill(128);
+    (*frame).clone()
+}
+
+#[derive(Debug, Clone)]
+pub struct DisplayInfo {
+    pub index: usize,
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: Option<u32>,
+}
+
+// Monitors in DXGI enumeration order, the same order display_index uses
+#[cfg(windows)]
+fn outputs() -> Vec<windows::Win32::Graphics::Dxgi::DXGI_OUTPUT_DESC> {
+    use windows::Win32::Graphics::Dxgi::{CreateDXGIFactory1, IDXGIFactory1};
+
+    let mut outputs = Vec::new();
+    unsafe {
+        let Ok(factory) = CreateDXGIFactory1::<IDXGIFactory1>() else {
+            return outputs;
+        };
+        let mut adapter_index = 0;
+        while let Ok(adapter) = factory.EnumAdapters1(adapter_index) {
+            let mut output_index = 0;
+            while let Ok(output) = adapter.EnumOutputs(output_index) {
+                if let Ok(desc) = output.GetDesc() {
+                    outputs.push(desc);
+                }
+                output_index += 1;
+            }
+            adapter_index += 1;
+        }
+    }
+    outputs
+}
+
+// Desktop coordinates of a monitor
+#[cfg(windows)]
+fn display_bounds(display_index: usize) -> Option<(i32, i32, u32, u32)> {
+    let bounds = outputs().get(display_index)?.DesktopCoordinates;
+    Some((
+        bounds.left,
+        bounds.top,
+        (bounds.right - bounds.left) as u32,
+        (bounds.bottom - bounds.top) as u32,
+    ))
+}
+
+#[cfg(windows)]
+pub fn list_displays() -> Vec<DisplayInfo> {
+    use windows::core::PCWSTR;
+    use windows::Win32::Graphics::Gdi::{EnumDisplaySettingsW, DEVMODEW, ENUM_CURRENT_SETTINGS};
+
+    outputs()
+        .iter()
+        .enumerate()
+        .map(|(index, desc)| {
+            let bounds = desc.DesktopCoordinates;
+            // DXGI has no refresh rate for the current mode, GDI does
+            let mut mode = DEVMODEW {
+                dmSize: std::mem::size_of::<DEVMODEW>() as u16,
+                ..Default::default()
+            };
+            let refresh_rate = unsafe {
+                EnumDisplaySettingsW(PCWSTR(desc.DeviceName.as_ptr()), ENUM_CURRENT_SETTINGS, &mut mode)
+            }
+            .as_bool()
+            .then_some(mode.dmDisplayFrequency)
+            .filter(|&hz| hz > 1);
+
+            DisplayInfo {
+                index,
+                width: (bounds.right - bounds.left) as u32,
+                height: (bounds.bottom - bounds.top) as u32,
+                refresh_rate,
+            }
+        })
+        .collect()
+}
+
+// Other platforms select the screen in the input URL instead
+#[cfg(not(windows))]
+fn display_bounds(_display_index: usize) -> Option<(i32, i32, u32, u32)> {
+    None
+}
+
+// avfoundation and x11grab give no way to enumerate screens up front
+#[cfg(not(windows))]
+pub fn list_displays() -> Vec<DisplayInfo> {
+    Vec::new()
 }
 
 impl Drop for VideoCapture {
     fn drop(&mut self) {
-        let _ = self.decoder.send_eof();
+        if let Input::Device { decoder, .. } = &mut self.input {
+            let _ = decoder.send_eof();
+        }
     }
 }
@@ -0,0 +1,467 @@
+use super::Chroma;
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::Frame;
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+// 5x7 glyphs for ' ' to '~', one byte per column, top row in the low bit
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x5f, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7f, 0x14, 0x7f, 0x14],
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12],
+    [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50],
+    [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1c, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1c, 0x00],
+    [0x08, 0x2a, 0x1c, 0x2a, 0x08],
+    [0x08, 0x08, 0x3e, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00],
+    [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3e, 0x51, 0x49, 0x45, 0x3e],
+    [0x00, 0x42, 0x7f, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46],
+    [0x21, 0x41, 0x45, 0x4b, 0x31],
+    [0x18, 0x14, 0x12, 0x7f, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3c, 0x4a, 0x49, 0x49, 0x30],
+    [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36],
+    [0x06, 0x49, 0x49, 0x29, 0x1e],
+    [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x08, 0x14, 0x22, 0x41, 0x00],
+    [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08],
+    [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3e],
+    [0x7e, 0x11, 0x11, 0x11, 0x7e],
+    [0x7f, 0x49, 0x49, 0x49, 0x36],
+    [0x3e, 0x41, 0x41, 0x41, 0x22],
+    [0x7f, 0x41, 0x41, 0x22, 0x1c],
+    [0x7f, 0x49, 0x49, 0x49, 0x41],
+    [0x7f, 0x09, 0x09, 0x01, 0x01],
+    [0x3e, 0x41, 0x41, 0x51, 0x32],
+    [0x7f, 0x08, 0x08, 0x08, 0x7f],
+    [0x00, 0x41, 0x7f, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3f, 0x01],
+    [0x7f, 0x08, 0x14, 0x22, 0x41],
+    [0x7f, 0x40, 0x40, 0x40, 0x40],
+    [0x7f, 0x02, 0x04, 0x02, 0x7f],
+    [0x7f, 0x04, 0x08, 0x10, 0x7f],
+    [0x3e, 0x41, 0x41, 0x41, 0x3e],
+    [0x7f, 0x09, 0x09, 0x09, 0x06],
+    [0x3e, 0x41, 0x51, 0x21, 0x5e],
+    [0x7f, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7f, 0x01, 0x01],
+    [0x3f, 0x40, 0x40, 0x40, 0x3f],
+    [0x1f, 0x20, 0x40, 0x20, 0x1f],
+    [0x7f, 0x20, 0x18, 0x20, 0x7f],
+    [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x03, 0x04, 0x78, 0x04, 0x03],
+    [0x61, 0x51, 0x49, 0x45, 0x43],
+    [0x00, 0x7f, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20],
+    [0x00, 0x41, 0x41, 0x7f, 0x00],
+    [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x01, 0x02, 0x04, 0x00],
+    [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7f, 0x48, 0x44, 0x44, 0x38],
+    [0x38, 0x44, 0x44, 0x44, 0x20],
+    [0x38, 0x44, 0x44, 0x48, 0x7f],
+    [0x38, 0x54, 0x54, 0x54, 0x18],
+    [0x08, 0x7e, 0x09, 0x01, 0x02],
+    [0x08, 0x14, 0x54, 0x54, 0x3c],
+    [0x7f, 0x08, 0x04, 0x04, 0x78],
+    [0x00, 0x44, 0x7d, 0x40, 0x00],
+    [0x20, 0x40, 0x44, 0x3d, 0x00],
+    [0x00, 0x7f, 0x10, 0x28, 0x44],
+    [0x00, 0x41, 0x7f, 0x40, 0x00],
+    [0x7c, 0x04, 0x18, 0x04, 0x78],
+    [0x7c, 0x08, 0x04, 0x04, 0x78],
+    [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0x7c, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7c],
+    [0x7c, 0x08, 0x04, 0x04, 0x08],
+    [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3f, 0x44, 0x40, 0x20],
+    [0x3c, 0x40, 0x40, 0x20, 0x7c],
+    [0x1c, 0x20, 0x40, 0x20, 0x1c],
+    [0x3c, 0x40, 0x30, 0x40, 0x3c],
+    [0x44, 0x28, 0x10, 0x28, 0x44],
+    [0x0c, 0x50, 0x50, 0x50, 0x3c],
+    [0x44, 0x64, 0x54, 0x4c, 0x44],
+    [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x7f, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00],
+    [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+const GLYPH_HEIGHT: i64 = 7;
+// Glyph plus a column of spacing, and a line plus two rows
+const GLYPH_ADVANCE: i64 = 6;
+const LINE_ADVANCE: i64 = 9;
+// Below two pixels a stroke can miss every chroma sample and lose its colour
+const MIN_STROKE: i64 = 2;
+// Burn-in line height as a share of the frame's; big enough to read off a
+// phone photo of the receiving screen
+const BURN_IN_LINE: f64 = 0.035;
+
+// BT.601 limited range, like the capture conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    y: u8,
+    u: u8,
+    v: u8,
+    alpha: u8,
+}
+
+impl Color {
+    pub const RED: Color = Color::rgba(255, 0, 0, 255);
+    const WHITE: Color = Color::rgba(255, 255, 255, 255);
+    const BLACK: Color = Color::rgba(0, 0, 0, 255);
+
+    const fn rgba(r: u8, g: u8, b: u8, alpha: u8) -> Self {
+        let (r, g, b) = (r as i32, g as i32, b as i32);
+        Self {
+            y: (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8,
+            u: (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8,
+            v: (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8,
+            alpha,
+        }
+    }
+
+    // "#rrggbb" or "#rrggbbaa"
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid = || SlumpError::Init(format!("Invalid colour: {}", s));
+        let hex = s.strip_prefix('#').ok_or_else(invalid)?;
+        if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+        Ok(Self::rgba(channel(0)?, channel(2)?, channel(4)?, alpha))
+    }
+}
+
+// Points run 0-1 across the frame, so annotations stay put when the adaptive
+// controller rescales
+#[derive(Debug, Clone)]
+pub enum Shape {
+    Line {
+        from: (f64, f64),
+        to: (f64, f64),
+    },
+    Rect {
+        from: (f64, f64),
+        to: (f64, f64),
+        fill: bool,
+    },
+    // `size` is the line height as a share of the frame's
+    Text {
+        at: (f64, f64),
+        text: String,
+        size: f64,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Annotation {
+    pub shape: Shape,
+    pub color: Color,
+    // Stroke in output pixels
+    pub thickness: u32,
+    // None stays until removed
+    pub ttl: Option<Duration>,
+}
+
+#[derive(Clone)]
+struct Placed {
+    id: u32,
+    annotation: Annotation,
+    expires: Option<Instant>,
+}
+
+// Drawn into every outgoing frame, in the order added. The capture stage
+// draws a copy, taken each frame, so annotations can change meanwhile.
+#[derive(Default, Clone)]
+pub struct Overlay {
+    placed: Vec<Placed>,
+    next_id: u32,
+}
+
+impl Overlay {
+    pub fn add(&mut self, annotation: Annotation) -> u32 {
+        self.next_id = self.next_id.wrapping_add(1);
+        let expires = annotation.ttl.map(|ttl| Instant::now() + ttl);
+        self.placed.push(Placed {
+            id: self.next_id,
+            annotation,
+            expires,
+        });
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let before = self.placed.len();
+        self.placed.retain(|placed| placed.id != id);
+        self.placed.len() != before
+    }
+
+    pub fn clear(&mut self) {
+        self.placed.clear();
+    }
+
+    // Drops annotations whose ttl has run out
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        self.placed
+            .retain(|placed| placed.expires.is_none_or(|expires| expires > now));
+    }
+
+    // Composites into a planar YUV frame; other formats are left alone
+    pub fn draw(&self, frame: &mut Frame) {
+        let now = Instant::now();
+        let mut live = self
+            .placed
+            .iter()
+            .filter(|placed| placed.expires.is_none_or(|expires| expires > now))
+            .peekable();
+        if live.peek().is_none() {
+            return;
+        }
+        let Some(mut canvas) = (unsafe { Canvas::new(frame) }) else {
+            return;
+        };
+        for placed in live {
+            canvas.draw(&placed.annotation);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Corner {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl FromStr for Corner {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "top-left" => Ok(Corner::TopLeft),
+            "top-right" => Ok(Corner::TopRight),
+            "bottom-left" => Ok(Corner::BottomLeft),
+            "bottom-right" => Ok(Corner::BottomRight),
+            other => Err(SlumpError::Init(format!("Unknown corner: {}", other))),
+        }
+    }
+}
+
+// White text on an opaque box in a corner, sized to the frame so it stays
+// legible after the adaptive controller rescales
+pub fn burn_in(frame: &mut Frame, corner: Corner, text: &str) {
+    let Some(mut canvas) = (unsafe { Canvas::new(frame) }) else {
+        return;
+    };
+    let scale = (BURN_IN_LINE * canvas.height as f64 / LINE_ADVANCE as f64)
+        .round()
+        .max(MIN_STROKE as f64) as i64;
+    let padding = scale * 2;
+    let columns = text
+        .lines()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0) as i64;
+    let rows = text.lines().count() as i64;
+    let width = columns * GLYPH_ADVANCE * scale + padding * 2;
+    let height = rows * LINE_ADVANCE * scale + padding * 2;
+    let left = match corner {
+        Corner::TopLeft | Corner::BottomLeft => padding,
+        Corner::TopRight | Corner::BottomRight => canvas.width - width - padding,
+    };
+    let top = match corner {
+        Corner::TopLeft | Corner::TopRight => padding,
+        Corner::BottomLeft | Corner::BottomRight => canvas.height - height - padding,
+    };
+    canvas.fill(left, top, left + width, top + height, Color::BLACK);
+    canvas.text((left + padding, top + padding), text, scale, Color::WHITE);
+}
+
+// One line of white text centred on the frame, for placeholders that say why
+// there is no picture
+pub fn notice(frame: &mut Frame, text: &str) {
+    let Some(mut canvas) = (unsafe { Canvas::new(frame) }) else {
+        return;
+    };
+    let columns = text.chars().count().max(1) as i64;
+    // As large as fits across, up to twice the burn-in size
+    let scale = (canvas.width * 9 / 10 / (columns * GLYPH_ADVANCE))
+        .min((2.0 * BURN_IN_LINE * canvas.height as f64 / LINE_ADVANCE as f64) as i64)
+        .max(1);
+    let left = (canvas.width - columns * GLYPH_ADVANCE * scale) / 2;
+    let top = (canvas.height - GLYPH_HEIGHT * scale) / 2;
+    canvas.text((left, top), text, scale, Color::WHITE);
+}
+
+struct Canvas {
+    y: *mut u8,
+    y_stride: isize,
+    u: *mut u8,
+    u_stride: isize,
+    v: *mut u8,
+    v_stride: isize,
+    // log2 chroma subsampling, across and down
+    shift: (u32, u32),
+    width: i64,
+    height: i64,
+}
+
+impl Canvas {
+    unsafe fn new(frame: &mut Frame) -> Option<Self> {
+        let f = &mut *frame.as_mut_ptr();
+        let chroma = Chroma::of(f.format)?;
+        if f.width <= 0 || f.height <= 0 || f.data[..3].iter().any(|plane| plane.is_null()) {
+            return None;
+        }
+        Some(Self {
+            y: f.data[0],
+            y_stride: f.linesize[0] as isize,
+            u: f.data[1],
+            u_stride: f.linesize[1] as isize,
+            v: f.data[2],
+            v_stride: f.linesize[2] as isize,
+            shift: chroma.shift(),
+            width: f.width as i64,
+            height: f.height as i64,
+        })
+    }
+
+    fn point(&self, (x, y): (f64, f64)) -> (i64, i64) {
+        (
+            (x * self.width as f64).round() as i64,
+            (y * self.height as f64).round() as i64,
+        )
+    }
+
+    fn draw(&mut self, annotation: &Annotation) {
+        let color = annotation.color;
+        let stroke = (annotation.thickness as i64).max(MIN_STROKE);
+        match &annotation.shape {
+            Shape::Line { from, to } => {
+                self.line(self.point(*from), self.point(*to), stroke, color)
+            }
+            Shape::Rect { from, to, fill } => {
+                let (a, b) = (self.point(*from), self.point(*to));
+                let (left, right) = (a.0.min(b.0), a.0.max(b.0));
+                let (top, bottom) = (a.1.min(b.1), a.1.max(b.1));
+                if *fill || right - left <= stroke * 2 || bottom - top <= stroke * 2 {
+                    self.fill(left, top, right, bottom, color);
+                } else {
+                    // Four sides that don't overlap, so translucent corners
+                    // aren't blended twice
+                    self.fill(left, top, right, top + stroke, color);
+                    self.fill(left, bottom - stroke, right, bottom, color);
+                    self.fill(left, top + stroke, left + stroke, bottom - stroke, color);
+                    self.fill(right - stroke, top + stroke, right, bottom - stroke, color);
+                }
+            }
+            Shape::Text { at, text, size } => {
+                let scale = ((size * self.height as f64) / LINE_ADVANCE as f64)
+                    .round()
+                    .max(MIN_STROKE as f64) as i64;
+                self.text(self.point(*at), text, scale, color);
+            }
+        }
+    }
+
+    // Steps along the longer axis with a run of `stroke` pixels across it,
+    // so no pixel is blended twice
+    fn line(&mut self, from: (i64, i64), to: (i64, i64), stroke: i64, color: Color) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let half = stroke / 2;
+        if dx.abs() >= dy.abs() {
+            let (start, end) = if dx >= 0 { (from, to) } else { (to, from) };
+            for x in start.0..=end.0 {
+                let y = start.1
+                    + if dx == 0 {
+                        0
+                    } else {
+                        (end.1 - start.1) * (x - start.0) / (end.0 - start.0)
+                    };
+                self.fill(x, y - half, x + 1, y - half + stroke, color);
+            }
+        } else {
+            let (start, end) = if dy >= 0 { (from, to) } else { (to, from) };
+            for y in start.1..=end.1 {
+                let x = start.0 + (end.0 - start.0) * (y - start.1) / (end.1 - start.1);
+                self.fill(x - half, y, x - half + stroke, y + 1, color);
+            }
+        }
+    }
+
+    // Top-left at `at`; characters outside the font draw as '?'
+    fn text(&mut self, at: (i64, i64), text: &str, scale: i64, color: Color) {
+        for (row, line) in text.lines().enumerate() {
+            let top = at.1 + row as i64 * LINE_ADVANCE * scale;
+            for (column, c) in line.chars().enumerate() {
+                let left = at.0 + column as i64 * GLYPH_ADVANCE * scale;
+                if left >= self.width {
+                    break;
+                }
+                let index = (c as usize).wrapping_sub(' ' as usize);
+                let glyph = FONT
+                    .get(index)
+                    .unwrap_or(&FONT[('?' as usize) - (' ' as usize)]);
+                for (gx, bits) in glyph.iter().enumerate() {
+                    for gy in 0..GLYPH_HEIGHT {
+                        if bits & (1 << gy) == 0 {
+                            continue;
+                        }
+                        let x = left + gx as i64 * scale;
+                        let y = top + gy * scale;
+                        self.fill(x, y, x + scale, y + scale, color);
+                    }
+                }
+            }
+        }
+    }
+
+    // Blends the pixels in [left, right) x [top, bottom), clipped to the frame.
+    // Chroma is taken once per subsampled block, at its top-left pixel.
+    fn fill(&mut self, left: i64, top: i64, right: i64, bottom: i64, color: Color) {
+        let (left, right) = (left.max(0), right.min(self.width));
+        let (top, bottom) = (top.max(0), bottom.min(self.height));
+        let alpha = color.alpha as u32;
+        let blend = |pixel: *mut u8, value: u8| unsafe {
+            *pixel = ((*pixel as u32 * (255 - alpha) + value as u32 * alpha + 127) / 255) as u8;
+        };
+        let (shift_x, shift_y) = self.shift;
+        let (mask_x, mask_y) = ((1 << shift_x) - 1, (1 << shift_y) - 1);
+        for y in top..bottom {
+            let luma = unsafe { self.y.offset(y as isize * self.y_stride) };
+            let u = unsafe { self.u.offset((y >> shift_y) as isize * self.u_stride) };
+            let v = unsafe { self.v.offset((y >> shift_y) as isize * self.v_stride) };
+            for x in left..right {
+                unsafe {
+                    blend(luma.offset(x as isize), color.y);
+                    if x & mask_x == 0 && y & mask_y == 0 {
+                        blend(u.offset((x >> shift_x) as isize), color.u);
+                        blend(v.offset((x >> shift_x) as isize), color.v);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use ffmpeg_next::{ffi, format::Pixel, util::frame, Frame};
+
+// Enough for the frame being scaled, the last capture kept for outputs, the
+// encode queue and the one being encoded, with a little to spare
+const POOL_SIZE: usize = 6;
+
+// Reuses frame buffers of one format and size. Frames go out as references to
+// buffers the pool also holds, and a buffer is handed out again only once
+// every other reference (encode queue, outputs, last_frame) has been dropped.
+pub struct FramePool {
+    format: Pixel,
+    width: u32,
+    height: u32,
+    frames: Vec<Frame>,
+    // Frames allocated outside the pool because every pooled one was in use
+    misses: u64,
+}
+
+impl FramePool {
+    pub fn new(format: Pixel, width: u32, height: u32) -> Self {
+        Self {
+            format,
+            width,
+            height,
+            frames: Vec::with_capacity(POOL_SIZE),
+            misses: 0,
+        }
+    }
+
+    // A frame whose contents may be overwritten. A new size or format empties
+    // the pool, since none of the old buffers fit.
+    pub fn get(&mut self, format: Pixel, width: u32, height: u32) -> Frame {
+        if (format, width, height) != (self.format, self.width, self.height) {
+            *self = Self::new(format, width, height);
+        }
+
+        let free = self
+            .frames
+            .iter()
+            .find(|frame| unsafe { ffi::av_frame_is_writable(frame.as_ptr() as *mut _) > 0 });
+        if let Some(frame) = free {
+            return share(frame);
+        }
+
+        let frame = share(&frame::Video::new(format, width, height));
+        if self.frames.len() < POOL_SIZE {
+            self.frames.push(share(&frame));
+        } else {
+            self.misses += 1;
+        }
+        frame
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+// A second reference to the same buffers; av_frame_ref rather than Clone,
+// which would copy the picture
+fn share(frame: &Frame) -> Frame {
+    let mut shared = Frame::empty();
+    unsafe {
+        ffi::av_frame_ref(shared.as_mut_ptr(), frame.as_ptr());
+    }
+    shared
+}
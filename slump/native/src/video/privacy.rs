@@ -0,0 +1,224 @@
+use super::Chroma;
+use crate::error::{Result, SlumpError};
+use ffmpeg_next::Frame;
+use std::{collections::BTreeMap, str::FromStr};
+
+// Luma pixels per pixelated block; subsampled chroma gets proportionally fewer
+const PIXEL_BLOCK: i64 = 16;
+// Three box blurs come close to a Gaussian, and at this radius text is gone
+const BLUR_RADIUS: i64 = 12;
+const BLUR_PASSES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Obscure {
+    Blur,
+    Pixelate,
+}
+
+impl FromStr for Obscure {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "blur" => Ok(Obscure::Blur),
+            "pixelate" => Ok(Obscure::Pixelate),
+            other => Err(SlumpError::Init(format!("Unknown privacy mode: {}", other))),
+        }
+    }
+}
+
+// In capture pixels, i.e. screen coordinates for a display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub mode: Obscure,
+}
+
+// Keyed by the caller's id, so a region can follow the window it hides
+#[derive(Default, Clone)]
+pub struct PrivacyRegions {
+    regions: BTreeMap<String, Region>,
+}
+
+impl PrivacyRegions {
+    pub fn set(&mut self, id: String, region: Region) {
+        self.regions.insert(id, region);
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        self.regions.remove(id).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    // Obscures a scaled planar YUV frame; `source` is the capture size the
+    // regions are in. Other formats are left alone.
+    pub fn apply(&self, frame: &mut Frame, source: (u32, u32)) {
+        if self.regions.is_empty() {
+            return;
+        }
+        let Some((planes, (chroma_x, chroma_y))) = (unsafe { planes(frame) }) else {
+            return;
+        };
+        let (width, height) = (planes[0].width, planes[0].height);
+        let scale_x = width as f64 / source.0.max(1) as f64;
+        let scale_y = height as f64 / source.1.max(1) as f64;
+        for region in self.regions.values() {
+            // Rounded outward, so scaling never uncovers an edge
+            let left = (region.x as f64 * scale_x).floor() as i64;
+            let top = (region.y as f64 * scale_y).floor() as i64;
+            let right = (region.x.saturating_add(region.width) as f64 * scale_x).ceil() as i64;
+            let bottom = (region.y.saturating_add(region.height) as f64 * scale_y).ceil() as i64;
+            for (index, plane) in planes.iter().enumerate() {
+                let (shift_x, shift_y) = if index == 0 {
+                    (0, 0)
+                } else {
+                    (chroma_x, chroma_y)
+                };
+                let rect = plane.clip(
+                    left >> shift_x,
+                    top >> shift_y,
+                    (right + (1 << shift_x) - 1) >> shift_x,
+                    (bottom + (1 << shift_y) - 1) >> shift_y,
+                );
+                let Some(rect) = rect else {
+                    continue;
+                };
+                match region.mode {
+                    Obscure::Pixelate => plane.pixelate(rect, PIXEL_BLOCK >> shift_x),
+                    Obscure::Blur => plane.blur(rect, BLUR_RADIUS >> shift_x),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Rect {
+    left: i64,
+    top: i64,
+    right: i64,
+    bottom: i64,
+}
+
+struct Plane {
+    data: *mut u8,
+    stride: isize,
+    width: i64,
+    height: i64,
+}
+
+// The planes and the chroma shift, across and down
+unsafe fn planes(frame: &mut Frame) -> Option<([Plane; 3], (u32, u32))> {
+    let f = &mut *frame.as_mut_ptr();
+    let chroma = Chroma::of(f.format)?;
+    if f.width <= 0 || f.height <= 0 || f.data[..3].iter().any(|plane| plane.is_null()) {
+        return None;
+    }
+    let (width, height) = (f.width as i64, f.height as i64);
+    let (chroma_width, chroma_height) = chroma.plane_size(width, height);
+    let plane = |index: usize, width: i64, height: i64| Plane {
+        data: f.data[index],
+        stride: f.linesize[index] as isize,
+        width,
+        height,
+    };
+    Some((
+        [
+            plane(0, width, height),
+            plane(1, chroma_width, chroma_height),
+            plane(2, chroma_width, chroma_height),
+        ],
+        chroma.shift(),
+    ))
+}
+
+impl Plane {
+    fn clip(&self, left: i64, top: i64, right: i64, bottom: i64) -> Option<Rect> {
+        let rect = Rect {
+            left: left.max(0),
+            top: top.max(0),
+            right: right.min(self.width),
+            bottom: bottom.min(self.height),
+        };
+        (rect.left < rect.right && rect.top < rect.bottom).then_some(rect)
+    }
+
+    fn at(&self, x: i64, y: i64) -> *mut u8 {
+        unsafe { self.data.offset(y as isize * self.stride + x as isize) }
+    }
+
+    // Each block becomes its average
+    fn pixelate(&self, rect: Rect, block: i64) {
+        let block = block.max(1);
+        for block_top in (rect.top..rect.bottom).step_by(block as usize) {
+            let block_bottom = (block_top + block).min(rect.bottom);
+            for block_left in (rect.left..rect.right).step_by(block as usize) {
+                let block_right = (block_left + block).min(rect.right);
+                let mut sum = 0u64;
+                for y in block_top..block_bottom {
+                    for x in block_left..block_right {
+                        sum += unsafe { *self.at(x, y) } as u64;
+                    }
+                }
+                let count = ((block_bottom - block_top) * (block_right - block_left)) as u64;
+                let average = ((sum + count / 2) / count) as u8;
+                for y in block_top..block_bottom {
+                    for x in block_left..block_right {
+                        unsafe { *self.at(x, y) = average };
+                    }
+                }
+            }
+        }
+    }
+
+    // Box blurs along rows, then columns, only reading inside the rect so
+    // nothing bleeds in from around it
+    fn blur(&self, rect: Rect, radius: i64) {
+        let radius = radius.max(1);
+        let mut line = Vec::new();
+        for _ in 0..BLUR_PASSES {
+            for y in rect.top..rect.bottom {
+                line.clear();
+                line.extend((rect.left..rect.right).map(|x| unsafe { *self.at(x, y) }));
+                box_blur(&mut line, radius);
+                for (x, &value) in (rect.left..rect.right).zip(&line) {
+                    unsafe { *self.at(x, y) = value };
+                }
+            }
+            for x in rect.left..rect.right {
+                line.clear();
+                line.extend((rect.top..rect.bottom).map(|y| unsafe { *self.at(x, y) }));
+                box_blur(&mut line, radius);
+                for (y, &value) in (rect.top..rect.bottom).zip(&line) {
+                    unsafe { *self.at(x, y) = value };
+                }
+            }
+        }
+    }
+}
+
+// Average over `radius` either side, the window shrinking at the ends
+fn box_blur(values: &mut [u8], radius: i64) {
+    let mut prefix = Vec::with_capacity(values.len() + 1);
+    prefix.push(0u32);
+    for &value in values.iter() {
+        prefix.push(prefix[prefix.len() - 1] + value as u32);
+    }
+    let last = values.len() as i64 - 1;
+    for (i, value) in values.iter_mut().enumerate() {
+        let low = (i as i64 - radius).max(0) as usize;
+        let high = (i as i64 + radius).min(last) as usize;
+        let count = (high - low + 1) as u32;
+        *value = ((prefix[high + 1] - prefix[low] + count / 2) / count) as u8;
+    }
+}
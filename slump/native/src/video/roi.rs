@@ -0,0 +1,103 @@
+use ffmpeg_next::{ffi, Frame};
+
+// In capture pixels, like privacy regions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    // 0-1: how much finer the region is quantised than the rest of the frame
+    pub boost: f64,
+}
+
+// Areas such as an editor pane that get a larger share of the bitrate, so
+// text stays crisp while the rest of the frame softens. Keyed by the
+// caller's id; where regions overlap, the one set first wins.
+#[derive(Default, Clone)]
+pub struct QualityRegions {
+    regions: Vec<(String, QualityRegion)>,
+}
+
+impl QualityRegions {
+    // Setting an id again moves or resizes it in place
+    pub fn set(&mut self, id: String, region: QualityRegion) {
+        match self
+            .regions
+            .iter_mut()
+            .find(|(existing, _)| *existing == id)
+        {
+            Some((_, existing)) => *existing = region,
+            None => self.regions.push((id, region)),
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let before = self.regions.len();
+        self.regions.retain(|(existing, _)| existing != id);
+        self.regions.len() != before
+    }
+
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+
+    // Attaches the regions to a scaled frame as region-of-interest side
+    // data; `source` is the capture size they are in. libvpx and libx264
+    // lower the quantiser there; encoders without ROI support ignore it.
+    // Nothing is ever truly lossless this way, but a boost of 1 comes close.
+    pub fn apply(&self, frame: &mut Frame, source: (u32, u32)) {
+        unsafe {
+            let f = frame.as_mut_ptr();
+            ffi::av_frame_remove_side_data(
+                f,
+                ffi::AVFrameSideDataType::AV_FRAME_DATA_REGIONS_OF_INTEREST,
+            );
+            if self.regions.is_empty() || (*f).width <= 0 || (*f).height <= 0 {
+                return;
+            }
+            let (width, height) = ((*f).width as f64, (*f).height as f64);
+            let scale_x = width / source.0.max(1) as f64;
+            let scale_y = height / source.1.max(1) as f64;
+            let rois: Vec<ffi::AVRegionOfInterest> = self
+                .regions
+                .iter()
+                .filter_map(|(_, region)| {
+                    // Rounded outward and clipped to the frame
+                    let left = (region.x as f64 * scale_x).floor().min(width);
+                    let top = (region.y as f64 * scale_y).floor().min(height);
+                    let right = (region.x.saturating_add(region.width) as f64 * scale_x)
+                        .ceil()
+                        .min(width);
+                    let bottom = (region.y.saturating_add(region.height) as f64 * scale_y)
+                        .ceil()
+                        .min(height);
+                    (left < right && top < bottom).then(|| ffi::AVRegionOfInterest {
+                        self_size: std::mem::size_of::<ffi::AVRegionOfInterest>() as u32,
+                        top: top as i32,
+                        bottom: bottom as i32,
+                        left: left as i32,
+                        right: right as i32,
+                        // Negative asks for finer quantisation
+                        qoffset: ffi::AVRational {
+                            num: -(region.boost.clamp(0.0, 1.0) * 1000.0).round() as i32,
+                            den: 1000,
+                        },
+                    })
+                })
+                .collect();
+            if rois.is_empty() {
+                return;
+            }
+            let size = std::mem::size_of_val(rois.as_slice());
+            let side_data = ffi::av_frame_new_side_data(
+                f,
+                ffi::AVFrameSideDataType::AV_FRAME_DATA_REGIONS_OF_INTEREST,
+                size as _,
+            );
+            if !side_data.is_null() {
+                std::ptr::copy_nonoverlapping(rois.as_ptr() as *const u8, (*side_data).data, size);
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stall {
+    VideoCapture,
+    AudioCapture,
+    VideoEncoder,
+    Transport { peer_id: String },
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stall::VideoCapture => write!(f, "video_capture"),
+            Stall::AudioCapture => write!(f, "audio_capture"),
+            Stall::VideoEncoder => write!(f, "video_encoder"),
+            Stall::Transport { peer_id } => write!(f, "transport:{}", peer_id),
+        }
+    }
+}
+
+// Tracks when each pipeline stage last made progress. The worker reports
+// progress as it goes and calls check() once per stats window.
+pub struct Watchdog {
+    timeout: Duration,
+    video_captured: Instant,
+    audio_captured: Instant,
+    // Last frame handed to the encoder, and the last packet it produced;
+    // encoder_output is also restarted by check(), produced never is
+    encoder_fed: Instant,
+    encoder_output: Instant,
+    produced: Instant,
+    // packets_sent per peer and when it last went up
+    peers: HashMap<String, (u64, Instant)>,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            timeout,
+            video_captured: now,
+            audio_captured: now,
+            encoder_fed: now,
+            encoder_output: now,
+            produced: now,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn video_captured(&mut self) {
+        self.video_captured = Instant::now();
+    }
+
+    pub fn audio_captured(&mut self) {
+        self.audio_captured = Instant::now();
+    }
+
+    pub fn encoder_fed(&mut self) {
+        self.encoder_fed = Instant::now();
+    }
+
+    pub fn encoder_output(&mut self) {
+        self.encoder_output = Instant::now();
+        self.produced = self.encoder_output;
+    }
+
+    pub fn peer_progress(&mut self, peer_id: &str, packets_sent: u64) {
+        let now = Instant::now();
+        match self.peers.get_mut(peer_id) {
+            Some((packets, moved)) if packets_sent != *packets => {
+                *packets = packets_sent;
+                *moved = now;
+            }
+            Some(_) => {}
+            None => {
+                self.peers.insert(peer_id.to_string(), (packets_sent, now));
+            }
+        }
+    }
+
+    pub fn retain_peers(&mut self, keep: impl Fn(&str) -> bool) {
+        self.peers.retain(|peer_id, _| keep(peer_id));
+    }
+
+    // Stages that made no progress within the timeout. A reported stage's
+    // timer restarts, so one that stays stuck comes back once per timeout
+    // instead of on every check. Inactive stages are never reported, and start
+    // fresh when they come back.
+    pub fn check(&mut self, video_active: bool, audio_active: bool) -> Vec<(Stall, Duration)> {
+        let now = Instant::now();
+        let timeout = self.timeout;
+        let mut stalls = Vec::new();
+        let mut expired = |since: &mut Instant, active: bool, stall: Stall| {
+            let idle = now.duration_since(*since);
+            if !active {
+                *since = now;
+            } else if idle >= timeout {
+                *since = now;
+                stalls.push((stall, idle));
+            }
+        };
+
+        expired(&mut self.video_captured, video_active, Stall::VideoCapture);
+        expired(&mut self.audio_captured, audio_active, Stall::AudioCapture);
+        // Peers only freeze if there is media to send, and an encoder only
+        // hangs if it is still being fed
+        let producing = now.duration_since(self.produced) < timeout;
+        let feeding = now.duration_since(self.encoder_fed) < timeout;
+        expired(&mut self.encoder_output, feeding, Stall::VideoEncoder);
+
+        // Peers that never sent anything are still connecting, not frozen
+        for (peer_id, (packets, moved)) in self.peers.iter_mut() {
+            let active = producing && *packets > 0;
+            expired(
+                moved,
+                active,
+                Stall::Transport {
+                    peer_id: peer_id.clone(),
+                },
+            );
+        }
+
+        stalls
+    }
+}
@@ -0,0 +1,148 @@
+use super::TrackKind;
+use std::{
+    sync::atomic::{AtomicI32, Ordering},
+    time::{Duration, Instant},
+};
+
+const VIDEO_CLOCK_RATE: i64 = 90_000;
+const AUDIO_CLOCK_RATE: i64 = 48_000;
+// How far consecutive audio packets may wander from the media clock before
+// they're put back on it, 40 ms; pacing jitter stays well inside that
+const MAX_AUDIO_DRIFT: u32 = 1920;
+
+// RTP timestamps for every track of a stream, counted from one epoch at the
+// track's clock rate, so a receiver lines tracks up by when their media was
+// captured rather than by when it happened to be sent.
+//
+// The audio offset can't go in the timestamps: sender reports pin each
+// track's RTP clock to when its packets were sent, which cancels any shift.
+// It holds sends back instead, audio's for a positive offset and video's and
+// the camera's for a negative one, and the receiver plays that side later.
+#[derive(Debug)]
+pub struct MediaClock {
+    epoch: Instant,
+    audio_offset_ms: AtomicI32,
+}
+
+impl Default for MediaClock {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl MediaClock {
+    pub fn new(audio_offset_ms: i32) -> Self {
+        Self {
+            epoch: Instant::now(),
+            audio_offset_ms: AtomicI32::new(audio_offset_ms),
+        }
+    }
+
+    // Takes effect from the next frame sent
+    pub fn set_audio_offset(&self, ms: i32) {
+        self.audio_offset_ms.store(ms, Ordering::Relaxed);
+    }
+
+    pub fn timestamp(&self, kind: TrackKind, captured_at: Instant) -> u32 {
+        let elapsed = captured_at.saturating_duration_since(self.epoch);
+        let rate = match kind {
+            TrackKind::Audio => AUDIO_CLOCK_RATE,
+            TrackKind::Video | TrackKind::Camera => VIDEO_CLOCK_RATE,
+        };
+        // Wraps, as RTP timestamps do
+        (elapsed.as_micros() as i64 * rate / 1_000_000) as u32
+    }
+
+    // How long this track's media is held back before it goes to peers
+    pub fn send_delay(&self, kind: TrackKind) -> Duration {
+        let offset_ms = self.audio_offset_ms.load(Ordering::Relaxed);
+        let delay_ms = match kind {
+            TrackKind::Audio => offset_ms.max(0),
+            TrackKind::Video | TrackKind::Camera => offset_ms.min(0).saturating_neg(),
+        };
+        Duration::from_millis(delay_ms as u64)
+    }
+}
+
+// RTP timestamps for consecutive audio packets. Each follows the last by its
+// length, so a receiver plays them back to back, until the count has drifted
+// from the media clock, e.g. after the microphone stalled or sending paused,
+// and it starts again from the clock.
+#[derive(Debug, Default)]
+pub struct AudioTimestamps {
+    next: Option<u32>,
+}
+
+impl AudioTimestamps {
+    // `clock` is the media clock's stamp for the packet, `samples` its length
+    pub fn next(&mut self, clock: u32, samples: u32) -> u32 {
+        let stamp = match self.next {
+            Some(next) if (clock.wrapping_sub(next) as i32).unsigned_abs() <= MAX_AUDIO_DRIFT => {
+                next
+            }
+            _ => clock,
+        };
+        self.next = Some(stamp.wrapping_add(samples));
+        stamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn stamps_each_track_at_its_clock_rate() {
+        let clock = MediaClock::new(0);
+        let at = clock.epoch + Duration::from_millis(500);
+        assert_eq!(clock.timestamp(TrackKind::Video, at), 45_000);
+        assert_eq!(clock.timestamp(TrackKind::Camera, at), 45_000);
+        assert_eq!(clock.timestamp(TrackKind::Audio, at), 24_000);
+    }
+
+    #[test]
+    fn audio_offset_holds_back_one_side() {
+        let clock = MediaClock::new(80);
+        assert_eq!(
+            clock.send_delay(TrackKind::Audio),
+            Duration::from_millis(80)
+        );
+        assert_eq!(clock.send_delay(TrackKind::Video), Duration::ZERO);
+        assert_eq!(clock.send_delay(TrackKind::Camera), Duration::ZERO);
+        // Timestamps stay on capture time either way
+        let at = clock.epoch + Duration::from_secs(1);
+        assert_eq!(clock.timestamp(TrackKind::Video, at), 90_000);
+        assert_eq!(clock.timestamp(TrackKind::Audio, at), 48_000);
+
+        clock.set_audio_offset(-80);
+        assert_eq!(clock.send_delay(TrackKind::Audio), Duration::ZERO);
+        assert_eq!(
+            clock.send_delay(TrackKind::Video),
+            Duration::from_millis(80)
+        );
+        assert_eq!(
+            clock.send_delay(TrackKind::Camera),
+            Duration::from_millis(80)
+        );
+        assert_eq!(clock.timestamp(TrackKind::Video, at), 90_000);
+    }
+
+    #[test]
+    fn audio_packets_follow_on_until_they_drift() {
+        let mut stamps = AudioTimestamps::default();
+        assert_eq!(stamps.next(1_000, 960), 1_000);
+        // Jitter in when the packet was read doesn't move it
+        assert_eq!(stamps.next(2_100, 960), 1_960);
+        assert_eq!(stamps.next(2_800, 960), 2_920);
+        // A stall puts it back on the clock
+        assert_eq!(stamps.next(20_000, 960), 20_000);
+        assert_eq!(stamps.next(20_960, 960), 20_960);
+        // And so does counting past it
+        assert_eq!(stamps.next(18_000, 960), 18_000);
+        // Across the wrap
+        let mut stamps = AudioTimestamps::default();
+        assert_eq!(stamps.next(u32::MAX - 100, 960), u32::MAX - 100);
+        assert_eq!(stamps.next(900, 960), 859);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::error::{Result, SlumpError};
+use async_trait::async_trait;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use webrtc::{
+    interceptor::{
+        stream_info::StreamInfo, Attributes, Interceptor, InterceptorBuilder, RTCPReader,
+        RTCPWriter, RTPReader, RTPWriter,
+    },
+    rtp::packet::Packet,
+    util::MarshalSize,
+};
+
+const MAX_LOSS_PERCENT: f64 = 100.0;
+const MAX_DELAY: Duration = Duration::from_secs(5);
+// A capped link queues at most this much before dropping, like a router's buffer
+const MAX_QUEUE: Duration = Duration::from_millis(500);
+
+// What the simulated uplink does to outgoing RTP. All zero passes packets
+// straight through.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImpairmentSettings {
+    pub loss_percent: f64,
+    pub latency: Duration,
+    // Extra delay drawn uniformly from zero up to this; packets can reorder
+    pub jitter: Duration,
+    pub bandwidth_kbps: Option<u32>,
+}
+
+impl ImpairmentSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=MAX_LOSS_PERCENT).contains(&self.loss_percent) {
+            return Err(SlumpError::Init(format!(
+                "Loss must be 0-100%, got {}",
+                self.loss_percent
+            )));
+        }
+        if self.latency + self.jitter > MAX_DELAY {
+            return Err(SlumpError::Init(format!(
+                "Latency plus jitter must be at most {} ms",
+                MAX_DELAY.as_millis()
+            )));
+        }
+        if self.bandwidth_kbps == Some(0) {
+            return Err(SlumpError::Init("Bandwidth cap must be non-zero".into()));
+        }
+        Ok(())
+    }
+
+    fn is_clear(&self) -> bool {
+        self.loss_percent == 0.0
+            && self.latency.is_zero()
+            && self.jitter.is_zero()
+            && self.bandwidth_kbps.is_none()
+    }
+}
+
+struct State {
+    settings: ImpairmentSettings,
+    // When the capped link finishes sending what is already queued
+    busy_until: Instant,
+    rng: u64,
+}
+
+impl State {
+    // xorshift64*; only has to look random to a congestion controller
+    fn next_random(&mut self) -> f64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        (self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Simulated bad network between every peer and the wire, for testing
+// adaptation without external tools. One handle is shared by all peers of a
+// stream, so they share the bandwidth cap the way they'd share an uplink;
+// changes apply from the next packet.
+#[derive(Clone)]
+pub struct Impairment {
+    state: Arc<parking_lot::Mutex<State>>,
+}
+
+impl Impairment {
+    pub fn new(settings: ImpairmentSettings) -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self {
+            state: Arc::new(parking_lot::Mutex::new(State {
+                settings,
+                busy_until: Instant::now(),
+                // xorshift never leaves zero
+                rng: seed | 1,
+            })),
+        }
+    }
+
+    pub fn settings(&self) -> ImpairmentSettings {
+        self.state.lock().settings
+    }
+
+    pub fn set(&self, settings: ImpairmentSettings) {
+        self.state.lock().settings = settings;
+    }
+
+    // None drops the packet; otherwise how long to hold it before sending
+    fn schedule(&self, bytes: usize) -> Option<Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        let settings = state.settings;
+        if settings.is_clear() {
+            return Some(Duration::ZERO);
+        }
+
+        if settings.loss_percent > 0.0 && state.next_random() * 100.0 < settings.loss_percent {
+            return None;
+        }
+        let mut delay = settings.latency;
+        if !settings.jitter.is_zero() {
+            delay += settings.jitter.mul_f64(state.next_random());
+        }
+        if let Some(kbps) = settings.bandwidth_kbps {
+            let start = state.busy_until.max(now);
+            if start - now > MAX_QUEUE {
+                return None;
+            }
+            state.busy_until =
+                start + Duration::from_secs_f64(bytes as f64 * 8.0 / (kbps as f64 * 1000.0));
+            delay += state.busy_until - now;
+        }
+        Some(delay)
+    }
+}
+
+impl InterceptorBuilder for Impairment {
+    fn build(
+        &self,
+        _id: &str,
+    ) -> webrtc::interceptor::error::Result<Arc<dyn Interceptor + Send + Sync>> {
+        Ok(Arc::new(ImpairmentInterceptor {
+            impairment: self.clone(),
+        }))
+    }
+}
+
+struct ImpairmentInterceptor {
+    impairment: Impairment,
+}
+
+#[async_trait]
+impl Interceptor for ImpairmentInterceptor {
+    async fn bind_rtcp_reader(
+        &self,
+        reader: Arc<dyn RTCPReader + Send + Sync>,
+    ) -> Arc<dyn RTCPReader + Send + Sync> {
+        reader
+    }
+
+    async fn bind_rtcp_writer(
+        &self,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+    ) -> Arc<dyn RTCPWriter + Send + Sync> {
+        writer
+    }
+
+    async fn bind_local_stream(
+        &self,
+        _info: &StreamInfo,
+        writer: Arc<dyn RTPWriter + Send + Sync>,
+    ) -> Arc<dyn RTPWriter + Send + Sync> {
+        Arc::new(ImpairedWriter {
+            impairment: self.impairment.clone(),
+            next: writer,
+        })
+    }
+
+    async fn unbind_local_stream(&self, _info: &StreamInfo) {}
+
+    async fn bind_remote_stream(
+        &self,
+        _info: &StreamInfo,
+        reader: Arc<dyn RTPReader + Send + Sync>,
+    ) -> Arc<dyn RTPReader + Send + Sync> {
+        reader
+    }
+
+    async fn unbind_remote_stream(&self, _info: &StreamInfo) {}
+
+    async fn close(&self) -> webrtc::interceptor::error::Result<()> {
+        Ok(())
+    }
+}
+
+struct ImpairedWriter {
+    impairment: Impairment,
+    next: Arc<dyn RTPWriter + Send + Sync>,
+}
+
+#[async_trait]
+impl RTPWriter for ImpairedWriter {
+    async fn write(
+        &self,
+        packet: &Packet,
+        attributes: &Attributes,
+    ) -> webrtc::interceptor::error::Result<usize> {
+        let size = packet.header.marshal_size() + packet.payload.len();
+        match self.impairment.schedule(size) {
+            // Lost on the way; the sender can't tell
+            None => Ok(size),
+            Some(delay) if delay.is_zero() => self.next.write(packet, attributes).await,
+            Some(delay) => {
+                let next = Arc::clone(&self.next);
+                let packet = packet.clone();
+                let attributes = attributes.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    if let Err(e) = next.write(&packet, &attributes).await {
+                        log::trace!("Delayed packet not sent: {}", e);
+                    }
+                });
+                Ok(size)
+            }
+        }
+    }
+}
@@ -1,4 +1,16 @@
+mod clock;
+mod impair;
+mod negotiation;
+mod reports;
+mod wire;
+
+pub use clock::{AudioTimestamps, MediaClock};
+pub use impair::{Impairment, ImpairmentSettings};
+pub use negotiation::{HeaderExtension, NegotiatedCodec, Negotiation};
+
 use crate::error::{Result, SlumpError};
+use reports::ReceiverReports;
+use wire::WireCounter;
 use bytes::Bytes;
 use futures_util::{
     stream::{SplitSink, SplitStream},
@@ -21,12 +33,19 @@ use tokio_tungstenite::{
 };
 use webrtc::{
     api::{
-        interceptor_registry::register_default_interceptors,
+        interceptor_registry::{configure_rtcp_reports, configure_twcc},
         media_engine::{MediaEngine, MIME_TYPE_OPUS, MIME_TYPE_VP8},
         APIBuilder,
     },
-    ice_transport::ice_server::RTCIceServer,
-    interceptor::registry::Registry,
+    data_channel::{
+        data_channel_message::DataChannelMessage, data_channel_state::RTCDataChannelState,
+        RTCDataChannel,
+    },
+    ice_transport::{ice_candidate::RTCIceCandidate, ice_server::RTCIceServer},
+    interceptor::{
+        nack::{generator::Generator, responder::Responder},
+        registry::Registry,
+    },
     media::{
         codec::h264::h264_errors::Error as H264Error,
         sample::Sample,
@@ -38,9 +57,12 @@ use webrtc::{
         sdp::session_description::RTCSessionDescription,
         RTCPeerConnection,
     },
+    rtcp::goodbye::Goodbye,
+    rtp_transceiver::{rtp_sender::RTCRtpSender, RTCPFeedback},
     rtp_transceiver::rtp_codec::{
         RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType, RTCRtpCodecParametersParameters,
     },
+    stats::StatsReportType,
     track::track_local::track_local_static_rtp::TrackLocalStaticRTPOptions,
     util::Unmarshal,
 };
@@ -52,21 +74,393 @@ pub struct IceCandidate {
     pub sdp_m_line_index: Option<u16>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+pub const SIGNAL_PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Capabilities {
+    #[serde(default)]
+    pub codecs: Vec<String>,
+    #[serde(default)]
+    pub simulcast: bool,
+}
+
+impl Capabilities {
+    pub fn local() -> Self {
+        Self {
+            codecs: vec![MIME_TYPE_VP8.to_owned(), MIME_TYPE_OPUS.to_owned()],
+            simulcast: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SignalMessage {
     Offer { sdp: String },
     Answer { sdp: String },
     Ice { candidate: IceCandidate },
+    Capabilities(Capabilities),
     Error(String),
 }
 
+// Wire format: the message variant is flattened next to the envelope fields, so
+// unversioned messages like {"Offer":{...}} still parse as version 1
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignalEnvelope {
+    #[serde(default = "legacy_protocol_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(flatten)]
+    pub message: SignalMessage,
+}
+
+fn legacy_protocol_version() -> u32 {
+    1
+}
+
+impl SignalEnvelope {
+    pub fn new(message: SignalMessage, peer_id: &str, session_id: &str) -> Self {
+        Self {
+            version: SIGNAL_PROTOCOL_VERSION,
+            peer_id: Some(peer_id.to_string()),
+            session_id: Some(session_id.to_string()),
+            message,
+        }
+    }
+}
+
+pub fn new_session_id() -> String {
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let count = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    format!("{:x}-{:x}", nanos, count)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackKind {
+    Video,
+    Camera,
+    Audio,
+}
+
+impl TrackKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TrackKind::Video => "video",
+            TrackKind::Camera => "camera",
+            TrackKind::Audio => "audio",
+        }
+    }
+}
+
+impl std::str::FromStr for TrackKind {
+    type Err = SlumpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "video" | "screen" => Ok(TrackKind::Video),
+            "camera" => Ok(TrackKind::Camera),
+            "audio" => Ok(TrackKind::Audio),
+            other => Err(SlumpError::Webrtc(format!("Unknown track kind: {}", other))),
+        }
+    }
+}
+
+struct LocalTrack {
+    track: Arc<TrackLocalStaticRTP>,
+    sender: Arc<RTCRtpSender>,
+    // Kept across replace_track, which reuses the sender
+    ssrc: u32,
+}
+
+impl LocalTrack {
+    fn create(kind: TrackKind) -> Result<Arc<TrackLocalStaticRTP>> {
+        let (capability, id, stream_id) = match kind {
+            TrackKind::Video => (
+                RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_VP8.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "profile-level-id=42e01f;level-asymmetry-allowed=1".to_owned(),
+                    rtcp_feedback: vec![],
+                },
+                "video",
+                "slump-video",
+            ),
+            // Separate stream id so receivers can lay the camera out on its own
+            TrackKind::Camera => (
+                RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_VP8.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: String::new(),
+                    rtcp_feedback: vec![],
+                },
+                "camera",
+                "slump-camera",
+            ),
+            TrackKind::Audio => (
+                RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_OPUS.to_owned(),
+                    clock_rate: 48000,
+                    channels: 2,
+                    sdp_fmtp_line: "minptime=10;useinbandfec=1".to_owned(),
+                    rtcp_feedback: vec![],
+                },
+                "audio",
+                "slump-audio",
+            ),
+        };
+
+        Ok(Arc::new(TrackLocalStaticRTP::new(
+            capability,
+            id.to_owned(),
+            stream_id.to_owned(),
+        )))
+    }
+
+    async fn attach(peer_connection: &RTCPeerConnection, kind: TrackKind) -> Result<Self> {
+        let track = Self::create(kind)?;
+        let sender = peer_connection
+            .add_track(Arc::clone(&track) as Arc<_>)
+            .await
+            .map_err(|e| SlumpError::Webrtc(e.to_string()))?;
+        let ssrc = sender
+            .get_parameters()
+            .await
+            .encodings
+            .first()
+            .map_or(0, |encoding| encoding.ssrc);
+
+        Ok(Self {
+            track,
+            sender,
+            ssrc,
+        })
+    }
+}
+
+// One peer's track, detached from the transport so media can be written
+// without holding the stream lock
+#[derive(Clone)]
+pub struct TrackWriter {
+    peer_id: String,
+    track: Arc<TrackLocalStaticRTP>,
+}
+
+impl TrackWriter {
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    #[tracing::instrument(name = "send", level = "trace", skip_all, fields(peer = %self.peer_id, bytes = frame.len()))]
+    pub async fn write(&self, frame: &[u8], timestamp: u32) -> Result<()> {
+        self.track.write_rtp(&frame, timestamp, None)?;
+        Ok(())
+    }
+}
+
+// A fresh track for an existing sender, made while the stream is locked; the
+// sender switches over in apply(), which is awaited without the lock, and
+// finish_swap() then records it on the transport
+pub struct TrackSwap {
+    peer_id: String,
+    kind: TrackKind,
+    sender: Arc<RTCRtpSender>,
+    track: Arc<TrackLocalStaticRTP>,
+}
+
+impl TrackSwap {
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    pub async fn apply(&self) -> Result<()> {
+        self.sender
+            .replace_track(Some(Arc::clone(&self.track) as Arc<_>))
+            .await
+            .map_err(|e| SlumpError::Webrtc(e.to_string()))
+    }
+}
+
+// What refresh() needs from a peer, detached from the transport like
+// TrackWriter so stats are gathered without holding the stream lock
+#[derive(Clone)]
+pub struct StatsProbe {
+    peer_id: String,
+    peer_connection: Arc<RTCPeerConnection>,
+    senders: Vec<(TrackKind, Arc<RTCRtpSender>)>,
+    last_stats: Arc<Mutex<Option<Stats>>>,
+    reports: ReceiverReports,
+    wire: WireCounter,
+}
+
+impl StatsProbe {
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    pub async fn refresh(&self) -> Result<Stats> {
+        let report = self.peer_connection.get_stats().await;
+        let now = Instant::now();
+
+        let mut bytes_sent = 0;
+        let mut packets_sent = 0;
+        let mut audio_packets_sent = 0;
+        let mut rtt = 0.0;
+        let mut packet_loss: f64 = 0.0;
+        let mut selected_pair = None;
+        let mut local_candidates = HashMap::new();
+        let mut remote_tracks = Vec::new();
+
+        let mut track_ssrcs = HashMap::new();
+        for (kind, sender) in &self.senders {
+            for encoding in sender.get_parameters().await.encodings {
+                track_ssrcs.insert(encoding.ssrc, *kind);
+            }
+        }
+
+        for stat in report.reports.values() {
+            match stat {
+                StatsReportType::OutboundRTP(outbound) => {
+                    bytes_sent += outbound.bytes_sent;
+                    packets_sent += outbound.packets_sent;
+                    if track_ssrcs.get(&outbound.ssrc) == Some(&TrackKind::Audio) {
+                        audio_packets_sent += outbound.packets_sent;
+                    }
+                }
+                StatsReportType::RemoteInboundRTP(remote) => {
+                    if let Some(round_trip) = remote.round_trip_time {
+                        rtt = round_trip * 1000.0;
+                    }
+                    packet_loss = packet_loss.max(remote.fraction_lost * 100.0);
+                    if let Some(&kind) = track_ssrcs.get(&remote.ssrc) {
+                        remote_tracks.push((kind, remote.packets_received, remote.packets_lost));
+                    }
+                }
+                StatsReportType::CandidatePair(pair) if pair.nominated => {
+                    if rtt == 0.0 {
+                        rtt = pair.current_round_trip_time * 1000.0;
+                    }
+                    selected_pair = Some(pair.local_candidate_id.clone());
+                }
+                StatsReportType::LocalCandidate(candidate) => {
+                    local_candidates.insert(
+                        candidate.id.clone(),
+                        format!(
+                            "{}:{} ({})",
+                            candidate.ip, candidate.port, candidate.candidate_type
+                        ),
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        // What the peer reports over RTCP wins; the stats report only covers
+        // what it doesn't
+        let quality = self.reports.quality();
+        if let Some(report_rtt) = quality.rtt_ms {
+            rtt = report_rtt;
+        }
+        packet_loss = packet_loss.max(quality.fraction_lost * 100.0);
+
+        let mut sent_bytes = SentBytes::default();
+        for (ssrc, bytes) in self.wire.bytes() {
+            if let Some(&kind) = track_ssrcs.get(&ssrc) {
+                *sent_bytes.track(kind) += bytes.media;
+            }
+            sent_bytes.rtx += bytes.rtx;
+            sent_bytes.fec += bytes.fec;
+            sent_bytes.padding += bytes.padding;
+        }
+
+        let mut last_stats = self.last_stats.lock().unwrap();
+        let sent_kbps = match last_stats.as_ref() {
+            Some(prev) => {
+                let elapsed = now.duration_since(prev.timestamp).as_secs_f64();
+                if elapsed > 0.0 {
+                    sent_bytes.rates_since(&prev.sent_bytes, elapsed)
+                } else {
+                    prev.sent_kbps
+                }
+            }
+            None => SentBitrates::default(),
+        };
+        let bitrate = match last_stats.as_ref() {
+            Some(prev) if bytes_sent >= prev.bytes_sent => {
+                let elapsed = now.duration_since(prev.timestamp).as_secs_f64();
+                if elapsed > 0.0 {
+                    ((bytes_sent - prev.bytes_sent) as f64 * 8.0) / elapsed / 1000.0
+                } else {
+                    prev.bitrate
+                }
+            }
+            _ => 0.0,
+        };
+
+        let tracks = remote_tracks
+            .into_iter()
+            .map(|(kind, received, lost)| {
+                let previous = last_stats
+                    .as_ref()
+                    .and_then(|prev| prev.tracks.iter().find(|track| track.kind == kind));
+                // Counters restart with a renegotiated track
+                let (window_received, window_lost) = match previous {
+                    Some(prev) if received >= prev.packets_received => (
+                        received - prev.packets_received,
+                        (lost - prev.packets_lost).max(0),
+                    ),
+                    _ => (received, lost.max(0)),
+                };
+                TrackLoss {
+                    kind,
+                    packets_received: received,
+                    packets_lost: lost,
+                    loss_percent: loss_percent(lost, received),
+                    window_loss_percent: loss_percent(window_lost, window_received),
+                    window_packets: window_received + window_lost as u64,
+                }
+            })
+            .collect();
+
+        let stats = Stats {
+            timestamp: now,
+            bytes_sent,
+            packets_sent,
+            audio_packets_sent,
+            rtt,
+            jitter: quality.jitter_ms,
+            bitrate,
+            packet_loss,
+            selected_candidate: selected_pair.and_then(|id| local_candidates.remove(&id)),
+            tracks,
+            sent_bytes,
+            sent_kbps,
+        };
+        *last_stats = Some(stats.clone());
+
+        Ok(stats)
+    }
+}
+
 pub struct WebRTCTransport {
+    peer_id: String,
     peer_connection: Arc<RTCPeerConnection>,
-    video_track: Arc<TrackLocalStaticRTP>,
-    audio_track: Arc<TrackLocalStaticRTP>,
+    tracks: HashMap<TrackKind, LocalTrack>,
+    control_channel: Arc<RTCDataChannel>,
+    caption_channel: Arc<RTCDataChannel>,
+    closed: bool,
     ws_sender: mpsc::UnboundedSender<Message>,
     last_stats: Arc<Mutex<Option<Stats>>>,
     last_ping: Arc<Mutex<Instant>>,
+    reports: ReceiverReports,
+    wire: WireCounter,
 }
 
 #[derive(Debug, Clone)]
@@ -74,15 +468,112 @@ pub struct Stats {
     pub timestamp: Instant,
     pub bytes_sent: u64,
     pub packets_sent: u64,
+    pub audio_packets_sent: u64,
     pub rtt: f64,
     pub jitter: f64,
     pub bitrate: f64,
+    pub packet_loss: f64,
+    pub selected_candidate: Option<String>,
+    pub tracks: Vec<TrackLoss>,
+    pub sent_bytes: SentBytes,
+    pub sent_kbps: SentBitrates,
+}
+
+// RTP bytes written to the peer since it connected, media per track and
+// overhead across all of them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SentBytes {
+    pub video: u64,
+    pub camera: u64,
+    pub audio: u64,
+    pub rtx: u64,
+    pub fec: u64,
+    pub padding: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SentBitrates {
+    pub video_kbps: f64,
+    pub camera_kbps: f64,
+    pub audio_kbps: f64,
+    pub rtx_kbps: f64,
+    pub fec_kbps: f64,
+    pub padding_kbps: f64,
+}
+
+// Summing peers gives the whole uplink
+impl std::ops::AddAssign for SentBitrates {
+    fn add_assign(&mut self, other: Self) {
+        self.video_kbps += other.video_kbps;
+        self.camera_kbps += other.camera_kbps;
+        self.audio_kbps += other.audio_kbps;
+        self.rtx_kbps += other.rtx_kbps;
+        self.fec_kbps += other.fec_kbps;
+        self.padding_kbps += other.padding_kbps;
+    }
+}
+
+impl SentBytes {
+    pub fn total(&self) -> u64 {
+        self.video + self.camera + self.audio + self.rtx + self.fec + self.padding
+    }
+
+    fn track(&mut self, kind: TrackKind) -> &mut u64 {
+        match kind {
+            TrackKind::Video => &mut self.video,
+            TrackKind::Camera => &mut self.camera,
+            TrackKind::Audio => &mut self.audio,
+        }
+    }
+
+    fn rates_since(&self, previous: &SentBytes, seconds: f64) -> SentBitrates {
+        let kbps =
+            |now: u64, before: u64| now.saturating_sub(before) as f64 * 8.0 / 1000.0 / seconds;
+        SentBitrates {
+            video_kbps: kbps(self.video, previous.video),
+            camera_kbps: kbps(self.camera, previous.camera),
+            audio_kbps: kbps(self.audio, previous.audio),
+            rtx_kbps: kbps(self.rtx, previous.rtx),
+            fec_kbps: kbps(self.fec, previous.fec),
+            padding_kbps: kbps(self.padding, previous.padding),
+        }
+    }
+}
+
+// Loss on one of our tracks as the peer counts it, from remote-inbound-rtp
+#[derive(Debug, Clone, Copy)]
+pub struct TrackLoss {
+    pub kind: TrackKind,
+    pub packets_received: u64,
+    pub packets_lost: i64,
+    // Percent since the track started
+    pub loss_percent: f64,
+    // Percent since the previous refresh, over `window_packets`; no new
+    // report in between leaves that at zero
+    pub window_loss_percent: f64,
+    pub window_packets: u64,
+}
+
+fn loss_percent(lost: i64, received: u64) -> f64 {
+    // Duplicates can push the peer's count below zero
+    let lost = lost.max(0) as f64;
+    let total = received as f64 + lost;
+    if total == 0.0 {
+        0.0
+    } else {
+        lost / total * 100.0
+    }
 }
 
 impl WebRTCTransport {
     pub async fn new(
+        peer_id: String,
         stun_servers: Vec<String>,
         turn_servers: Vec<(String, Option<String>, Option<String>)>,
+        track_kinds: &[TrackKind],
+        // Packets per track kept for retransmission; a power of two
+        rtp_history: u16,
+        impairment: Option<Impairment>,
     ) -> Result<Self> {
         // Configure WebRTC
         let mut media_engine = MediaEngine::default();
@@ -119,8 +610,33 @@ impl WebRTCTransport {
             RTPCodecType::Audio,
         )?;
 
+        // The default interceptors, except that NACK keeps `rtp_history` packets
         let mut registry = Registry::new();
-        register_default_interceptors(&mut registry, &mut media_engine)?;
+        // First in the chain is closest to the wire, so retransmissions are
+        // impaired too
+        if let Some(impairment) = impairment {
+            registry.add(Box::new(impairment));
+        }
+        let wire = WireCounter::default();
+        registry.add(Box::new(wire.clone()));
+        registry = configure_rtcp_reports(registry);
+        // Sequence numbers on what we send, so the peer returns TWCC feedback
+        registry = configure_twcc(registry, &mut media_engine)?;
+        let reports = ReceiverReports::default();
+        registry.add(Box::new(reports.clone()));
+        for parameter in ["", "pli"] {
+            media_engine.register_feedback(
+                RTCPFeedback {
+                    typ: "nack".to_owned(),
+                    parameter: parameter.to_owned(),
+                },
+                RTPCodecType::Video,
+            );
+        }
+        registry.add(Box::new(Generator::builder()));
+        registry.add(Box::new(
+            Responder::builder().with_log2_size(rtp_history.trailing_zeros() as u8),
+        ));
 
         // Configure ICE servers
         let mut ice_servers = vec![];
@@ -134,6 +650,9 @@ impl WebRTCTransport {
             });
         }
         
+        // webrtc-rs has no hook for TURN-over-TLS certificate settings, so turns:
+        // servers are always verified against the bundled web PKI roots; the
+        // stream warns when signaling was given settings these won't get
         for (url, username, credential) in turn_servers {
             ice_servers.push(RTCIceServer {
                 urls: vec![url],
@@ -155,57 +674,25 @@ impl WebRTCTransport {
 
         let peer_connection = Arc::new(api.new_peer_connection(config).await?);
 
-        // Create video track
-        let video_track = Arc::new(
-            TrackLocalStaticRTP::new(
-                RTCRtpCodecCapability {
-                    mime_type: MIME_TYPE_VP8.to_owned(),
-                    clock_rate: 90000,
-                    channels: 0,
-                    sdp_fmtp_line: "profile-level-id=42e01f;level-asymmetry-allowed=1".to_owned(),
-                    rtcp_feedback: vec![],
-                },
-                "video".to_owned(),
-                "slump-video".to_owned(),
-            )
-            .map_err(|e| SlumpError::Webrtc(e.to_string()))?,
-        );
-
-        // Create audio track
-        let audio_track = Arc::new(
-            TrackLocalStaticRTP::new(
-                RTCRtpCodecCapability {
-                    mime_type: MIME_TYPE_OPUS.to_owned(),
-                    clock_rate: 48000,
-                    channels: 2,
-                    sdp_fmtp_line: "minptime=10;useinbandfec=1".to_owned(),
-                    rtcp_feedback: vec![],
-                },
-                "audio".to_owned(),
-                "slump-audio".to_owned(),
-            )
-            .map_err(|e| SlumpError::Webrtc(e.to_string()))?,
-        );
-
-        // Add tracks to peer connection
-        let rtp_sender = peer_connection
-            .add_track(Arc::clone(&video_track) as Arc<_>)
-            .await
-            .map_err(|e| SlumpError::Webrtc(e.to_string()))?;
-
-        let _rtp_sender_audio = peer_connection
-            .add_track(Arc::clone(&audio_track) as Arc<_>)
-            .await
-            .map_err(|e| SlumpError::Webrtc(e.to_string()))?;
+        // Create and attach the requested tracks; omitted kinds get no m-line
+        let mut tracks = HashMap::new();
+        for &kind in track_kinds {
+            tracks.insert(kind, LocalTrack::attach(&peer_connection, kind).await?);
+        }
 
         // Setup data channel for control messages
-        let data_channel = peer_connection
+        let control_channel = peer_connection
             .create_data_channel("control", None)
+            .await
+            .map_err(|e| SlumpError::Webrtc(e.to_string()))?;
+        // Timed text pushed by the app, see send_caption
+        let caption_channel = peer_connection
+            .create_data_channel("captions", None)
+            .await
             .map_err(|e| SlumpError::Webrtc(e.to_string()))?;
 
-        // Setup stats collection
+        // Setup stats collection, refreshed from the streaming loop
         let last_stats = Arc::new(Mutex::new(None));
-        let last_stats_clone = Arc::clone(&last_stats);
         
         // Setup ping/pong for connection monitoring
         let last_ping = Arc::new(Mutex::new(Instant::now()));
@@ -241,23 +728,298 @@ impl WebRTCTransport {
         });
 
         Ok(Self {
+            peer_id,
             peer_connection,
-            video_track,
-            audio_track,
+            tracks,
+            control_channel,
+            caption_channel,
+            closed: false,
             ws_sender,
             last_stats,
             last_ping,
+            reports,
+            wire,
         })
     }
 
-    pub async fn send_video_frame(&self, frame: &[u8], timestamp: u32) -> Result<()> {
-        self.video_track.write_rtp(&frame, timestamp, None)?;
+    // Packetizing happens inside the track write, so one span covers both
+    #[tracing::instrument(name = "send", level = "trace", skip_all, fields(peer = %self.peer_id, kind = ?kind, bytes = frame.len()))]
+    pub async fn send_frame(&self, kind: TrackKind, frame: &[u8], timestamp: u32) -> Result<()> {
+        if let Some(local) = self.tracks.get(&kind) {
+            local.track.write_rtp(&frame, timestamp, None)?;
+        }
         Ok(())
     }
 
-    pub async fn send_audio_frame(&self, frame: &[u8], timestamp: u32) -> Result<()> {
-        self.audio_track.write_rtp(&frame, timestamp, None)?;
-        Ok(())
+    // Queue a remote signal (answer or ICE) for the signaling task
+    pub fn handle_signal(&self, message: &SignalMessage) -> Result<()> {
+        let text = serde_json::to_string(message).map_err(|e| SlumpError::Webrtc(e.to_string()))?;
+        self.ws_sender
+            .send(Message::Text(text))
+            .map_err(|e| SlumpError::Webrtc(e.to_string()))
+    }
+
+    // Forward locally gathered ICE candidates to the remote side
+    pub fn forward_local_candidates(&self, outgoing: mpsc::UnboundedSender<SignalMessage>) {
+        self.peer_connection
+            .on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+                let outgoing = outgoing.clone();
+                Box::pin(async move {
+                    let Some(candidate) = candidate else {
+                        return;
+                    };
+                    match candidate.to_json() {
+                        Ok(init) => {
+                            let _ = outgoing.send(SignalMessage::Ice {
+                                candidate: IceCandidate {
+                                    candidate: init.candidate,
+                                    sdp_mid: init.sdp_mid,
+                                    sdp_m_line_index: init.sdp_mline_index,
+                                },
+                            });
+                        }
+                        Err(e) => log::warn!("Failed to serialize ICE candidate: {}", e),
+                    }
+                })
+            }));
+    }
+
+    // Text the peer sends on the control channel; binary messages are ignored
+    pub fn on_control_message(&self, handler: impl Fn(String) + Send + Sync + 'static) {
+        let handler = Arc::new(handler);
+        self.control_channel
+            .on_message(Box::new(move |message: DataChannelMessage| {
+                let handler = Arc::clone(&handler);
+                Box::pin(async move {
+                    if !message.is_string {
+                        return;
+                    }
+                    match String::from_utf8(message.data.to_vec()) {
+                        Ok(text) => handler(text),
+                        Err(e) => log::warn!("Dropped a control message that is not UTF-8: {}", e),
+                    }
+                })
+            }));
+    }
+
+    // Queues a caption message without waiting for the send; false until the
+    // channel has opened
+    pub fn send_caption(&self, message: String) -> bool {
+        self.send_text(&self.caption_channel, message, "caption")
+    }
+
+    // As send_caption, for messages to the peer on the control channel
+    pub fn send_control(&self, message: String) -> bool {
+        self.send_text(&self.control_channel, message, "control message")
+    }
+
+    fn send_text(&self, channel: &Arc<RTCDataChannel>, message: String, what: &'static str) -> bool {
+        if channel.ready_state() != RTCDataChannelState::Open {
+            return false;
+        }
+        let Ok(rt) = crate::runtime::get() else {
+            return false;
+        };
+        let channel = Arc::clone(channel);
+        let peer_id = self.peer_id.clone();
+        rt.spawn(async move {
+            if let Err(e) = channel.send_text(message).await {
+                log::warn!("Failed to send {} to {}: {}", what, peer_id, e);
+            }
+        });
+        true
+    }
+
+    // A later replace_track leaves this writer on the detached track
+    pub fn writer(&self, kind: TrackKind) -> Option<TrackWriter> {
+        self.tracks.get(&kind).map(|local| TrackWriter {
+            peer_id: self.peer_id.clone(),
+            track: Arc::clone(&local.track),
+        })
+    }
+
+    pub fn has_track(&self, kind: TrackKind) -> bool {
+        self.tracks.contains_key(&kind)
+    }
+
+    // PLIs and FIRs the peer sent since the last call, per track
+    pub fn take_keyframe_requests(&self) -> Vec<(TrackKind, u32)> {
+        let requests = self.reports.take_keyframe_requests();
+        self.tracks
+            .iter()
+            .filter_map(|(&kind, local)| Some((kind, *requests.get(&local.ssrc)?)))
+            .collect()
+    }
+
+    // Adding a track changes the m-lines, so the caller must renegotiate
+    pub async fn add_track(&mut self, kind: TrackKind) -> Result<bool> {
+        if self.tracks.contains_key(&kind) {
+            return Ok(false);
+        }
+
+        let local = LocalTrack::attach(&self.peer_connection, kind).await?;
+        self.tracks.insert(kind, local);
+        Ok(true)
+    }
+
+    pub async fn remove_track(&mut self, kind: TrackKind) -> Result<bool> {
+        let Some(local) = self.tracks.remove(&kind) else {
+            return Ok(false);
+        };
+
+        self.peer_connection
+            .remove_track(&local.sender)
+            .await
+            .map_err(|e| SlumpError::Webrtc(e.to_string()))?;
+        Ok(true)
+    }
+
+    // Swaps the track behind an existing sender, no renegotiation needed
+    pub async fn replace_track(&mut self, kind: TrackKind) -> Result<bool> {
+        let Some(swap) = self.track_swap(kind)? else {
+            return Ok(false);
+        };
+        swap.apply().await?;
+        self.finish_swap(swap);
+        Ok(true)
+    }
+
+    // replace_track in parts, for callers that can't hold the transport
+    // across the await; None if there is no such track
+    pub fn track_swap(&self, kind: TrackKind) -> Result<Option<TrackSwap>> {
+        let Some(local) = self.tracks.get(&kind) else {
+            return Ok(None);
+        };
+        Ok(Some(TrackSwap {
+            peer_id: self.peer_id.clone(),
+            kind,
+            sender: Arc::clone(&local.sender),
+            track: LocalTrack::create(kind)?,
+        }))
+    }
+
+    // A track removed or replaced meanwhile is left as it is
+    pub fn finish_swap(&mut self, swap: TrackSwap) {
+        if let Some(local) = self.tracks.get_mut(&swap.kind) {
+            if Arc::ptr_eq(&local.sender, &swap.sender) {
+                local.track = swap.track;
+            }
+        }
+    }
+
+    pub async fn set_remote_answer(&self, sdp: String) -> Result<()> {
+        let answer =
+            RTCSessionDescription::answer(sdp).map_err(|e| SlumpError::Webrtc(e.to_string()))?;
+        self.peer_connection
+            .set_remote_description(answer)
+            .await
+            .map_err(|e| SlumpError::Webrtc(e.to_string()))
+    }
+
+    pub async fn create_offer(&self) -> Result<String> {
+        let offer = self
+            .peer_connection
+            .create_offer(None)
+            .await
+            .map_err(|e| SlumpError::Webrtc(e.to_string()))?;
+        let sdp = offer.sdp.clone();
+
+        self.peer_connection
+            .set_local_description(offer)
+            .await
+            .map_err(|e| SlumpError::Webrtc(e.to_string()))?;
+        Ok(sdp)
+    }
+
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    pub fn stats_probe(&self) -> StatsProbe {
+        StatsProbe {
+            peer_id: self.peer_id.clone(),
+            peer_connection: Arc::clone(&self.peer_connection),
+            senders: self
+                .tracks
+                .iter()
+                .map(|(&kind, local)| (kind, Arc::clone(&local.sender)))
+                .collect(),
+            last_stats: Arc::clone(&self.last_stats),
+            reports: self.reports.clone(),
+            wire: self.wire.clone(),
+        }
+    }
+
+    // Negotiated SDP and ICE state, for a diagnostics bundle
+    pub async fn diagnostics(&self) -> serde_json::Value {
+        let report = self.peer_connection.get_stats().await;
+        let mut candidates = Vec::new();
+        let mut pairs = Vec::new();
+        for stat in report.reports.values() {
+            match stat {
+                StatsReportType::LocalCandidate(candidate)
+                | StatsReportType::RemoteCandidate(candidate) => {
+                    candidates.push(serde_json::to_value(candidate).unwrap_or_default());
+                }
+                StatsReportType::CandidatePair(pair) => {
+                    pairs.push(serde_json::to_value(pair).unwrap_or_default());
+                }
+                _ => {}
+            }
+        }
+
+        let local = self.peer_connection.local_description().await;
+        let remote = self.peer_connection.remote_description().await;
+        serde_json::json!({
+            "peer_id": self.peer_id,
+            "connection_state": self.peer_connection.connection_state().to_string(),
+            "ice_connection_state": self.peer_connection.ice_connection_state().to_string(),
+            "ice_gathering_state": self.peer_connection.ice_gathering_state().to_string(),
+            "local_description": local.map(|description| description.sdp),
+            "remote_description": remote.map(|description| description.sdp),
+            "candidates": candidates,
+            "candidate_pairs": pairs,
+        })
+    }
+
+    // Orderly teardown: flush senders, announce BYE, then close channels and the connection
+    pub async fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+
+        let mut sources = Vec::new();
+        for local in self.tracks.values() {
+            let parameters = local.sender.get_parameters().await;
+            sources.extend(parameters.encodings.iter().map(|encoding| encoding.ssrc));
+
+            if let Err(e) = local.sender.stop().await {
+                log::warn!("Failed to stop sender for {}: {}", self.peer_id, e);
+            }
+        }
+
+        if !sources.is_empty() {
+            let bye = Goodbye {
+                sources,
+                reason: Bytes::from_static(b"stream stopped"),
+            };
+            if let Err(e) = self.peer_connection.write_rtcp(&[Box::new(bye)]).await {
+                log::warn!("Failed to send RTCP BYE to {}: {}", self.peer_id, e);
+            }
+        }
+
+        if let Err(e) = self.control_channel.close().await {
+            log::warn!("Failed to close control channel for {}: {}", self.peer_id, e);
+        }
+        if let Err(e) = self.caption_channel.close().await {
+            log::warn!("Failed to close caption channel for {}: {}", self.peer_id, e);
+        }
+
+        self.peer_connection
+            .close()
+            .await
+            .map_err(|e| SlumpError::Webrtc(e.to_string()))
     }
 
     pub fn get_stats(&self) -> Option<Stats> {
@@ -267,13 +1029,26 @@ impl WebRTCTransport {
     pub fn is_connected(&self) -> bool {
         self.last_ping.lock().unwrap().elapsed() < Duration::from_secs(5)
     }
+
+    // ICE gave up on every candidate pair; usually a NAT that needs TURN
+    pub fn ice_failed(&self) -> bool {
+        self.peer_connection.connection_state() == RTCPeerConnectionState::Failed
+    }
 }
 
 impl Drop for WebRTCTransport {
     fn drop(&mut self) {
-        let pc = Arc::clone(&self.peer_connection);
-        tokio::spawn(async move {
-            let _ = pc.close().await;
-        });
+        if self.closed {
+            return;
+        }
+
+        // Best effort only; callers should await close() before dropping
+        log::warn!("Transport {} dropped without close()", self.peer_id);
+        if let Ok(rt) = crate::runtime::get() {
+            let pc = Arc::clone(&self.peer_connection);
+            rt.spawn(async move {
+                let _ = pc.close().await;
+            });
+        }
     }
 }
@@ -0,0 +1,123 @@
+use super::WebRTCTransport;
+use std::fmt::{self, Write};
+
+// What one peer's session settled on, read back from the peer connection
+#[derive(Debug, Clone, Default)]
+pub struct Negotiation {
+    pub signaling_state: String,
+    pub local_sdp: Option<String>,
+    pub remote_sdp: Option<String>,
+    pub codecs: Vec<NegotiatedCodec>,
+    pub header_extensions: Vec<HeaderExtension>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NegotiatedCodec {
+    // Media id of the transceiver, e.g. "0"
+    pub mid: String,
+    pub kind: String,
+    pub mime_type: String,
+    pub payload_type: u8,
+    pub clock_rate: u32,
+    pub channels: u16,
+    pub fmtp: String,
+    // RTCP feedback, e.g. "nack pli"
+    pub feedback: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeaderExtension {
+    pub mid: String,
+    pub id: isize,
+    pub uri: String,
+}
+
+impl WebRTCTransport {
+    pub async fn negotiation(&self) -> Negotiation {
+        let mut negotiation = Negotiation {
+            signaling_state: self.peer_connection.signaling_state().to_string(),
+            local_sdp: self
+                .peer_connection
+                .local_description()
+                .await
+                .map(|description| description.sdp),
+            remote_sdp: self
+                .peer_connection
+                .remote_description()
+                .await
+                .map(|description| description.sdp),
+            ..Default::default()
+        };
+
+        // The sender's parameters only list what both sides agreed on once
+        // the answer is applied
+        for transceiver in self.peer_connection.get_transceivers().await {
+            let mid = transceiver.mid().unwrap_or_default().to_string();
+            let kind = transceiver.kind().to_string();
+            let parameters = transceiver.sender().await.get_parameters().await;
+            for codec in parameters.rtp_parameters.codecs {
+                negotiation.codecs.push(NegotiatedCodec {
+                    mid: mid.clone(),
+                    kind: kind.clone(),
+                    mime_type: codec.capability.mime_type,
+                    payload_type: codec.payload_type,
+                    clock_rate: codec.capability.clock_rate,
+                    channels: codec.capability.channels,
+                    fmtp: codec.capability.sdp_fmtp_line,
+                    feedback: codec
+                        .capability
+                        .rtcp_feedback
+                        .into_iter()
+                        .map(|feedback| {
+                            format!("{} {}", feedback.typ, feedback.parameter)
+                                .trim_end()
+                                .to_string()
+                        })
+                        .collect(),
+                });
+            }
+            for extension in parameters.rtp_parameters.header_extensions {
+                negotiation.header_extensions.push(HeaderExtension {
+                    mid: mid.clone(),
+                    id: extension.id,
+                    uri: extension.uri,
+                });
+            }
+        }
+        negotiation
+    }
+}
+
+// One line per codec and extension, grouped by media id
+impl fmt::Display for Negotiation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "signaling: {}", self.signaling_state)?;
+        if self.remote_sdp.is_none() {
+            writeln!(f, "no answer applied yet; codecs are the local offer")?;
+        }
+        for codec in &self.codecs {
+            let mut line = format!(
+                "mid {} {}: {} pt={} {}Hz",
+                codec.mid, codec.kind, codec.mime_type, codec.payload_type, codec.clock_rate
+            );
+            if codec.channels > 0 {
+                let _ = write!(line, "/{}", codec.channels);
+            }
+            if !codec.fmtp.is_empty() {
+                let _ = write!(line, " fmtp[{}]", codec.fmtp);
+            }
+            if !codec.feedback.is_empty() {
+                let _ = write!(line, " fb[{}]", codec.feedback.join(", "));
+            }
+            writeln!(f, "{}", line)?;
+        }
+        for extension in &self.header_extensions {
+            writeln!(
+                f,
+                "mid {} extmap {}: {}",
+                extension.mid, extension.id, extension.uri
+            )?;
+        }
+        Ok(())
+    }
+}
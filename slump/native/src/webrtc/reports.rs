@@ -0,0 +1,387 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use webrtc::{
+    interceptor::{
+        stream_info::StreamInfo, Attributes, Interceptor, InterceptorBuilder, RTCPReader,
+        RTCPWriter, RTPReader, RTPWriter,
+    },
+    rtcp::{
+        packet::Packet,
+        payload_feedbacks::{
+            full_intra_request::FullIntraRequest, picture_loss_indication::PictureLossIndication,
+        },
+        receiver_report::ReceiverReport,
+        reception_report::ReceptionReport,
+        sender_report::SenderReport,
+        transport_feedbacks::transport_layer_cc::{
+            PacketStatusChunk, SymbolTypeTcc, TransportLayerCc,
+        },
+    },
+};
+
+// A peer that stops reporting stops counting, rather than showing its last
+// numbers forever
+const REPORT_TTL: Duration = Duration::from_secs(10);
+// Weight of each TWCC loss sample; feedback comes every ~100 ms, so a single
+// one says little
+const TWCC_SMOOTHING: f64 = 0.2;
+// Seconds from the NTP epoch (1900) to the Unix one
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+// How the remote side says our media arrives, worst of our streams
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkQuality {
+    // None until a report echoes one of our sender reports
+    pub rtt_ms: Option<f64>,
+    pub jitter_ms: f64,
+    // 0-1
+    pub fraction_lost: f64,
+}
+
+struct Report {
+    rtt_ms: Option<f64>,
+    jitter_ms: f64,
+    fraction_lost: f64,
+    received: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    // Per local SSRC, to turn jitter from RTP units into time
+    clock_rates: HashMap<u32, u32>,
+    // Per local SSRC the report is about
+    reports: HashMap<u32, Report>,
+    twcc_loss: Option<(f64, Instant)>,
+    // PLIs and FIRs per local SSRC, since the last take
+    keyframe_requests: HashMap<u32, u32>,
+}
+
+// Reads the RTCP a peer sends back: reception reports for RTT, jitter and
+// loss, transport-wide congestion control feedback for loss at packet
+// granularity, and keyframe requests. One per peer connection.
+#[derive(Clone, Default)]
+pub struct ReceiverReports {
+    state: Arc<parking_lot::Mutex<State>>,
+}
+
+impl ReceiverReports {
+    pub fn quality(&self) -> LinkQuality {
+        let now = Instant::now();
+        let state = self.state.lock();
+        let mut quality = LinkQuality::default();
+        for report in state.reports.values() {
+            if now.duration_since(report.received) > REPORT_TTL {
+                continue;
+            }
+            if let Some(rtt) = report.rtt_ms {
+                quality.rtt_ms = Some(quality.rtt_ms.map_or(rtt, |worst| worst.max(rtt)));
+            }
+            quality.jitter_ms = quality.jitter_ms.max(report.jitter_ms);
+            quality.fraction_lost = quality.fraction_lost.max(report.fraction_lost);
+        }
+        if let Some((loss, received)) = state.twcc_loss {
+            if now.duration_since(received) <= REPORT_TTL {
+                quality.fraction_lost = quality.fraction_lost.max(loss);
+            }
+        }
+        quality
+    }
+
+    pub fn take_keyframe_requests(&self) -> HashMap<u32, u32> {
+        std::mem::take(&mut self.state.lock().keyframe_requests)
+    }
+
+    fn receive(&self, packets: &[Box<dyn Packet + Send + Sync>]) {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        for packet in packets {
+            let packet = packet.as_any();
+            if let Some(receiver_report) = packet.downcast_ref::<ReceiverReport>() {
+                for report in &receiver_report.reports {
+                    state.reception(report, now);
+                }
+            } else if let Some(sender_report) = packet.downcast_ref::<SenderReport>() {
+                // A peer that sends media too puts its reports in its own SRs
+                for report in &sender_report.reports {
+                    state.reception(report, now);
+                }
+            } else if let Some(feedback) = packet.downcast_ref::<TransportLayerCc>() {
+                if let Some(loss) = twcc_loss(feedback) {
+                    let smoothed = match state.twcc_loss {
+                        Some((previous, _)) => previous + (loss - previous) * TWCC_SMOOTHING,
+                        None => loss,
+                    };
+                    state.twcc_loss = Some((smoothed, now));
+                }
+            } else if let Some(pli) = packet.downcast_ref::<PictureLossIndication>() {
+                state.keyframe_request(pli.media_ssrc);
+            } else if let Some(fir) = packet.downcast_ref::<FullIntraRequest>() {
+                for entry in &fir.fir {
+                    state.keyframe_request(entry.ssrc);
+                }
+            }
+        }
+    }
+}
+
+impl State {
+    fn keyframe_request(&mut self, ssrc: u32) {
+        if self.clock_rates.contains_key(&ssrc) {
+            *self.keyframe_requests.entry(ssrc).or_default() += 1;
+        }
+    }
+
+    fn reception(&mut self, report: &ReceptionReport, now: Instant) {
+        // Reports about SSRCs we don't send are someone else's
+        let Some(&clock_rate) = self.clock_rates.get(&report.ssrc) else {
+            return;
+        };
+        let previous_rtt = self.reports.get(&report.ssrc).and_then(|r| r.rtt_ms);
+        self.reports.insert(
+            report.ssrc,
+            Report {
+                rtt_ms: round_trip(report).or(previous_rtt),
+                jitter_ms: report.jitter as f64 * 1000.0 / clock_rate as f64,
+                fraction_lost: report.fraction_lost as f64 / 256.0,
+                received: now,
+            },
+        );
+    }
+}
+
+// RFC 3550 6.4.1: arrival minus the echoed sender report time minus how long
+// the peer held it, all in 1/65536 s
+fn round_trip(report: &ReceptionReport) -> Option<f64> {
+    if report.last_sender_report == 0 {
+        return None;
+    }
+    let rtt = ntp_middle_now()
+        .wrapping_sub(report.last_sender_report)
+        .wrapping_sub(report.delay);
+    // Wrapped below zero: clock step or a bogus report
+    if rtt > u32::MAX / 2 {
+        return None;
+    }
+    Some(rtt as f64 * 1000.0 / 65536.0)
+}
+
+// The middle 32 bits of the NTP timestamp, as in the LSR field. The sender
+// report interceptor stamps from the same system clock.
+fn ntp_middle_now() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((now.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (((seconds & 0xffff) << 16) | (fraction >> 16)) as u32
+}
+
+// Share of the packets covered by one feedback message that never arrived
+fn twcc_loss(feedback: &TransportLayerCc) -> Option<f64> {
+    let total = feedback.packet_status_count as usize;
+    if total == 0 {
+        return None;
+    }
+    let mut seen = 0;
+    let mut lost = 0;
+    for chunk in &feedback.packet_chunks {
+        let (symbol_count, missing) = match chunk {
+            PacketStatusChunk::RunLengthChunk(run) => {
+                let count = run.run_length as usize;
+                let missing = if run.packet_status_symbol == SymbolTypeTcc::PacketNotReceived {
+                    count
+                } else {
+                    0
+                };
+                (count, missing)
+            }
+            PacketStatusChunk::StatusVectorChunk(vector) => {
+                // The last vector can be padded past the status count
+                let symbols = &vector.symbol_list[..vector.symbol_list.len().min(total - seen)];
+                let missing = symbols
+                    .iter()
+                    .filter(|&&symbol| symbol == SymbolTypeTcc::PacketNotReceived)
+                    .count();
+                (symbols.len(), missing)
+            }
+        };
+        let symbol_count = symbol_count.min(total - seen);
+        seen += symbol_count;
+        lost += missing.min(symbol_count);
+        if seen == total {
+            break;
+        }
+    }
+    Some(lost as f64 / total as f64)
+}
+
+impl InterceptorBuilder for ReceiverReports {
+    fn build(
+        &self,
+        _id: &str,
+    ) -> webrtc::interceptor::error::Result<Arc<dyn Interceptor + Send + Sync>> {
+        Ok(Arc::new(ReportsInterceptor {
+            reports: self.clone(),
+        }))
+    }
+}
+
+struct ReportsInterceptor {
+    reports: ReceiverReports,
+}
+
+#[async_trait]
+impl Interceptor for ReportsInterceptor {
+    async fn bind_rtcp_reader(
+        &self,
+        reader: Arc<dyn RTCPReader + Send + Sync>,
+    ) -> Arc<dyn RTCPReader + Send + Sync> {
+        Arc::new(ReportReader {
+            reports: self.reports.clone(),
+            next: reader,
+        })
+    }
+
+    async fn bind_rtcp_writer(
+        &self,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+    ) -> Arc<dyn RTCPWriter + Send + Sync> {
+        writer
+    }
+
+    async fn bind_local_stream(
+        &self,
+        info: &StreamInfo,
+        writer: Arc<dyn RTPWriter + Send + Sync>,
+    ) -> Arc<dyn RTPWriter + Send + Sync> {
+        self.reports
+            .state
+            .lock()
+            .clock_rates
+            .insert(info.ssrc, info.clock_rate);
+        writer
+    }
+
+    async fn unbind_local_stream(&self, info: &StreamInfo) {
+        let mut state = self.reports.state.lock();
+        state.clock_rates.remove(&info.ssrc);
+        state.reports.remove(&info.ssrc);
+    }
+
+    async fn bind_remote_stream(
+        &self,
+        _info: &StreamInfo,
+        reader: Arc<dyn RTPReader + Send + Sync>,
+    ) -> Arc<dyn RTPReader + Send + Sync> {
+        reader
+    }
+
+    async fn unbind_remote_stream(&self, _info: &StreamInfo) {}
+
+    async fn close(&self) -> webrtc::interceptor::error::Result<()> {
+        Ok(())
+    }
+}
+
+struct ReportReader {
+    reports: ReceiverReports,
+    next: Arc<dyn RTCPReader + Send + Sync>,
+}
+
+#[async_trait]
+impl RTCPReader for ReportReader {
+    async fn read(
+        &self,
+        buf: &mut [u8],
+        attributes: &Attributes,
+    ) -> webrtc::interceptor::error::Result<(Vec<Box<dyn Packet + Send + Sync>>, Attributes)> {
+        let (packets, attributes) = self.next.read(buf, attributes).await?;
+        self.reports.receive(&packets);
+        Ok((packets, attributes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::rtcp::transport_feedbacks::transport_layer_cc::{
+        RunLengthChunk, StatusChunkTypeTcc, StatusVectorChunk, SymbolSizeTypeTcc,
+    };
+
+    fn feedback(
+        packet_status_count: u16,
+        packet_chunks: Vec<PacketStatusChunk>,
+    ) -> TransportLayerCc {
+        TransportLayerCc {
+            packet_status_count,
+            packet_chunks,
+            ..Default::default()
+        }
+    }
+
+    fn run(symbol: SymbolTypeTcc, run_length: u16) -> PacketStatusChunk {
+        PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+            type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+            packet_status_symbol: symbol,
+            run_length,
+        })
+    }
+
+    fn vector(symbol_list: Vec<SymbolTypeTcc>) -> PacketStatusChunk {
+        PacketStatusChunk::StatusVectorChunk(StatusVectorChunk {
+            type_tcc: StatusChunkTypeTcc::StatusVectorChunk,
+            symbol_size: SymbolSizeTypeTcc::OneBit,
+            symbol_list,
+        })
+    }
+
+    #[test]
+    fn twcc_loss_counts_run_length_chunks() {
+        let loss = twcc_loss(&feedback(
+            10,
+            vec![
+                run(SymbolTypeTcc::PacketReceivedSmallDelta, 6),
+                run(SymbolTypeTcc::PacketNotReceived, 4),
+            ],
+        ));
+        assert_eq!(loss, Some(0.4));
+    }
+
+    #[test]
+    fn twcc_loss_stops_at_the_status_count() {
+        use SymbolTypeTcc::{PacketNotReceived as Lost, PacketReceivedSmallDelta as Got};
+        // 16 statuses, the last vector padded with "lost" past them
+        let mut symbols = vec![Got, Lost];
+        symbols.extend([Lost; 12]);
+        let loss = twcc_loss(&feedback(16, vec![run(Got, 14), vector(symbols)]));
+        assert_eq!(loss, Some(1.0 / 16.0));
+
+        // A run longer than the count is cut the same way
+        let loss = twcc_loss(&feedback(4, vec![run(Lost, 20)]));
+        assert_eq!(loss, Some(1.0));
+        assert_eq!(twcc_loss(&feedback(0, vec![run(Lost, 3)])), None);
+    }
+
+    #[test]
+    fn round_trip_needs_an_echoed_sender_report() {
+        let report = ReceptionReport {
+            last_sender_report: 0,
+            delay: 0,
+            ..Default::default()
+        };
+        assert_eq!(round_trip(&report), None);
+
+        // Sent 100 ms ago, held 20 ms by the peer
+        let report = ReceptionReport {
+            last_sender_report: ntp_middle_now().wrapping_sub(6554),
+            delay: 1311,
+            ..Default::default()
+        };
+        let rtt = round_trip(&report).unwrap();
+        assert!((79.0..=90.0).contains(&rtt), "{rtt}");
+    }
+}
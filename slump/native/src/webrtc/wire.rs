@@ -0,0 +1,198 @@
+use async_trait::async_trait;
+use std::{collections::HashMap, sync::Arc};
+use webrtc::{
+    interceptor::{
+        stream_info::StreamInfo, Attributes, Interceptor, InterceptorBuilder, RTCPReader,
+        RTCPWriter, RTPReader, RTPWriter,
+    },
+    rtp::packet::Packet,
+    util::MarshalSize,
+};
+
+// RTP bytes written for one SSRC, by what they carried
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WireBytes {
+    pub media: u64,
+    // NACK retransmissions, on the media SSRC or an RTX one
+    pub rtx: u64,
+    // ULPFEC, FlexFEC and RED
+    pub fec: u64,
+    // Padding-only packets, e.g. bandwidth probes
+    pub padding: u64,
+}
+
+struct Stream {
+    payload_type: u8,
+    kind: StreamKind,
+    highest_sequence: Option<u16>,
+    bytes: WireBytes,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum StreamKind {
+    Media,
+    Rtx,
+    Fec,
+}
+
+impl StreamKind {
+    fn of(mime_type: &str) -> Self {
+        let codec = mime_type
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match codec.as_str() {
+            "rtx" => StreamKind::Rtx,
+            "ulpfec" | "red" => StreamKind::Fec,
+            _ if codec.starts_with("flexfec") => StreamKind::Fec,
+            _ => StreamKind::Media,
+        }
+    }
+}
+
+// Counts what actually leaves for one peer, after the NACK responder, so
+// retransmissions are included. Sits above the impairment, which stands in
+// for the network.
+#[derive(Clone, Default)]
+pub struct WireCounter {
+    streams: Arc<parking_lot::Mutex<HashMap<u32, Stream>>>,
+}
+
+impl WireCounter {
+    // Running totals per SSRC
+    pub fn bytes(&self) -> HashMap<u32, WireBytes> {
+        self.streams
+            .lock()
+            .iter()
+            .map(|(&ssrc, stream)| (ssrc, stream.bytes))
+            .collect()
+    }
+
+    fn count(&self, packet: &Packet, size: usize) {
+        let size = size as u64;
+        let mut streams = self.streams.lock();
+        let Some(stream) = streams.get_mut(&packet.header.ssrc) else {
+            return;
+        };
+        let padding_only = packet.header.padding
+            && packet
+                .payload
+                .last()
+                .is_none_or(|&count| count as usize == packet.payload.len());
+        if padding_only {
+            stream.bytes.padding += size;
+            return;
+        }
+        match stream.kind {
+            StreamKind::Rtx => stream.bytes.rtx += size,
+            StreamKind::Fec => stream.bytes.fec += size,
+            // A second payload type on a media SSRC is FEC sent alongside it
+            StreamKind::Media if packet.header.payload_type != stream.payload_type => {
+                stream.bytes.fec += size
+            }
+            StreamKind::Media => {
+                let sequence = packet.header.sequence_number;
+                let newer = stream
+                    .highest_sequence
+                    .is_none_or(|highest| (sequence.wrapping_sub(highest) as i16) > 0);
+                if newer {
+                    stream.highest_sequence = Some(sequence);
+                    stream.bytes.media += size;
+                } else {
+                    stream.bytes.rtx += size;
+                }
+            }
+        }
+    }
+}
+
+impl InterceptorBuilder for WireCounter {
+    fn build(
+        &self,
+        _id: &str,
+    ) -> webrtc::interceptor::error::Result<Arc<dyn Interceptor + Send + Sync>> {
+        Ok(Arc::new(WireInterceptor {
+            counter: self.clone(),
+        }))
+    }
+}
+
+struct WireInterceptor {
+    counter: WireCounter,
+}
+
+#[async_trait]
+impl Interceptor for WireInterceptor {
+    async fn bind_rtcp_reader(
+        &self,
+        reader: Arc<dyn RTCPReader + Send + Sync>,
+    ) -> Arc<dyn RTCPReader + Send + Sync> {
+        reader
+    }
+
+    async fn bind_rtcp_writer(
+        &self,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+    ) -> Arc<dyn RTCPWriter + Send + Sync> {
+        writer
+    }
+
+    async fn bind_local_stream(
+        &self,
+        info: &StreamInfo,
+        writer: Arc<dyn RTPWriter + Send + Sync>,
+    ) -> Arc<dyn RTPWriter + Send + Sync> {
+        let mut streams = self.counter.streams.lock();
+        let stream = streams.entry(info.ssrc).or_insert_with(|| Stream {
+            payload_type: info.payload_type,
+            kind: StreamKind::Media,
+            highest_sequence: None,
+            bytes: WireBytes::default(),
+        });
+        stream.payload_type = info.payload_type;
+        stream.kind = StreamKind::of(&info.mime_type);
+        drop(streams);
+        Arc::new(CountingWriter {
+            counter: self.counter.clone(),
+            next: writer,
+        })
+    }
+
+    // Totals stay until the transport goes, so rates don't jump backwards
+    // when a track is renegotiated
+    async fn unbind_local_stream(&self, _info: &StreamInfo) {}
+
+    async fn bind_remote_stream(
+        &self,
+        _info: &StreamInfo,
+        reader: Arc<dyn RTPReader + Send + Sync>,
+    ) -> Arc<dyn RTPReader + Send + Sync> {
+        reader
+    }
+
+    async fn unbind_remote_stream(&self, _info: &StreamInfo) {}
+
+    async fn close(&self) -> webrtc::interceptor::error::Result<()> {
+        Ok(())
+    }
+}
+
+struct CountingWriter {
+    counter: WireCounter,
+    next: Arc<dyn RTPWriter + Send + Sync>,
+}
+
+#[async_trait]
+impl RTPWriter for CountingWriter {
+    async fn write(
+        &self,
+        packet: &Packet,
+        attributes: &Attributes,
+    ) -> webrtc::interceptor::error::Result<usize> {
+        let written = self.next.write(packet, attributes).await?;
+        self.counter
+            .count(packet, packet.header.marshal_size() + packet.payload.len());
+        Ok(written)
+    }
+}
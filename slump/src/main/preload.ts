@@ -11,6 +11,9 @@ try {
   native = null;
 }
 
+// One stream at a time; the renderer only ever drives the one
+let stream: any = null;
+
 contextBridge.exposeInMainWorld('slump', {
   startOAuth: () => ipcRenderer.invoke('oauth:start'),
   logout: () => ipcRenderer.invoke('oauth:logout'),
@@ -20,17 +23,25 @@ contextBridge.exposeInMainWorld('slump', {
     ipcRenderer.on('oauth:success', listener);
     return () => ipcRenderer.removeListener('oauth:success', listener);
   },
-  startStream: (bitrateKbps: number, width: number, height: number, fps: number) => {
+  startStream: async (bitrateKbps: number, width: number, height: number, fps: number) => {
     if (!native) throw new Error('Native module not loaded');
-    return native.start_stream(bitrateKbps, width, height, fps);
+    if (stream) await stream.stop();
+    stream = new native.SlumpStream();
+    return stream.start({ bitrateKbps, width, height, fps }, () => {});
   },
-  stopStream: () => {
-    if (!native) throw new Error('Native module not loaded');
-    return native.stop_stream();
+  stopStream: async () => {
+    if (!stream) return false;
+    const running = stream;
+    stream = null;
+    return running.stop();
   },
   getStats: () => {
-    if (!native) throw new Error('Native module not loaded');
-    return native.get_stats();
+    if (!stream) throw new Error('Stream is not running');
+    const stats = stream.getStats();
+    return {
+      bitrate_kbps: Math.round(stats.videoKbps ?? 0),
+      latency_ms: Math.round(stats.rtt ?? 0)
+    };
   }
 });
 
@@ -41,8 +52,8 @@ declare global {
       logout: () => Promise<boolean>;
       authStatus: () => Promise<boolean>;
       onOAuthSuccess: (cb: () => void) => () => void;
-      startStream: (bitrateKbps: number, width: number, height: number, fps: number) => boolean;
-      stopStream: () => boolean;
+      startStream: (bitrateKbps: number, width: number, height: number, fps: number) => Promise<boolean>;
+      stopStream: () => Promise<boolean>;
       getStats: () => { bitrate_kbps: number; latency_ms: number };
     };
   }